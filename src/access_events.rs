@@ -0,0 +1,358 @@
+//! Structured access events for download analytics (feature `access-log`).
+//!
+//! With [`S3OriginBuilder::access_events`](crate::S3OriginBuilder::access_events) the origin
+//! records an [`AccessEvent`] for every response served from an object: the key and object
+//! metadata, the status, the body bytes actually sent and the client.  Responses not served
+//! from an object (errors, redirects) produce no event.  Like
+//! [`access_log`](crate::access_log) entries, an event is complete once the body has been sent
+//! or dropped.
+//!
+//! Events are buffered and handed to an [`AccessEventSink`] in batches, once
+//! [`batch_size`](AccessEvents::batch_size) events are waiting or
+//! [`flush_interval`](AccessEvents::flush_interval) after the first one, whichever comes first.
+//! Sending runs in the background on the Tokio runtime; failures are logged and the batch is
+//! dropped.  The crate ships a [`TracingEventSink`].  A Kinesis Data Firehose sink takes a few
+//! lines with `aws-sdk-firehose`:
+//!
+//! ```rust,ignore
+//! use aws_sdk_firehose::{primitives::Blob, types::Record, Client};
+//! use axum_static_s3::access_events::{AccessEvent, AccessEventSink, SinkFuture};
+//!
+//! struct FirehoseSink { client: Client, stream: String }
+//!
+//! impl AccessEventSink for FirehoseSink {
+//!     fn send<'a>(&'a self, events: Vec<AccessEvent>) -> SinkFuture<'a> {
+//!         Box::pin(async move {
+//!             let records = events.iter()
+//!                 .map(|event| Record::builder().data(Blob::new(event.to_json() + "\n")).build())
+//!                 .collect::<Result<Vec<_>, _>>()?;
+//!             self.client.put_record_batch()
+//!                 .delivery_stream_name(&self.stream)
+//!                 .set_records(Some(records))
+//!                 .send()
+//!                 .await?;
+//!             Ok(())
+//!         })
+//!     }
+//! }
+//! ```
+//!
+//! Firehose accepts up to 500 records per batch, the default [`batch_size`](AccessEvents::batch_size).
+use std::{
+    fmt::{self, Write},
+    future::Future,
+    mem,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{Body, BodyDataStream, Bytes},
+    http::{header, Method, Request, StatusCode},
+    response::Response,
+    BoxError,
+};
+use futures_core::Stream;
+
+use crate::{admin::json_string, client_identity::ClientIdentity, S3ObjectMeta};
+
+
+/// The future returned by [`AccessEventSink::send`].
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send + 'a>>;
+
+
+/// The client a response was served to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// The client address, as found by the origin's
+    /// [`client_identity`](crate::S3OriginBuilder::client_identity), if known.
+    pub address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+}
+
+impl ClientInfo {
+    pub(crate) fn from_request(req: &Request<()>, identity: ClientIdentity) -> Self {
+        let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_owned);
+        Self {
+            address: identity.client_ip(req),
+            user_agent: header(header::USER_AGENT),
+            referer: header(header::REFERER),
+        }
+    }
+}
+
+
+/// A response served from an object.
+#[derive(Clone, Debug)]
+pub struct AccessEvent {
+    /// When the request was received.
+    pub time: SystemTime,
+    pub method: Method,
+    /// The request path and query, as received by the origin.
+    pub path: String,
+    /// The object served.
+    pub object: S3ObjectMeta,
+    pub status: StatusCode,
+    /// Body bytes sent to the client.
+    pub bytes_sent: u64,
+    pub client: ClientInfo,
+}
+
+impl AccessEvent {
+    /// The event as a JSON object, without a trailing newline.
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<&str>| value.map(json_string).unwrap_or("null".into());
+        let millis = self.time.duration_since(UNIX_EPOCH).map(|time| time.as_millis()).unwrap_or_default();
+
+        let mut json = String::from("{");
+        let _ = write!(json, "\"time_ms\":{}", millis);
+        let _ = write!(json, ",\"method\":{}", json_string(self.method.as_str()));
+        let _ = write!(json, ",\"path\":{}", json_string(&self.path));
+        let _ = write!(json, ",\"bucket\":{}", json_string(&self.object.bucket));
+        let _ = write!(json, ",\"key\":{}", json_string(&self.object.key));
+        let _ = write!(json, ",\"etag\":{}", optional(self.object.etag.as_deref()));
+        let _ = write!(json, ",\"version_id\":{}", optional(self.object.version_id.as_deref()));
+        let _ = write!(json, ",\"status\":{}", self.status.as_u16());
+        let _ = write!(json, ",\"bytes_sent\":{}", self.bytes_sent);
+        let _ = write!(json, ",\"client_ip\":{}", optional(self.client.address.map(|address| address.to_string()).as_deref()));
+        let _ = write!(json, ",\"user_agent\":{}", optional(self.client.user_agent.as_deref()));
+        let _ = write!(json, ",\"referer\":{}", optional(self.client.referer.as_deref()));
+        json.push('}');
+        json
+    }
+}
+
+
+/// Receives batches of access events.
+pub trait AccessEventSink: Send + Sync + 'static {
+    fn send<'a>(&'a self, events: Vec<AccessEvent>) -> SinkFuture<'a>;
+}
+
+
+/// Emits each event as a JSON `tracing` event at level `INFO`, with the target
+/// `axum_static_s3::access_events`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingEventSink;
+
+impl AccessEventSink for TracingEventSink {
+    fn send<'a>(&'a self, events: Vec<AccessEvent>) -> SinkFuture<'a> {
+        for event in &events {
+            tracing::info!(target: "axum_static_s3::access_events", "{}", event.to_json());
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
+
+
+/// Access event configuration.
+#[derive(Clone)]
+pub struct AccessEvents {
+    sink: Arc<dyn AccessEventSink>,
+    batch_size: usize,
+    flush_interval: Duration,
+}
+
+impl AccessEvents {
+    /// Send events to `sink`.
+    pub fn new(sink: impl AccessEventSink) -> Self {
+        Self { sink: Arc::new(sink), batch_size: 500, flush_interval: Duration::from_secs(1) }
+    }
+
+    /// Send a batch once this many events are waiting; defaults to 500.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Send waiting events at the latest this long after the first; defaults to 1 second.
+    pub fn flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+}
+
+impl fmt::Debug for AccessEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessEvents")
+            .field("sink", &crate::redact::Opaque("sink"))
+            .field("batch_size", &self.batch_size)
+            .field("flush_interval", &self.flush_interval)
+            .finish()
+    }
+}
+
+
+/// Buffers events for an origin.
+#[derive(Debug)]
+pub(crate) struct EventBuffer {
+    config: AccessEvents,
+    events: Mutex<Vec<AccessEvent>>,
+}
+
+impl EventBuffer {
+    pub(crate) fn new(config: AccessEvents) -> Arc<Self> {
+        Arc::new(Self { config, events: Mutex::default() })
+    }
+
+    fn push(self: &Arc<Self>, event: AccessEvent) {
+        let mut events = self.events.lock().unwrap_or_else(PoisonError::into_inner);
+        events.push(event);
+        if events.len() >= self.config.batch_size {
+            let batch = mem::take(&mut *events);
+            drop(events);
+            self.send(batch);
+        } else if events.len() == 1 {
+            // The first waiting event starts the flush timer
+            let (buffer, interval) = (self.clone(), self.config.flush_interval);
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                runtime.spawn(async move {
+                    tokio::time::sleep(interval).await;
+                    buffer.flush();
+                });
+            }
+        }
+    }
+
+    /// Send the waiting events.
+    pub(crate) fn flush(&self) {
+        let batch = mem::take(&mut *self.events.lock().unwrap_or_else(PoisonError::into_inner));
+        if !batch.is_empty() {
+            self.send(batch);
+        }
+    }
+
+    fn send(&self, batch: Vec<AccessEvent>) {
+        let sink = self.config.sink.clone();
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        runtime.spawn(async move {
+            let count = batch.len();
+            if let Err(error) = sink.send(batch).await {
+                tracing::warn!("S3Origin: sending {} access events failed: {}", count, error);
+            }
+        });
+    }
+
+    /// Record an event for the response once its body has been sent.
+    pub(crate) fn observe<F, E>(self: &Arc<Self>, method: Method, path: String, client: ClientInfo, response: F) -> impl Future<Output = Result<Response, E>> + Send + 'static
+    where
+        F: Future<Output = Result<Response, E>> + Send + 'static,
+    {
+        let buffer = self.clone();
+        let time = SystemTime::now();
+        async move {
+            let response = response.await?;
+            let Some(object) = response.extensions().get::<S3ObjectMeta>().cloned() else {
+                return Ok(response);
+            };
+            let event = AccessEvent { time, method, path, object, status: response.status(), bytes_sent: 0, client };
+            let (parts, body) = response.into_parts();
+            let body = EventBody { inner: body.into_data_stream(), buffer, event: Some(event) };
+            Ok(Response::from_parts(parts, Body::from_stream(body)))
+        }
+    }
+}
+
+
+/// Counts body bytes and records the event when the body ends or is dropped.
+struct EventBody {
+    inner: BodyDataStream,
+    buffer: Arc<EventBuffer>,
+    event: Option<AccessEvent>,
+}
+
+impl EventBody {
+    fn emit(&mut self) {
+        if let Some(event) = self.event.take() {
+            self.buffer.push(event);
+        }
+    }
+}
+
+impl Stream for EventBody {
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(data))) => {
+                if let Some(event) = self.event.as_mut() {
+                    event.bytes_sent += data.len() as u64;
+                }
+            }
+            Poll::Ready(_) => self.emit(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl Drop for EventBody {
+    fn drop(&mut self) {
+        self.emit();
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    struct Channel(mpsc::UnboundedSender<Vec<AccessEvent>>);
+
+    impl AccessEventSink for Channel {
+        fn send<'a>(&'a self, events: Vec<AccessEvent>) -> SinkFuture<'a> {
+            self.0.send(events).unwrap();
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn object(key: &str) -> Response {
+        let mut response = Response::new(Body::from("hello"));
+        response.extensions_mut().insert(S3ObjectMeta {
+            bucket: "my-bucket".into(),
+            key: key.into(),
+            etag: Some("\"abc\"".into()),
+            version_id: None,
+            content_length: Some(5),
+            last_modified: None,
+        });
+        response
+    }
+
+    async fn serve(buffer: &Arc<EventBuffer>, response: Response) {
+        let request = Request::get("/a.txt").header(header::USER_AGENT, "curl/8").body(()).unwrap();
+        let client = ClientInfo::from_request(&request, ClientIdentity::new());
+        let response = buffer.observe(Method::GET, "/a.txt".into(), client, async { Ok::<_, ()>(response) }).await.unwrap();
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sends_events_in_batches() {
+        let (sender, mut batches) = mpsc::unbounded_channel();
+        let buffer = EventBuffer::new(AccessEvents::new(Channel(sender)).batch_size(2).flush_interval(Duration::from_millis(20)));
+
+        serve(&buffer, object("a.txt")).await;
+        serve(&buffer, Response::new(Body::from("not found"))).await;
+        serve(&buffer, object("b.txt")).await;
+        let batch = batches.recv().await.unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0].object.key, "a.txt");
+        assert_eq!(batch[0].bytes_sent, 5);
+        assert_eq!(batch[0].client.user_agent.as_deref(), Some("curl/8"));
+        assert_eq!(batch[1].object.key, "b.txt");
+
+        // A partial batch is sent after the flush interval
+        serve(&buffer, object("c.txt")).await;
+        let batch = batches.recv().await.unwrap();
+        assert_eq!(batch.len(), 1);
+        assert!(batch[0].to_json().contains(r#""bucket":"my-bucket","key":"c.txt","etag":"\"abc\"","version_id":null,"status":200,"bytes_sent":5,"client_ip":null,"user_agent":"curl/8""#));
+    }
+}
@@ -0,0 +1,332 @@
+//! Structured access logging (feature `access-log`).
+//!
+//! With [`S3OriginBuilder::access_log`](crate::S3OriginBuilder::access_log) the origin records
+//! one [`AccessLogEntry`] per request: method, path, resolved S3 key, status, bytes sent, time
+//! spent waiting for S3 and the S3 request ID.  The entry is emitted when the response body has
+//! been sent completely, or when it is dropped (e.g. the client disconnected), so `bytes_sent`
+//! is the number of body bytes actually handed to the server.
+//!
+//! Entries are formatted as [`LogFormat::Common`] or [`LogFormat::Json`] and passed to an
+//! [`AccessLogSink`]; the default sink emits them as `tracing` events with the target
+//! `axum_static_s3::access_log`.
+use std::{
+    fmt::Write,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{Body, BodyDataStream, Bytes},
+    http::{Method, StatusCode},
+    response::Response,
+};
+use futures_core::Stream;
+
+use crate::admin::json_string;
+use crate::metadata::{S3Latency, S3RequestId};
+use crate::ResolvedKey;
+
+
+/// The format of access log lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Common Log Format, followed by the key, the S3 latency in milliseconds and the S3 request
+    /// ID (default).
+    ///
+    /// `- - - [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326 "index.html" 12 "4QX1…"`
+    #[default]
+    Common,
+    /// One JSON object per line.
+    Json,
+}
+
+
+/// A single access log record.
+#[derive(Clone, Debug)]
+pub struct AccessLogEntry {
+    /// When the request was received.
+    pub time: SystemTime,
+    pub method: Method,
+    /// The request path and query, as received by the origin.
+    pub path: String,
+    /// The S3 key the response was served from, see [`ResolvedKey`].
+    pub key: Option<String>,
+    pub status: StatusCode,
+    /// Body bytes sent to the client.
+    pub bytes_sent: u64,
+    /// Time spent waiting for S3 responses, if S3 was called.
+    pub s3_latency: Option<Duration>,
+    /// The S3 request ID of the response, if S3 returned one.
+    pub request_id: Option<String>,
+}
+
+
+impl AccessLogEntry {
+    /// Format the entry as a log line, without a trailing newline.
+    pub fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Common => self.to_common(),
+            LogFormat::Json => self.to_json(),
+        }
+    }
+
+    fn to_common(&self) -> String {
+        let (year, month, day, hour, minute, second) = civil_time(self.time);
+        const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+        let mut line = format!(
+            "- - - [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{} {} HTTP/1.1\" {} {}",
+            day, MONTHS[month as usize - 1], year, hour, minute, second,
+            self.method, self.path, self.status.as_u16(), self.bytes_sent,
+        );
+        let _ = write!(line, " \"{}\"", self.key.as_deref().unwrap_or("-"));
+        match self.s3_latency {
+            Some(latency) => { let _ = write!(line, " {}", latency.as_millis()); }
+            None => line.push_str(" -"),
+        }
+        let _ = write!(line, " \"{}\"", self.request_id.as_deref().unwrap_or("-"));
+        line
+    }
+
+    fn to_json(&self) -> String {
+        let (year, month, day, hour, minute, second) = civil_time(self.time);
+
+        let mut line = String::from("{");
+        let _ = write!(line, "\"time\":\"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z\"", year, month, day, hour, minute, second);
+        let _ = write!(line, ",\"method\":{}", json_string(self.method.as_str()));
+        let _ = write!(line, ",\"path\":{}", json_string(&self.path));
+        let _ = write!(line, ",\"key\":{}", self.key.as_deref().map(json_string).unwrap_or("null".into()));
+        let _ = write!(line, ",\"status\":{}", self.status.as_u16());
+        let _ = write!(line, ",\"bytes_sent\":{}", self.bytes_sent);
+        match self.s3_latency {
+            Some(latency) => { let _ = write!(line, ",\"s3_latency_ms\":{:.3}", latency.as_secs_f64() * 1000.0); }
+            None => line.push_str(",\"s3_latency_ms\":null"),
+        }
+        let _ = write!(line, ",\"request_id\":{}", self.request_id.as_deref().map(json_string).unwrap_or("null".into()));
+        line.push('}');
+        line
+    }
+}
+
+
+/// Receives formatted access log lines.
+pub trait AccessLogSink: Send + Sync + 'static {
+    fn log(&self, entry: &AccessLogEntry, line: &str);
+}
+
+
+/// Emits access log lines as `tracing` events at level `INFO` (default sink).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingSink;
+
+impl AccessLogSink for TracingSink {
+    fn log(&self, _entry: &AccessLogEntry, line: &str) {
+        tracing::info!(target: "axum_static_s3::access_log", "{}", line);
+    }
+}
+
+
+/// Access log configuration.
+#[derive(Clone)]
+pub struct AccessLog {
+    format: LogFormat,
+    sink: Arc<dyn AccessLogSink>,
+}
+
+
+impl AccessLog {
+    pub fn new() -> Self {
+        Self {
+            format: LogFormat::default(),
+            sink: Arc::new(TracingSink),
+        }
+    }
+
+    /// Set the log line format.
+    ///
+    /// This is optional, and defaults to [`LogFormat::Common`].
+    ///
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set where log lines are sent.
+    ///
+    /// This is optional, and defaults to [`TracingSink`].
+    ///
+    pub fn sink(mut self, sink: impl AccessLogSink) -> Self {
+        self.sink = Arc::new(sink);
+        self
+    }
+
+    /// Log the response once its body has been sent.
+    pub(crate) fn instrument<F, E>(&self, method: Method, path: String, response: F) -> impl Future<Output = Result<Response, E>> + Send + 'static
+    where
+        F: Future<Output = Result<Response, E>> + Send + 'static,
+    {
+        let log = self.clone();
+        let time = SystemTime::now();
+        async move {
+            let response = response.await?;
+            let extensions = response.extensions();
+            let entry = AccessLogEntry {
+                time,
+                method,
+                path,
+                key: extensions.get::<ResolvedKey>().map(|key| key.0.clone()),
+                status: response.status(),
+                bytes_sent: 0,
+                s3_latency: extensions.get::<S3Latency>().map(|latency| latency.0),
+                request_id: extensions.get::<S3RequestId>().map(|ids| ids.request_id.clone()),
+            };
+
+            let (parts, body) = response.into_parts();
+            let body = LoggedBody { inner: body.into_data_stream(), log, entry: Some(entry) };
+            Ok(Response::from_parts(parts, Body::from_stream(body)))
+        }
+    }
+}
+
+impl std::fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("format", &self.format)
+            .field("sink", &crate::redact::Opaque("sink"))
+            .finish()
+    }
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Counts body bytes and emits the entry when the body ends or is dropped.
+struct LoggedBody {
+    inner: BodyDataStream,
+    log: AccessLog,
+    entry: Option<AccessLogEntry>,
+}
+
+impl LoggedBody {
+    fn emit(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.log.sink.log(&entry, &entry.format(self.log.format));
+        }
+    }
+}
+
+impl Stream for LoggedBody {
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(data))) => {
+                if let Some(entry) = self.entry.as_mut() {
+                    entry.bytes_sent += data.len() as u64;
+                }
+            }
+            Poll::Ready(_) => self.emit(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        self.emit();
+    }
+}
+
+
+/// UTC calendar date and time of a timestamp.
+fn civil_time(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rem) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            method: Method::GET,
+            path: "/docs/a \"b\".html".into(),
+            key: Some("site/docs/a \"b\".html".into()),
+            status: StatusCode::OK,
+            bytes_sent: 2326,
+            s3_latency: Some(Duration::from_millis(12)),
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn formats_common_log() {
+        assert_eq!(
+            entry().format(LogFormat::Common),
+            r#"- - - [10/Oct/2000:13:55:36 +0000] "GET /docs/a "b".html HTTP/1.1" 200 2326 "site/docs/a "b".html" 12 "-""#
+        );
+    }
+
+    #[test]
+    fn formats_json() {
+        assert_eq!(
+            entry().format(LogFormat::Json),
+            r#"{"time":"2000-10-10T13:55:36Z","method":"GET","path":"/docs/a \"b\".html","key":"site/docs/a \"b\".html","status":200,"bytes_sent":2326,"s3_latency_ms":12.000,"request_id":null}"#
+        );
+    }
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<String>>);
+
+    impl AccessLogSink for Arc<Collect> {
+        fn log(&self, _entry: &AccessLogEntry, line: &str) {
+            self.0.lock().unwrap().push(line.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_after_body_is_sent() {
+        let lines = Arc::new(Collect::default());
+        let log = AccessLog::new().format(LogFormat::Json).sink(lines.clone());
+
+        let response = async {
+            let mut response = Response::new(Body::from("hello"));
+            response.extensions_mut().insert(ResolvedKey("hello.txt".into()));
+            Ok::<_, ()>(response)
+        };
+        let response = log.instrument(Method::GET, "/hello.txt".into(), response).await.unwrap();
+        assert!(lines.0.lock().unwrap().is_empty());
+
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let lines = lines.0.lock().unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains(r#""key":"hello.txt","status":200,"bytes_sent":5,"s3_latency_ms":null"#));
+    }
+}
@@ -0,0 +1,244 @@
+//! Introspection and cache administration endpoints.
+//!
+//! [`S3Origin::admin_router`] serves
+//!
+//! - `GET /stats`: in-flight requests, cache statistics, feature counters and cost drivers as
+//!   JSON, e.g. `{"in_flight":2,"cache":{"capacity":1048576,"size":5120,"entries":3,"hits":40,"misses":3,"hit_ratio":0.930},"features":null,"cost":{"get_requests":3,...}}`.
+//!   `cache` and `features` are `null` unless [`cache`](crate::S3OriginBuilder::cache) and
+//!   [`feature_telemetry`](crate::S3OriginBuilder::feature_telemetry) are enabled, and `cost`
+//!   holds the S3 [cost](crate::cost) drivers;
+//! - `POST /purge`: evicts the whole cache, `?key=` a single key or `?prefix=` all keys below
+//!   a prefix (relative to the bucket prefix), and answers `{"purged":N}`;
+//! - `GET /digest?key=`: the SHA-256 of an object (relative to the bucket prefix), e.g.
+//!   `{"key":"site/app.js","sha256":"9f86d0…","size":1234,"etag":"\"abc\"","source":"computed"}`,
+//!   to verify a deployment against its build manifest.  The digest is the object's full-object
+//!   `ChecksumSHA256` when it has one (`"source":"checksum"`); otherwise the object is read and
+//!   hashed, and the digest kept until its ETag changes (`"source":"cache"`).
+//!
+//! The router is always behind the caller's authentication layer; mount it on an internal path,
+//! e.g. `Router::new().nest("/_admin", origin.admin_router(auth))`.
+use std::{
+    convert::Infallible,
+    fmt::Write,
+    sync::{atomic::Ordering, Arc},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Uri},
+    response::{IntoResponse, Response},
+    routing::{get, post, Route},
+    Router,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{S3Error, S3Origin, S3OriginInner};
+
+
+/// Counts a request as in flight until dropped.
+pub(crate) struct InFlight(Arc<S3OriginInner>);
+
+impl InFlight {
+    pub(crate) fn new(origin: Arc<S3OriginInner>) -> Self {
+        origin.in_flight.fetch_add(1, Ordering::Relaxed);
+        Self(origin)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+
+impl S3Origin {
+    /// The number of requests currently being served, until their response head is ready.
+    ///
+    pub fn in_flight(&self) -> usize {
+        self.inner.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// A router exposing statistics, a cache purge and an object digest endpoint, see
+    /// [`admin`](crate::admin).
+    ///
+    /// Every route is wrapped in `auth`, e.g. a `ValidateRequestHeaderLayer` or an
+    /// `axum::middleware::from_fn` checking a token; it must reject unauthorized requests.
+    ///
+    pub fn admin_router<L>(&self, auth: L) -> Router
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        Router::new()
+            .route("/stats", get(stats))
+            .route("/purge", post(purge))
+            .route("/digest", get(digest))
+            .route_layer(auth)
+            .with_state(self.clone())
+    }
+}
+
+
+/// `value` as a JSON string.
+pub(crate) fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => { let _ = write!(escaped, "\\u{:04x}", c as u32); }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+
+fn json(body: String) -> Response {
+    ([(header::CONTENT_TYPE, HeaderValue::from_static("application/json"))], body).into_response()
+}
+
+
+async fn stats(State(origin): State<S3Origin>) -> Response {
+    let mut body = format!("{{\"in_flight\":{},\"cache\":", origin.in_flight());
+    match origin.cache_stats() {
+        Some(stats) => {
+            let _ = write!(
+                body,
+                "{{\"capacity\":{},\"size\":{},\"entries\":{},\"hits\":{},\"misses\":{},\"hit_ratio\":",
+                stats.capacity, stats.size, stats.entries, stats.hits, stats.misses,
+            );
+            match stats.hit_ratio() {
+                Some(ratio) => { let _ = write!(body, "{:.3}}}", ratio); }
+                None => body.push_str("null}"),
+            }
+        }
+        None => body.push_str("null"),
+    }
+    body.push_str(",\"features\":");
+    match origin.feature_counters() {
+        Some(counters) => {
+            let _ = write!(body, "{{\"requests\":{}", counters.requests());
+            for (feature, count) in counters.snapshot() {
+                let _ = write!(body, ",\"{}\":{}", feature, count);
+            }
+            body.push('}');
+        }
+        None => body.push_str("null"),
+    }
+    let cost = origin.cost_stats();
+    let _ = write!(
+        body,
+        ",\"cost\":{{\"get_requests\":{},\"head_requests\":{},\"list_requests\":{},\"other_requests\":{},\"bytes_egressed\":{},\"cache_avoided\":{}}}",
+        cost.get_requests, cost.head_requests, cost.list_requests, cost.other_requests, cost.bytes_egressed, cost.cache_avoided,
+    );
+    body.push('}');
+    json(body)
+}
+
+
+/// The percent-decoded query parameter `name`.
+fn param(uri: &Uri, name: &str) -> Option<String> {
+    uri.query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_encoding::percent_decode_str(value).decode_utf8_lossy().into_owned())
+}
+
+
+async fn purge(State(origin): State<S3Origin>, uri: Uri) -> Response {
+    let inner = &origin.inner;
+    let param = |name: &str| param(&uri, name);
+
+    if let (Some(store), Some(key)) = (&inner.cache_store, param("key")) {
+        store.invalidate(&format!("{}{}", inner.bucket_prefix(), key.trim_start_matches('/'))).await;
+    }
+    let purged = match &inner.cache {
+        None => 0,
+        Some(cache) => match (param("key"), param("prefix")) {
+            (Some(key), _) => inner.invalidate_cached(cache, &format!("{}{}", inner.bucket_prefix(), key.trim_start_matches('/'))),
+            (None, Some(prefix)) => cache.invalidate_prefix(&format!("{}{}", inner.bucket_prefix(), prefix.trim_start_matches('/'))),
+            (None, None) => cache.invalidate_prefix(""),
+        },
+    };
+    json(format!("{{\"purged\":{}}}", purged))
+}
+
+
+async fn digest(State(origin): State<S3Origin>, uri: Uri) -> Response {
+    let Some(key) = param(&uri, "key").filter(|key| !key.is_empty()) else {
+        return S3Error::BadRequest.into_response();
+    };
+    let inner = &origin.inner;
+    match crate::digest::digest(inner, &format!("{}{}", inner.bucket_prefix(), key.trim_start_matches('/'))).await {
+        Ok(body) => json(body),
+        Err(e) => e.into_response(),
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::config::{BehaviorVersion, Region};
+    use axum::{body::Body, http::StatusCode, middleware::{self, Next}};
+
+    async fn require_token(request: Request, next: Next) -> Response {
+        match request.headers().get(header::AUTHORIZATION) {
+            Some(token) if token == "Bearer secret" => next.run(request).await,
+            _ => StatusCode::UNAUTHORIZED.into_response(),
+        }
+    }
+
+    async fn call(router: &mut Router, request: axum::http::request::Builder) -> (StatusCode, String) {
+        let request = request.header(header::AUTHORIZATION, "Bearer secret").body(Body::empty()).unwrap();
+        let response = router.call(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn serves_stats_and_purges() {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .build();
+        let origin = crate::S3OriginBuilder::new()
+            .bucket("my-bucket")
+            .prefix("site/")
+            .client(aws_sdk_s3::Client::from_conf(config))
+            .cache(1024)
+            .build()
+            .unwrap();
+        let cache = origin.inner.cache.clone().unwrap();
+        for key in ["site/a.html", "site/docs/b.html", "site/docs/c.html"] {
+            let object = crate::cache::CachedObject { metadata: Default::default(), etag: None, body: "x".into() };
+            cache.insert(key.into(), object);
+        }
+        let mut router = origin.admin_router(middleware::from_fn(require_token));
+
+        let (status, body) = call(&mut router, Request::get("/stats")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"in_flight":0,"cache":{"capacity":1024,"size":46,"entries":3,"hits":0,"misses":0,"hit_ratio":null},"features":null,"cost":{"get_requests":0,"head_requests":0,"list_requests":0,"other_requests":0,"bytes_egressed":0,"cache_avoided":0}}"#);
+
+        assert_eq!(call(&mut router, Request::post("/purge?key=a.html")).await.1, r#"{"purged":1}"#);
+        assert_eq!(call(&mut router, Request::post("/purge?prefix=docs%2F")).await.1, r#"{"purged":2}"#);
+        assert_eq!(call(&mut router, Request::post("/purge")).await.1, r#"{"purged":0}"#);
+
+        let response = router.call(Request::get("/stats").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,61 @@
+/// Which `x-amz-*` response headers from S3 are forwarded to the client.
+///
+/// By default nothing is forwarded: user metadata (`x-amz-meta-*`) and infrastructure headers
+/// (`x-amz-version-id`, `x-amz-server-side-encryption`, ...) are stripped.  Entries of the
+/// allow-list are matched case-insensitively, either exactly or, if they end in `*`, by prefix.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AmzHeaderPolicy {
+    allow: Vec<String>,
+}
+
+
+impl AmzHeaderPolicy {
+    pub(crate) fn new(allow: impl IntoIterator<Item = String>) -> Result<Self, &'static str> {
+        let allow = allow.into_iter()
+            .map(|pattern| pattern.to_ascii_lowercase())
+            .collect::<Vec<_>>();
+
+        if allow.iter().any(|pattern| !pattern.starts_with("x-amz-")) {
+            return Err("forwarded headers must start with x-amz-");
+        }
+
+        Ok(Self { allow })
+    }
+
+    /// Whether a (lowercase) header name may be forwarded.
+    pub(crate) fn allows(&self, name: &str) -> bool {
+        self.allow.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        })
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_by_default() {
+        let policy = AmzHeaderPolicy::default();
+        assert!(!policy.allows("x-amz-meta-build-id"));
+        assert!(!policy.allows("x-amz-version-id"));
+    }
+
+    #[test]
+    fn allow_list() {
+        let policy = AmzHeaderPolicy::new(["X-Amz-Meta-Build-Id".to_string(), "x-amz-version-*".to_string()]).unwrap();
+        assert!(policy.allows("x-amz-meta-build-id"));
+        assert!(policy.allows("x-amz-version-id"));
+        assert!(!policy.allows("x-amz-meta-owner"));
+        assert!(!policy.allows("x-amz-server-side-encryption"));
+    }
+
+    #[test]
+    fn only_amz_headers_can_be_allowed() {
+        assert!(AmzHeaderPolicy::new(["content-type".to_string()]).is_err());
+        assert!(AmzHeaderPolicy::new(["*".to_string()]).is_err());
+    }
+}
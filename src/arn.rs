@@ -0,0 +1,116 @@
+//! Validation of S3 Access Point, Object Lambda Access Point and Multi-Region Access Point ARNs.
+//!
+//! The AWS SDK accepts access point ARNs wherever a bucket name is expected and routes requests
+//! to the access point endpoint, so the ARN is passed through as the bucket.  It is validated
+//! when the origin is built, so a typo fails at startup rather than on the first request.
+//!
+//! Multi-Region Access Point ARNs have no region; the SDK signs their requests with SigV4A for
+//! all regions and AWS routes each request to the nearest healthy region.
+
+
+/// What the builder's `bucket` refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BucketKind {
+    /// A bucket name.
+    Bucket,
+    /// An S3 Access Point ARN (`arn:aws:s3:{region}:{account}:accesspoint/{name}`).
+    AccessPoint,
+    /// An Object Lambda Access Point ARN
+    /// (`arn:aws:s3-object-lambda:{region}:{account}:accesspoint/{name}`).
+    ///
+    /// GetObject responses are transformed by a Lambda function, so HeadObject metadata may
+    /// not describe what GetObject returns.
+    ObjectLambda,
+    /// A Multi-Region Access Point ARN (`arn:aws:s3::{account}:accesspoint/{alias}.mrap`).
+    MultiRegionAccessPoint,
+}
+
+
+impl BucketKind {
+    /// Classify and validate a bucket name or ARN.
+    pub(crate) fn parse(bucket: &str) -> Result<Self, &'static str> {
+        if !bucket.starts_with("arn:") {
+            if bucket.ends_with(".mrap") {
+                return Err("Multi-Region Access Points must be given as ARN (arn:aws:s3::{account}:accesspoint/{alias})");
+            }
+            return Ok(BucketKind::Bucket);
+        }
+
+        let parts: Vec<&str> = bucket.splitn(6, ':').collect();
+        let [_, partition, service, region, account, resource] = parts[..] else {
+            return Err("invalid bucket ARN");
+        };
+
+        let kind = match (service, region) {
+            ("s3", "") => BucketKind::MultiRegionAccessPoint,
+            ("s3", _) => BucketKind::AccessPoint,
+            ("s3-object-lambda", "") => return Err("invalid bucket ARN"),
+            ("s3-object-lambda", _) => BucketKind::ObjectLambda,
+            _ => return Err("bucket ARN must be an S3 Access Point or Object Lambda Access Point"),
+        };
+        if !partition.starts_with("aws") {
+            return Err("invalid bucket ARN");
+        }
+        if account.len() != 12 || !account.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("invalid account ID in bucket ARN");
+        }
+        let name = resource.strip_prefix("accesspoint/")
+            .or_else(|| resource.strip_prefix("accesspoint:"))
+            .ok_or("bucket ARN must be an S3 Access Point or Object Lambda Access Point")?;
+        let valid = match kind {
+            BucketKind::MultiRegionAccessPoint => is_valid_mrap_alias(name),
+            _ => is_valid_access_point_name(name),
+        };
+        if !valid {
+            return Err("invalid access point name in bucket ARN");
+        }
+        Ok(kind)
+    }
+}
+
+
+/// Access point names are 3–50 lowercase letters, digits and hyphens, starting and ending with
+/// a letter or digit.
+fn is_valid_access_point_name(name: &str) -> bool {
+    (3..=50).contains(&name.len())
+        && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+
+/// Multi-Region Access Point aliases are generated by AWS, e.g. `mfzwi23gnjvgw.mrap`.
+fn is_valid_mrap_alias(alias: &str) -> bool {
+    alias.strip_suffix(".mrap").is_some_and(|name| {
+        !name.is_empty() && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+    })
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_arns() {
+        assert_eq!(BucketKind::parse("my-bucket"), Ok(BucketKind::Bucket));
+        assert_eq!(BucketKind::parse("arn:aws:s3:us-east-1:123456789012:accesspoint/static"), Ok(BucketKind::AccessPoint));
+        assert_eq!(BucketKind::parse("arn:aws-cn:s3:cn-north-1:123456789012:accesspoint:static"), Ok(BucketKind::AccessPoint));
+        assert_eq!(BucketKind::parse("arn:aws:s3-object-lambda:eu-west-1:123456789012:accesspoint/watermark"), Ok(BucketKind::ObjectLambda));
+        assert_eq!(BucketKind::parse("arn:aws:s3::123456789012:accesspoint/mfzwi23gnjvgw.mrap"), Ok(BucketKind::MultiRegionAccessPoint));
+    }
+
+    #[test]
+    fn rejects_invalid_arns() {
+        assert!(BucketKind::parse("arn:aws:s3:us-east-1:123456789012").is_err());
+        assert!(BucketKind::parse("arn:aws:s3:::my-bucket").is_err());
+        assert!(BucketKind::parse("arn:aws:sqs:us-east-1:123456789012:accesspoint/static").is_err());
+        assert!(BucketKind::parse("arn:aws:s3:us-east-1:1234:accesspoint/static").is_err());
+        assert!(BucketKind::parse("arn:aws:s3:us-east-1:123456789012:accesspoint/Static_AP").is_err());
+        assert!(BucketKind::parse("arn:aws:s3:us-east-1:123456789012:bucket/static").is_err());
+        assert!(BucketKind::parse("arn:aws:s3::123456789012:accesspoint/static").is_err());
+        assert!(BucketKind::parse("arn:aws:s3-object-lambda::123456789012:accesspoint/watermark.mrap").is_err());
+        assert!(BucketKind::parse("mfzwi23gnjvgw.mrap").is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Pluggable object backends.
+//!
+//! Objects are fetched from S3 by default.  With [`backend`](crate::S3OriginBuilder::backend) an
+//! [`ObjectBackend`] serves them instead, e.g. a local directory during development, an
+//! in-memory map in tests or another object store.  Responses are built the same way for every
+//! backend: backends return objects as S3 `GetObject` and `HeadObject` outputs, whose builders
+//! take the body and metadata:
+//!
+//! ```rust
+//! use aws_sdk_s3::{operation::get_object::GetObjectOutput, primitives::ByteStream};
+//!
+//! let output = GetObjectOutput::builder()
+//!     .body(ByteStream::from_static(b"<h1>Hello</h1>"))
+//!     .content_length(14)
+//!     .content_type("text/html")
+//!     .e_tag("\"1\"")
+//!     .build();
+//! # let _ = output;
+//! ```
+//!
+//! With the `object-store` feature, [`ObjectStoreBackend`] serves objects from any store of the
+//! `object_store` crate: Google Cloud Storage, Azure Blob Storage or S3-compatible stores.
+//!
+//! Features that talk to S3 directly keep using the S3 client: [parallel
+//! fetches](crate::S3OriginBuilder::parallel_fetch), [resumed
+//! bodies](crate::S3OriginBuilder::resume_retries), [required
+//! tags](crate::S3OriginBuilder::required_tags), [`customize_request`](crate::S3OriginBuilder::customize_request),
+//! manifests, prefix pointers, previews and [`load_maintenance`](crate::S3Origin::load_maintenance).
+//! The first two are skipped with other backends; configure the others only with S3.
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use aws_sdk_s3::{
+    operation::{get_object::GetObjectOutput, head_object::HeadObjectOutput},
+    Client as S3Client,
+};
+
+use crate::S3Error;
+#[cfg(feature = "object-store")]
+pub use crate::object_store_backend::ObjectStoreBackend;
+
+
+/// The future returned by [`ObjectBackend`] methods.
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, S3Error>> + Send + 'a>>;
+
+
+/// A source of objects by key.
+///
+/// Keys include the bucket prefix.  Missing objects are reported as [`S3Error::NotFound`].
+pub trait ObjectBackend: Send + Sync + 'static {
+    /// The object at `key`, or the bytes of it selected by `range` (a `Range` header value such as
+    /// `bytes=0-99`), with `content_range` set.
+    fn get<'a>(&'a self, key: &'a str, range: Option<&'a str>) -> BackendFuture<'a, GetObjectOutput>;
+
+    /// The metadata of the object at `key`.
+    fn head<'a>(&'a self, key: &'a str) -> BackendFuture<'a, HeadObjectOutput>;
+
+    /// The keys starting with `prefix`, in lexicographical order.
+    fn list<'a>(&'a self, prefix: &'a str) -> BackendFuture<'a, Vec<String>>;
+}
+
+
+/// A backend shared by several origins.
+impl<T: ObjectBackend> ObjectBackend for Arc<T> {
+    fn get<'a>(&'a self, key: &'a str, range: Option<&'a str>) -> BackendFuture<'a, GetObjectOutput> {
+        (**self).get(key, range)
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> BackendFuture<'a, HeadObjectOutput> {
+        (**self).head(key)
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BackendFuture<'a, Vec<String>> {
+        (**self).list(prefix)
+    }
+}
+
+
+/// Objects in an S3 bucket, the default backend.
+#[derive(Clone)]
+pub struct S3Backend {
+    client: Arc<S3Client>,
+    bucket: String,
+}
+
+impl S3Backend {
+    /// The objects in `bucket`.
+    pub fn new(client: S3Client, bucket: impl Into<String>) -> Self {
+        Self { client: Arc::new(client), bucket: bucket.into() }
+    }
+}
+
+impl ObjectBackend for S3Backend {
+    fn get<'a>(&'a self, key: &'a str, range: Option<&'a str>) -> BackendFuture<'a, GetObjectOutput> {
+        Box::pin(async move {
+            let output = self.client.get_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .set_range(range.map(str::to_owned))
+                .send()
+                .await?;
+            Ok(output)
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> BackendFuture<'a, HeadObjectOutput> {
+        Box::pin(async move {
+            let output = self.client.head_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .send()
+                .await?;
+            Ok(output)
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BackendFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            let mut pages = self.client.list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .into_paginator()
+                .send();
+            while let Some(page) = pages.next().await {
+                let page = page.map_err(S3Error::from)?;
+                keys.extend(page.contents().iter().filter_map(|object| object.key().map(str::to_owned)));
+            }
+            Ok(keys)
+        })
+    }
+}
+
+impl fmt::Debug for S3Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Backend").field("bucket", &self.bucket).finish_non_exhaustive()
+    }
+}
+
@@ -0,0 +1,58 @@
+//! Enforcing the expected bucket owner.
+//!
+//! A bucket name alone does not identify a bucket across accounts: after a typo, or after the
+//! intended bucket is deleted and its name claimed elsewhere, requests may reach someone else's
+//! bucket.  With [`expected_bucket_owner`](crate::S3OriginBuilder::expected_bucket_owner), every
+//! request the origin's client sends carries `x-amz-expected-bucket-owner`, and S3 refuses it
+//! with `403 Access Denied` unless the bucket belongs to that account.
+use aws_sdk_s3::config::{
+    interceptors::BeforeTransmitInterceptorContextMut, ConfigBag, Intercept, RuntimeComponents,
+};
+use axum::BoxError;
+
+
+/// Adds `x-amz-expected-bucket-owner` to every request.
+#[derive(Debug)]
+pub(crate) struct ExpectedBucketOwner {
+    account_id: String,
+}
+
+impl ExpectedBucketOwner {
+    /// Fails unless `account_id` is a 12-digit AWS account ID.
+    pub(crate) fn new(account_id: String) -> Result<Self, &'static str> {
+        match account_id.len() == 12 && account_id.bytes().all(|byte| byte.is_ascii_digit()) {
+            true => Ok(Self { account_id }),
+            false => Err("expected_bucket_owner must be a 12-digit account ID"),
+        }
+    }
+}
+
+impl Intercept for ExpectedBucketOwner {
+    fn name(&self) -> &'static str {
+        "ExpectedBucketOwner"
+    }
+
+    fn modify_before_signing(
+        &self,
+        context: &mut BeforeTransmitInterceptorContextMut<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        context.request_mut().headers_mut().insert("x-amz-expected-bucket-owner", self.account_id.clone());
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_account_ids() {
+        assert!(ExpectedBucketOwner::new("123456789012".into()).is_ok());
+        assert!(ExpectedBucketOwner::new("12345678901".into()).is_err());
+        assert!(ExpectedBucketOwner::new("12345678901a".into()).is_err());
+    }
+}
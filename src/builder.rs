@@ -1,20 +1,143 @@
-use std::sync::Arc;
+use std::{future::Future, sync::Arc, time::Duration};
 
 use aws_sdk_s3::Client as S3Client;
 use aws_config::SdkConfig as AwsSdkConfig;
+use aws_smithy_runtime_api::client::auth::AuthSchemeId;
 
-use crate::S3Origin;
+use crate::{CustomizeRequest, HeadPolicy, MethodPolicy, PathSource, QueryPolicy, S3Origin, TrailingSlash, VersionQuery, WebsiteRedirect};
+use crate::preview::Preview;
+use crate::render::Render;
+use crate::telemetry::{FeatureCounters, Features, Telemetry, TelemetryCallback};
+use crate::amz_headers::AmzHeaderPolicy;
+use crate::cache_control::{CacheControlPolicy, CacheControlRule};
+use crate::content_disposition::ContentDispositionPolicy;
+use crate::rules::{self, PathRules, Rule, RuleConflict, RuleKind};
+use crate::redact::opaque;
+use crate::arn::BucketKind;
+use crate::credentials::AssumeRole;
+use crate::bucket_owner::ExpectedBucketOwner;
+use crate::http_client::HttpSettings;
+use crate::cost;
+use crate::cache::{MemoryCache, TtlPolicy, DEFAULT_CACHE_TTL};
+use crate::store::{CacheStore, StoreTier};
+use crate::backend::ObjectBackend;
+use crate::auth::{AuthDecision, Authorizer, Credentials, PasswordHash, TokenValidator};
+use crate::signed_url::SignedUrls;
+use crate::signed_cookie::SignedCookies;
+use crate::hotlink::HotlinkProtection;
+use crate::health::{HealthCheck, HealthState};
+use crate::sitemap::{Sitemap, SitemapState};
+use crate::synthetic::SyntheticResponse;
+use crate::well_known::WellKnown;
+use crate::segments::SegmentCache;
+use crate::precompressed::{Precompressed, PrecompressedVariants};
+use crate::client_identity::ClientIdentity;
+use crate::ip_filter::IpFilter;
+use crate::rate_limit::{RateLimit, RateLimiter};
+use crate::quota::ByteQuota;
+use crate::concurrency::{AdaptiveConcurrency, ConcurrencyLimiter};
+use crate::lambda::LambdaLimit;
+use crate::object_tags::{RequiredTags, TagCheck};
+use crate::canary::Canary;
+use crate::maintenance::Maintenance;
+use crate::shutdown::{Drain, DrainMode};
+use crate::experiment::Experiment;
+use crate::locale::Locales;
+use crate::inject::HtmlInjection;
+use crate::range::RangePolicy;
+use crate::preload::Preloads;
+use crate::parallel::ParallelFetch;
+use crate::{ChecksumTrailer, ChecksumVerification, HotKeys};
 
 use super::S3OriginInner;
+use crate::set::Shared;
 
 
 pub struct S3OriginBuilder {
     bucket: Option<String>,
     bucket_prefix: Option<String>,
+    canary: Option<Canary>,
+    maintenance: Option<Maintenance>,
+    drain_mode: DrainMode,
+    experiments: Vec<Experiment>,
+    locales: Option<Locales>,
+    image_negotiation: bool,
+    precompressed: Option<Precompressed>,
+    customize_request: Option<CustomizeRequest>,
+    html_injection: Option<HtmlInjection>,
+    decompress: bool,
+    ranges: RangePolicy,
+    preloads: Vec<(String, String)>,
+    resume_retries: u32,
+    parallel_fetch: Option<ParallelFetch>,
+    readahead: usize,
+    verify_checksums: Option<ChecksumVerification>,
+    checksum_trailer: Option<ChecksumTrailer>,
     s3_client: Option<S3Client>,
     aws_sdk_config: Option<AwsSdkConfig>,
+    anonymous: bool,
+    assume_role: Option<(String, String)>,
+    expected_bucket_owner: Option<String>,
+    http_settings: HttpSettings,
+    /// The bucket, client and cache of an [`S3OriginSet`](crate::S3OriginSet).
+    pub(crate) shared: Option<Shared>,
+    endpoint_url: Option<String>,
     prune_path: usize,
+    path_source: PathSource,
+    stage_prefix: bool,
     max_size: Option<i64>,
+    lambda_limit: Option<LambdaLimit>,
+    parallel_head: bool,
+    head_policy: Option<HeadPolicy>,
+    method_policy: MethodPolicy,
+    query_policy: QueryPolicy,
+    website_redirect: WebsiteRedirect,
+    clean_urls: bool,
+    trailing_slash: TrailingSlash,
+    rules: Vec<Rule>,
+    immutable_assets: bool,
+    forward_amz_headers: Vec<String>,
+    renderers: Vec<(String, Arc<dyn Render>)>,
+    preview: Option<Preview>,
+    version_query: Option<VersionQuery>,
+    error_id_header: bool,
+    retry_after: u64,
+    feature_telemetry: Option<TelemetryCallback>,
+    cache: Option<usize>,
+    cache_ttl: Duration,
+    cache_ttl_bounds: Option<(Duration, Duration)>,
+    hot_keys: Option<HotKeys>,
+    cache_store: Option<(Arc<dyn CacheStore>, usize)>,
+    backend: Option<Arc<dyn ObjectBackend>>,
+    authorize: Option<Authorizer>,
+    basic_auth: Vec<(String, String)>,
+    bearer_token: Option<Arc<TokenValidator>>,
+    signed_urls: Option<SignedUrls>,
+    signed_cookies: Option<SignedCookies>,
+    hotlink: Option<HotlinkProtection>,
+    health: HealthCheck,
+    sitemap: Option<Sitemap>,
+    synthetic: Vec<(String, String, axum::body::Bytes)>,
+    well_known: Option<WellKnown>,
+    client_identity: ClientIdentity,
+    ip_filter: Option<IpFilter>,
+    rate_limit: Option<RateLimit>,
+    byte_quota: Option<ByteQuota>,
+    adaptive_concurrency: Option<AdaptiveConcurrency>,
+    required_tags: Option<RequiredTags>,
+    #[cfg(feature = "moka")]
+    moka_cache: bool,
+    stale_while_revalidate: Duration,
+    verify_cached_versions: bool,
+    segment_cache: Option<SegmentCache>,
+    #[cfg(feature = "trace")]
+    span: Option<crate::span::SpanConfig>,
+    #[cfg(feature = "access-log")]
+    access_log: Option<crate::access_log::AccessLog>,
+    #[cfg(feature = "access-log")]
+    access_events: Option<crate::access_events::AccessEvents>,
+    #[cfg(feature = "manifest")]
+    manifest: Option<crate::manifest::Manifest>,
 }
 
 
@@ -23,16 +146,104 @@ impl S3OriginBuilder {
         Self {
             bucket: None,
             bucket_prefix: None,
+            canary: None,
+            maintenance: None,
+            drain_mode: DrainMode::Reject,
+            experiments: Vec::new(),
+            locales: None,
+            image_negotiation: false,
+            precompressed: None,
+            customize_request: None,
+            html_injection: None,
+            decompress: false,
+            ranges: RangePolicy::default(),
+            preloads: Vec::new(),
+            resume_retries: 0,
+            parallel_fetch: None,
+            readahead: 0,
+            verify_checksums: None,
+            checksum_trailer: None,
             s3_client: None,
             aws_sdk_config: None,
+            anonymous: false,
+            assume_role: None,
+            expected_bucket_owner: None,
+            http_settings: HttpSettings::default(),
+            shared: None,
+            endpoint_url: None,
             prune_path: 0,
+            path_source: PathSource::default(),
+            stage_prefix: true,
             max_size: None,
+            lambda_limit: None,
+            parallel_head: false,
+            head_policy: None,
+            method_policy: MethodPolicy::default(),
+            query_policy: QueryPolicy::Ignore,
+            website_redirect: WebsiteRedirect::default(),
+            clean_urls: false,
+            trailing_slash: TrailingSlash::default(),
+            rules: Vec::new(),
+            immutable_assets: false,
+            forward_amz_headers: Vec::new(),
+            renderers: Vec::new(),
+            preview: None,
+            version_query: None,
+            error_id_header: false,
+            retry_after: crate::DEFAULT_RETRY_AFTER,
+            feature_telemetry: None,
+            cache: None,
+            cache_ttl: DEFAULT_CACHE_TTL,
+            cache_ttl_bounds: None,
+            hot_keys: None,
+            cache_store: None,
+            backend: None,
+            authorize: None,
+            basic_auth: Vec::new(),
+            bearer_token: None,
+            signed_urls: None,
+            signed_cookies: None,
+            hotlink: None,
+            health: HealthCheck::default(),
+            sitemap: None,
+            synthetic: Vec::new(),
+            well_known: None,
+            client_identity: ClientIdentity::default(),
+            ip_filter: None,
+            rate_limit: None,
+            byte_quota: None,
+            adaptive_concurrency: None,
+            required_tags: None,
+            #[cfg(feature = "moka")]
+            moka_cache: false,
+            stale_while_revalidate: Duration::ZERO,
+            verify_cached_versions: false,
+            segment_cache: None,
+            #[cfg(feature = "trace")]
+            span: None,
+            #[cfg(feature = "access-log")]
+            access_log: None,
+            #[cfg(feature = "access-log")]
+            access_events: None,
+            #[cfg(feature = "manifest")]
+            manifest: None,
         }
     }
 
     /// Set the bucket name.
     /// 
     /// This is required.
+    /// An S3 Access Point ARN (`arn:aws:s3:{region}:{account}:accesspoint/{name}`) or an
+    /// Object Lambda Access Point ARN (`arn:aws:s3-object-lambda:…`) may be given instead; the
+    /// SDK routes requests to the access point.  ARNs are validated by [`build`](Self::build).
+    /// The client's region must match the ARN's region unless `use_arn_region` is enabled on
+    /// the S3 client config.
+    /// 
+    /// A Multi-Region Access Point ARN (`arn:aws:s3::{account}:accesspoint/{alias}.mrap`) routes
+    /// every request to the nearest region, or to the active region if failover controls are
+    /// configured.  Requests are signed with SigV4A, and the client still needs a region for
+    /// endpoint resolution.  Failover happens within AWS: this origin never retries another
+    /// region, and a region whose replica has not caught up yet answers `404 Not Found`.
     /// 
     pub fn bucket(mut self, bucket: impl Into<String>) -> Self {
         self.bucket = Some(bucket.into());
@@ -41,13 +252,234 @@ impl S3OriginBuilder {
 
     /// Set the bucket prefix.
     /// 
-    /// This is optional, and defaults to an empty string.
+    /// This is optional, and defaults to an empty string.  It can be switched at runtime with
+    /// [`S3Origin::set_prefix`] or [`S3Origin::watch_prefix`].
     /// 
     pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
         self.bucket_prefix = Some(prefix.into());
         self
     }
 
+    /// Start in maintenance mode, answering every request with this page.
+    /// 
+    /// This is optional, and defaults to serving normally.  See [`maintenance`](crate::maintenance);
+    /// maintenance mode can be turned on and off at runtime with [`S3Origin::set_maintenance`].
+    /// 
+    pub fn maintenance(mut self, maintenance: Maintenance) -> Self {
+        self.maintenance = Some(maintenance);
+        self
+    }
+
+    /// Set how new calls are handled after [`S3Origin::shutdown`].
+    /// 
+    /// This is optional, and defaults to [`DrainMode::Reject`]; see [`shutdown`](crate::shutdown).
+    /// 
+    pub fn drain_mode(mut self, mode: DrainMode) -> Self {
+        self.drain_mode = mode;
+        self
+    }
+
+    /// Serve a share of requests from a canary prefix.
+    /// 
+    /// This is optional, and defaults to no canary.  See [`canary`](crate::canary); the split can
+    /// be changed at runtime with [`S3Origin::set_canary`].
+    /// 
+    pub fn canary(mut self, canary: Canary) -> Self {
+        self.canary = Some(canary);
+        self
+    }
+
+    /// Add an A/B experiment selecting a variant prefix by request header or cookie.
+    /// 
+    /// This is optional, and defaults to no experiments.  Experiments are evaluated in the order
+    /// they were added and take precedence over the [`canary`](Self::canary); see
+    /// [`experiment`](crate::experiment).
+    /// 
+    pub fn experiment(mut self, experiment: Experiment) -> Self {
+        self.experiments.push(experiment);
+        self
+    }
+
+    /// Serve paths from locale prefixes negotiated by `Accept-Language`.
+    /// 
+    /// This is optional, and defaults to disabled.  See [`locale`](crate::locale).
+    /// 
+    pub fn locales(mut self, locales: Locales) -> Self {
+        self.locales = Some(locales);
+        self
+    }
+
+    /// Serve AVIF or WebP copies of images to clients that accept them.
+    /// 
+    /// This is optional, and defaults to disabled.  Requests for `.jpg`, `.jpeg`, `.png` and
+    /// `.gif` keys from clients explicitly accepting `image/avif` or `image/webp` try
+    /// `{key}.avif` and `{key}.webp` (best first) before the original key.  Responses for these
+    /// keys carry `Vary: Accept`.
+    /// 
+    pub fn image_negotiation(mut self, enabled: bool) -> Self {
+        self.image_negotiation = enabled;
+        self
+    }
+
+    /// Serve Brotli or gzip variants uploaded next to objects, see
+    /// [`precompressed`](crate::precompressed).
+    /// 
+    /// This is optional, and defaults to serving objects as uploaded.  [`build`](Self::build)
+    /// fails without an encoding.
+    /// 
+    pub fn precompressed(mut self, precompressed: Precompressed) -> Self {
+        self.precompressed = Some(precompressed);
+        self
+    }
+
+    /// Adjust every GetObject request before it is sent.
+    /// 
+    /// This is optional, and defaults to sending requests as built.  `customize` receives the
+    /// client request and the GetObject builder, with bucket, key and `Range` already set, and
+    /// returns the builder to send, e.g. to enable checksum mode, set an expected bucket owner
+    /// or SSE-C parameters:
+    /// 
+    /// ```rust,ignore
+    /// .customize_request(|_request, builder| builder.checksum_mode(ChecksumMode::Enabled))
+    /// ```
+    /// 
+    /// HeadObject requests and the cache's own requests are not customized.
+    /// 
+    pub fn customize_request<F>(mut self, customize: F) -> Self
+    where
+        F: Fn(&axum::http::Request<()>, aws_sdk_s3::operation::get_object::builders::GetObjectFluentBuilder) -> aws_sdk_s3::operation::get_object::builders::GetObjectFluentBuilder + Send + Sync + 'static,
+    {
+        self.customize_request = Some(Arc::new(customize));
+        self
+    }
+
+    /// Patch runtime values into HTML documents.
+    /// 
+    /// This is optional, and defaults to serving documents unchanged.  See
+    /// [`inject`](crate::inject).
+    /// 
+    pub fn html_injection(mut self, injection: HtmlInjection) -> Self {
+        self.html_injection = Some(injection);
+        self
+    }
+
+    /// Decompress gzip-encoded objects for clients that do not accept gzip.
+    /// 
+    /// This is optional, and defaults to false: objects with `Content-Encoding: gzip` metadata
+    /// are served with that header to every client.  When enabled, complete responses are
+    /// decompressed while streaming unless `Accept-Encoding` accepts gzip; `Content-Length` is
+    /// dropped and the `ETag` weakened.  Range responses are always served compressed.
+    /// 
+    pub fn decompress(mut self, enabled: bool) -> Self {
+        self.decompress = enabled;
+        self
+    }
+
+    /// Whether `Range` headers are forwarded to S3.
+    /// 
+    /// This is optional, and defaults to true.  When disabled, ranges are ignored and every
+    /// response is a `200 OK` with the whole object.  Malformed ranges, other units and multiple
+    /// ranges are always ignored.
+    /// 
+    pub fn ranges(mut self, enabled: bool) -> Self {
+        self.ranges.enabled = enabled;
+        self
+    }
+
+    /// Serve at most `max_size` bytes per range.
+    /// 
+    /// This is optional, and defaults to serving ranges of any size.  Larger ranges, including
+    /// open-ended ones such as `bytes=0-`, are shortened; clients learn the range served from
+    /// `Content-Range`.
+    /// 
+    pub fn max_range_size(mut self, max_size: u64) -> Self {
+        self.ranges.max_size = Some(max_size);
+        self
+    }
+
+    /// Serve objects of these content types whole, ignoring `Range`; a value ending in `/`
+    /// matches the whole type, e.g. `text/`.
+    /// 
+    /// This is optional, and defaults to serving ranges of every content type.  The content
+    /// type is only known from the S3 response, so a ranged request for such an object is
+    /// fetched a second time without the range.
+    /// 
+    pub fn no_ranges_for<I, S>(mut self, content_types: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.ranges.whole_content_types.extend(content_types.into_iter().map(|content_type| content_type.into().to_ascii_lowercase()));
+        self
+    }
+
+    /// Add a `Link` header to successful responses for paths matching `pattern`.
+    /// 
+    /// This is optional, and defaults to no preloads.  `link` is a complete `Link` value, e.g.
+    /// `</assets/app.3f9ab2.js>; rel=preload; as=script`.  Patterns follow the same syntax as
+    /// [`cache_control`](Self::cache_control), but every matching rule applies.  See
+    /// [`preload`](crate::preload) for Early Hints.
+    /// 
+    pub fn preload(mut self, pattern: impl Into<String>, link: impl Into<String>) -> Self {
+        self.preloads.push((pattern.into(), link.into()));
+        self
+    }
+
+    /// Resume object bodies that fail mid-stream with up to `retries` ranged GETs.
+    /// 
+    /// This is optional, and defaults to 0: a failing body truncates the download.  After a read
+    /// error the body reissues a GET from the first byte not yet delivered, conditional on the
+    /// object's ETag, so the client receives the rest of the same object version.  Objects
+    /// without an ETag are not resumed.  Retries bypass
+    /// [`customize_request`](Self::customize_request).
+    /// 
+    pub fn resume_retries(mut self, retries: u32) -> Self {
+        self.resume_retries = retries;
+        self
+    }
+
+    /// Fetch large objects with concurrent ranged GETs.
+    /// 
+    /// This is optional, and defaults to disabled.  See [`parallel`](crate::parallel).
+    /// 
+    pub fn parallel_fetch(mut self, parallel_fetch: ParallelFetch) -> Self {
+        self.parallel_fetch = Some(parallel_fetch);
+        self
+    }
+
+    /// Read object bodies up to `bytes` ahead of the client.
+    /// 
+    /// This is optional, and defaults to 0: the S3 body is read in lockstep with the client.
+    /// With a budget of a few chunks (e.g. 256 KiB), a slow client does not stall the S3
+    /// connection and a slow S3 read does not stall the client.
+    /// 
+    pub fn readahead(mut self, bytes: usize) -> Self {
+        self.readahead = bytes;
+        self
+    }
+
+    /// Verify object bodies against their CRC32 or SHA256 checksum as they stream.
+    /// 
+    /// This is optional, and defaults to disabled.  Objects must have been uploaded with a
+    /// full-object checksum; see [`ChecksumVerification`] for what happens on a mismatch.
+    /// Objects served from the [`cache`](Self::cache) are not verified.
+    /// 
+    pub fn verify_checksums(mut self, verification: ChecksumVerification) -> Self {
+        self.verify_checksums = Some(verification);
+        self
+    }
+
+    /// Send the checksum of the body in a trailer to clients that accept trailers.
+    /// 
+    /// This is optional, and defaults to no trailers.  The checksum is computed as the body
+    /// streams, for GETs with `TE: trailers`; over HTTP/1.1 these responses are chunked instead
+    /// of carrying a `Content-Length`.  See [`checksum`](crate::checksum).
+    /// 
+    pub fn checksum_trailer(mut self, trailer: ChecksumTrailer) -> Self {
+        self.checksum_trailer = Some(trailer);
+        self
+    }
+
     /// Set the S3 client.
     /// 
     /// This is optional, and defaults to a new client created from the AWS SDK config.
@@ -69,6 +501,32 @@ impl S3OriginBuilder {
         self
     }
 
+    /// Set where the request path used for the key comes from.
+    /// 
+    /// This is optional, and defaults to [`PathSource::Request`], the URI as received by the
+    /// origin.  Under `Router::nest_service` axum has already removed the mount prefix from it,
+    /// so `prune_path` only needs to account for components *outside* the router (e.g. an
+    /// API Gateway stage).  [`PathSource::Nested`] and [`PathSource::OriginalUri`] derive the
+    /// path from the extensions axum records when nesting.
+    /// 
+    pub fn path_source(mut self, path_source: PathSource) -> Self {
+        self.path_source = path_source;
+        self
+    }
+
+    /// Remove the API Gateway stage from the start of request paths.
+    /// 
+    /// This is optional, and defaults to true.  The stage is read from the
+    /// [`StagePrefix`](crate::StagePrefix) request extension or the `X-Forwarded-Prefix`
+    /// header, and only removed when the path starts with it; the same origin serves requests
+    /// through the gateway and direct invocations without adjusting
+    /// [`prune_path`](Self::prune_path).
+    /// 
+    pub fn stage_prefix(mut self, enabled: bool) -> Self {
+        self.stage_prefix = enabled;
+        self
+    }
+
     /// Set the AWS SDK config.
     /// 
     /// This is optional, and defaults to a new client created from the AWS SDK config.
@@ -79,6 +537,102 @@ impl S3OriginBuilder {
         self
     }
 
+    /// Send unsigned requests, for public buckets.
+    /// 
+    /// This is optional, and defaults to `false`.
+    /// The credentials provider of `client` or `config` is never consulted, so the origin runs
+    /// without any AWS credentials (e.g. in CI preview containers) and never waits on the
+    /// default credential chain.  The config still needs a region, e.g.
+    /// `aws_config::defaults(BehaviorVersion::latest()).no_credentials().region("us-east-1").load().await`.
+    /// 
+    pub fn anonymous(mut self, anonymous: bool) -> Self {
+        self.anonymous = anonymous;
+        self
+    }
+
+    /// Access the bucket with the credentials of an assumed IAM role.
+    /// 
+    /// This is optional, and defaults to the credentials of `client` or `config`.
+    /// The base credentials for STS AssumeRole come from `config`, which is required.  The
+    /// assumed credentials are cached by the S3 client and refreshed before they expire, so
+    /// cross-account buckets need no hand-assembled credential provider.
+    /// 
+    pub fn assume_role(mut self, role_arn: impl Into<String>, session_name: impl Into<String>) -> Self {
+        self.assume_role = Some((role_arn.into(), session_name.into()));
+        self
+    }
+
+    /// Only access the bucket if it belongs to this AWS account (a 12-digit ID).
+    /// 
+    /// This is optional, and defaults to no check.  Every S3 request carries
+    /// `x-amz-expected-bucket-owner`, so a mistyped or re-created bucket name in another account
+    /// fails with `403 Access Denied` instead of serving that bucket's objects.
+    /// [`build`](Self::build) fails if `account_id` is not 12 digits.
+    /// 
+    pub fn expected_bucket_owner(mut self, account_id: impl Into<String>) -> Self {
+        self.expected_bucket_owner = Some(account_id.into());
+        self
+    }
+
+    /// Send S3 requests to this endpoint, with path-style addressing.
+    /// 
+    /// This is optional, and defaults to the endpoint of `client` or `config`.  Use it for an
+    /// S3-compatible server such as MinIO or LocalStack during local development.
+    /// 
+    pub fn endpoint_url(mut self, endpoint_url: impl Into<String>) -> Self {
+        self.endpoint_url = Some(endpoint_url.into());
+        self
+    }
+
+    /// Fail S3 requests that cannot connect within `timeout`, see [`http_client`](crate::http_client).
+    /// 
+    /// This is optional, and defaults to the timeout of `client` or `config`, or else the SDK
+    /// default of 3.1 seconds.
+    /// 
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.http_settings.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail S3 requests when no data was read for `timeout`, e.g. from a stalled connection.
+    /// 
+    /// This is optional, and defaults to the timeout of `client` or `config`, or else none.  It
+    /// also applies while streaming object bodies.
+    /// 
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.http_settings.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail S3 requests that have not completed within `timeout`, including retries.
+    /// 
+    /// This is optional, and defaults to the timeout of `client` or `config`, or else none.
+    /// 
+    pub fn operation_timeout(mut self, timeout: Duration) -> Self {
+        self.http_settings.operation_timeout = Some(timeout);
+        self
+    }
+
+    /// Open at most `max` connections to the S3 endpoint; further requests wait for one.
+    /// 
+    /// This is optional, and defaults to no limit.  It replaces the HTTP client of `client` or
+    /// `config` with a new connection pool.  [`build`](Self::build) fails if `max` is zero.
+    /// 
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.http_settings.max_connections = Some(max);
+        self
+    }
+
+    /// Close connections to the S3 endpoint that were idle for `timeout`.
+    /// 
+    /// This is optional, and defaults to 90 seconds.  It replaces the HTTP client of `client` or
+    /// `config` with a new connection pool.
+    /// 
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.http_settings.pool_idle_timeout = Some(timeout);
+        self
+    }
+
     /// Set the maximum size of the file to serve.
     /// 
     /// This is optional, and defaults to no maximum size.
@@ -89,33 +643,999 @@ impl S3OriginBuilder {
         self
     }
 
-    /// Build the S3 origin.
+    /// Redirect objects too large for a buffered Lambda response to a presigned S3 URL, see
+    /// [`lambda`](crate::lambda).
     /// 
-    /// This will return an error a required parameter is not provided.
+    /// This is optional, and defaults to streaming every object.  [`build`](Self::build) fails
+    /// if presigned URLs would be valid for more than 7 days.
     /// 
-    pub fn build(self) -> Result<S3Origin, &'static str> {
-        let bucket = self.bucket.ok_or("bucket is required")?;
-        let bucket_prefix = self.bucket_prefix.unwrap_or_default();
-        
-        let s3_client = if let Some(client) = self.s3_client {
+    pub fn lambda_response_limit(mut self, limit: LambdaLimit) -> Self {
+        self.lambda_limit = Some(limit);
+        self
+    }
+
+    /// Race a HeadObject request against the GetObject request.
+    /// 
+    /// This is optional, and defaults to disabled. It only applies when [`max_size`](Self::max_size)
+    /// is set and the request is not a range request, and never to Object Lambda Access Points.
+    /// If the HEAD response arrives first and disqualifies the object, the GET is cancelled and an
+    /// HTTP 413 is returned without waiting for the GET. This trades an extra S3 request for lower
+    /// latency on rejected objects.
+    /// 
+    pub fn parallel_head(mut self, enabled: bool) -> Self {
+        self.parallel_head = enabled;
+        self
+    }
+
+    /// Set how `HEAD` requests are served.
+    /// 
+    /// This is optional, and defaults to [`HeadPolicy::HeadObject`], or [`HeadPolicy::GetObject`]
+    /// for Object Lambda Access Points, whose HeadObject metadata describes the untransformed
+    /// object.
+    /// 
+    pub fn head_policy(mut self, policy: HeadPolicy) -> Self {
+        self.head_policy = Some(policy);
+        self
+    }
+
+    /// Set which request methods are served, and how the others are rejected.
+    /// 
+    /// This is optional, and defaults to serving `GET` and `HEAD`, answering `OPTIONS`, and
+    /// rejecting other methods with `405 Method Not Allowed`; see [`MethodPolicy`].
+    /// 
+    pub fn method_policy(mut self, policy: MethodPolicy) -> Self {
+        self.method_policy = policy;
+        self
+    }
+
+    /// Set how query strings are treated.
+    /// 
+    /// This is optional, and defaults to [`QueryPolicy::Ignore`]; see [`QueryPolicy`] to include
+    /// parameters such as `?v=` in the cache key, or to reject unexpected parameters.
+    /// 
+    pub fn query_policy(mut self, policy: QueryPolicy) -> Self {
+        self.query_policy = policy;
+        self
+    }
+
+    /// Set how objects with `x-amz-website-redirect-location` metadata are served.
+    /// 
+    /// This is optional, and defaults to [`WebsiteRedirect::Permanent`] (a `301` redirect, as
+    /// S3 static website hosting does).
+    /// 
+    pub fn website_redirect(mut self, website_redirect: WebsiteRedirect) -> Self {
+        self.website_redirect = website_redirect;
+        self
+    }
+
+    /// Resolve clean URLs the way static site generators (Hugo, Next.js export, ...) expect.
+    /// 
+    /// This is optional, and defaults to disabled (the key is used as-is).
+    /// When enabled, a request for `/about` tries the keys `about`, `about.html` and
+    /// `about/index.html` in order, serving the first that exists; `about.html` is skipped if
+    /// the file name has an extension.  A request for `/about/` or the root path serves
+    /// `about/index.html` or `index.html`.  Each miss costs one S3 request.
+    /// 
+    pub fn clean_urls(mut self, enabled: bool) -> Self {
+        self.clean_urls = enabled;
+        self
+    }
+
+    /// Set whether clean URLs are redirected to add or remove a trailing slash.
+    /// 
+    /// This is optional, and defaults to [`TrailingSlash::Ignore`].
+    /// Only applies with [`clean_urls`](Self::clean_urls) enabled.
+    /// 
+    pub fn trailing_slash(mut self, trailing_slash: TrailingSlash) -> Self {
+        self.trailing_slash = trailing_slash;
+        self
+    }
+
+    /// Add a `Cache-Control` rule.
+    /// 
+    /// Responses for paths matching the glob `pattern` are served with `Cache-Control: {value}`,
+    /// replacing any `Cache-Control` metadata stored on the object. Rules are evaluated in the
+    /// order they are added and the first match wins, see [`rules`](crate::rules).
+    /// 
+    /// Patterns are matched against the request path relative to the bucket prefix.  A pattern
+    /// without a `/` (e.g. `*.woff2`) matches the file name in any directory; a pattern with a
+    /// `/` (e.g. `/fonts/**`) matches the whole path.
+    /// 
+    pub fn cache_control(self, pattern: impl Into<String>, value: impl Into<String>) -> Self {
+        self.rule(RuleKind::CacheControl, pattern.into(), Some(value.into()))
+    }
+
+    /// Detect content-hashed assets and set `Cache-Control` from the file name.
+    /// 
+    /// This is optional, and defaults to disabled.
+    /// When enabled, fingerprinted file names such as `app.3f9ab2.js` are served with
+    /// `public, max-age=31536000, immutable`, and HTML documents with `no-cache`.
+    /// Explicit [`cache_control`](Self::cache_control) rules take precedence.
+    /// 
+    pub fn immutable_assets(mut self, enabled: bool) -> Self {
+        self.immutable_assets = enabled;
+        self
+    }
+
+    /// Serve paths matching the glob `pattern` as downloads.
+    /// 
+    /// Matching responses get `Content-Disposition: attachment; filename="…"` with the file name
+    /// taken from the key, so browsers show a save dialog instead of rendering inline.  Patterns
+    /// follow the same rules as [`cache_control`](Self::cache_control); use `**` for every path.
+    /// Other paths use the object's `Content-Disposition` metadata, if any.
+    /// 
+    pub fn attachment(self, pattern: impl Into<String>) -> Self {
+        self.rule(RuleKind::Attachment, pattern.into(), None)
+    }
+
+    /// Redirect paths matching the glob `pattern` to `location`.
+    /// 
+    /// Matching requests are answered with `301 Moved Permanently` without asking S3, e.g. for
+    /// pages that moved to another site.  `location` must be an absolute URL or a path starting
+    /// with `/`, which is sent unchanged.  Patterns follow the same rules as
+    /// [`cache_control`](Self::cache_control).
+    /// 
+    pub fn redirect(self, pattern: impl Into<String>, location: impl Into<String>) -> Self {
+        self.rule(RuleKind::Redirect, pattern.into(), Some(location.into()))
+    }
+
+    /// Serve paths matching the glob `pattern` without authentication.
+    /// 
+    /// Matching requests skip [`authorize`](Self::authorize), [`basic_auth`](Self::basic_auth),
+    /// [`bearer_token`](Self::bearer_token), [`signed_urls`](Self::signed_urls) and
+    /// [`signed_cookies`](Self::signed_cookies), e.g. for the assets of a login page.  The
+    /// [`ip_filter`](Self::ip_filter) and [`rate_limit`](Self::rate_limit) still apply.  Patterns
+    /// follow the same rules as [`cache_control`](Self::cache_control).
+    /// 
+    pub fn public(self, pattern: impl Into<String>) -> Self {
+        self.rule(RuleKind::Public, pattern.into(), None)
+    }
+
+    /// Serve paths matching the glob `pattern` with `Content-Type: {value}`.
+    /// 
+    /// This replaces the `Content-Type` metadata stored on the object, e.g. for objects uploaded
+    /// without one (`application/wasm` for `*.wasm`).  Patterns follow the same rules as
+    /// [`cache_control`](Self::cache_control).
+    /// 
+    pub fn content_type(self, pattern: impl Into<String>, value: impl Into<String>) -> Self {
+        self.rule(RuleKind::ContentType, pattern.into(), Some(value.into()))
+    }
+
+    fn rule(mut self, kind: RuleKind, pattern: String, value: Option<String>) -> Self {
+        let index = self.rules.iter().filter(|rule| rule.kind == kind).count();
+        self.rules.push(Rule { kind, index, pattern, value });
+        self
+    }
+
+    /// Forward an `x-amz-*` response header from S3 to the client.
+    /// 
+    /// This is optional; by default all `x-amz-*` headers, including user metadata
+    /// (`x-amz-meta-*`), are stripped.  `name` is matched case-insensitively and may end in `*`
+    /// to allow a prefix, e.g. `x-amz-meta-*` forwards all user metadata.
+    /// Names not starting with `x-amz-` make [`build`](Self::build) fail.
+    /// 
+    pub fn forward_amz_header(mut self, name: impl Into<String>) -> Self {
+        self.forward_amz_headers.push(name.into());
+        self
+    }
+
+    /// Serve a rendered form of objects with the file extension `extension`.
+    /// 
+    /// The rendered form is served to clients whose `Accept` header prefers it over the object's
+    /// native content type, see [`render`](crate::render).  Registering an extension again
+    /// replaces its renderer.
+    /// 
+    /// ```rust,no_run
+    /// # use axum_static_s3::{S3OriginBuilder, render::CsvTable};
+    /// let builder = S3OriginBuilder::new()
+    ///     .renderer("csv", CsvTable::new().max_rows(100));
+    /// ```
+    /// 
+    pub fn renderer(mut self, extension: impl Into<String>, renderer: impl Render) -> Self {
+        let extension = extension.into().trim_start_matches('.').to_ascii_lowercase();
+        self.renderers.retain(|(registered, _)| *registered != extension);
+        self.renderers.push((extension, Arc::new(renderer)));
+        self
+    }
+
+    /// Render Markdown objects to HTML.
+    /// 
+    /// This is optional, and defaults to serving Markdown as-is.
+    /// Shorthand for registering `renderer` for the `md` and `markdown` extensions with
+    /// [`renderer`](Self::renderer).
+    /// 
+    #[cfg(feature = "markdown")]
+    pub fn markdown(self, renderer: crate::markdown::MarkdownRenderer) -> Self {
+        self.renderer("md", renderer.clone())
+            .renderer("markdown", renderer)
+    }
+
+    /// Enable truncated previews of CSV/TSV and NDJSON objects with `?preview=N`.
+    /// 
+    /// This is optional, and defaults to disabled (the query parameter is ignored).
+    /// See [`preview`](crate::preview) for details.
+    /// 
+    pub fn preview(mut self, preview: Preview) -> Self {
+        self.preview = Some(preview);
+        self
+    }
+
+    /// Serve legacy `?v=123` asset URLs from versioned keys.
+    /// 
+    /// This is optional, and defaults to disabled (the query string is ignored).
+    /// See [`VersionQuery`] for the template syntax.
+    /// 
+    /// ```rust,no_run
+    /// # use axum_static_s3::{S3OriginBuilder, VersionQuery};
+    /// // `/js/app.js?v=123` is served from `v123/js/app.js`
+    /// let builder = S3OriginBuilder::new()
+    ///     .version_query(VersionQuery::new("v{version}/{path}"));
+    /// ```
+    /// 
+    pub fn version_query(mut self, version_query: VersionQuery) -> Self {
+        self.version_query = Some(version_query);
+        self
+    }
+
+    /// Include the S3 request IDs of failed requests in an `X-Error-Id` response header.
+    /// 
+    /// This is optional, and defaults to disabled.  The header value is
+    /// `{x-amz-request-id}; id2={x-amz-id-2}`, which is what AWS support asks for when
+    /// investigating a failed request.  With the `trace` feature the IDs are logged either way.
+    /// 
+    pub fn error_id_header(mut self, enabled: bool) -> Self {
+        self.error_id_header = enabled;
+        self
+    }
+
+    /// Set the `Retry-After` sent when S3 throttles requests, in seconds.
+    /// 
+    /// This is optional, and defaults to 1 second.  Throttling (`SlowDown`, `503`) is served as
+    /// `503 Service Unavailable` with this header, see [`S3Error::Throttled`](crate::S3Error::Throttled).
+    /// 
+    pub fn retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = seconds;
+        self
+    }
+
+    /// Report which optional features fired for each request.
+    /// 
+    /// This is optional, and defaults to disabled.  After every request `callback` receives the
+    /// features that fired for it and the origin's aggregate counters, which are also available
+    /// from [`S3Origin::feature_counters`].  See [`telemetry`](crate::telemetry).
+    /// 
+    pub fn feature_telemetry(mut self, callback: impl Fn(Features, &FeatureCounters) + Send + Sync + 'static) -> Self {
+        self.feature_telemetry = Some(Arc::new(callback));
+        self
+    }
+
+    /// Keep small objects in memory, up to `capacity` bytes in total.
+    /// 
+    /// This is optional, and defaults to disabled.  Objects up to an eighth of the capacity are
+    /// cached on their first GET and served from memory for [`cache_ttl`](Self::cache_ttl);
+    /// ranged requests always go to S3.  See [`S3Origin::prefetch`] to warm the cache.
+    /// 
+    pub fn cache(mut self, capacity: usize) -> Self {
+        self.cache = Some(capacity);
+        self
+    }
+
+    /// Set how long objects are served from the cache.
+    /// 
+    /// This is optional, and defaults to 60 seconds.  Changes to an object in S3 become
+    /// visible once its cache entry expires.
+    /// 
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Cache each object for as long as its `Cache-Control` or `Expires` metadata allows,
+    /// clamped to `min..=max`.
+    /// 
+    /// This is optional, and defaults to disabled: every object is cached for
+    /// [`cache_ttl`](Self::cache_ttl), which remains the TTL of objects without such metadata.
+    /// `s-maxage` takes precedence over `max-age`; `no-store` and `no-cache` give `min`.
+    /// 
+    pub fn cache_ttl_from_metadata(mut self, min: Duration, max: Duration) -> Self {
+        self.cache_ttl_bounds = Some((min, max));
+        self
+    }
+
+    /// Cache only keys requested at least as often as `hot_keys` sets.
+    /// 
+    /// This is optional, and defaults to disabled: every object small enough is cached.  Without
+    /// an explicit [`cache`](Self::cache) a cache of [`HotKeys::capacity`] is created, so this
+    /// alone enables caching for the most requested objects.  See [`S3Origin::hot_keys`].
+    /// 
+    pub fn hot_keys(mut self, hot_keys: HotKeys) -> Self {
+        self.hot_keys = Some(hot_keys);
+        self
+    }
+
+    /// Store cached objects in a [`moka`] cache instead of the built-in LRU.
+    /// 
+    /// This is optional, and defaults to false.  See [`S3Origin::moka_cache`].
+    /// 
+    #[cfg(feature = "moka")]
+    pub fn moka_cache(mut self, enabled: bool) -> Self {
+        self.moka_cache = enabled;
+        self
+    }
+
+    /// Use `store` as a second cache tier, for objects up to `max_size` bytes.
+    /// 
+    /// This is optional, and defaults to none.  Objects missing from the in-memory
+    /// [`cache`](Self::cache) are looked up in the store before S3, and objects fetched from S3
+    /// are written to it in the background with the [`cache_ttl`](Self::cache_ttl).  See
+    /// [`store`](crate::store).
+    /// 
+    pub fn cache_store(mut self, store: impl CacheStore, max_size: usize) -> Self {
+        self.cache_store = Some((Arc::new(store), max_size));
+        self
+    }
+
+    /// Serve objects from `backend` instead of S3.
+    /// 
+    /// This is optional, and defaults to the S3 bucket.  Without a
+    /// [`client`](Self::client) or [`config`](Self::config), features
+    /// that need S3 fail their requests.  See [`backend`](crate::backend).
+    /// 
+    pub fn backend(mut self, backend: impl ObjectBackend) -> Self {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Decide for every request whether it is served, before any S3 call.
+    /// 
+    /// This is optional, and defaults to serving every request.  Denials and redirects are
+    /// sent with `Cache-Control: private, no-store` unless the response sets its own.  See
+    /// [`auth`](crate::auth); for callbacks that need to await, use
+    /// [`authorize_async`](Self::authorize_async).
+    /// 
+    pub fn authorize<F>(mut self, authorize: F) -> Self
+    where
+        F: Fn(&axum::http::Request<()>) -> AuthDecision + Send + Sync + 'static,
+    {
+        self.authorize = Some(Authorizer::Sync(Arc::new(authorize)));
+        self
+    }
+
+    /// Like [`authorize`](Self::authorize), with a callback returning a future, e.g. to look up
+    /// a session.
+    /// 
+    /// The future must not borrow the request; copy what it needs before the `async` block.
+    /// 
+    pub fn authorize_async<F, Fut>(mut self, authorize: F) -> Self
+    where
+        F: Fn(&axum::http::Request<()>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = AuthDecision> + Send + 'static,
+    {
+        self.authorize = Some(Authorizer::new_async(authorize));
+        self
+    }
+
+    /// Require HTTP Basic authentication with this user name and password.
+    /// 
+    /// This is optional, and may be called several times for more users.  `password_hash` is
+    /// the SHA-256 digest of the password in hex (e.g. `echo -n "$PASSWORD" | sha256sum`).  This
+    /// is not a password hash: it is unsalted and fast to brute-force, so only use it with long
+    /// random passwords.  With the `password-hash` feature, an Argon2 PHC string or a bcrypt
+    /// hash is accepted instead, see [`auth`](crate::auth).
+    /// 
+    /// Requests without valid credentials are answered with `401 Unauthorized` and a
+    /// `WWW-Authenticate` challenge, before the [`authorize`](Self::authorize) callback.
+    /// [`build`](Self::build) fails if the hash is none of these.
+    /// 
+    pub fn basic_auth(mut self, user: impl Into<String>, password_hash: impl Into<String>) -> Self {
+        self.basic_auth.push((user.into(), password_hash.into()));
+        self
+    }
+
+    /// Require a bearer token (`Authorization: Bearer {token}`) accepted by `validate`.
+    /// 
+    /// This is optional, and defaults to no bearer tokens.  With [`basic_auth`](Self::basic_auth)
+    /// too, either is accepted.
+    /// 
+    pub fn bearer_token<F>(mut self, validate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.bearer_token = Some(Arc::new(validate));
+        self
+    }
+
+    /// Only serve requests with a valid signed URL, see [`signed_url`](crate::signed_url).
+    /// 
+    /// This is optional, and defaults to serving unsigned requests.  Signatures are checked
+    /// before the [`authorize`](Self::authorize) callback.
+    /// 
+    pub fn signed_urls(mut self, signed_urls: SignedUrls) -> Self {
+        self.signed_urls = Some(signed_urls);
+        self
+    }
+
+    /// Only serve requests with valid signed cookies for their path, see
+    /// [`signed_cookie`](crate::signed_cookie).
+    /// 
+    /// This is optional, and defaults to serving requests without cookies.  With
+    /// [`signed_urls`](Self::signed_urls) too, either grants access.
+    /// 
+    pub fn signed_cookies(mut self, signed_cookies: SignedCookies) -> Self {
+        self.signed_cookies = Some(signed_cookies);
+        self
+    }
+
+    /// Only serve images and video to pages on allowed hosts, see [`hotlink`](crate::hotlink).
+    /// 
+    /// This is optional, and defaults to serving every site.
+    /// 
+    pub fn hotlink_protection(mut self, hotlink: HotlinkProtection) -> Self {
+        self.hotlink = Some(hotlink);
+        self
+    }
+
+    /// Configure the checks of [`health_router`](S3Origin::health_router), see [`health`](crate::health).
+    /// 
+    /// This is optional, and defaults to a HeadObject for `index.html` under the bucket prefix,
+    /// cached for 5 seconds with a 2 second timeout.
+    /// 
+    pub fn health_check(mut self, health: HealthCheck) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Generate the sitemap of [`sitemap_router`](S3Origin::sitemap_router) from the bucket
+    /// listing, see [`sitemap`](crate::sitemap).
+    /// 
+    /// This is optional, and defaults to no sitemap.  [`build`](Self::build) fails unless the
+    /// base URL is an `http` or `https` URL and the globs are valid.
+    /// 
+    pub fn sitemap(mut self, sitemap: Sitemap) -> Self {
+        self.sitemap = Some(sitemap);
+        self
+    }
+
+    /// Answer `GET` and `HEAD` requests for `path` with `body`, without a request to S3.
+    /// 
+    /// Meant for small files every site is asked for, such as `/robots.txt` or `/favicon.ico`:
+    /// 
+    /// ```rust
+    /// # let (builder, favicon) = (axum_static_s3::S3OriginBuilder::new(), Vec::<u8>::new());
+    /// let builder = builder
+    ///     .synthetic_response("/robots.txt", "text/plain", "User-agent: *\nDisallow: /private/\n")
+    ///     .synthetic_response("/favicon.ico", "image/x-icon", favicon);
+    /// ```
+    /// 
+    /// This is optional, and defaults to serving every path from S3.  `path` is matched against
+    /// the whole request path, before [`prune_path`](Self::prune_path) and the bucket prefix
+    /// apply.  The responses carry an `ETag` and `Cache-Control: public, max-age=3600`, and no
+    /// other header policies apply to them.  [`build`](Self::build) fails unless
+    /// `content_type` is a valid header value.
+    /// 
+    pub fn synthetic_response(mut self, path: impl Into<String>, content_type: impl Into<String>, body: impl Into<axum::body::Bytes>) -> Self {
+        self.synthetic.push((path.into(), content_type.into(), body.into()));
+        self
+    }
+
+    /// Serve `/.well-known/` from a separate prefix or inline files, see
+    /// [`well_known`](crate::well_known).
+    /// 
+    /// This is optional, and defaults to serving `/.well-known/` like any other path.
+    /// [`build`](Self::build) fails if the prefix is empty or a content type is not a valid
+    /// header value.
+    /// 
+    pub fn well_known(mut self, well_known: WellKnown) -> Self {
+        self.well_known = Some(well_known);
+        self
+    }
+
+    /// Identify clients by address behind proxies, see [`client_identity`](crate::client_identity).
+    /// 
+    /// This is optional, and defaults to the peer address from axum's `ConnectInfo`.  The
+    /// [`ip_filter`](Self::ip_filter), [`rate_limit`](Self::rate_limit) and
+    /// `access_events` all use it, unless the filter or limit sets its
+    /// own `trusted_proxies`.
+    /// 
+    pub fn client_identity(mut self, client_identity: ClientIdentity) -> Self {
+        self.client_identity = client_identity;
+        self
+    }
+
+    /// Only serve requests from allowed networks, see [`ip_filter`](crate::ip_filter).
+    /// 
+    /// This is optional, and defaults to serving every address.  [`build`](Self::build) fails
+    /// if the filter has an entry that is not a network or address.
+    /// 
+    pub fn ip_filter(mut self, ip_filter: IpFilter) -> Self {
+        self.ip_filter = Some(ip_filter);
+        self
+    }
+
+    /// Limit the request rate of every client, see [`rate_limit`](crate::rate_limit).
+    /// 
+    /// This is optional, and defaults to no limit.  [`build`](Self::build) fails unless the
+    /// rate and burst are positive.
+    /// 
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Limit the bytes served per day or month under path prefixes or to tenants, see
+    /// [`quota`](crate::quota).
+    /// 
+    /// This is optional, and defaults to no quota.  [`build`](Self::build) fails unless the
+    /// quota is answered with `429` or `403`.
+    /// 
+    pub fn byte_quota(mut self, byte_quota: ByteQuota) -> Self {
+        self.byte_quota = Some(byte_quota);
+        self
+    }
+
+    /// Limit the requests served at once, adapting to S3 latency and errors, see
+    /// [`concurrency`](crate::concurrency).
+    /// 
+    /// This is optional, and defaults to no limit.  [`build`](Self::build) fails unless
+    /// `1 <= min <= initial <= max` and the backoff is between 0 and 1.
+    /// 
+    pub fn adaptive_concurrency(mut self, adaptive_concurrency: AdaptiveConcurrency) -> Self {
+        self.adaptive_concurrency = Some(adaptive_concurrency);
+        self
+    }
+
+    /// Only serve objects carrying the required tags, see [`RequiredTags`].
+    /// 
+    /// This is optional, and defaults to serving objects regardless of their tags.  Checking
+    /// tags costs a GetObjectTagging request per key and [cache TTL](RequiredTags::cache_ttl).
+    /// The [`sitemap`](Self::sitemap) only lists pages carrying the tags.
+    /// 
+    pub fn required_tags(mut self, required_tags: RequiredTags) -> Self {
+        self.required_tags = Some(required_tags);
+        self
+    }
+
+    /// Keep serving expired cache entries for up to `window` while revalidating them.
+    /// 
+    /// This is optional, and defaults to zero: expired entries are fetched again before
+    /// responding.  Within the window the first request for an expired entry triggers a
+    /// conditional GET on its ETag in the background, and requests are answered from the stale
+    /// copy until it completes.  Requires a Tokio runtime.
+    /// 
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.stale_while_revalidate = window;
+        self
+    }
+
+    /// Check every cache hit against S3 before serving it.
+    /// 
+    /// This is optional, and defaults to `false`: cached objects are served until their TTL
+    /// expires, even if they were overwritten in the meantime.  When enabled, each hit costs a
+    /// HeadObject request, and an object whose version ID (or, in unversioned buckets, ETag)
+    /// changed is fetched again.  The cache still saves transferring the bodies.
+    /// 
+    pub fn verify_cached_versions(mut self, verify: bool) -> Self {
+        self.verify_cached_versions = verify;
+        self
+    }
+
+    /// Cache the first and last segments of audio and video files for ranged requests, see
+    /// [`segments`](crate::segments).
+    /// 
+    /// This is optional, and defaults to serving every range from S3.  Segments are kept in the
+    /// [`cache`](Self::cache); without one this has no effect.  [`build`](Self::build) fails
+    /// unless the segment size is positive and at least one segment is cached.
+    /// 
+    pub fn segment_cache(mut self, segment_cache: SegmentCache) -> Self {
+        self.segment_cache = Some(segment_cache);
+        self
+    }
+
+    /// Set the name, target and fields of the request span.
+    /// 
+    /// This is optional, and defaults to a span named `s3_origin` with the target
+    /// `axum_static_s3` and all fields.  See [`span`](crate::span).
+    /// 
+    #[cfg(feature = "trace")]
+    pub fn span(mut self, span: crate::span::SpanConfig) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// Record an access log entry for every request.
+    /// 
+    /// This is optional, and defaults to disabled.
+    /// See [`access_log`](crate::access_log) for the recorded fields and formats.
+    /// 
+    #[cfg(feature = "access-log")]
+    pub fn access_log(mut self, access_log: crate::access_log::AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// Record an access event for every response served from an object.
+    /// 
+    /// This is optional, and defaults to disabled.  See [`access_events`](crate::access_events)
+    /// for the recorded fields and batching.  Requires a Tokio runtime.
+    /// 
+    #[cfg(feature = "access-log")]
+    pub fn access_events(mut self, access_events: crate::access_events::AccessEvents) -> Self {
+        self.access_events = Some(access_events);
+        self
+    }
+
+    /// Map request paths to object keys through a deployment manifest.
+    /// 
+    /// This is optional, and defaults to disabled.  Paths listed in the manifest are served
+    /// from their mapped key with the manifest's headers; other paths are resolved as usual.
+    /// See [`manifest`](crate::manifest) for the format and reloading.
+    /// 
+    #[cfg(feature = "manifest")]
+    pub fn manifest(mut self, manifest: crate::manifest::Manifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    /// Check the path rules for rules that can never apply.
+    /// 
+    /// Returns every rule that duplicates, conflicts with or is shadowed by an earlier rule of
+    /// the same kind.  [`build`](Self::build) fails if this returns an error.
+    /// 
+    pub fn validate(&self) -> Result<(), Vec<RuleConflict>> {
+        let conflicts = rules::validate(&self.rules);
+        match conflicts.is_empty() {
+            true => Ok(()),
+            false => Err(conflicts),
+        }
+    }
+
+    /// The S3 client, with the credential and endpoint options applied.
+    ///
+    /// Requests sent outside of any origin's [scope](cost::scope) count towards `cost`.
+    fn make_client(&mut self, cost: &Arc<cost::CostCounters>) -> Result<S3Client, &'static str> {
+        let s3_client = if let Some(client) = self.s3_client.take() {
             client
-        } else if let Some(config) = self.aws_sdk_config {
-            S3Client::new(&config)
+        } else if let Some(config) = &self.aws_sdk_config {
+            S3Client::new(config)
+        } else if self.backend.is_some() {
+            // Only used by features that need S3
+            S3Client::from_conf(aws_sdk_s3::Config::builder().behavior_version(aws_sdk_s3::config::BehaviorVersion::latest()).build())
         } else {
             return Err("either s3_client or aws_sdk_config must be provided");
         };
+        let s3_client = self.http_settings.apply(s3_client)?;
+        let s3_client = match self.assume_role.take() {
+            Some(_) if self.anonymous => return Err("anonymous and assume_role are mutually exclusive"),
+            Some((role_arn, session_name)) => {
+                let config = self.aws_sdk_config.take().ok_or("assume_role requires aws_sdk_config")?;
+                if config.time_source().is_none() {
+                    return Err("assume_role requires a time source in aws_sdk_config");
+                }
+                let credentials = AssumeRole::new(role_arn, session_name, config);
+                S3Client::from_conf(s3_client.config().to_builder().credentials_provider(credentials).build())
+            }
+            None => s3_client,
+        };
+        let s3_client = match self.anonymous {
+            true => {
+                let config = s3_client.config().to_builder()
+                    .allow_no_auth()
+                    .auth_scheme_preference([AuthSchemeId::new("noAuth")]);
+                S3Client::from_conf(config.build())
+            }
+            false => s3_client,
+        };
+        let s3_client = match self.endpoint_url.take() {
+            Some(endpoint_url) => {
+                let config = s3_client.config().to_builder().endpoint_url(endpoint_url).force_path_style(true);
+                S3Client::from_conf(config.build())
+            }
+            None => s3_client,
+        };
+        let s3_client = match self.expected_bucket_owner.take() {
+            Some(account_id) => {
+                let interceptor = ExpectedBucketOwner::new(account_id)?;
+                S3Client::from_conf(s3_client.config().to_builder().interceptor(interceptor).build())
+            }
+            None => s3_client,
+        };
+        let interceptor = cost::CostInterceptor(cost.clone());
+        Ok(S3Client::from_conf(s3_client.config().to_builder().interceptor(interceptor).build()))
+    }
+
+    /// Build the S3 origin.
+    /// 
+    /// This will return an error a required parameter is not provided, or if
+    /// [`validate`](Self::validate) fails.
+    /// 
+    pub fn build(mut self) -> Result<S3Origin, &'static str> {
+        if self.validate().is_err() {
+            return Err("rules conflict, see S3OriginBuilder::validate");
+        }
+
+        if let Some(ip_filter) = &self.ip_filter {
+            ip_filter.validate()?;
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.validate()?;
+        }
+        if let Some(precompressed) = &self.precompressed {
+            precompressed.validate()?;
+        }
+        if let Some(byte_quota) = &self.byte_quota {
+            byte_quota.validate()?;
+        }
+        if let Some(adaptive_concurrency) = &self.adaptive_concurrency {
+            adaptive_concurrency.validate()?;
+        }
+        if let Some(segment_cache) = &self.segment_cache {
+            segment_cache.validate()?;
+        }
+        let sitemap = self.sitemap.take().map(SitemapState::new).transpose()?;
+        let mut synthetic = std::mem::take(&mut self.synthetic).into_iter()
+            .map(|(path, content_type, body)| SyntheticResponse::new(&path, &content_type, body))
+            .collect::<Result<Vec<_>, _>>()?;
+        let well_known = match self.well_known.take() {
+            Some(well_known) => {
+                let (files, prefix) = well_known.resolve()?;
+                synthetic.extend(files);
+                prefix
+            }
+            None => None,
+        };
+        let lambda_limit = match self.lambda_limit.take() {
+            Some(limit) => limit.resolve()?,
+            None => None,
+        };
+
+        let shared = self.shared.take();
+        let bucket = match &shared {
+            Some(shared) => shared.bucket.clone(),
+            None => self.bucket.take().ok_or("bucket is required")?,
+        };
+        let bucket_kind = BucketKind::parse(&bucket)?;
+        let object_lambda = bucket_kind == BucketKind::ObjectLambda;
+        let bucket_prefix = self.bucket_prefix.take().unwrap_or_default();
+        
+        let cost = Arc::new(cost::CostCounters::default());
+        let s3_client = match &shared {
+            Some(shared) => shared.client.clone(),
+            None => Arc::new(self.make_client(&cost)?),
+        };
+        if bucket_kind == BucketKind::MultiRegionAccessPoint && s3_client.config().region().is_none() {
+            return Err("Multi-Region Access Points need a client region for endpoint resolution");
+        }
+
+        let cache_control = CacheControlPolicy {
+            rules: self.rules
+                .iter()
+                .filter(|rule| rule.kind == RuleKind::CacheControl)
+                .map(|rule| CacheControlRule::new(&rule.pattern, rule.value.as_deref().unwrap_or_default()))
+                .collect::<Result<_, _>>()?,
+            immutable_assets: self.immutable_assets,
+        };
+
+        let content_disposition = ContentDispositionPolicy::new(
+            self.rules
+                .iter()
+                .filter(|rule| rule.kind == RuleKind::Attachment)
+                .map(|rule| &rule.pattern)
+        )?;
+        let redirects = PathRules::new(&self.rules, RuleKind::Redirect)?;
+        let public_paths = PathRules::new(&self.rules, RuleKind::Public)?;
+        let content_types = PathRules::new(&self.rules, RuleKind::ContentType)?;
+        let amz_headers = AmzHeaderPolicy::new(self.forward_amz_headers)?;
+        let credentials = match (self.basic_auth.is_empty(), self.bearer_token) {
+            (true, None) => None,
+            (_, bearer) => Some(Credentials {
+                users: self.basic_auth
+                    .into_iter()
+                    .map(|(user, hash)| Some((user, PasswordHash::parse(&hash)?)))
+                    .collect::<Option<_>>()
+                    .ok_or("invalid basic_auth password hash")?,
+                bearer,
+            }),
+        };
 
         Ok(S3Origin {
+            permit: None,
             inner: Arc::new(S3OriginInner {
                 bucket,
-                bucket_prefix,
-                s3_client: Arc::new(s3_client),
+                bucket_prefix: std::sync::RwLock::new(bucket_prefix.into()),
+                canary: std::sync::RwLock::new(self.canary),
+                maintenance: std::sync::RwLock::new(self.maintenance),
+                drain: Arc::new(Drain::new(self.drain_mode)),
+                experiments: self.experiments,
+                locales: self.locales,
+                image_negotiation: self.image_negotiation,
+                precompressed: self.precompressed.map(PrecompressedVariants::new),
+                customize_request: self.customize_request,
+                html_injection: self.html_injection,
+                decompress: self.decompress,
+                ranges: self.ranges,
+                preloads: Preloads::new(&self.preloads)?,
+                resume_retries: self.resume_retries,
+                parallel_fetch: self.parallel_fetch,
+                readahead: self.readahead,
+                transfers: Arc::default(),
+                digests: Default::default(),
+                verify_checksums: self.verify_checksums,
+                checksum_trailer: self.checksum_trailer,
+                hot_keys: self.hot_keys.map(crate::hot_keys::HotKeyTracker::new),
+                authorize: self.authorize,
+                credentials,
+                signed_urls: self.signed_urls,
+                signed_cookies: self.signed_cookies,
+                hotlink: self.hotlink,
+                health: HealthState::new(self.health),
+                sitemap,
+                synthetic,
+                well_known,
+                client_identity: self.client_identity,
+                ip_filter: self.ip_filter,
+                rate_limit: self.rate_limit.map(|rate_limit| RateLimiter::new(rate_limit, self.client_identity)),
+                quota: self.byte_quota.map(Arc::new),
+                lambda_limit,
+                concurrency: self.adaptive_concurrency.map(|config| Arc::new(ConcurrencyLimiter::new(config))),
+                required_tags: self.required_tags.map(TagCheck::new),
+                cache_store: self.cache_store.map(|(store, max_size)| {
+                    StoreTier::new(store, TtlPolicy { ttl: self.cache_ttl, bounds: self.cache_ttl_bounds }, max_size)
+                }),
+                backend: self.backend,
+                s3_client,
                 prune_path: self.prune_path,
+                path_source: self.path_source,
+                stage_prefix: self.stage_prefix,
                 max_size: self.max_size,
+                // The HEAD precheck would see the size of the untransformed object
+                parallel_head: self.parallel_head && !object_lambda,
+                head_policy: self.head_policy.unwrap_or(match object_lambda {
+                    true => HeadPolicy::GetObject,
+                    false => HeadPolicy::HeadObject,
+                }),
+                method_policy: self.method_policy,
+                query_policy: self.query_policy,
+                website_redirect: self.website_redirect,
+                clean_urls: self.clean_urls,
+                trailing_slash: self.trailing_slash,
+                cache_control,
+                content_disposition,
+                redirects,
+                public_paths,
+                content_types,
+                amz_headers,
+                rules: self.rules,
+                renderers: self.renderers,
+                preview: self.preview,
+                version_query: self.version_query,
+                error_id_header: self.error_id_header,
+                retry_after: self.retry_after,
+                telemetry: self.feature_telemetry.map(|callback| Telemetry {
+                    counters: Arc::default(),
+                    callback,
+                }),
+                cache: shared.and_then(|shared| shared.cache).or_else(|| {
+                    self.cache.or(self.hot_keys.map(|hot_keys| hot_keys.cache_capacity())).map(|capacity| {
+                        let cache = MemoryCache::new(capacity, self.cache_ttl, self.stale_while_revalidate)
+                            .ttl_from_metadata(self.cache_ttl_bounds);
+                        #[cfg(feature = "moka")]
+                        let cache = cache.with_moka(self.moka_cache);
+                        Arc::new(cache)
+                    })
+                }),
+                verify_cached_versions: self.verify_cached_versions,
+                segments: self.segment_cache,
+                in_flight: Default::default(),
+                requests: Default::default(),
+                cost,
+                #[cfg(feature = "trace")]
+                span: match &self.span {
+                    Some(span) => crate::span::RequestSpan::new(span),
+                    None => crate::span::RequestSpan::default_span(),
+                },
+                #[cfg(feature = "access-log")]
+                access_log: self.access_log,
+                #[cfg(feature = "access-log")]
+                access_events: self.access_events.map(crate::access_events::EventBuffer::new),
+                #[cfg(feature = "manifest")]
+                manifest: self.manifest.map(crate::manifest::ManifestState::new),
             })
         })
     }
 }
+/// Prints the configuration; the S3 client, SDK config (and their credentials) and callbacks
+/// are not printed.
+impl std::fmt::Debug for S3OriginBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("S3OriginBuilder");
+        debug
+            .field("bucket", &self.bucket)
+            .field("bucket_prefix", &self.bucket_prefix)
+            .field("canary", &self.canary)
+            .field("maintenance", &self.maintenance)
+            .field("drain_mode", &self.drain_mode)
+            .field("experiments", &self.experiments)
+            .field("locales", &self.locales)
+            .field("image_negotiation", &self.image_negotiation)
+            .field("precompressed", &self.precompressed)
+            .field("customize_request", &opaque(&self.customize_request, "callback"))
+            .field("html_injection", &self.html_injection)
+            .field("decompress", &self.decompress)
+            .field("ranges", &self.ranges)
+            .field("preloads", &self.preloads)
+            .field("resume_retries", &self.resume_retries)
+            .field("parallel_fetch", &self.parallel_fetch)
+            .field("readahead", &self.readahead)
+            .field("verify_checksums", &self.verify_checksums)
+            .field("checksum_trailer", &self.checksum_trailer)
+            .field("s3_client", &opaque(&self.s3_client, "client"))
+            .field("aws_sdk_config", &opaque(&self.aws_sdk_config, "config"))
+            .field("anonymous", &self.anonymous)
+            .field("assume_role", &self.assume_role)
+            .field("expected_bucket_owner", &self.expected_bucket_owner)
+            .field("http_settings", &self.http_settings)
+            .field("endpoint_url", &self.endpoint_url)
+            .field("shared", &self.shared)
+            .field("prune_path", &self.prune_path)
+            .field("path_source", &self.path_source)
+            .field("stage_prefix", &self.stage_prefix)
+            .field("max_size", &self.max_size)
+            .field("lambda_limit", &self.lambda_limit)
+            .field("parallel_head", &self.parallel_head)
+            .field("head_policy", &self.head_policy)
+            .field("method_policy", &self.method_policy)
+            .field("query_policy", &self.query_policy)
+            .field("website_redirect", &self.website_redirect)
+            .field("clean_urls", &self.clean_urls)
+            .field("trailing_slash", &self.trailing_slash)
+            .field("rules", &self.rules)
+            .field("immutable_assets", &self.immutable_assets)
+            .field("forward_amz_headers", &self.forward_amz_headers)
+            .field("renderers", &self.renderers.iter().map(|(extension, _)| extension).collect::<Vec<_>>())
+            .field("preview", &self.preview)
+            .field("version_query", &self.version_query)
+            .field("error_id_header", &self.error_id_header)
+            .field("retry_after", &self.retry_after)
+            .field("feature_telemetry", &opaque(&self.feature_telemetry, "callback"))
+            .field("cache", &self.cache)
+            .field("cache_ttl", &self.cache_ttl)
+            .field("cache_ttl_bounds", &self.cache_ttl_bounds)
+            .field("hot_keys", &self.hot_keys)
+            .field("cache_store", &opaque(&self.cache_store, "store"))
+            .field("backend", &opaque(&self.backend, "backend"))
+            .field("authorize", &self.authorize)
+            .field("basic_auth", &self.basic_auth.iter().map(|(user, _)| user).collect::<Vec<_>>())
+            .field("bearer_token", &opaque(&self.bearer_token, "callback"))
+            .field("signed_urls", &self.signed_urls)
+            .field("signed_cookies", &self.signed_cookies)
+            .field("hotlink", &self.hotlink)
+            .field("health", &self.health)
+            .field("sitemap", &self.sitemap)
+            .field("synthetic", &self.synthetic.iter().map(|(path, _, _)| path).collect::<Vec<_>>())
+            .field("well_known", &self.well_known)
+            .field("client_identity", &self.client_identity)
+            .field("ip_filter", &self.ip_filter)
+            .field("rate_limit", &self.rate_limit)
+            .field("byte_quota", &self.byte_quota)
+            .field("adaptive_concurrency", &self.adaptive_concurrency)
+            .field("required_tags", &self.required_tags)
+            .field("stale_while_revalidate", &self.stale_while_revalidate)
+            .field("verify_cached_versions", &self.verify_cached_versions)
+            .field("segment_cache", &self.segment_cache);
+        #[cfg(feature = "trace")]
+        debug.field("span", &self.span);
+        #[cfg(feature = "access-log")]
+        debug.field("access_log", &self.access_log);
+        #[cfg(feature = "access-log")]
+        debug.field("access_events", &self.access_events);
+        #[cfg(feature = "manifest")]
+        debug.field("manifest", &self.manifest);
+        #[cfg(feature = "moka")]
+        debug.field("moka_cache", &self.moka_cache);
+        debug.finish()
+    }
+}
+
 impl Default for S3OriginBuilder {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,775 @@
+//! In-memory cache of whole objects.
+//!
+//! With [`S3OriginBuilder::cache`](crate::S3OriginBuilder::cache) small objects are kept in
+//! memory after the first GET and served without a round trip to S3 until their TTL expires.
+//! Ranged requests bypass the cache, unless [segments](crate::segments) of media objects are
+//! cached, and HEAD requests are served from it but never populate it.  Once the cache exceeds its capacity the least recently used entries are evicted.
+//!
+//! Objects with the same ETag, e.g. one asset reachable under several keys through manifest
+//! aliases or per-locale copies, share one copy of their body.
+//!
+//! With [`cache_ttl_from_metadata`](crate::S3OriginBuilder::cache_ttl_from_metadata) each entry
+//! lives as long as the object's `Cache-Control` (`s-maxage`, then `max-age`) or `Expires`
+//! metadata allows, within configured bounds.
+//!
+//! With [`stale_while_revalidate`](crate::S3OriginBuilder::stale_while_revalidate) an expired
+//! entry is still served for a while, and revalidated with a conditional GET on its ETag in the
+//! background.  Only one request per entry triggers a revalidation.
+//!
+//! Entries remember which version of their object they hold: its version ID in versioned
+//! buckets, its ETag otherwise.  Whenever S3 reports another version of a cached key, e.g. to a
+//! ranged or HEAD request, the key's entries are evicted.  With
+//! [`verify_cached_versions`](crate::S3OriginBuilder::verify_cached_versions) every hit is
+//! checked with a HeadObject first, so an overwritten object is not served from the cache even
+//! within its TTL.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use aws_sdk_s3::{
+    operation::get_object::GetObjectOutput,
+    primitives::{DateTime, DateTimeFormat},
+};
+use axum::body::Bytes;
+
+use crate::{cost, metadata::ObjectMetadata, probe, S3Error, S3Origin, S3OriginInner};
+#[cfg(feature = "moka")]
+use crate::moka_store::MokaEntry;
+
+
+/// Default time an object is served from the cache.
+pub(crate) const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60);
+
+
+/// An object held in memory.
+#[derive(Debug)]
+pub(crate) struct CachedObject {
+    pub(crate) metadata: ObjectMetadata,
+    pub(crate) etag: Option<String>,
+    pub(crate) body: Bytes,
+}
+
+impl CachedObject {
+    /// Read a GetObject output into memory.
+    pub(crate) async fn collect(output: GetObjectOutput) -> Result<Self, S3Error> {
+        let mut metadata = ObjectMetadata::from(&output);
+        let etag = output.e_tag().map(str::to_owned);
+        let body = output.body.collect().await
+            .map_err(|_| S3Error::InternalServerError)?
+            .into_bytes();
+        metadata.hash_e_tag(&body);
+        Ok(Self { metadata, etag, body })
+    }
+
+    /// The version ID of the object, in versioned buckets.
+    fn version_id(&self) -> Option<&str> {
+        self.metadata.amz_headers.iter()
+            .find(|(name, _)| name == "x-amz-version-id")
+            .map(|(_, version_id)| version_id.as_str())
+    }
+
+    /// Whether this is the version of the object S3 reports with `version_id` and `etag`: the
+    /// same version ID if both have one, the same ETag otherwise.
+    pub(crate) fn is_version(&self, version_id: Option<&str>, etag: Option<&str>) -> bool {
+        match (self.version_id(), version_id) {
+            (Some(cached), Some(current)) => cached == current,
+            _ => self.etag.as_deref() == etag,
+        }
+    }
+}
+
+
+/// Whether the cached `object` is still the current version of `key`, see
+/// [`verify_cached_versions`](crate::S3OriginBuilder::verify_cached_versions).
+///
+/// The cached object is served while S3 cannot be asked.
+pub(crate) async fn is_current(origin: &S3OriginInner, key: &str, object: &CachedObject) -> bool {
+    match probe::head(origin, key).await {
+        Ok(head) => object.is_version(head.version_id(), head.e_tag()),
+        Err(S3Error::NotFound) => false,
+        Err(_) => true,
+    }
+}
+
+
+struct Entry {
+    object: Arc<CachedObject>,
+    expires: Instant,
+    last_used: u64,
+    revalidating: bool,
+    /// The body is accounted in [`Entries::bodies`] rather than per entry.
+    shared: bool,
+}
+
+
+/// A cached object.
+pub(crate) struct Hit {
+    pub(crate) object: Arc<CachedObject>,
+    /// The entry is stale, and the caller is the one to revalidate it.
+    pub(crate) revalidate: bool,
+}
+
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    /// Keys by last use, oldest first.
+    lru: BTreeMap<u64, String>,
+    tick: u64,
+    /// Bytes accounted against the capacity: keys, shared bodies and unshared bodies.
+    size: usize,
+    /// Bodies by ETag, with the number of entries sharing them.
+    bodies: HashMap<String, (Bytes, usize)>,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) -> bool {
+        let Some(entry) = self.map.remove(key) else {
+            return false;
+        };
+        self.lru.remove(&entry.last_used);
+        self.size -= key.len();
+        self.release(&entry.object, entry.shared);
+        true
+    }
+
+    /// Share the body of a cached object with the same ETag, or make `object`'s body the shared
+    /// one.  Returns whether the body is now accounted in `bodies`.
+    fn share(&mut self, object: &mut CachedObject) -> bool {
+        let Some(etag) = &object.etag else {
+            return false;
+        };
+        match self.bodies.get_mut(etag) {
+            Some((body, refs)) if body.len() == object.body.len() => {
+                object.body = body.clone();
+                *refs += 1;
+                true
+            }
+            // The same ETag for a different body; keep it separate
+            Some(_) => false,
+            None => {
+                self.bodies.insert(etag.clone(), (object.body.clone(), 1));
+                self.size += object.body.len();
+                true
+            }
+        }
+    }
+
+    /// Stop accounting the body of a removed object.
+    fn release(&mut self, object: &CachedObject, shared: bool) {
+        let etag = object.etag.as_ref().filter(|_| shared);
+        let Some(etag) = etag else {
+            self.size -= object.body.len();
+            return;
+        };
+        if let Some((body, refs)) = self.bodies.get_mut(etag) {
+            *refs -= 1;
+            if *refs == 0 {
+                self.size -= body.len();
+                self.bodies.remove(etag);
+            }
+        }
+    }
+
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.map.get_mut(key) {
+            self.lru.remove(&entry.last_used);
+            entry.last_used = tick;
+            self.lru.insert(tick, key.to_owned());
+        }
+    }
+}
+
+
+/// A size-bounded LRU cache of objects by S3 key.
+pub(crate) struct MemoryCache {
+    capacity: usize,
+    ttl: TtlPolicy,
+    /// How long expired entries are still served while being revalidated.
+    stale: Duration,
+    entries: Mutex<Entries>,
+    /// Replaces `entries` as the store, see [`moka_store`](crate::moka_store).
+    #[cfg(feature = "moka")]
+    moka: Option<moka::sync::Cache<String, MokaEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl MemoryCache {
+    pub(crate) fn new(capacity: usize, ttl: Duration, stale: Duration) -> Self {
+        Self {
+            capacity,
+            ttl: TtlPolicy { ttl, bounds: None },
+            stale,
+            entries: Mutex::default(),
+            #[cfg(feature = "moka")]
+            moka: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Derive TTLs from object metadata, clamped to `min..=max`.
+    pub(crate) fn ttl_from_metadata(mut self, bounds: Option<(Duration, Duration)>) -> Self {
+        self.ttl.bounds = bounds;
+        self
+    }
+
+    /// Store entries in a moka cache instead of the built-in LRU.
+    #[cfg(feature = "moka")]
+    pub(crate) fn with_moka(mut self, enabled: bool) -> Self {
+        self.moka = enabled.then(|| crate::moka_store::build(self.capacity, self.stale));
+        self
+    }
+
+    #[cfg(feature = "moka")]
+    pub(crate) fn moka(&self) -> Option<&moka::sync::Cache<String, MokaEntry>> {
+        self.moka.as_ref()
+    }
+
+    fn is_moka(&self) -> bool {
+        #[cfg(feature = "moka")]
+        return self.moka.is_some();
+        #[cfg(not(feature = "moka"))]
+        return false;
+    }
+
+    /// How long `object` is served from the cache.
+    fn ttl(&self, object: &CachedObject) -> Duration {
+        self.ttl.ttl(&object.metadata)
+    }
+
+    /// Objects larger than an eighth of the capacity are not cached, so a single object
+    /// cannot evict most of the cache.
+    pub(crate) fn admits(&self, content_length: Option<i64>) -> bool {
+        content_length.is_some_and(|length| usize::try_from(length).is_ok_and(|length| length <= self.capacity / 8))
+    }
+
+    /// The cached object for `key`, unless missing or expired beyond the staleness window.
+    pub(crate) fn get(&self, key: &str) -> Option<Hit> {
+        let now = Instant::now();
+        #[cfg(feature = "moka")]
+        if let Some(moka) = &self.moka {
+            let entry = moka.get(key).filter(|entry| entry.expires + self.stale > now);
+            let Some(entry) = entry else {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            };
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            let revalidate = entry.expires <= now && entry.claim_revalidation();
+            return Some(Hit { object: entry.object, revalidate });
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(entry) = entries.map.get_mut(key) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        if entry.expires + self.stale <= now {
+            entries.remove(key);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        let revalidate = entry.expires <= now && !entry.revalidating;
+        entry.revalidating |= revalidate;
+        let object = entry.object.clone();
+        entries.touch(key);
+        Some(Hit { object, revalidate })
+    }
+
+    /// The fresh cached object for `key`, without counting a lookup.
+    pub(crate) fn peek(&self, key: &str) -> Option<Arc<CachedObject>> {
+        let now = Instant::now();
+        #[cfg(feature = "moka")]
+        if let Some(moka) = &self.moka {
+            return moka.get(key).filter(|entry| entry.expires > now).map(|entry| entry.object);
+        }
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.map.get(key).filter(|entry| entry.expires > now).map(|entry| entry.object.clone())
+    }
+
+    /// The stale entry for `key` is unchanged in S3; serve it for another TTL.
+    pub(crate) fn extend(&self, key: &str) {
+        #[cfg(feature = "moka")]
+        if let Some(moka) = &self.moka {
+            if let Some(entry) = moka.get(key) {
+                let expires = Instant::now() + self.ttl(&entry.object);
+                moka.insert(key.to_owned(), MokaEntry::new(entry.object, expires));
+            }
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(entry) = entries.map.get_mut(key) {
+            entry.expires = Instant::now() + self.ttl(&entry.object);
+            entry.revalidating = false;
+        }
+    }
+
+    /// Revalidating `key` failed; the next request for it tries again.
+    pub(crate) fn revalidation_failed(&self, key: &str) {
+        #[cfg(feature = "moka")]
+        if let Some(moka) = &self.moka {
+            if let Some(entry) = moka.get(key) {
+                entry.revalidation_failed();
+            }
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(entry) = entries.map.get_mut(key) {
+            entry.revalidating = false;
+        }
+    }
+
+    pub(crate) fn invalidate(&self, key: &str) -> bool {
+        #[cfg(feature = "moka")]
+        if let Some(moka) = &self.moka {
+            return moka.remove(key).is_some();
+        }
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner).remove(key)
+    }
+
+    /// Evict all entries whose key starts with `prefix`, returning how many were evicted.
+    pub(crate) fn invalidate_prefix(&self, prefix: &str) -> usize {
+        #[cfg(feature = "moka")]
+        if let Some(moka) = &self.moka {
+            let keys: Vec<_> = moka.iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .map(|(key, _)| key)
+                .collect();
+            return keys.iter().filter(|key| moka.remove(key.as_str()).is_some()).count();
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let keys: Vec<_> = entries.map.keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        for key in &keys {
+            entries.remove(key);
+        }
+        keys.len()
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        #[cfg(feature = "moka")]
+        if let Some(moka) = &self.moka {
+            moka.run_pending_tasks();
+            return CacheStats {
+                capacity: self.capacity,
+                size: usize::try_from(moka.weighted_size()).unwrap_or(usize::MAX),
+                entries: usize::try_from(moka.entry_count()).unwrap_or(usize::MAX),
+                hits: self.hits.load(Ordering::Relaxed),
+                misses: self.misses.load(Ordering::Relaxed),
+            };
+        }
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        CacheStats {
+            capacity: self.capacity,
+            size: entries.size,
+            entries: entries.map.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Cache `object` for `key`, evicting the least recently used entries to make room.
+    pub(crate) fn insert(&self, key: String, object: CachedObject) -> Arc<CachedObject> {
+        let ttl = self.ttl(&object);
+        self.insert_for(key, object, ttl)
+    }
+
+    /// Cache `object` for `key` for `ttl`.
+    pub(crate) fn insert_for(&self, key: String, mut object: CachedObject, ttl: Duration) -> Arc<CachedObject> {
+        #[cfg(feature = "moka")]
+        if let Some(moka) = &self.moka {
+            let object = Arc::new(object);
+            let expires = Instant::now() + ttl;
+            moka.insert(key, MokaEntry::new(object.clone(), expires));
+            return object;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.remove(&key);
+
+        // A shared body is already accounted, and cannot be evicted while this entry holds it
+        let shared = entries.share(&mut object);
+        let object = Arc::new(object);
+        let weight = key.len() + if shared { 0 } else { object.body.len() };
+        while entries.size + weight > self.capacity {
+            let Some((_, oldest)) = entries.lru.pop_first() else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        if entries.size + weight <= self.capacity {
+            entries.tick += 1;
+            let last_used = entries.tick;
+            let entry = Entry { object: object.clone(), expires: Instant::now() + ttl, last_used, revalidating: false, shared };
+            entries.map.insert(key.clone(), entry);
+            entries.lru.insert(last_used, key);
+            entries.size += weight;
+        } else if shared {
+            entries.release(&object, true);
+        }
+        object
+    }
+
+    /// The number of cached objects, including expired ones not yet evicted.
+    pub(crate) fn len(&self) -> usize {
+        #[cfg(feature = "moka")]
+        if let Some(moka) = &self.moka {
+            moka.run_pending_tasks();
+            return usize::try_from(moka.entry_count()).unwrap_or(usize::MAX);
+        }
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner).map.len()
+    }
+}
+
+impl fmt::Debug for MemoryCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryCache")
+            .field("capacity", &self.capacity)
+            .field("ttl", &self.ttl)
+            .field("stale", &self.stale)
+            .field("store", &if self.is_moka() { "moka" } else { "lru" })
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+
+/// How long objects are cached, see [`cache_ttl`](crate::S3OriginBuilder::cache_ttl) and
+/// [`cache_ttl_from_metadata`](crate::S3OriginBuilder::cache_ttl_from_metadata).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct TtlPolicy {
+    pub(crate) ttl: Duration,
+    /// Bounds for TTLs derived from object metadata, if enabled.
+    pub(crate) bounds: Option<(Duration, Duration)>,
+}
+
+impl TtlPolicy {
+    pub(crate) fn ttl(&self, metadata: &ObjectMetadata) -> Duration {
+        let Some((min, max)) = self.bounds else {
+            return self.ttl;
+        };
+        metadata_ttl(metadata.cache_control.as_deref(), metadata.expires.as_deref(), SystemTime::now())
+            .unwrap_or(self.ttl)
+            .clamp(min, max.max(min))
+    }
+}
+
+
+/// The freshness lifetime given by `Cache-Control` or `Expires` metadata.
+/// 
+/// `no-store` and `no-cache` give zero; `s-maxage` takes precedence over `max-age` as for
+/// shared caches, and both over `Expires`.
+fn metadata_ttl(cache_control: Option<&str>, expires: Option<&str>, now: SystemTime) -> Option<Duration> {
+    let (mut max_age, mut s_maxage) = (None, None);
+    for directive in cache_control.unwrap_or_default().split(',') {
+        let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+        let seconds = value.trim().trim_matches('"').parse().ok().map(Duration::from_secs);
+        match name.trim().to_ascii_lowercase().as_str() {
+            "no-store" | "no-cache" => return Some(Duration::ZERO),
+            "s-maxage" => s_maxage = seconds,
+            "max-age" => max_age = seconds,
+            _ => {}
+        }
+    }
+    if let Some(ttl) = s_maxage.or(max_age) {
+        return Some(ttl);
+    }
+    let expires = DateTime::from_str(expires?.trim(), DateTimeFormat::HttpDate).ok()?;
+    let expires = SystemTime::try_from(expires).ok()?;
+    Some(expires.duration_since(now).unwrap_or_default())
+}
+
+
+/// Statistics of an origin's cache, see [`S3Origin::cache_stats`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The configured capacity, in bytes.
+    pub capacity: usize,
+    /// The bytes currently cached.
+    pub size: usize,
+    /// The number of cached objects, including expired ones not yet evicted.
+    pub entries: usize,
+    /// Lookups answered from the cache, including stale entries.
+    pub hits: u64,
+    /// Lookups of keys that were not cached, or expired.
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// The share of lookups answered from the cache, if there were any.
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let lookups = self.hits + self.misses;
+        (lookups > 0).then(|| self.hits as f64 / lookups as f64)
+    }
+}
+
+
+/// Revalidate a stale entry in the background with a conditional GET on its ETag.
+/// 
+/// An unchanged object (`304 Not Modified`) is served for another TTL, a changed one replaces
+/// the entry and a deleted one is evicted.  After other errors the stale entry stays until the
+/// next request retries or the staleness window ends.
+pub(crate) fn revalidate(origin: &S3OriginInner, cache: Arc<MemoryCache>, key: String, cache_key: String, etag: Option<String>) {
+    if let Some(backend) = origin.backend.clone() {
+        // Backends take no conditions, so the object is fetched again
+        tokio::spawn(async move {
+            let output = backend.get(&key, None).await;
+            refresh(&cache, cache_key, output).await;
+        });
+        return;
+    }
+
+    let request = origin.s3_client.get_object()
+        .bucket(&origin.bucket)
+        .key(&key)
+        .set_if_none_match(etag);
+    tokio::spawn(cost::scope(Some(origin.cost.clone()), async move {
+        match request.send().await {
+            Err(error) if error.raw_response().is_some_and(|raw| raw.status().as_u16() == 304) => cache.extend(&cache_key),
+            output => refresh(&cache, cache_key, output.map_err(S3Error::from)).await,
+        }
+    }));
+}
+
+
+/// Replace or evict a revalidated entry.
+async fn refresh(cache: &MemoryCache, cache_key: String, output: Result<GetObjectOutput, S3Error>) {
+    match output {
+        Ok(output) if cache.admits(output.content_length()) => match CachedObject::collect(output).await {
+            Ok(object) => {
+                cache.insert(cache_key, object);
+            }
+            Err(_) => cache.revalidation_failed(&cache_key),
+        },
+        // Grew too large to cache
+        Ok(_) => {
+            cache.invalidate(&cache_key);
+        }
+        Err(S3Error::NotFound) => {
+            cache.invalidate(&cache_key);
+        }
+        Err(_) => cache.revalidation_failed(&cache_key),
+    }
+}
+
+
+impl S3Origin {
+    /// Statistics of the cache, if [`cache`](crate::S3OriginBuilder::cache) is enabled.
+    ///
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Fetch objects into the cache ahead of the first request, e.g. at (Lambda) cold start.
+    ///
+    /// Keys are relative to the bucket prefix, like request paths (`index.html`,
+    /// `assets/main.js`).  Objects are fetched one after another; objects too large for the
+    /// cache are skipped.  Does nothing unless [`cache`](crate::S3OriginBuilder::cache) is
+    /// enabled.
+    ///
+    /// Returns the keys that could not be fetched.
+    ///
+    pub async fn prefetch(&self, keys: &[&str]) -> Result<(), Vec<(String, S3Error)>> {
+        let inner = &self.inner;
+        let Some(cache) = &inner.cache else {
+            return Ok(());
+        };
+
+        let mut failed = Vec::new();
+        for key in keys {
+            let full_key = format!("{}{}", inner.bucket_prefix(), key.trim_start_matches('/'));
+            let output = match &inner.backend {
+                Some(backend) => backend.get(&full_key, None).await,
+                None => inner.s3_client.get_object()
+                    .bucket(&inner.bucket)
+                    .key(&full_key)
+                    .send()
+                    .await
+                    .map_err(S3Error::from),
+            };
+            let result = match output {
+                Ok(output) if cache.admits(output.content_length()) => CachedObject::collect(output).await
+                    .map(|object| {
+                        cache.insert(full_key, object);
+                    }),
+                Ok(_) => Ok(()),
+                Err(error) => Err(error),
+            };
+            if let Err(error) = result {
+                failed.push((key.to_string(), error));
+            }
+        }
+
+        match failed.is_empty() {
+            true => Ok(()),
+            false => Err(failed),
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    fn object(body: &'static [u8]) -> CachedObject {
+        CachedObject { metadata: ObjectMetadata::default(), etag: None, body: Bytes::from_static(body) }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = MemoryCache::new(20, DEFAULT_CACHE_TTL, Duration::ZERO);
+        cache.insert("a".into(), object(b"1234567"));
+        cache.insert("b".into(), object(b"1234567"));
+        assert!(cache.get("a").is_some());
+
+        cache.insert("c".into(), object(b"1234567"));
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+        assert_eq!(cache.len(), 2);
+
+        // Replacing an entry frees its old size
+        cache.insert("c".into(), object(b"12"));
+        cache.insert("d".into(), object(b"12"));
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn shares_bodies_by_etag() {
+        let cache = MemoryCache::new(1024, DEFAULT_CACHE_TTL, Duration::ZERO);
+        let tagged = |body: &'static [u8]| CachedObject { etag: Some("\"abc\"".into()), ..object(body) };
+        let first = cache.insert("en/app.js".into(), tagged(b"0123456789"));
+        let second = cache.insert("de/app.js".into(), tagged(b"0123456789"));
+        assert_eq!(first.body.as_ptr(), second.body.as_ptr());
+        assert_eq!(cache.stats().size, 9 + 9 + 10);
+
+        // Dropping one alias keeps the body for the other
+        cache.invalidate("en/app.js");
+        assert_eq!(cache.stats().size, 9 + 10);
+        cache.invalidate("de/app.js");
+        assert_eq!(cache.stats().size, 0);
+
+        // The same ETag for a different length is kept apart
+        cache.insert("a".into(), tagged(b"1"));
+        cache.insert("b".into(), tagged(b"12"));
+        assert_eq!(cache.stats().size, 1 + 1 + 1 + 2);
+        cache.invalidate("a");
+        cache.invalidate("b");
+        assert_eq!(cache.stats().size, 0);
+    }
+
+    #[test]
+    fn derives_ttls_from_metadata() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777 - 120);
+        assert_eq!(metadata_ttl(Some("public, max-age=300"), None, now), Some(Duration::from_secs(300)));
+        assert_eq!(metadata_ttl(Some("max-age=300, s-maxage=\"60\""), None, now), Some(Duration::from_secs(60)));
+        assert_eq!(metadata_ttl(Some("no-cache"), None, now), Some(Duration::ZERO));
+        assert_eq!(metadata_ttl(Some("public"), Some("Sun, 06 Nov 1994 08:49:37 GMT"), now), Some(Duration::from_secs(120)));
+        assert_eq!(metadata_ttl(None, Some("Sun, 06 Nov 1994 08:45:37 GMT"), now), Some(Duration::ZERO));
+        assert_eq!(metadata_ttl(None, Some("soon"), now), None);
+
+        let cache = MemoryCache::new(1024, DEFAULT_CACHE_TTL, Duration::ZERO)
+            .ttl_from_metadata(Some((Duration::from_secs(10), Duration::from_secs(3600))));
+        let with_cache_control = |cache_control: &str| CachedObject {
+            metadata: ObjectMetadata { cache_control: Some(cache_control.into()), ..ObjectMetadata::default() },
+            ..object(b"1")
+        };
+        assert_eq!(cache.ttl(&with_cache_control("max-age=31536000")), Duration::from_secs(3600));
+        assert_eq!(cache.ttl(&with_cache_control("no-store")), Duration::from_secs(10));
+        assert_eq!(cache.ttl(&object(b"1")), DEFAULT_CACHE_TTL);
+    }
+
+    #[cfg(feature = "moka")]
+    #[test]
+    fn stores_entries_in_moka() {
+        let cache = MemoryCache::new(1024, Duration::ZERO, Duration::from_secs(60)).with_moka(true);
+        cache.insert("site/a".into(), object(b"1"));
+        cache.insert("site/b".into(), object(b"1"));
+        assert!(cache.get("site/a").unwrap().revalidate);
+        assert!(!cache.get("site/a").unwrap().revalidate);
+        cache.revalidation_failed("site/a");
+        assert!(cache.get("site/a").unwrap().revalidate);
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.size, stats.hits), (2, 14, 3));
+        assert_eq!(cache.invalidate_prefix("site/"), 2);
+        assert!(cache.get("site/b").is_none());
+        assert_eq!(cache.len(), 0);
+        assert!(!cache.moka().unwrap().contains_key("site/a"));
+    }
+
+    #[test]
+    fn counts_hits_and_invalidates_prefixes() {
+        let cache = MemoryCache::new(1024, DEFAULT_CACHE_TTL, Duration::ZERO);
+        cache.insert("site/a".into(), object(b"1"));
+        cache.insert("site/b".into(), object(b"1"));
+        cache.insert("other/c".into(), object(b"1"));
+        assert!(cache.get("site/a").is_some());
+        assert!(cache.get("site/x").is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.size, stats.hits, stats.misses), (3, 22, 1, 1));
+        assert_eq!(stats.hit_ratio(), Some(0.5));
+
+        assert_eq!(cache.invalidate_prefix("site/"), 2);
+        assert!(!cache.invalidate("site/a"));
+        assert_eq!(cache.stats().size, 8);
+    }
+
+    #[test]
+    fn expires_entries() {
+        let cache = MemoryCache::new(1024, Duration::ZERO, Duration::ZERO);
+        cache.insert("a".into(), object(b"1"));
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn serves_stale_entries_while_revalidating() {
+        let cache = MemoryCache::new(1024, Duration::ZERO, Duration::from_secs(60));
+        cache.insert("a".into(), object(b"1"));
+        assert!(cache.get("a").unwrap().revalidate);
+        assert!(!cache.get("a").unwrap().revalidate);
+
+        cache.revalidation_failed("a");
+        assert!(cache.get("a").unwrap().revalidate);
+
+        let cache = MemoryCache::new(1024, DEFAULT_CACHE_TTL, Duration::from_secs(60));
+        cache.insert("a".into(), object(b"1"));
+        assert!(!cache.get("a").unwrap().revalidate);
+    }
+
+    #[test]
+    fn admits_small_objects() {
+        let cache = MemoryCache::new(800, DEFAULT_CACHE_TTL, Duration::ZERO);
+        assert!(cache.admits(Some(100)));
+        assert!(!cache.admits(Some(101)));
+        assert!(!cache.admits(None));
+    }
+
+    #[test]
+    fn compares_versions() {
+        let tagged = CachedObject { etag: Some("\"1\"".into()), ..object(b"1") };
+        assert!(tagged.is_version(None, Some("\"1\"")));
+        assert!(!tagged.is_version(None, Some("\"2\"")));
+        assert!(!tagged.is_version(None, None));
+
+        let mut versioned = CachedObject { etag: Some("\"1\"".into()), ..object(b"1") };
+        versioned.metadata.amz_headers.push(("x-amz-version-id".into(), "v1".into()));
+        assert!(versioned.is_version(Some("v1"), Some("\"2\"")));
+        assert!(!versioned.is_version(Some("v2"), Some("\"1\"")));
+        assert!(versioned.is_version(None, Some("\"1\"")));
+    }
+}
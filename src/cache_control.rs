@@ -0,0 +1,190 @@
+use axum::http::HeaderValue;
+use globset::GlobMatcher;
+
+use crate::pattern::{compile_glob, file_name};
+use crate::rules::Source;
+
+/// `Cache-Control` value for content-hashed (fingerprinted) assets.
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// `Cache-Control` value for HTML documents when immutable-asset detection is enabled.
+pub const NO_CACHE: &str = "no-cache";
+
+/// Minimum number of characters in a filename segment before it is considered a content hash.
+const MIN_HASH_LEN: usize = 6;
+
+
+/// A single `Cache-Control` rule: a glob pattern and the header value to emit when it matches.
+#[derive(Clone, Debug)]
+pub(crate) struct CacheControlRule {
+    matcher: GlobMatcher,
+    value: HeaderValue,
+}
+
+impl CacheControlRule {
+    /// Compile a rule.
+    ///
+    /// Patterns without a `/` are matched against the file name only; patterns with a `/`
+    /// are matched against the whole request path (relative to the bucket prefix).
+    pub(crate) fn new(pattern: &str, value: &str) -> Result<Self, &'static str> {
+        let matcher = compile_glob(pattern).map_err(|_| "invalid cache_control pattern")?;
+        let value = HeaderValue::from_str(value).map_err(|_| "invalid cache_control value")?;
+        Ok(Self { matcher, value })
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        self.matcher.is_match(path)
+    }
+}
+
+
+/// The `Cache-Control` policy of an origin.
+///
+/// Resolution order is:
+/// 1. The first configured rule matching the path.
+/// 2. Immutable-asset detection (if enabled).
+/// 3. The `Cache-Control` metadata stored on the S3 object (if any).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CacheControlPolicy {
+    pub(crate) rules: Vec<CacheControlRule>,
+    pub(crate) immutable_assets: bool,
+}
+
+impl CacheControlPolicy {
+    /// Resolve the `Cache-Control` header value for a path.
+    ///
+    /// `path` is the request path relative to the bucket prefix, `content_type` and `s3_value`
+    /// are taken from the S3 object.
+    pub(crate) fn resolve(&self, path: &str, content_type: Option<&str>, s3_value: Option<&str>) -> Option<(HeaderValue, Source)> {
+        if let Some(index) = self.matching_rule(path) {
+            return Some((self.rules[index].value.clone(), Source::Rule));
+        }
+
+        if self.immutable_assets {
+            if is_html(path, content_type) {
+                return Some((HeaderValue::from_static(NO_CACHE), Source::Detected));
+            }
+            if is_content_hashed(file_name(path)) {
+                return Some((HeaderValue::from_static(IMMUTABLE), Source::Detected));
+            }
+        }
+
+        s3_value
+            .and_then(|value| HeaderValue::from_str(value).ok())
+            .map(|value| (value, Source::Object))
+    }
+}
+
+
+impl CacheControlPolicy {
+    /// The index of the first rule matching the path.
+    pub(crate) fn matching_rule(&self, path: &str) -> Option<usize> {
+        self.rules.iter().position(|rule| rule.matches(path))
+    }
+
+    /// The value immutable-asset detection assigns from the path alone, if enabled.
+    pub(crate) fn detected(&self, path: &str) -> Option<&'static str> {
+        if !self.immutable_assets {
+            None
+        } else if is_html(path, None) {
+            Some(NO_CACHE)
+        } else if is_content_hashed(file_name(path)) {
+            Some(IMMUTABLE)
+        } else {
+            None
+        }
+    }
+}
+
+
+fn is_html(path: &str, content_type: Option<&str>) -> bool {
+    if let Some(content_type) = content_type {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        if mime.eq_ignore_ascii_case("text/html") {
+            return true;
+        }
+    }
+    let name = file_name(path).to_ascii_lowercase();
+    name.ends_with(".html") || name.ends_with(".htm")
+}
+
+
+/// Detects fingerprinted file names such as `app.3f9ab2.js` or `index-B7x2kQ9a.js`.
+///
+/// A file name is considered content-hashed when a segment between the stem and the
+/// extension (separated by `.`, `-` or `_`) is either hexadecimal of at least 6 characters,
+/// or an alphanumeric token of at least 8 characters that mixes letters and digits.
+pub(crate) fn is_content_hashed(file_name: &str) -> bool {
+    // Strip the extension; a name without an extension is never considered hashed.
+    let Some((stem, _extension)) = file_name.rsplit_once('.') else {
+        return false;
+    };
+
+    // The first segment is the original file stem, so only later segments are candidates.
+    stem.split(['.', '-', '_'])
+        .skip(1)
+        .any(is_hash_segment)
+}
+
+
+fn is_hash_segment(segment: &str) -> bool {
+    if segment.len() < MIN_HASH_LEN || !segment.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return false;
+    }
+
+    let has_digit = segment.chars().any(|c| c.is_ascii_digit());
+    if segment.chars().all(|c| c.is_ascii_hexdigit()) {
+        return has_digit || segment.len() >= 8;
+    }
+
+    let has_alpha = segment.chars().any(|c| c.is_ascii_alphabetic());
+    segment.len() >= 8 && has_digit && has_alpha
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_content_hashed_names() {
+        assert!(is_content_hashed("app.3f9ab2.js"));
+        assert!(is_content_hashed("main.3f9ab2c1d4e5.css"));
+        assert!(is_content_hashed("index-B7x2kQ9a.js"));
+        assert!(is_content_hashed("chunk-vendors.0a1b2c3d.js"));
+
+        assert!(!is_content_hashed("app.js"));
+        assert!(!is_content_hashed("index.html"));
+        assert!(!is_content_hashed("jquery.min.js"));
+        assert!(!is_content_hashed("my-component.js"));
+        assert!(!is_content_hashed("3f9ab2c1"));
+        assert!(!is_content_hashed("background-image.png"));
+    }
+
+    #[test]
+    fn rules_take_precedence() {
+        let policy = CacheControlPolicy {
+            rules: vec![CacheControlRule::new("*.js", "max-age=60").unwrap()],
+            immutable_assets: true,
+        };
+
+        assert_eq!(policy.resolve("assets/app.3f9ab2.js", None, None).unwrap().0, "max-age=60");
+        assert_eq!(policy.resolve("assets/app.3f9ab2.css", None, None).unwrap().0, IMMUTABLE);
+        assert_eq!(policy.resolve("index.html", None, None).unwrap().0, NO_CACHE);
+        assert_eq!(policy.resolve("docs/", Some("text/html; charset=utf-8"), None).unwrap().0, NO_CACHE);
+        assert_eq!(policy.resolve("logo.png", None, Some("max-age=5")).unwrap().0, "max-age=5");
+        assert!(policy.resolve("logo.png", None, None).is_none());
+    }
+
+    #[test]
+    fn patterns_with_directories_match_full_path() {
+        let policy = CacheControlPolicy {
+            rules: vec![CacheControlRule::new("/fonts/**", "public, max-age=86400").unwrap()],
+            immutable_assets: false,
+        };
+
+        assert!(policy.resolve("fonts/inter/regular.woff2", None, None).is_some());
+        assert!(policy.resolve("assets/fonts/regular.woff2", None, None).is_none());
+    }
+}
@@ -0,0 +1,138 @@
+//! Weighted routing between the stable prefix and a canary prefix.
+//!
+//! With a [`Canary`] configured, a percentage of requests is served from the canary prefix and
+//! the rest from the bucket prefix, behind the same URLs.  Without stickiness every request
+//! rolls the dice, so a page and its assets may come from different builds; a
+//! [`sticky`](Canary::sticky) canary assigns each client once and remembers the assignment in a
+//! cookie.  Responses of a sticky canary depend on the cookie and must not be stored by shared
+//! caches under the URL alone.
+//!
+//! The split can be changed, or the canary removed, at runtime with [`S3Origin::set_canary`].
+use std::{sync::PoisonError, time::Duration};
+
+use axum::http::{header, HeaderMap, HeaderValue};
+
+use crate::S3Origin;
+
+
+const CANARY: &str = "canary";
+const STABLE: &str = "stable";
+
+
+/// A canary prefix and the share of requests it serves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Canary {
+    prefix: String,
+    percent: u8,
+    cookie: Option<(String, Duration)>,
+}
+
+impl Canary {
+    /// Serve `percent` of requests (at most 100) from `prefix`.
+    pub fn new(prefix: impl Into<String>, percent: u8) -> Self {
+        Self { prefix: prefix.into(), percent: percent.min(100), cookie: None }
+    }
+
+    /// Remember each client's assignment in the cookie `name` for `max_age`.
+    ///
+    /// Clients presenting the cookie keep their build even when the split changes; a cookie of
+    /// a removed canary is ignored.
+    pub fn sticky(mut self, name: impl Into<String>, max_age: Duration) -> Self {
+        self.cookie = Some((name.into(), max_age));
+        self
+    }
+
+    /// The canary prefix.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The percentage of requests served from the canary prefix.
+    pub fn percent(&self) -> u8 {
+        self.percent
+    }
+
+    /// Whether assignments are remembered in a cookie, which responses then depend on.
+    pub(crate) fn is_sticky(&self) -> bool {
+        self.cookie.is_some()
+    }
+
+    /// Whether a request goes to the canary, and the `Set-Cookie` value for a new assignment.
+    pub(crate) fn choose(&self, headers: &HeaderMap) -> (bool, Option<HeaderValue>) {
+        let Some((name, max_age)) = &self.cookie else {
+            return (self.roll(), None);
+        };
+        let assigned = headers.get_all(header::COOKIE).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value);
+        match assigned {
+            Some(CANARY) => (true, None),
+            Some(STABLE) => (false, None),
+            _ => {
+                let canary = self.roll();
+                let value = format!(
+                    "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+                    name,
+                    if canary { CANARY } else { STABLE },
+                    max_age.as_secs(),
+                );
+                (canary, HeaderValue::try_from(value).ok())
+            }
+        }
+    }
+
+    fn roll(&self) -> bool {
+        fastrand::u8(0..100) < self.percent
+    }
+}
+
+
+impl S3Origin {
+    /// The current canary, if any.
+    ///
+    pub fn canary(&self) -> Option<Canary> {
+        self.inner.canary.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Replace the canary, or remove it with `None`; applies to new requests.
+    ///
+    pub fn set_canary(&self, canary: Option<Canary>) {
+        #[cfg(feature = "trace")]
+        tracing::info!("S3Origin: canary set to {:?}", canary);
+        *self.inner.canary.write().unwrap_or_else(PoisonError::into_inner) = canary;
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    fn cookie(value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(header::COOKIE, HeaderValue::from_static(value))])
+    }
+
+    #[test]
+    fn splits_by_percentage() {
+        assert_eq!(Canary::new("canary/", 100).choose(&HeaderMap::new()), (true, None));
+        assert_eq!(Canary::new("canary/", 0).choose(&HeaderMap::new()), (false, None));
+        assert_eq!(Canary::new("canary/", 150).percent(), 100);
+    }
+
+    #[test]
+    fn sticks_to_cookie() {
+        let canary = Canary::new("canary/", 0).sticky("build", Duration::from_secs(3600));
+
+        let (chosen, set_cookie) = canary.choose(&HeaderMap::new());
+        assert!(!chosen);
+        assert_eq!(set_cookie.unwrap(), "build=stable; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax");
+
+        assert_eq!(canary.choose(&cookie("theme=dark; build=canary")), (true, None));
+        assert_eq!(canary.choose(&cookie("build=stable")), (false, None));
+        assert!(canary.choose(&cookie("build=other")).1.is_some());
+    }
+}
@@ -0,0 +1,374 @@
+//! Verifying object bodies against their S3 checksums.
+//!
+//! With [`verify_checksums`](crate::S3OriginBuilder::verify_checksums), whole-object GETs ask S3
+//! for the object's checksum (`x-amz-checksum-mode: ENABLED`) and the body is hashed as it
+//! streams.  Full-object CRC32 and SHA256 checksums are verified; composite checksums of
+//! multipart uploads, ranged responses and objects without a checksum are streamed unverified.
+//!
+//! With [`ChecksumVerification::Fail`] the last chunk is held back until the checksum is known,
+//! so a corrupted download ends in an error instead of completing.  Mismatches are counted in
+//! [`TransferStats::checksum_failures`](crate::TransferStats::checksum_failures).
+//!
+//! With [`checksum_trailer`](crate::S3OriginBuilder::checksum_trailer), clients that send
+//! `TE: trailers` get the checksum of the body they received in a trailer, `x-content-sha256` or
+//! `x-content-crc32` as lowercase hex, computed as the body streams.  Over HTTP/1.1 trailers need
+//! a chunked body, so `Content-Length` is dropped from those responses; HTTP/2 keeps it.
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use aws_sdk_s3::operation::get_object::GetObjectOutput;
+use axum::{
+    body::{Body, BodyDataStream, Bytes},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, Version},
+    response::Response,
+};
+use base64::Engine as _;
+use futures_core::Stream;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use pin_project::pin_project;
+use sha2::Digest as _;
+
+use crate::transfer::TransferCounters;
+
+
+/// What to do when a body does not match its checksum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumVerification {
+    /// Fail the response before its last chunk, so the client sees an incomplete download.
+    Fail,
+    /// Complete the response; the mismatch is only counted (and logged with the `trace` feature).
+    Log,
+}
+
+
+/// The checksum sent in a trailer, see [`checksum_trailer`](crate::S3OriginBuilder::checksum_trailer).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumTrailer {
+    /// SHA-256 in `x-content-sha256`.
+    Sha256,
+    /// CRC32 in `x-content-crc32`.
+    Crc32,
+}
+
+impl ChecksumTrailer {
+    fn name(self) -> HeaderName {
+        match self {
+            ChecksumTrailer::Sha256 => HeaderName::from_static("x-content-sha256"),
+            ChecksumTrailer::Crc32 => HeaderName::from_static("x-content-crc32"),
+        }
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            ChecksumTrailer::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            ChecksumTrailer::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+
+    /// Send the checksum of `response`'s body in a trailer, if `req` accepts trailers.
+    pub(crate) fn apply<B>(self, req: &Request<B>, mut response: Response) -> Response {
+        let accepts = req.headers().get_all(header::TE).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case("trailers"));
+        let served = matches!(response.status(), StatusCode::OK | StatusCode::PARTIAL_CONTENT);
+        if !accepts || !served || req.method() != Method::GET {
+            return response;
+        }
+        let name = self.name();
+        let headers = response.headers_mut();
+        headers.append(header::TRAILER, HeaderValue::from_name(name.clone()));
+        // HTTP/1.1 only sends trailers after a chunked body
+        if req.version() < Version::HTTP_2 {
+            headers.remove(header::CONTENT_LENGTH);
+        }
+        response.map(|body| Body::new(Trailer { body, hasher: Some(self.hasher()), name }))
+    }
+}
+
+
+/// The checksum an object body must match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Expected {
+    Crc32([u8; 4]),
+    Sha256([u8; 32]),
+}
+
+impl Expected {
+    /// The full-object checksum of a whole-object response.
+    pub(crate) fn from_output(output: &GetObjectOutput) -> Option<Self> {
+        if output.content_range().is_some() {
+            return None;
+        }
+        output.checksum_sha256().and_then(full_object)
+            .and_then(|digest| Some(Expected::Sha256(digest.try_into().ok()?)))
+            .or_else(|| {
+                let digest = output.checksum_crc32().and_then(full_object)?;
+                Some(Expected::Crc32(digest.try_into().ok()?))
+            })
+    }
+}
+
+
+/// The digest of a full-object checksum header.
+pub(crate) fn full_object(value: &str) -> Option<Vec<u8>> {
+    // Composite checksums end in `-{parts}` and cover the parts' checksums, not the bytes
+    match value.contains('-') {
+        true => None,
+        false => base64::engine::general_purpose::STANDARD.decode(value).ok(),
+    }
+}
+
+
+enum Hasher {
+    Crc32(crc32fast::Hasher),
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    fn new(expected: &Expected) -> Self {
+        match expected {
+            Expected::Crc32(_) => Hasher::Crc32(crc32fast::Hasher::new()),
+            Expected::Sha256(_) => Hasher::Sha256(sha2::Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Crc32(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    fn matches(self, expected: &Expected) -> bool {
+        match (self, expected) {
+            (Hasher::Crc32(hasher), Expected::Crc32(digest)) => hasher.finalize().to_be_bytes() == *digest,
+            (Hasher::Sha256(hasher), Expected::Sha256(digest)) => hasher.finalize()[..] == digest[..],
+            _ => false,
+        }
+    }
+
+    /// The digest as lowercase hex.
+    fn hex(self) -> String {
+        let digest = match self {
+            Hasher::Crc32(hasher) => hasher.finalize().to_be_bytes().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+        };
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+
+/// Verify `body` against `expected` as it streams.
+pub(crate) fn verify(body: Body, expected: Expected, mode: ChecksumVerification, key: &str, counters: Arc<TransferCounters>) -> Body {
+    Body::from_stream(Verify {
+        body: body.into_data_stream(),
+        hasher: Some(Hasher::new(&expected)),
+        expected,
+        mode,
+        held: None,
+        key: key.into(),
+        counters,
+    })
+}
+
+
+struct Verify {
+    body: BodyDataStream,
+    /// Taken once the body ends.
+    hasher: Option<Hasher>,
+    expected: Expected,
+    mode: ChecksumVerification,
+    /// The latest chunk, held back until the next one arrives ([`ChecksumVerification::Fail`]).
+    held: Option<Bytes>,
+    key: Box<str>,
+    counters: Arc<TransferCounters>,
+}
+
+impl Stream for Verify {
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let Some(hasher) = &mut this.hasher else {
+                return Poll::Ready(this.held.take().map(Ok));
+            };
+            match Pin::new(&mut this.body).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    hasher.update(&chunk);
+                    if this.mode == ChecksumVerification::Log {
+                        return Poll::Ready(Some(Ok(chunk)));
+                    }
+                    if let Some(held) = this.held.replace(chunk) {
+                        return Poll::Ready(Some(Ok(held)));
+                    }
+                }
+                Poll::Ready(Some(Err(error))) => {
+                    this.hasher = None;
+                    this.held = None;
+                    return Poll::Ready(Some(Err(error)));
+                }
+                Poll::Ready(None) => {
+                    let Some(hasher) = this.hasher.take() else {
+                        continue;
+                    };
+                    if hasher.matches(&this.expected) {
+                        continue;
+                    }
+                    #[cfg(feature = "trace")]
+                    tracing::warn!("S3Origin: checksum mismatch for {}", this.key);
+                    this.counters.checksum_failed();
+                    if this.mode == ChecksumVerification::Fail {
+                        this.held = None;
+                        return Poll::Ready(Some(Err(axum::Error::new(format!("checksum mismatch for {}", this.key)))));
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+
+/// A body followed by a trailer with its checksum.
+#[pin_project]
+struct Trailer {
+    #[pin]
+    body: Body,
+    /// Taken once the body ends, or dropped on errors.
+    hasher: Option<Hasher>,
+    name: HeaderName,
+}
+
+impl HttpBody for Trailer {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let mut this = self.project();
+        loop {
+            let Some(hasher) = this.hasher.as_mut() else {
+                return Poll::Ready(None);
+            };
+            match this.body.as_mut().poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => {
+                    // Trailers of the inner body are replaced by the checksum
+                    if let Some(data) = frame.data_ref() {
+                        hasher.update(data);
+                        return Poll::Ready(Some(Ok(frame)));
+                    }
+                }
+                Poll::Ready(Some(Err(error))) => {
+                    *this.hasher = None;
+                    return Poll::Ready(Some(Err(error)));
+                }
+                Poll::Ready(None) => {
+                    let mut trailers = HeaderMap::new();
+                    let hex = this.hasher.take().map(Hasher::hex).unwrap_or_default();
+                    if let Ok(value) = HeaderValue::try_from(hex) {
+                        trailers.insert(this.name.clone(), value);
+                    }
+                    return Poll::Ready(Some(Ok(Frame::trailers(trailers))));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.hasher.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        // An exact hint would turn into a `Content-Length`, and drop the trailers over HTTP/1.1
+        let mut hint = SizeHint::new();
+        hint.set_lower(self.body.size_hint().lower());
+        hint
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    fn output(crc32: Option<&str>, sha256: Option<&str>) -> GetObjectOutput {
+        GetObjectOutput::builder().set_checksum_crc32(crc32.map(Into::into)).set_checksum_sha256(sha256.map(Into::into)).build()
+    }
+
+    #[test]
+    fn reads_full_object_checksums() {
+        assert_eq!(Expected::from_output(&output(Some("DUoRhQ=="), None)), Some(Expected::Crc32([0x0d, 0x4a, 0x11, 0x85])));
+        let sha256 = "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=";
+        assert!(matches!(Expected::from_output(&output(Some("DUoRhQ=="), Some(sha256))), Some(Expected::Sha256(_))));
+        assert_eq!(Expected::from_output(&output(Some("DUoRhQ==-3"), None)), None);
+        assert_eq!(Expected::from_output(&output(None, None)), None);
+    }
+
+    #[tokio::test]
+    async fn fails_mismatching_bodies() {
+        let counters = Arc::new(TransferCounters::default());
+        let expected = Expected::from_output(&output(Some("DUoRhQ=="), None)).unwrap();
+
+        let body = verify(Body::from("hello world"), expected.clone(), ChecksumVerification::Fail, "ok", counters.clone());
+        assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), "hello world");
+
+        let body = verify(Body::from("hello w0rld"), expected.clone(), ChecksumVerification::Fail, "bad", counters.clone());
+        assert!(axum::body::to_bytes(body, usize::MAX).await.is_err());
+
+        let body = verify(Body::from("hello w0rld"), expected, ChecksumVerification::Log, "bad", counters.clone());
+        assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), "hello w0rld");
+        assert_eq!(counters.stats().checksum_failures, 2);
+    }
+
+    /// The body and trailers of `response`.
+    async fn frames(response: Response) -> (Vec<u8>, Option<HeaderMap>) {
+        let mut body = std::pin::pin!(response.into_body());
+        let (mut data, mut trailers) = (Vec::new(), None);
+        while let Some(frame) = std::future::poll_fn(|cx| body.as_mut().poll_frame(cx)).await {
+            match frame.unwrap().into_data() {
+                Ok(chunk) => data.extend_from_slice(&chunk),
+                Err(frame) => trailers = frame.into_trailers().ok(),
+            }
+        }
+        (data, trailers)
+    }
+
+    #[tokio::test]
+    async fn sends_checksum_trailers() {
+        let request = |te: Option<&str>, version| {
+            let mut request = Request::builder().version(version);
+            if let Some(te) = te {
+                request = request.header(header::TE, te);
+            }
+            request.body(()).unwrap()
+        };
+        let ok = || {
+            let mut response = Response::new(Body::from("hello world"));
+            response.headers_mut().insert(header::CONTENT_LENGTH, HeaderValue::from(11));
+            response
+        };
+
+        let response = ChecksumTrailer::Crc32.apply(&request(Some("gzip, trailers"), Version::HTTP_11), ok());
+        assert_eq!(response.headers()[header::TRAILER], "x-content-crc32");
+        assert!(response.headers().get(header::CONTENT_LENGTH).is_none());
+        assert!(response.body().size_hint().exact().is_none());
+        let (data, trailers) = frames(response).await;
+        assert_eq!(data, b"hello world");
+        assert_eq!(trailers.unwrap()["x-content-crc32"], "0d4a1185");
+
+        let response = ChecksumTrailer::Sha256.apply(&request(Some("trailers"), Version::HTTP_2), ok());
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "11");
+        let (_, trailers) = frames(response).await;
+        assert_eq!(trailers.unwrap()["x-content-sha256"], "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
+
+        let response = ChecksumTrailer::Sha256.apply(&request(None, Version::HTTP_11), ok());
+        assert!(response.headers().get(header::TRAILER).is_none());
+        assert_eq!(frames(response).await.1, None);
+    }
+}
@@ -0,0 +1,128 @@
+use axum::{
+    extract::OriginalUri,
+    http::{header, HeaderValue, Request, StatusCode},
+    response::Response,
+};
+
+use crate::telemetry::Feature;
+
+
+/// Trailing-slash handling in clean-URL mode.
+///
+/// See [`S3OriginBuilder::clean_urls`](crate::S3OriginBuilder::clean_urls).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Serve `/about` and `/about/` alike, without redirecting (default).
+    #[default]
+    Ignore,
+    /// Redirect `/about` to `/about/` when it resolves to `about/index.html`.
+    Add,
+    /// Redirect `/about/` to `/about`.
+    Remove,
+}
+
+
+/// A key to try when resolving a clean URL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Candidate {
+    pub(crate) key: String,
+    /// The key is a directory index reached without a trailing slash.
+    pub(crate) directory_index: bool,
+    /// The feature that produced this candidate, recorded when it is served.
+    pub(crate) feature: Option<Feature>,
+}
+
+
+/// The keys to try, in order, for a key derived from a request path.
+///
+/// `{key}`, `{key}.html` (only if the file name has no extension), `{key}/index.html`.  A key
+/// that is empty or ends in `/` resolves to `{key}index.html` only.
+pub(crate) fn candidates(key: &str) -> Vec<Candidate> {
+    let candidate = |key: String, directory_index: bool, feature: Option<Feature>| Candidate { key, directory_index, feature };
+
+    if key.is_empty() || key.ends_with('/') {
+        return vec![candidate(format!("{}index.html", key), false, None)];
+    }
+
+    let mut candidates = vec![candidate(key.to_string(), false, None)];
+    if !crate::pattern::file_name(key).contains('.') {
+        candidates.push(candidate(format!("{}.html", key), false, Some(Feature::CleanUrls)));
+    }
+    candidates.push(candidate(format!("{}/index.html", key), true, Some(Feature::CleanUrls)));
+    candidates
+}
+
+
+/// A `301` redirect to the request URI with a trailing slash added or removed.
+///
+/// The location is built from the [`OriginalUri`] if there is one, since under
+/// `Router::nest_service` the request URI lacks the mount prefix.  Returns `None` if the URI is
+/// the root path or already has the requested form.
+pub(crate) fn slash_redirect<B>(req: &Request<B>, add: bool) -> Option<Response> {
+    let uri = req.extensions().get::<OriginalUri>().map_or(req.uri(), |original| &original.0);
+    // `//host/` and `/\host/` would be links to another host
+    let path = format!("/{}", uri.path().trim_start_matches(['/', '\\']));
+    let location = match add {
+        true if !path.ends_with('/') => format!("{}/", path),
+        false if path.ends_with('/') && path != "/" => path.trim_end_matches('/').to_string(),
+        _ => return None,
+    };
+    let location = match uri.query() {
+        Some(query) => format!("{}?{}", location, query),
+        None => location,
+    };
+
+    let mut response = Response::new(axum::body::Body::empty());
+    *response.status_mut() = StatusCode::MOVED_PERMANENTLY;
+    response.headers_mut().insert(header::LOCATION, HeaderValue::from_str(&location).ok()?);
+    Some(response)
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    fn keys(key: &str) -> Vec<String> {
+        candidates(key).into_iter().map(|candidate| candidate.key).collect()
+    }
+
+    #[test]
+    fn resolution_order() {
+        assert_eq!(keys("site/about"), ["site/about", "site/about.html", "site/about/index.html"]);
+        assert_eq!(keys("site/app.js"), ["site/app.js", "site/app.js/index.html"]);
+        assert_eq!(keys("site/about/"), ["site/about/index.html"]);
+        assert_eq!(keys(""), ["index.html"]);
+        assert!(candidates("about")[2].directory_index);
+    }
+
+    #[test]
+    fn slash_redirects() {
+        let request = |uri: &'static str| Request::get(uri).body(()).unwrap();
+
+        let response = slash_redirect(&request("/docs/about?x=1"), true).unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[header::LOCATION], "/docs/about/?x=1");
+
+        let response = slash_redirect(&request("/docs/about/"), false).unwrap();
+        assert_eq!(response.headers()[header::LOCATION], "/docs/about");
+
+        // Repeated leading slashes would make the location protocol-relative
+        let response = slash_redirect(&request("//evil.example/"), false).unwrap();
+        assert_eq!(response.headers()[header::LOCATION], "/evil.example");
+        let response = slash_redirect(&request("/\\evil.example/"), false).unwrap();
+        assert_eq!(response.headers()[header::LOCATION], "/evil.example");
+        let response = slash_redirect(&request("//evil.example"), true).unwrap();
+        assert_eq!(response.headers()[header::LOCATION], "/evil.example/");
+
+        assert!(slash_redirect(&request("/docs/about/"), true).is_none());
+        assert!(slash_redirect(&request("/"), false).is_none());
+
+        // Nested under `/docs`
+        let mut nested = request("/about");
+        nested.extensions_mut().insert(OriginalUri("/docs/about".parse().unwrap()));
+        let response = slash_redirect(&nested, true).unwrap();
+        assert_eq!(response.headers()[header::LOCATION], "/docs/about/");
+    }
+}
@@ -0,0 +1,188 @@
+//! Identifying clients by address.
+//!
+//! The [`ip_filter`](crate::ip_filter), the [`rate_limit`](crate::rate_limit) and
+//! [`access_events`](crate::access_events) all need the client's address.  By default it is the
+//! peer address from axum's [`ConnectInfo`], so the router must be served with
+//! `into_make_service_with_connect_info::<SocketAddr>()`.  Behind proxies or load balancers the
+//! peer is the nearest proxy, and the client address has to be read from the forwarding header
+//! the proxies append to instead.  [`client_identity`](crate::S3OriginBuilder::client_identity)
+//! configures this once for all of them:
+//!
+//! ```rust
+//! use axum_static_s3::client_identity::{ClientIdentity, ForwardedHeader};
+//!
+//! // CloudFront in front of a load balancer, which both append to `X-Forwarded-For`
+//! let identity = ClientIdentity::new().trusted_proxies(2).header(ForwardedHeader::XForwardedFor);
+//! ```
+//!
+//! Only the configured header is read.  Proxies pass on the other one as the client sent it, so
+//! the client could choose its own address with it.
+//!
+//! IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`) are reported as IPv4, so one client has one
+//! address whichever way it connects.
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{header, HeaderMap, HeaderName, Request},
+};
+
+
+/// Which forwarding header the trusted proxies append to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ForwardedHeader {
+    /// `X-Forwarded-For`, as set by CloudFront, load balancers and most reverse proxies.
+    #[default]
+    XForwardedFor,
+    /// The standard `Forwarded` header (RFC 7239).
+    Forwarded,
+}
+
+
+/// Client identity configuration.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClientIdentity {
+    trusted_proxies: usize,
+    header: ForwardedHeader,
+}
+
+impl ClientIdentity {
+    /// The peer address, without trusting forwarding headers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the client address from the forwarding headers set by this many proxies.
+    ///
+    /// With `1`, the client is the last address in `X-Forwarded-For` (the one the nearest proxy
+    /// saw); with `2` (e.g. CloudFront in front of a load balancer), the one before it.  Only
+    /// enable this if every request passes through the proxies, since clients can send these
+    /// headers themselves.
+    ///
+    /// Defaults to `0`, using the peer address.
+    pub fn trusted_proxies(mut self, hops: usize) -> Self {
+        self.trusted_proxies = hops;
+        self
+    }
+
+    /// Read the client address from this header; defaults to [`ForwardedHeader::XForwardedFor`].
+    pub fn header(mut self, header: ForwardedHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// The client address of `req`, if known.
+    pub fn client_ip<B>(&self, req: &Request<B>) -> Option<IpAddr> {
+        let peer = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip());
+        self.resolve(peer, req.headers())
+    }
+
+    /// The identity with `trusted_proxies` overridden, if given.
+    pub(crate) fn with_trusted_proxies(self, hops: Option<usize>) -> Self {
+        match hops {
+            Some(hops) => self.trusted_proxies(hops),
+            None => self,
+        }
+    }
+
+    /// The client address: the peer, or the one `trusted_proxies` hops back in the forwarding headers.
+    fn resolve(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        if self.trusted_proxies == 0 {
+            return peer.map(|ip| ip.to_canonical());
+        }
+        let forwarded = forwarded_for(headers, self.header);
+        let index = forwarded.len().checked_sub(self.trusted_proxies)?;
+        forwarded.get(index).copied().flatten().map(|ip| ip.to_canonical())
+    }
+}
+
+
+/// The forwarded client addresses, nearest proxy last; `None` for obfuscated or unknown entries.
+fn forwarded_for(headers: &HeaderMap, header: ForwardedHeader) -> Vec<Option<IpAddr>> {
+    let values = |name| headers.get_all(name).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+
+    match header {
+        ForwardedHeader::XForwardedFor => values(HeaderName::from_static("x-forwarded-for")).iter()
+            .map(|node| parse_node(node))
+            .collect(),
+        ForwardedHeader::Forwarded => values(header::FORWARDED).iter()
+            .map(|element| element.split(';')
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| parse_node(node)))
+            .collect(),
+    }
+}
+
+
+/// An address, optionally quoted, bracketed or with a port.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    IpAddr::from_str(node).ok()
+        .or_else(|| SocketAddr::from_str(node).ok().map(|addr| addr.ip()))
+        .or_else(|| IpAddr::from_str(node.strip_prefix('[')?.strip_suffix(']')?).ok())
+        .or_else(|| IpAddr::from_str(node.split(':').next()?).ok())
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn finds_client_addresses() {
+        let peer = Some(ip("172.16.0.1"));
+        let identity = |hops| ClientIdentity::new().trusted_proxies(hops);
+        let headers = HeaderMap::from_iter([
+            (HeaderName::from_static("x-forwarded-for"), HeaderValue::from_static("6.6.6.6, 1.2.3.4, 5.6.7.8")),
+        ]);
+        assert_eq!(identity(0).resolve(peer, &headers), peer);
+        assert_eq!(identity(0).resolve(Some(ip("::ffff:172.16.0.1")), &headers), peer);
+        assert_eq!(identity(1).resolve(peer, &headers), Some(ip("5.6.7.8")));
+        assert_eq!(identity(2).resolve(peer, &headers), Some(ip("1.2.3.4")));
+        assert_eq!(identity(4).resolve(peer, &headers), None);
+        assert_eq!(identity(1).resolve(peer, &HeaderMap::new()), None);
+        assert_eq!(identity(1).header(ForwardedHeader::Forwarded).resolve(peer, &headers), None);
+
+        let forwarded = |hops| identity(hops).header(ForwardedHeader::Forwarded);
+        let headers = HeaderMap::from_iter([
+            (header::FORWARDED, HeaderValue::from_static(r#"for=192.0.2.60;proto=http, for="[2001:db8::1]:4711";by=proxy"#)),
+            (HeaderName::from_static("x-forwarded-for"), HeaderValue::from_static("6.6.6.6")),
+        ]);
+        assert_eq!(forwarded(1).resolve(peer, &headers), Some(ip("2001:db8::1")));
+        assert_eq!(forwarded(2).resolve(peer, &headers), Some(ip("192.0.2.60")));
+        assert_eq!(identity(1).resolve(peer, &headers), Some(ip("6.6.6.6")));
+
+        let headers = HeaderMap::from_iter([(header::FORWARDED, HeaderValue::from_static("for=_hidden"))]);
+        assert_eq!(forwarded(1).resolve(peer, &headers), None);
+        assert_eq!(identity(2).with_trusted_proxies(None), identity(2));
+        assert_eq!(identity(2).with_trusted_proxies(Some(0)), identity(0));
+    }
+
+    #[test]
+    fn ignores_headers_the_proxies_do_not_set() {
+        // A load balancer appended the client to `X-Forwarded-For` and passed its `Forwarded` on
+        let headers = HeaderMap::from_iter([
+            (header::FORWARDED, HeaderValue::from_static("for=10.0.0.1")),
+            (HeaderName::from_static("x-forwarded-for"), HeaderValue::from_static("203.0.113.7")),
+        ]);
+        let identity = ClientIdentity::new().trusted_proxies(1);
+        assert_eq!(identity.resolve(None, &headers), Some(ip("203.0.113.7")));
+
+        // Without the configured header, the address is unknown rather than the client's choice
+        let headers = HeaderMap::from_iter([(header::FORWARDED, HeaderValue::from_static("for=10.0.0.1"))]);
+        assert_eq!(identity.resolve(None, &headers), None);
+    }
+}
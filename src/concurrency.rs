@@ -0,0 +1,276 @@
+//! Adaptive concurrency limits.
+//!
+//! With [`adaptive_concurrency`](crate::S3OriginBuilder::adaptive_concurrency), an origin serves
+//! at most a limited number of requests at once; further requests wait in `poll_ready`, so a load
+//! shedding or timeout layer in front of the origin sees the backpressure.  The limit adapts to
+//! how S3 copes, additive-increase/multiplicative-decrease:
+//!
+//! - every response ready within the latency target, while the limit is in use, raises the limit
+//!   by `1 / limit`, about one per round of requests;
+//! - a response slower than the target, or failing with a `5xx` from S3 (throttling, errors or
+//!   an unreachable endpoint), multiplies the limit by the backoff factor.  Requests that started
+//!   before the last decrease do not decrease it again, so a burst of failures counts once.
+//!
+//! During a burst, requests then queue in front of S3 instead of piling onto it and failing with
+//! `502` or `503`.
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use axum_static_s3::concurrency::AdaptiveConcurrency;
+//!
+//! let concurrency = AdaptiveConcurrency::new()
+//!     .initial(50)
+//!     .limits(4, 400)
+//!     .latency_target(Duration::from_millis(500));
+//! ```
+//!
+//! Calls without a preceding `poll_ready` are counted, but never wait.
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use axum::response::Response;
+
+use crate::{S3Error, S3Origin};
+
+
+/// Adaptive concurrency limit configuration.
+#[derive(Clone, Debug)]
+pub struct AdaptiveConcurrency {
+    initial: usize,
+    min: usize,
+    max: usize,
+    latency_target: Duration,
+    backoff: f64,
+}
+
+impl AdaptiveConcurrency {
+    /// A limit starting at 20 requests, between 1 and 500, with a latency target of 1 second
+    /// and a backoff of 0.75.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start with this limit.
+    pub fn initial(mut self, limit: usize) -> Self {
+        self.initial = limit;
+        self
+    }
+
+    /// Keep the limit between `min` and `max`.
+    pub fn limits(mut self, min: usize, max: usize) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    /// Treat responses slower than `target` until their head was ready as a sign of overload.
+    pub fn latency_target(mut self, target: Duration) -> Self {
+        self.latency_target = target;
+        self
+    }
+
+    /// Multiply the limit by `factor` on overload.
+    pub fn backoff(mut self, factor: f64) -> Self {
+        self.backoff = factor;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), &'static str> {
+        let limits = 1 <= self.min && self.min <= self.initial && self.initial <= self.max;
+        match limits && self.backoff > 0.0 && self.backoff < 1.0 {
+            true => Ok(()),
+            false => Err("adaptive_concurrency needs 1 <= min <= initial <= max and a backoff between 0 and 1"),
+        }
+    }
+}
+
+impl Default for AdaptiveConcurrency {
+    fn default() -> Self {
+        Self { initial: 20, min: 1, max: 500, latency_target: Duration::from_secs(1), backoff: 0.75 }
+    }
+}
+
+
+#[derive(Debug)]
+struct State {
+    limit: f64,
+    active: usize,
+    /// When the limit was last decreased.
+    decreased: Instant,
+    /// Calls waiting in `poll_ready`.
+    waiters: Vec<Waker>,
+}
+
+
+/// The concurrency limit of an origin.
+#[derive(Debug)]
+pub(crate) struct ConcurrencyLimiter {
+    config: AdaptiveConcurrency,
+    state: Mutex<State>,
+}
+
+impl ConcurrencyLimiter {
+    pub(crate) fn new(config: AdaptiveConcurrency) -> Self {
+        let state = State { limit: config.initial as f64, active: 0, decreased: Instant::now(), waiters: Vec::new() };
+        Self { config, state: Mutex::new(state) }
+    }
+
+    pub(crate) fn limit(&self) -> usize {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).limit as usize
+    }
+
+    /// A permit once the origin serves fewer requests than the limit.
+    pub(crate) fn poll_acquire(self: &Arc<Self>, cx: &mut Context<'_>) -> Poll<Permit> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.active < state.limit as usize {
+            state.active += 1;
+            return Poll::Ready(Permit::new(self.clone()));
+        }
+        if !state.waiters.iter().any(|waiter| waiter.will_wake(cx.waker())) {
+            state.waiters.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    /// A permit regardless of the limit, for calls without `poll_ready`.
+    pub(crate) fn acquire(self: &Arc<Self>) -> Permit {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).active += 1;
+        Permit::new(self.clone())
+    }
+
+    /// Release a permit, adapting the limit to how the request fared.
+    fn release(&self, started: Instant, outcome: Option<(Duration, bool)>) {
+        let AdaptiveConcurrency { min, max, latency_target, backoff, .. } = self.config;
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let saturated = state.active >= state.limit as usize;
+        state.active = state.active.saturating_sub(1);
+        let Some((latency, failed)) = outcome else {
+            return Self::wake(&mut state);
+        };
+        if failed || latency > latency_target {
+            // Requests started before the last decrease saw the same overload
+            if started >= state.decreased {
+                state.limit = (state.limit * backoff).max(min as f64);
+                state.decreased = Instant::now();
+            }
+        } else if saturated {
+            state.limit = (state.limit + 1.0 / state.limit).min(max as f64);
+        }
+        Self::wake(&mut state);
+    }
+
+    fn wake(state: &mut State) {
+        // Waiters may have gone away; every one left polls again
+        if state.active < state.limit as usize {
+            state.waiters.drain(..).for_each(Waker::wake);
+        }
+    }
+}
+
+
+/// A slot of the concurrency limit, released when the response head is ready or on drop.
+#[derive(Debug)]
+pub(crate) struct Permit {
+    limiter: Arc<ConcurrencyLimiter>,
+    started: Instant,
+    released: bool,
+}
+
+impl Permit {
+    fn new(limiter: Arc<ConcurrencyLimiter>) -> Self {
+        Self { limiter, started: Instant::now(), released: false }
+    }
+
+    /// Release the permit after `response`.
+    pub(crate) fn finish(mut self, response: &Response) {
+        let failed = matches!(
+            response.extensions().get::<S3Error>(),
+            Some(S3Error::BadGateway | S3Error::Throttled | S3Error::InternalServerError)
+        );
+        self.limiter.release(self.started, Some((self.started.elapsed(), failed)));
+        self.released = true;
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.released {
+            self.limiter.release(self.started, None);
+        }
+    }
+}
+
+
+impl S3Origin {
+    /// The current [adaptive concurrency](crate::concurrency) limit, if configured.
+    ///
+    pub fn concurrency_limit(&self) -> Option<usize> {
+        self.inner.concurrency.as_ref().map(|limiter| limiter.limit())
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+
+    fn limiter(initial: usize) -> Arc<ConcurrencyLimiter> {
+        let config = AdaptiveConcurrency::new().initial(initial).limits(2, 8).latency_target(Duration::from_millis(100));
+        Arc::new(ConcurrencyLimiter::new(config))
+    }
+
+    #[test]
+    fn adapts_the_limit() {
+        let limiter = limiter(4);
+        // Grows only while in use
+        limiter.acquire().finish(&().into_response());
+        assert_eq!(limiter.state.lock().unwrap().limit, 4.0);
+        let permits = (0..4).map(|_| limiter.acquire()).collect::<Vec<_>>();
+        for permit in permits {
+            permit.finish(&().into_response());
+        }
+        assert_eq!(limiter.state.lock().unwrap().limit, 4.25);
+
+        // A burst of failures decreases the limit once
+        let permits = (0..4).map(|_| limiter.acquire()).collect::<Vec<_>>();
+        for permit in permits {
+            permit.finish(&S3Error::Throttled.into_response());
+        }
+        assert_eq!(limiter.limit(), 3);
+        limiter.acquire().finish(&S3Error::BadGateway.into_response());
+        assert_eq!(limiter.limit(), 2);
+        limiter.acquire().finish(&S3Error::BadGateway.into_response());
+        assert_eq!(limiter.limit(), 2);
+        // Client errors say nothing about S3
+        limiter.acquire().finish(&S3Error::NotFound.into_response());
+        assert_eq!(limiter.limit(), 2);
+        assert_eq!(limiter.state.lock().unwrap().active, 0);
+
+        assert!(AdaptiveConcurrency::new().initial(0).validate().is_err());
+        assert!(AdaptiveConcurrency::new().backoff(1.0).validate().is_err());
+        assert!(AdaptiveConcurrency::new().validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn waits_for_permits() {
+        let limiter = limiter(2);
+        let first = std::future::poll_fn(|cx| limiter.poll_acquire(cx)).await;
+        let _second = std::future::poll_fn(|cx| limiter.poll_acquire(cx)).await;
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { std::future::poll_fn(|cx| limiter.poll_acquire(cx)).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        let third = tokio::time::timeout(Duration::from_secs(5), waiting).await.unwrap().unwrap();
+        assert_eq!(limiter.state.lock().unwrap().active, 2);
+        drop(third);
+    }
+}
@@ -0,0 +1,142 @@
+//! Conditional requests.
+//!
+//! Responses carry the object's `ETag` and `Last-Modified`, and requests whose `If-None-Match`
+//! or `If-Modified-Since` match are answered with `304 Not Modified`.  Backends that return no
+//! ETag (local directories, S3 Object Lambda, some object stores) get a weak one synthesized from
+//! the object size and modification time, or from a hash of the body when the object is cached,
+//! so conditional requests work the same whatever serves the objects.
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+
+
+/// A weak ETag from the size and modification time of an object.
+pub(crate) fn weak_etag(size: i64, last_modified: &DateTime) -> String {
+    format!("W/\"{:x}-{:x}\"", size, last_modified.secs())
+}
+
+
+/// A weak ETag from the hash of a whole body.
+pub(crate) fn hash_etag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("W/\"{}\"", hex)
+}
+
+
+/// Answer with `304 Not Modified` if the request's validators match a successful response.
+///
+/// `If-None-Match` takes precedence over `If-Modified-Since`, and uses the weak comparison.
+pub(crate) fn apply(request_headers: &HeaderMap, response: Response) -> Response {
+    if !matches!(response.status(), StatusCode::OK | StatusCode::PARTIAL_CONTENT) || !not_modified(request_headers, response.headers()) {
+        return response;
+    }
+    let (mut parts, _) = response.into_parts();
+    parts.status = StatusCode::NOT_MODIFIED;
+    for name in [
+        header::CONTENT_LENGTH,
+        header::CONTENT_TYPE,
+        header::CONTENT_ENCODING,
+        header::CONTENT_RANGE,
+        header::CONTENT_DISPOSITION,
+        header::CONTENT_LANGUAGE,
+    ] {
+        parts.headers.remove(name);
+    }
+    Response::from_parts(parts, Body::empty())
+}
+
+
+fn not_modified(request_headers: &HeaderMap, response_headers: &HeaderMap) -> bool {
+    let if_none_match = request_headers.get_all(header::IF_NONE_MATCH).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .collect::<Vec<_>>();
+    if !if_none_match.is_empty() {
+        let Some(etag) = response_headers.get(header::ETAG).and_then(|value| value.to_str().ok()) else {
+            return false;
+        };
+        return if_none_match.iter().any(|tag| *tag == "*" || opaque(tag) == opaque(etag));
+    }
+
+    let date = |headers: &HeaderMap, name| {
+        let value = headers.get(name)?.to_str().ok()?;
+        DateTime::from_str(value.trim(), DateTimeFormat::HttpDate).ok()
+    };
+    match (date(request_headers, header::IF_MODIFIED_SINCE), date(response_headers, header::LAST_MODIFIED)) {
+        (Some(since), Some(last_modified)) => last_modified.secs() <= since.secs(),
+        _ => false,
+    }
+}
+
+
+/// The opaque tag of an entity tag, for the weak comparison.
+fn opaque(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn object() -> Response {
+        let mut response = Response::new(Body::from("hello"));
+        let headers = response.headers_mut();
+        headers.insert(header::ETAG, HeaderValue::from_static("\"abc\""));
+        headers.insert(header::LAST_MODIFIED, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(5));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("max-age=60"));
+        response
+    }
+
+    fn request(name: header::HeaderName, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn synthesizes_etags() {
+        let last_modified = DateTime::from_secs(1_445_412_480);
+        assert_eq!(weak_etag(1024, &last_modified), "W/\"400-56273e80\"");
+        assert!(hash_etag(b"hello").starts_with("W/\""));
+        assert_eq!(hash_etag(b"hello"), hash_etag(b"hello"));
+        assert_ne!(hash_etag(b"hello"), hash_etag(b"world"));
+    }
+
+    #[test]
+    fn answers_not_modified() {
+        let response = apply(&request(header::IF_NONE_MATCH, "\"xyz\", W/\"abc\""), object());
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], "\"abc\"");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "max-age=60");
+        assert!(!response.headers().contains_key(header::CONTENT_LENGTH));
+
+        assert_eq!(apply(&request(header::IF_NONE_MATCH, "*"), object()).status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(apply(&request(header::IF_NONE_MATCH, "\"xyz\""), object()).status(), StatusCode::OK);
+        assert_eq!(apply(&HeaderMap::new(), object()).status(), StatusCode::OK);
+
+        let since = request(header::IF_MODIFIED_SINCE, "Wed, 21 Oct 2015 07:28:00 GMT");
+        assert_eq!(apply(&since, object()).status(), StatusCode::NOT_MODIFIED);
+        let since = request(header::IF_MODIFIED_SINCE, "Tue, 20 Oct 2015 07:28:00 GMT");
+        assert_eq!(apply(&since, object()).status(), StatusCode::OK);
+
+        // If-None-Match wins over If-Modified-Since
+        let mut headers = request(header::IF_NONE_MATCH, "\"xyz\"");
+        headers.insert(header::IF_MODIFIED_SINCE, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(apply(&headers, object()).status(), StatusCode::OK);
+
+        let mut missing = object();
+        *missing.status_mut() = StatusCode::NOT_FOUND;
+        assert_eq!(apply(&request(header::IF_NONE_MATCH, "*"), missing).status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,111 @@
+use axum::http::HeaderValue;
+use globset::GlobMatcher;
+
+use crate::pattern::{compile_glob, file_name};
+use crate::rules::Source;
+
+
+/// The `Content-Disposition` policy of an origin.
+///
+/// Paths matching one of the attachment patterns are served with
+/// `Content-Disposition: attachment; filename="…"`, derived from the key's file name, so
+/// browsers show a save dialog.  Other paths use the object's `Content-Disposition` metadata.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ContentDispositionPolicy {
+    attachments: Vec<GlobMatcher>,
+}
+
+
+impl ContentDispositionPolicy {
+    pub(crate) fn new<'a>(patterns: impl IntoIterator<Item = &'a String>) -> Result<Self, &'static str> {
+        let attachments = patterns.into_iter()
+            .map(|pattern| compile_glob(pattern))
+            .collect::<Result<_, _>>()
+            .map_err(|_| "invalid attachment pattern")?;
+        Ok(Self { attachments })
+    }
+
+    /// Resolve the `Content-Disposition` header value for a path relative to the bucket prefix.
+    pub(crate) fn resolve(&self, path: &str, s3_value: Option<&str>) -> Option<(HeaderValue, Source)> {
+        if self.matching_rule(path).is_some() {
+            return Some((attachment(file_name(path)), Source::Rule));
+        }
+        s3_value
+            .and_then(|value| HeaderValue::from_str(value).ok())
+            .map(|value| (value, Source::Object))
+    }
+
+    /// The index of the first attachment pattern matching the path.
+    pub(crate) fn matching_rule(&self, path: &str) -> Option<usize> {
+        self.attachments.iter().position(|matcher| matcher.is_match(path))
+    }
+}
+
+
+/// Build an `attachment` disposition for a file name (RFC 6266).
+///
+/// The quoted `filename` is an ASCII fallback; non-ASCII names are additionally sent as a
+/// percent-encoded `filename*`.
+pub(crate) fn attachment(file_name: &str) -> HeaderValue {
+    if file_name.is_empty() {
+        return HeaderValue::from_static("attachment");
+    }
+
+    let fallback: String = file_name.chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect();
+
+    let value = if file_name.is_ascii() && !file_name.chars().any(|c| c.is_ascii_control()) {
+        format!("attachment; filename=\"{}\"", fallback)
+    } else {
+        format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encode_ext_value(file_name))
+    };
+
+    HeaderValue::from_str(&value).unwrap_or(HeaderValue::from_static("attachment"))
+}
+
+
+/// Percent-encode a value for an RFC 5987 `ext-value`.
+fn encode_ext_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9'
+            | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attachment_filenames() {
+        assert_eq!(attachment("report.pdf"), "attachment; filename=\"report.pdf\"");
+        assert_eq!(attachment("say \"hi\".txt"), "attachment; filename=\"say _hi_.txt\"");
+        assert_eq!(attachment("résumé.pdf"), "attachment; filename=\"r_sum_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf");
+        assert_eq!(attachment(""), "attachment");
+    }
+
+    #[test]
+    fn matching_paths_are_attachments() {
+        let patterns = ["/downloads/**".to_string(), "*.zip".to_string()];
+        let policy = ContentDispositionPolicy::new(&patterns).unwrap();
+
+        assert_eq!(policy.resolve("downloads/v1/setup.exe", None).unwrap().0, "attachment; filename=\"setup.exe\"");
+        assert_eq!(policy.resolve("assets/site.zip", None).unwrap().0, "attachment; filename=\"site.zip\"");
+        assert_eq!(policy.resolve("index.html", Some("inline")).unwrap().0, "inline");
+        assert!(policy.resolve("index.html", None).is_none());
+    }
+}
@@ -0,0 +1,166 @@
+//! Counting the drivers of S3 cost.
+//!
+//! S3 bills per request, by request class, and per byte transferred out.  Every origin counts
+//! the requests it sends to S3, by operation and including retries, the bytes of object bodies
+//! S3 returned, and the requests answered from the cache that would otherwise have reached S3.
+//! [`S3Origin::cost_stats`] returns the counts; the [`metrics`](crate::metrics) and
+//! [`admin`](crate::admin) routers expose them.
+//!
+//! Origins of an [`S3OriginSet`](crate::S3OriginSet) share a client, and its connection pool, but
+//! count separately, so the spend of each mount can be attributed.  Requests made outside of
+//! serving, such as [`prefetch`](S3Origin::prefetch) or health checks, count towards the base
+//! origin of the set.  Requests of a custom [`backend`](crate::S3OriginBuilder::backend) are not
+//! counted.
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use aws_sdk_s3::config::{
+    interceptors::BeforeDeserializationInterceptorContextRef, ConfigBag, Intercept, RuntimeComponents,
+};
+use aws_smithy_runtime_api::client::orchestrator::Metadata;
+use axum::BoxError;
+
+use crate::S3Origin;
+
+
+/// S3 cost drivers of an origin, see [`S3Origin::cost_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CostStats {
+    /// GetObject requests, billed as GET requests.
+    pub get_requests: u64,
+    /// HeadObject requests, billed as GET requests.
+    pub head_requests: u64,
+    /// ListObjectsV2 requests, billed as LIST requests.
+    pub list_requests: u64,
+    /// Other requests, such as GetObjectTagging.
+    pub other_requests: u64,
+    /// Bytes of the object bodies S3 returned.
+    pub bytes_egressed: u64,
+    /// Requests answered from the cache without a request to S3.
+    pub cache_avoided: u64,
+}
+
+
+#[derive(Debug, Default)]
+pub(crate) struct CostCounters {
+    get_requests: AtomicU64,
+    head_requests: AtomicU64,
+    list_requests: AtomicU64,
+    other_requests: AtomicU64,
+    bytes_egressed: AtomicU64,
+    cache_avoided: AtomicU64,
+}
+
+impl CostCounters {
+    pub(crate) fn cache_hit(&self) {
+        self.cache_avoided.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn request(&self, operation: &str, bytes: Option<u64>) {
+        let counter = match operation {
+            "GetObject" => &self.get_requests,
+            "HeadObject" => &self.head_requests,
+            "ListObjectsV2" | "ListObjects" => &self.list_requests,
+            _ => &self.other_requests,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(bytes) = bytes {
+            self.bytes_egressed.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CostStats {
+        CostStats {
+            get_requests: self.get_requests.load(Ordering::Relaxed),
+            head_requests: self.head_requests.load(Ordering::Relaxed),
+            list_requests: self.list_requests.load(Ordering::Relaxed),
+            other_requests: self.other_requests.load(Ordering::Relaxed),
+            bytes_egressed: self.bytes_egressed.load(Ordering::Relaxed),
+            cache_avoided: self.cache_avoided.load(Ordering::Relaxed),
+        }
+    }
+}
+
+
+tokio::task_local! {
+    /// The counters of the origin serving the current request.
+    static COUNTERS: Option<Arc<CostCounters>>;
+}
+
+/// Count the S3 requests sent by `future` towards `counters`.
+pub(crate) fn scope<F: Future>(counters: Option<Arc<CostCounters>>, future: F) -> impl Future<Output = F::Output> {
+    COUNTERS.scope(counters, future)
+}
+
+/// The counters of the current scope, for requests sent later, e.g. while streaming a body.
+pub(crate) fn current() -> Option<Arc<CostCounters>> {
+    COUNTERS.try_with(Clone::clone).ok().flatten()
+}
+
+
+/// Counts every response the client receives, towards the current scope or else the origin
+/// that built the client.
+#[derive(Debug)]
+pub(crate) struct CostInterceptor(pub(crate) Arc<CostCounters>);
+
+impl Intercept for CostInterceptor {
+    fn name(&self) -> &'static str {
+        "CostInterceptor"
+    }
+
+    fn read_before_deserialization(
+        &self,
+        context: &BeforeDeserializationInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let operation = cfg.load::<Metadata>().map(Metadata::name).unwrap_or_default();
+        let response = context.response();
+        let bytes = match (operation, response.status().is_success()) {
+            ("GetObject", true) => response.headers().get("content-length").and_then(|length| length.parse().ok()),
+            _ => None,
+        };
+        current().unwrap_or_else(|| self.0.clone()).request(operation, bytes);
+        Ok(())
+    }
+}
+
+
+impl S3Origin {
+    /// The S3 requests, bytes and cache hits of this origin, see [`cost`](crate::cost).
+    ///
+    pub fn cost_stats(&self) -> CostStats {
+        self.inner.cost.stats()
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_requests_by_class() {
+        let counters = CostCounters::default();
+        counters.request("GetObject", Some(100));
+        counters.request("GetObject", None);
+        counters.request("HeadObject", None);
+        counters.request("ListObjectsV2", None);
+        counters.request("GetObjectTagging", None);
+        counters.cache_hit();
+        assert_eq!(counters.stats(), CostStats {
+            get_requests: 2,
+            head_requests: 1,
+            list_requests: 1,
+            other_requests: 1,
+            bytes_egressed: 100,
+            cache_avoided: 1,
+        });
+    }
+}
@@ -0,0 +1,54 @@
+//! Credential providers configured through the builder.
+use std::fmt;
+
+use aws_config::{sts::AssumeRoleProvider, SdkConfig};
+use aws_credential_types::provider::{future, ProvideCredentials};
+use tokio::sync::OnceCell;
+
+
+/// Credentials of an assumed IAM role, e.g. for serving a bucket in another account.
+///
+/// The base credentials come from the SDK config.  [`AssumeRoleProvider`] can only be built
+/// asynchronously, so it is built on first use.  It calls STS on every invocation; the S3
+/// client's identity cache keeps the credentials and asks again shortly before they expire.
+pub(crate) struct AssumeRole {
+    role_arn: String,
+    session_name: String,
+    config: SdkConfig,
+    provider: OnceCell<AssumeRoleProvider>,
+}
+
+impl AssumeRole {
+    pub(crate) fn new(role_arn: String, session_name: String, config: SdkConfig) -> Self {
+        Self { role_arn, session_name, config, provider: OnceCell::new() }
+    }
+
+    async fn provider(&self) -> &AssumeRoleProvider {
+        self.provider.get_or_init(|| {
+            AssumeRoleProvider::builder(&self.role_arn)
+                .session_name(&self.session_name)
+                .configure(&self.config)
+                .build()
+        }).await
+    }
+}
+
+impl fmt::Debug for AssumeRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AssumeRole")
+            .field("role_arn", &self.role_arn)
+            .field("session_name", &self.session_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProvideCredentials for AssumeRole {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(async move {
+            self.provider().await.provide_credentials().await
+        })
+    }
+}
@@ -0,0 +1,189 @@
+//! Transparent decompression of gzip-encoded objects.
+//!
+//! Objects uploaded pre-compressed carry `Content-Encoding: gzip` metadata, which is forwarded
+//! as is.  With [`decompress`](crate::S3OriginBuilder::decompress), complete (`200 OK`)
+//! responses are decompressed while streaming for clients that do not accept gzip.  Their
+//! length changes, so `Content-Length` is dropped and the `ETag` is weakened.
+use std::{
+    io::Write,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use flate2::write::MultiGzDecoder;
+use futures_core::Stream;
+use pin_project::pin_project;
+
+use crate::{
+    negotiation,
+    telemetry::{self, Feature},
+    vary,
+};
+
+
+/// Decompress a gzip-encoded response unless the request accepts gzip.
+///
+/// Responses with gzip encoding vary by `Accept-Encoding`, whether or not they are decompressed.
+pub(crate) fn apply(request_headers: &HeaderMap, mut response: Response) -> Response {
+    let is_gzip = response.headers().get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "gzip" | "x-gzip"));
+    if !is_gzip {
+        return response;
+    }
+    vary::add(response.headers_mut(), "accept-encoding");
+    if response.status() != StatusCode::OK || accepts_gzip(request_headers) {
+        return response;
+    }
+
+    let headers = response.headers_mut();
+    headers.remove(header::CONTENT_ENCODING);
+    headers.remove(header::CONTENT_LENGTH);
+    if let Some(etag) = headers.remove(header::ETAG) {
+        let weak = match etag.as_bytes().starts_with(b"W/") {
+            true => Some(etag),
+            false => HeaderValue::from_bytes(&[b"W/", etag.as_bytes()].concat()).ok(),
+        };
+        if let Some(weak) = weak {
+            headers.insert(header::ETAG, weak);
+        }
+    }
+
+    let mut response = response.map(|body| Body::from_stream(Decompress {
+        body: body.into_data_stream(),
+        decoder: Some(MultiGzDecoder::new(Vec::new())),
+    }));
+    telemetry::record(&mut response, Feature::Decompression);
+    response
+}
+
+
+/// Whether `Accept-Encoding` accepts gzip; a missing header does not, since clients that
+/// send none (e.g. scripts) rarely decode it.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    let Some(preferences) = negotiation::preferences(headers, &header::ACCEPT_ENCODING) else {
+        return false;
+    };
+    let quality = |value: &str| preferences.iter().find(|preference| preference.value == value).map(|preference| preference.q);
+    quality("gzip")
+        .or_else(|| quality("x-gzip"))
+        .or_else(|| quality("*"))
+        .is_some_and(|q| q > 0.0)
+}
+
+
+#[pin_project]
+struct Decompress<S> {
+    #[pin]
+    body: S,
+    /// Taken once the body ends.
+    decoder: Option<MultiGzDecoder<Vec<u8>>>,
+}
+
+impl<S: Stream<Item = Result<Bytes, axum::Error>>> Stream for Decompress<S> {
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            let Some(decoder) = this.decoder.as_mut() else {
+                return Poll::Ready(None);
+            };
+            match this.body.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    if let Err(error) = decoder.write_all(&chunk) {
+                        *this.decoder = None;
+                        return Poll::Ready(Some(Err(axum::Error::new(error))));
+                    }
+                    let output = std::mem::take(decoder.get_mut());
+                    if !output.is_empty() {
+                        return Poll::Ready(Some(Ok(output.into())));
+                    }
+                }
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Some(Err(error))),
+                Poll::Ready(None) => {
+                    let output = decoder.try_finish().map(|()| std::mem::take(decoder.get_mut()));
+                    *this.decoder = None;
+                    match output {
+                        Ok(output) if output.is_empty() => return Poll::Ready(None),
+                        Ok(output) => return Poll::Ready(Some(Ok(output.into()))),
+                        Err(error) => return Poll::Ready(Some(Err(axum::Error::new(error)))),
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn response(body: Vec<u8>) -> Response {
+        let mut response = Response::new(Body::from(body));
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(10));
+        headers.insert(header::ETAG, HeaderValue::from_static("\"abc\""));
+        response
+    }
+
+    fn accept(value: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = value {
+            headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn negotiates_gzip() {
+        assert!(accepts_gzip(&accept(Some("gzip, deflate, br"))));
+        assert!(accepts_gzip(&accept(Some("*"))));
+        assert!(!accepts_gzip(&accept(Some("gzip;q=0, *"))));
+        assert!(!accepts_gzip(&accept(Some("br"))));
+        assert!(!accepts_gzip(&accept(Some("identity"))));
+        assert!(!accepts_gzip(&accept(None)));
+    }
+
+    #[tokio::test]
+    async fn decompresses_for_clients_without_gzip() {
+        let text = "hello world ".repeat(1000);
+        let compressed = gzip(text.as_bytes());
+
+        let passed = apply(&accept(Some("gzip")), response(compressed.clone()));
+        assert_eq!(passed.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(passed.headers()[header::VARY], "accept-encoding");
+        assert_eq!(axum::body::to_bytes(passed.into_body(), usize::MAX).await.unwrap(), compressed);
+
+        let decompressed = apply(&accept(None), response(compressed.clone()));
+        assert!(!decompressed.headers().contains_key(header::CONTENT_ENCODING));
+        assert!(!decompressed.headers().contains_key(header::CONTENT_LENGTH));
+        assert_eq!(decompressed.headers()[header::ETAG], "W/\"abc\"");
+        assert_eq!(decompressed.headers()[header::VARY], "accept-encoding");
+        assert_eq!(axum::body::to_bytes(decompressed.into_body(), usize::MAX).await.unwrap(), text);
+
+        // Ranges of the compressed bytes cannot be decompressed
+        let mut partial = response(compressed);
+        *partial.status_mut() = StatusCode::PARTIAL_CONTENT;
+        assert_eq!(apply(&accept(None), partial).headers()[header::CONTENT_ENCODING], "gzip");
+
+        let corrupt = apply(&accept(None), response(b"not gzip".to_vec()));
+        assert!(axum::body::to_bytes(corrupt.into_body(), usize::MAX).await.is_err());
+    }
+}
@@ -0,0 +1,275 @@
+//! Verifying that a deployment was uploaded completely.
+//!
+//! A build knows which objects it uploads, and usually their sizes and ETags.
+//! [`S3Origin::verify_deployment`] checks such a [`DeploymentManifest`] against the bucket,
+//! e.g. at startup or from a CLI run after the upload, and reports every object that is missing
+//! or differs, before HTML referencing a bundle that never uploaded is served:
+//!
+//! ```rust,no_run
+//! # async fn example(origin: axum_static_s3::S3Origin) {
+//! use axum_static_s3::deployment::DeploymentManifest;
+//!
+//! let manifest = DeploymentManifest::new()
+//!     .object("index.html")
+//!     .object_with("assets/app.3f9ab2.js", 48_213, "\"9b2cf535f27731c974343645a3985328\"")
+//!     .pattern("assets/index-*.css");
+//! let report = origin.verify_deployment(&manifest).await;
+//! for problem in &report.problems {
+//!     eprintln!("{}", problem);
+//! }
+//! # }
+//! ```
+//!
+//! With the `manifest` feature, the manifest can be read from JSON written by the build with
+//! [`DeploymentManifest::from_json`]:
+//!
+//! ```json
+//! {
+//!   "objects": {
+//!     "index.html": {},
+//!     "assets/app.3f9ab2.js": { "size": 48213, "etag": "\"9b2cf535f27731c974343645a3985328\"" }
+//!   },
+//!   "patterns": ["assets/index-*.css"]
+//! }
+//! ```
+use std::fmt;
+
+use crate::{probe::ObjectStatus, S3Error, S3Origin};
+
+
+/// An object a deployment uploads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ExpectedObject {
+    /// Relative to the bucket prefix.
+    key: String,
+    size: Option<i64>,
+    etag: Option<String>,
+}
+
+
+/// The objects of a deployment, see [`deployment`](crate::deployment).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeploymentManifest {
+    objects: Vec<ExpectedObject>,
+    patterns: Vec<String>,
+}
+
+impl DeploymentManifest {
+    /// An empty manifest.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expect an object at `key`, relative to the bucket prefix.
+    pub fn object(mut self, key: impl Into<String>) -> Self {
+        self.objects.push(ExpectedObject { key: key.into(), size: None, etag: None });
+        self
+    }
+
+    /// Expect an object at `key` with this size and ETag.
+    pub fn object_with(mut self, key: impl Into<String>, size: i64, etag: impl Into<String>) -> Self {
+        self.objects.push(ExpectedObject { key: key.into(), size: Some(size), etag: Some(etag.into()) });
+        self
+    }
+
+    /// Expect at least one key matching the glob `pattern`, see [`S3Origin::exists_matching`].
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    /// Read a manifest from JSON, see [`deployment`](crate::deployment).
+    #[cfg(feature = "manifest")]
+    pub fn from_json(json: &[u8]) -> Result<Self, crate::manifest::ManifestError> {
+        #[derive(serde::Deserialize)]
+        struct File {
+            #[serde(default)]
+            objects: std::collections::BTreeMap<String, Object>,
+            #[serde(default)]
+            patterns: Vec<String>,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct Object {
+            size: Option<i64>,
+            etag: Option<String>,
+        }
+
+        let file: File = serde_json::from_slice(json).map_err(crate::manifest::ManifestError::Parse)?;
+        let objects = file.objects.into_iter()
+            .map(|(key, object)| ExpectedObject { key, size: object.size, etag: object.etag })
+            .collect();
+        Ok(Self { objects, patterns: file.patterns })
+    }
+}
+
+
+/// Something wrong with a deployment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeploymentProblem {
+    /// There is no object at the key.
+    Missing { key: String },
+    /// The object has a different size.
+    SizeMismatch { key: String, expected: i64, actual: Option<i64> },
+    /// The object has a different ETag, i.e. other contents.
+    EtagMismatch { key: String, expected: String, actual: Option<String> },
+    /// No key matches the pattern.
+    NoMatch { pattern: String },
+    /// The object or pattern could not be checked.
+    Failed { key: String, error: S3Error },
+}
+
+impl fmt::Display for DeploymentProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeploymentProblem::Missing { key } => write!(f, "{} is missing", key),
+            DeploymentProblem::SizeMismatch { key, expected, actual } => {
+                write!(f, "{} has {} bytes, expected {}", key, display(actual), expected)
+            }
+            DeploymentProblem::EtagMismatch { key, expected, actual } => {
+                write!(f, "{} has ETag {}, expected {}", key, display(actual), expected)
+            }
+            DeploymentProblem::NoMatch { pattern } => write!(f, "no key matches {}", pattern),
+            DeploymentProblem::Failed { key, error } => write!(f, "{} could not be checked: {:?}", key, error),
+        }
+    }
+}
+
+
+fn display<T: fmt::Display>(value: &Option<T>) -> String {
+    value.as_ref().map_or("none".to_owned(), ToString::to_string)
+}
+
+
+/// The outcome of [`S3Origin::verify_deployment`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeploymentReport {
+    /// The objects and patterns checked.
+    pub checked: usize,
+    /// What is wrong, in manifest order; empty if the deployment is complete.
+    pub problems: Vec<DeploymentProblem>,
+}
+
+impl DeploymentReport {
+    /// Whether every object and pattern was found as expected.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+
+/// The problem with an object, if any.
+fn check(expected: &ExpectedObject, status: Option<&ObjectStatus>) -> Option<DeploymentProblem> {
+    let key = expected.key.clone();
+    let (size, etag) = match status {
+        Some(ObjectStatus::Found { size, etag }) => (size, etag),
+        Some(ObjectStatus::Missing) => return Some(DeploymentProblem::Missing { key }),
+        Some(ObjectStatus::Failed(error)) => return Some(DeploymentProblem::Failed { key, error: *error }),
+        None => return Some(DeploymentProblem::Failed { key, error: S3Error::InternalServerError }),
+    };
+    if let Some(expected) = expected.size.filter(|expected| Some(*expected) != *size) {
+        return Some(DeploymentProblem::SizeMismatch { key, expected, actual: *size });
+    }
+    // Build tools write ETags with or without their quotes
+    let unquoted = |etag: &str| etag.trim_matches('"').to_owned();
+    match &expected.etag {
+        Some(expected) if etag.as_deref().map(unquoted) != Some(unquoted(expected)) => {
+            Some(DeploymentProblem::EtagMismatch { key, expected: expected.clone(), actual: etag.clone() })
+        }
+        _ => None,
+    }
+}
+
+
+impl S3Origin {
+    /// Check that every object and pattern of `manifest` is in the bucket, see
+    /// [`deployment`](crate::deployment).
+    ///
+    /// Objects are checked with [`probe`](Self::probe), patterns with
+    /// [`exists_matching`](Self::exists_matching).
+    ///
+    pub async fn verify_deployment(&self, manifest: &DeploymentManifest) -> DeploymentReport {
+        let keys = manifest.objects.iter().map(|object| object.key.as_str()).collect::<Vec<_>>();
+        let statuses = self.probe(&keys).await;
+        let mut problems = manifest.objects.iter()
+            .filter_map(|object| check(object, statuses.get(&object.key)))
+            .collect::<Vec<_>>();
+        for pattern in &manifest.patterns {
+            match self.exists_matching(pattern).await {
+                Ok(true) => {}
+                Ok(false) => problems.push(DeploymentProblem::NoMatch { pattern: pattern.clone() }),
+                Err(error) => problems.push(DeploymentProblem::Failed { key: pattern.clone(), error }),
+            }
+        }
+        DeploymentReport { checked: manifest.objects.len() + manifest.patterns.len(), problems }
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{MockObject, MockS3};
+
+    fn found(size: i64, etag: &str) -> ObjectStatus {
+        ObjectStatus::Found { size: Some(size), etag: Some(etag.into()) }
+    }
+
+    #[test]
+    fn checks_objects() {
+        let manifest = DeploymentManifest::new().object("index.html").object_with("app.js", 5, "abc");
+        let (index, app) = (&manifest.objects[0], &manifest.objects[1]);
+        assert_eq!(check(index, Some(&found(1, "\"x\""))), None);
+        assert_eq!(check(index, Some(&ObjectStatus::Missing)), Some(DeploymentProblem::Missing { key: "index.html".into() }));
+        assert_eq!(check(app, Some(&found(5, "\"abc\""))), None);
+        let problem = check(app, Some(&found(6, "\"abc\""))).unwrap();
+        assert_eq!(problem, DeploymentProblem::SizeMismatch { key: "app.js".into(), expected: 5, actual: Some(6) });
+        assert_eq!(problem.to_string(), "app.js has 6 bytes, expected 5");
+        let problem = check(app, Some(&found(5, "\"abd\""))).unwrap();
+        assert_eq!(problem.to_string(), "app.js has ETag \"abd\", expected abc");
+        assert!(matches!(check(app, Some(&ObjectStatus::Failed(S3Error::Throttled))), Some(DeploymentProblem::Failed { .. })));
+    }
+
+    #[tokio::test]
+    async fn reports_incomplete_deployments() {
+        // A deployment with a stale bundle and a missing stylesheet
+        let s3 = MockS3::new("my-bucket")
+            .object("site/index.html", MockObject::new("<h1>Hello</h1>"))
+            .object("site/app.js", MockObject::new("old()"));
+        let origin = s3.origin().prefix("site/").build().unwrap();
+        let mut statuses = origin.probe(&["index.html", "app.js"]).await;
+        let mut etag = |key| match statuses.remove(key) {
+            Some(ObjectStatus::Found { etag: Some(etag), .. }) => etag,
+            status => panic!("{} is {:?}", key, status),
+        };
+        let (index_etag, app_etag) = (etag("index.html"), etag("app.js"));
+        let manifest = DeploymentManifest::new()
+            .object_with("index.html", 14, index_etag.trim_matches('"'))
+            .object_with("app.js", 5, "\"new\"")
+            .object("app.css")
+            .pattern("app.*.js")
+            .pattern("*.html");
+
+        let report = origin.verify_deployment(&manifest).await;
+        assert!(!report.is_ok());
+        assert_eq!(report.checked, 5);
+        assert_eq!(report.problems, [
+            DeploymentProblem::EtagMismatch { key: "app.js".into(), expected: "\"new\"".into(), actual: Some(app_etag) },
+            DeploymentProblem::Missing { key: "app.css".into() },
+            DeploymentProblem::NoMatch { pattern: "app.*.js".into() },
+        ]);
+        assert!(origin.verify_deployment(&DeploymentManifest::new().object("index.html")).await.is_ok());
+    }
+
+    #[cfg(feature = "manifest")]
+    #[test]
+    fn reads_json_manifests() {
+        let json = br#"{"objects": {"index.html": {}, "app.js": {"size": 5, "etag": "\"abc\""}}, "patterns": ["*.css"]}"#;
+        let manifest = DeploymentManifest::from_json(json).unwrap();
+        assert_eq!(manifest, DeploymentManifest::new().object_with("app.js", 5, "\"abc\"").object("index.html").pattern("*.css"));
+        assert!(DeploymentManifest::from_json(b"{\"objects\": []}").is_err());
+    }
+}
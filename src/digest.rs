@@ -0,0 +1,114 @@
+//! SHA-256 digests of objects, for the [`admin`](crate::admin) router.
+//!
+//! A digest is read from the object's full-object `ChecksumSHA256` when it was uploaded with
+//! one, and computed by streaming the object otherwise.  Computed digests are kept by key and
+//! ETag, so an object is read once until it changes.
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Mutex, PoisonError},
+};
+
+use aws_sdk_s3::types::ChecksumMode;
+use sha2::Digest as _;
+
+use crate::{admin::json_string, checksum, S3Error, S3OriginInner};
+
+
+/// Computed digests kept before the cache is cleared.
+const MAX_DIGESTS: usize = 4096;
+
+
+/// Computed digests by key and ETag.
+#[derive(Debug, Default)]
+pub(crate) struct DigestCache {
+    digests: Mutex<HashMap<(String, String), [u8; 32]>>,
+}
+
+impl DigestCache {
+    fn get(&self, key: &str, etag: &str) -> Option<[u8; 32]> {
+        let digests = self.digests.lock().unwrap_or_else(PoisonError::into_inner);
+        digests.get(&(key.to_owned(), etag.to_owned())).copied()
+    }
+
+    fn insert(&self, key: &str, etag: &str, digest: [u8; 32]) {
+        let mut digests = self.digests.lock().unwrap_or_else(PoisonError::into_inner);
+        if digests.len() >= MAX_DIGESTS {
+            digests.clear();
+        }
+        digests.insert((key.to_owned(), etag.to_owned()), digest);
+    }
+}
+
+
+/// The digest of the object at `key`, as JSON.
+pub(crate) async fn digest(origin: &S3OriginInner, key: &str) -> Result<String, S3Error> {
+    let head = match &origin.backend {
+        Some(backend) => backend.head(key).await?,
+        None => origin.s3_client.head_object()
+            .bucket(&origin.bucket)
+            .key(key)
+            .checksum_mode(ChecksumMode::Enabled)
+            .send()
+            .await?,
+    };
+    let etag = head.e_tag();
+    let stored = head.checksum_sha256()
+        .and_then(checksum::full_object)
+        .and_then(|digest| <[u8; 32]>::try_from(digest).ok());
+    let cached = etag.and_then(|etag| origin.digests.get(key, etag));
+    let (digest, source) = match (stored, cached) {
+        (Some(digest), _) => (digest, "checksum"),
+        (None, Some(digest)) => (digest, "cache"),
+        (None, None) => {
+            let digest = compute(origin, key).await?;
+            if let Some(etag) = etag {
+                origin.digests.insert(key, etag, digest);
+            }
+            (digest, "computed")
+        }
+    };
+
+    let mut body = format!("{{\"key\":{},\"sha256\":\"", json_string(key));
+    for byte in digest {
+        let _ = write!(body, "{:02x}", byte);
+    }
+    let size = head.content_length().map_or("null".to_owned(), |size| size.to_string());
+    let etag = etag.map_or("null".to_owned(), json_string);
+    let _ = write!(body, "\",\"size\":{},\"etag\":{},\"source\":\"{}\"}}", size, etag, source);
+    Ok(body)
+}
+
+
+/// Hash the object at `key` as it streams.
+async fn compute(origin: &S3OriginInner, key: &str) -> Result<[u8; 32], S3Error> {
+    let output = match &origin.backend {
+        Some(backend) => backend.get(key, None).await?,
+        None => origin.s3_client.get_object().bucket(&origin.bucket).key(key).send().await?,
+    };
+    let mut body = output.body;
+    let mut hasher = sha2::Sha256::new();
+    while let Some(chunk) = body.next().await {
+        hasher.update(chunk.map_err(|_| S3Error::BadGateway)?);
+    }
+    Ok(hasher.finalize().into())
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caches_digests() {
+        let cache = DigestCache::default();
+        cache.insert("a.txt", "\"1\"", [1; 32]);
+        assert_eq!(cache.get("a.txt", "\"1\""), Some([1; 32]));
+        assert_eq!(cache.get("a.txt", "\"2\""), None);
+        for i in 0..MAX_DIGESTS {
+            cache.insert(&i.to_string(), "\"1\"", [0; 32]);
+        }
+        assert_eq!(cache.get("a.txt", "\"1\""), None);
+    }
+}
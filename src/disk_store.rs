@@ -0,0 +1,305 @@
+//! A [`CacheStore`] on the local disk.
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::body::Bytes;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use sha2::Digest as _;
+
+use crate::store::{CacheStore, StoreFuture, StoredObject};
+
+
+const MAGIC: &str = "axum-static-s3 1";
+
+
+/// A [`CacheStore`] keeping one file per object in a directory, e.g. on an instance's SSD or a
+/// Lambda's `/tmp`.
+///
+/// Files are named by the SHA-256 of the key and written atomically.  Expired files are removed
+/// when they are next read; the directory is not otherwise size-bounded.
+///
+/// On Unix, the directory is made readable by the owner only (`0700`) on first use, also if it
+/// already existed, and files are written with `0600`.  Cached objects may be gated content, so on shared hosts or volumes that end up in
+/// snapshots, entries can also be [encrypted](Self::encrypted) with ChaCha20-Poly1305.
+pub struct DiskStore {
+    directory: PathBuf,
+    cipher: Option<ChaCha20Poly1305>,
+    /// Set once the directory exists with its permissions.
+    prepared: tokio::sync::OnceCell<()>,
+}
+
+impl DiskStore {
+    /// Store objects in `directory`, which is created on first use.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self { directory: directory.into(), cipher: None, prepared: tokio::sync::OnceCell::new() }
+    }
+
+    /// Encrypt entries with a key generated for this process.
+    ///
+    /// Entries written by other processes, including earlier runs, cannot be read and are removed
+    /// as misses.  Use [`encryption_key`](Self::encryption_key) to share entries between them.
+    pub fn encrypted(self) -> Self {
+        let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+        Self { cipher: Some(ChaCha20Poly1305::new(&key)), ..self }
+    }
+
+    /// Encrypt entries with this 256-bit key, e.g. the plaintext of a KMS data key.
+    pub fn encryption_key(self, key: [u8; 32]) -> Self {
+        Self { cipher: Some(ChaCha20Poly1305::new(&key.into())), ..self }
+    }
+
+    /// Create the directory, and restrict it to its owner even if it existed.
+    async fn prepare(&self) -> std::io::Result<()> {
+        self.prepared.get_or_try_init(|| async {
+            let mut directory = tokio::fs::DirBuilder::new();
+            directory.recursive(true);
+            #[cfg(unix)]
+            directory.mode(0o700);
+            directory.create(&self.directory).await?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt as _;
+                tokio::fs::set_permissions(&self.directory, std::fs::Permissions::from_mode(0o700)).await?;
+            }
+            Ok::<_, std::io::Error>(())
+        }).await?;
+        Ok(())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let digest = sha2::Sha256::digest(key.as_bytes());
+        let name: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.directory.join(name)
+    }
+}
+
+impl CacheStore for DiskStore {
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StoredObject>> {
+        Box::pin(async move {
+            self.prepare().await?;
+            let path = self.path(key);
+            let contents = match tokio::fs::read(&path).await {
+                Ok(contents) => contents,
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(error) => return Err(error.into()),
+            };
+            let contents = match &self.cipher {
+                Some(cipher) => decrypt(cipher, key, &contents).map(Bytes::from),
+                None => Some(Bytes::from(contents)),
+            };
+            match contents.and_then(|contents| decode(contents, SystemTime::now())) {
+                Some(object) => Ok(Some(object)),
+                // Expired or unreadable
+                None => {
+                    remove(&path).await?;
+                    Ok(None)
+                }
+            }
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, object: StoredObject, ttl: Duration) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            self.prepare().await?;
+
+            let mut contents = encode(&object, SystemTime::now() + ttl);
+            if let Some(cipher) = &self.cipher {
+                contents = encrypt(cipher, key, &contents)?;
+            }
+            let path = self.path(key);
+            let temporary = path.with_extension(format!("tmp{}", fastrand::u64(..)));
+            if let Err(error) = write_private(&temporary, &contents).await {
+                let _ = tokio::fs::remove_file(&temporary).await;
+                return Err(error.into());
+            }
+            if let Err(error) = tokio::fs::rename(&temporary, &path).await {
+                let _ = tokio::fs::remove_file(&temporary).await;
+                return Err(error.into());
+            }
+            Ok(())
+        })
+    }
+
+    fn invalidate<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move { remove(&self.path(key)).await })
+    }
+}
+
+impl fmt::Debug for DiskStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiskStore")
+            .field("directory", &self.directory)
+            .field("encrypted", &self.cipher.is_some())
+            .finish()
+    }
+}
+
+
+/// Write a file only its owner can read.
+async fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt as _;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    file.write_all(contents).await?;
+    file.flush().await
+}
+
+
+/// A random nonce followed by the sealed contents, bound to the cache key.
+fn encrypt(cipher: &ChaCha20Poly1305, key: &str, contents: &[u8]) -> Result<Vec<u8>, axum::BoxError> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = cipher.encrypt(&nonce, Payload { msg: contents, aad: key.as_bytes() })
+        .map_err(|_| "failed to encrypt cache entry")?;
+    let mut file = nonce.to_vec();
+    file.extend_from_slice(&sealed);
+    Ok(file)
+}
+
+
+/// Open a file written by [`encrypt`]; `None` if it was written with another key or tampered with.
+fn decrypt(cipher: &ChaCha20Poly1305, key: &str, file: &[u8]) -> Option<Vec<u8>> {
+    const NONCE_SIZE: usize = 12;
+    let (nonce, sealed) = (file.get(..NONCE_SIZE)?, file.get(NONCE_SIZE..)?);
+    cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad: key.as_bytes() }).ok()
+}
+
+
+async fn remove(path: &Path) -> Result<(), axum::BoxError> {
+    match tokio::fs::remove_file(path).await {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+        _ => Ok(()),
+    }
+}
+
+
+/// A header of `name: value` lines, an empty line and the body.
+fn encode(object: &StoredObject, expires: SystemTime) -> Vec<u8> {
+    let expires = expires.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut header = format!("{}\nexpires-at: {}\n", MAGIC, expires);
+    if let Some(etag) = &object.etag {
+        header.push_str(&format!("etag: {}\n", etag));
+    }
+    for (name, value) in &object.headers {
+        // Header values cannot contain line breaks, but be safe
+        if !value.contains(['\r', '\n']) {
+            header.push_str(&format!("{}: {}\n", name, value));
+        }
+    }
+    header.push('\n');
+
+    let mut contents = header.into_bytes();
+    contents.extend_from_slice(&object.body);
+    contents
+}
+
+
+/// Read a file written by [`encode`], unless it has expired.
+fn decode(contents: Bytes, now: SystemTime) -> Option<StoredObject> {
+    let end = contents.windows(2).position(|window| window == b"\n\n")?;
+    let header = std::str::from_utf8(&contents[..end]).ok()?;
+    let mut lines = header.lines();
+    if lines.next()? != MAGIC {
+        return None;
+    }
+
+    let mut object = StoredObject { body: contents.slice(end + 2..), ..StoredObject::default() };
+    for line in lines {
+        let (name, value) = line.split_once(": ")?;
+        match name {
+            "expires-at" => {
+                let expires = UNIX_EPOCH + Duration::from_secs(value.parse().ok()?);
+                if expires <= now {
+                    return None;
+                }
+            }
+            "etag" => object.etag = Some(value.to_owned()),
+            name => object.headers.push((name.to_owned(), value.to_owned())),
+        }
+    }
+    Some(object)
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_objects() {
+        let object = StoredObject {
+            body: Bytes::from_static(b"a\n\nb"),
+            etag: Some("\"abc\"".into()),
+            headers: vec![("content-type".into(), "text/plain".into())],
+        };
+        let now = SystemTime::now();
+        let contents = Bytes::from(encode(&object, now + Duration::from_secs(60)));
+        assert_eq!(decode(contents.clone(), now), Some(object));
+        assert_eq!(decode(contents, now + Duration::from_secs(61)), None);
+        assert_eq!(decode(Bytes::from_static(b"garbage"), now), None);
+    }
+
+    #[tokio::test]
+    async fn stores_files() {
+        let directory = std::env::temp_dir().join(format!("axum-static-s3-{}", fastrand::u64(..)));
+        let store = DiskStore::new(&directory);
+        let object = StoredObject { body: Bytes::from_static(b"1"), ..StoredObject::default() };
+
+        assert_eq!(store.get("a").await.unwrap(), None);
+        store.put("a", object.clone(), Duration::from_secs(60)).await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), Some(object));
+        store.invalidate("a").await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), None);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_files_private() {
+        let directory = std::env::temp_dir().join(format!("axum-static-s3-{}", fastrand::u64(..)));
+        let store = DiskStore::new(&directory).encrypted();
+        let object = StoredObject { body: Bytes::from_static(b"secret"), ..StoredObject::default() };
+        store.put("a", object.clone(), Duration::from_secs(60)).await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), Some(object.clone()));
+
+        let contents = std::fs::read(store.path("a")).unwrap();
+        assert!(!contents.windows(6).any(|window| window == b"secret"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            assert_eq!(std::fs::metadata(&directory).unwrap().permissions().mode() & 0o777, 0o700);
+            assert_eq!(std::fs::metadata(store.path("a")).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        // Another key, or the file moved to another cache key
+        assert_eq!(DiskStore::new(&directory).encrypted().get("a").await.unwrap(), None);
+        store.put("a", object.clone(), Duration::from_secs(60)).await.unwrap();
+        std::fs::rename(store.path("a"), store.path("b")).unwrap();
+        assert_eq!(store.get("b").await.unwrap(), None);
+
+        // A directory left readable by others, e.g. by an earlier version
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            std::fs::set_permissions(&directory, std::fs::Permissions::from_mode(0o755)).unwrap();
+            assert_eq!(DiskStore::new(&directory).get("a").await.unwrap(), None);
+            assert_eq!(std::fs::metadata(&directory).unwrap().permissions().mode() & 0o777, 0o700);
+        }
+
+        let shared = DiskStore::new(&directory).encryption_key([7; 32]);
+        shared.put("c", object.clone(), Duration::from_secs(60)).await.unwrap();
+        assert_eq!(DiskStore::new(&directory).encryption_key([7; 32]).get("c").await.unwrap(), Some(object));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+//! Configuration from environment variables.
+//!
+//! Lambda functions and containers are configured through their environment, so
+//! [`S3Origin::from_env`] and [`S3OriginBuilder::env`] read these variables (empty values
+//! count as unset):
+//!
+//! | Variable                          | Builder option                                            |
+//! |-----------------------------------|-----------------------------------------------------------|
+//! | `S3_ORIGIN_BUCKET`                | [`bucket`](S3OriginBuilder::bucket) (required)            |
+//! | `S3_ORIGIN_PREFIX`                | [`prefix`](S3OriginBuilder::prefix)                       |
+//! | `S3_ORIGIN_MAX_SIZE`              | [`max_size`](S3OriginBuilder::max_size), in bytes         |
+//! | `S3_ORIGIN_PRUNE_PATH`            | [`prune_path`](S3OriginBuilder::prune_path)               |
+//! | `S3_ORIGIN_CLEAN_URLS`            | [`clean_urls`](S3OriginBuilder::clean_urls)               |
+//! | `S3_ORIGIN_IMMUTABLE_ASSETS`      | [`immutable_assets`](S3OriginBuilder::immutable_assets)   |
+//! | `S3_ORIGIN_CACHE`                 | [`cache`](S3OriginBuilder::cache), capacity in bytes      |
+//! | `S3_ORIGIN_CACHE_TTL`             | [`cache_ttl`](S3OriginBuilder::cache_ttl), in seconds     |
+//! | `S3_ORIGIN_ANONYMOUS`             | [`anonymous`](S3OriginBuilder::anonymous)                 |
+//! | `S3_ORIGIN_ROLE_ARN`              | [`assume_role`](S3OriginBuilder::assume_role)             |
+//! | `S3_ORIGIN_ROLE_SESSION_NAME`     | the session name for the role, default `axum-static-s3`   |
+//! | `S3_ORIGIN_EXPECTED_BUCKET_OWNER` | [`expected_bucket_owner`](S3OriginBuilder::expected_bucket_owner) |
+//! | `S3_ORIGIN_ENDPOINT_URL`          | [`endpoint_url`](S3OriginBuilder::endpoint_url), e.g. a local MinIO for development |
+//!
+//! Flags accept `true`/`false`, `1`/`0` and `yes`/`no`.
+use std::{str::FromStr, time::Duration};
+
+use aws_config::BehaviorVersion;
+
+use crate::{S3Origin, S3OriginBuilder};
+
+
+impl S3OriginBuilder {
+    /// Apply the `S3_ORIGIN_*` environment variables, see [`env`](crate::env).
+    ///
+    /// Fails if a variable has an invalid value; options not set in the environment keep their
+    /// current value.
+    ///
+    pub fn env(self) -> Result<Self, &'static str> {
+        self.apply_env(|name| std::env::var(name).ok())
+    }
+
+    fn apply_env(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self, &'static str> {
+        let var = |name: &str| var(name).filter(|value| !value.trim().is_empty());
+
+        if let Some(bucket) = var("S3_ORIGIN_BUCKET") {
+            self = self.bucket(bucket);
+        }
+        if let Some(prefix) = var("S3_ORIGIN_PREFIX") {
+            self = self.prefix(prefix);
+        }
+        if let Some(max_size) = parse(var("S3_ORIGIN_MAX_SIZE"), "S3_ORIGIN_MAX_SIZE must be a number of bytes")? {
+            self = self.max_size(max_size);
+        }
+        if let Some(prune_path) = parse(var("S3_ORIGIN_PRUNE_PATH"), "S3_ORIGIN_PRUNE_PATH must be a number")? {
+            self = self.prune_path(prune_path);
+        }
+        if let Some(enabled) = flag(var("S3_ORIGIN_CLEAN_URLS"), "S3_ORIGIN_CLEAN_URLS must be true or false")? {
+            self = self.clean_urls(enabled);
+        }
+        if let Some(enabled) = flag(var("S3_ORIGIN_IMMUTABLE_ASSETS"), "S3_ORIGIN_IMMUTABLE_ASSETS must be true or false")? {
+            self = self.immutable_assets(enabled);
+        }
+        if let Some(capacity) = parse(var("S3_ORIGIN_CACHE"), "S3_ORIGIN_CACHE must be a number of bytes")? {
+            self = self.cache(capacity);
+        }
+        if let Some(seconds) = parse(var("S3_ORIGIN_CACHE_TTL"), "S3_ORIGIN_CACHE_TTL must be a number of seconds")? {
+            self = self.cache_ttl(Duration::from_secs(seconds));
+        }
+        if let Some(anonymous) = flag(var("S3_ORIGIN_ANONYMOUS"), "S3_ORIGIN_ANONYMOUS must be true or false")? {
+            self = self.anonymous(anonymous);
+        }
+        if let Some(role_arn) = var("S3_ORIGIN_ROLE_ARN") {
+            let session_name = var("S3_ORIGIN_ROLE_SESSION_NAME").unwrap_or_else(|| "axum-static-s3".into());
+            self = self.assume_role(role_arn, session_name);
+        }
+        if let Some(account_id) = var("S3_ORIGIN_EXPECTED_BUCKET_OWNER") {
+            self = self.expected_bucket_owner(account_id);
+        }
+        if let Some(endpoint_url) = var("S3_ORIGIN_ENDPOINT_URL") {
+            self = self.endpoint_url(endpoint_url);
+        }
+        Ok(self)
+    }
+}
+
+
+impl S3Origin {
+    /// Build an origin from the `S3_ORIGIN_*` environment variables, see [`env`](crate::env).
+    ///
+    /// The AWS SDK config is loaded from the environment as well, with
+    /// `aws_config::load_defaults`.
+    ///
+    pub async fn from_env() -> Result<S3Origin, &'static str> {
+        let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        S3OriginBuilder::new().config(config).env()?.build()
+    }
+}
+
+
+fn parse<T: FromStr>(value: Option<String>, error: &'static str) -> Result<Option<T>, &'static str> {
+    value.map(|value| value.trim().parse().map_err(|_| error)).transpose()
+}
+
+
+fn flag(value: Option<String>, error: &'static str) -> Result<Option<bool>, &'static str> {
+    value.map(|value| match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(error),
+    }).transpose()
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use aws_sdk_s3::config::Region;
+
+    fn builder() -> S3OriginBuilder {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .build();
+        S3OriginBuilder::new().client(aws_sdk_s3::Client::from_conf(config))
+    }
+
+    #[test]
+    fn reads_variables() {
+        let env = HashMap::from([
+            ("S3_ORIGIN_BUCKET", "my-bucket"),
+            ("S3_ORIGIN_PREFIX", "site/"),
+            ("S3_ORIGIN_MAX_SIZE", "6291456"),
+            ("S3_ORIGIN_CLEAN_URLS", "yes"),
+            ("S3_ORIGIN_CACHE", " 1048576 "),
+            ("S3_ORIGIN_PRUNE_PATH", ""),
+        ]);
+        let origin = builder()
+            .apply_env(|name| env.get(name).map(|value| value.to_string()))
+            .unwrap()
+            .build()
+            .unwrap();
+        let debug = format!("{:?}", origin);
+        assert!(debug.contains("bucket: \"my-bucket\", bucket_prefix: \"site/\""));
+        assert!(debug.contains("prune_path: 0"));
+        assert!(debug.contains("max_size: Some(6291456)"));
+        assert!(debug.contains("clean_urls: true"));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let error = builder().apply_env(|name| (name == "S3_ORIGIN_MAX_SIZE").then(|| "6MB".into())).unwrap_err();
+        assert_eq!(error, "S3_ORIGIN_MAX_SIZE must be a number of bytes");
+        let error = builder().apply_env(|name| (name == "S3_ORIGIN_ANONYMOUS").then(|| "maybe".into())).unwrap_err();
+        assert_eq!(error, "S3_ORIGIN_ANONYMOUS must be true or false");
+    }
+}
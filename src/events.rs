@@ -0,0 +1,176 @@
+//! Cache invalidation from S3 event notifications.
+//!
+//! Long-running deployments with a [`cache`](crate::S3OriginBuilder::cache) serve overwritten
+//! objects until their TTL expires.  Configure the bucket to send `s3:ObjectCreated:*` and
+//! `s3:ObjectRemoved:*` notifications to an SQS queue (directly or through SNS) and pass every
+//! message body to [`S3Origin::handle_s3_event`]; the affected entries are evicted and fetched
+//! again on the next request.
+//!
+//! The queue is consumed by the application, e.g. in a Lambda SQS trigger or a polling task:
+//!
+//! ```rust,ignore
+//! loop {
+//!     let received = sqs.receive_message().queue_url(&queue_url).wait_time_seconds(20).send().await?;
+//!     for message in received.messages() {
+//!         if let Some(body) = message.body() {
+//!             origin.handle_s3_event(body)?;
+//!         }
+//!         sqs.delete_message().queue_url(&queue_url)
+//!             .set_receipt_handle(message.receipt_handle().map(str::to_owned))
+//!             .send().await?;
+//!     }
+//! }
+//! ```
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::S3Origin;
+
+
+/// A message that is not an S3 event notification.
+#[derive(Debug)]
+pub struct EventError(serde_json::Error);
+
+impl fmt::Display for EventError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed S3 event notification: {}", self.0)
+    }
+}
+
+impl std::error::Error for EventError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+
+/// An S3 event notification, an SNS notification wrapping one, or the `s3:TestEvent` sent
+/// when notifications are configured.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Message {
+    Records {
+        #[serde(rename = "Records")]
+        records: Vec<Record>,
+    },
+    Sns {
+        #[serde(rename = "Message")]
+        message: String,
+    },
+    Test {
+        #[serde(rename = "Event")]
+        _event: String,
+    },
+}
+
+#[derive(Deserialize)]
+struct Record {
+    #[serde(rename = "eventName")]
+    event_name: String,
+    s3: Entity,
+}
+
+#[derive(Deserialize)]
+struct Entity {
+    bucket: Bucket,
+    object: Object,
+}
+
+#[derive(Deserialize)]
+struct Bucket {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Object {
+    /// URL-encoded, with spaces as `+`.
+    key: String,
+}
+
+
+/// The S3 keys changed by a notification message, for `bucket`.
+///
+/// With an access point ARN as `bucket` the records carry the underlying bucket name, so
+/// records of every bucket are used.
+fn changed_keys(message: &str, bucket: &str) -> Result<Vec<String>, EventError> {
+    let records = match serde_json::from_str(message).map_err(EventError)? {
+        Message::Records { records } => records,
+        Message::Sns { message } => return changed_keys(&message, bucket),
+        Message::Test { .. } => return Ok(Vec::new()),
+    };
+
+    let any_bucket = bucket.starts_with("arn:");
+    Ok(records.into_iter()
+        .filter(|record| ["ObjectCreated:", "ObjectRemoved:", "ObjectTagging:"].iter().any(|kind| record.event_name.starts_with(kind)))
+        .filter(|record| any_bucket || record.s3.bucket.name == bucket)
+        .map(|record| {
+            let key = record.s3.object.key.replace('+', " ");
+            percent_encoding::percent_decode_str(&key).decode_utf8_lossy().into_owned()
+        })
+        .collect())
+}
+
+
+impl S3Origin {
+    /// Evict the objects changed according to an S3 event notification from the cache.
+    ///
+    /// `message` is the body of an SQS message: an S3 event notification, or an SNS
+    /// notification wrapping one.  Test events and events for other buckets are ignored.
+    /// Returns the number of changed keys; nothing is evicted unless the cache is enabled.
+    /// Tagging events evict the cached tags of [required tags](crate::S3OriginBuilder::required_tags).
+    /// Keys are also invalidated in the [`cache_store`](crate::S3OriginBuilder::cache_store), in
+    /// the background when called within a Tokio runtime.
+    ///
+    pub fn handle_s3_event(&self, message: &str) -> Result<usize, EventError> {
+        let keys = changed_keys(message, &self.inner.bucket)?;
+        if let Some(cache) = &self.inner.cache {
+            for key in &keys {
+                self.inner.invalidate_cached(cache, key);
+            }
+        }
+        if let Some(required_tags) = &self.inner.required_tags {
+            for key in &keys {
+                required_tags.invalidate(key);
+            }
+        }
+        if let Some(store) = &self.inner.cache_store {
+            store.invalidate_in_background(keys.clone());
+        }
+        Ok(keys.len())
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    const EVENT: &str = r#"{"Records":[
+        {"eventName":"ObjectCreated:Put","s3":{"bucket":{"name":"my-bucket"},"object":{"key":"site/docs/read+me%C3%A9.html","size":12}}},
+        {"eventName":"ObjectRemoved:Delete","s3":{"bucket":{"name":"my-bucket"},"object":{"key":"site/old.js"}}},
+        {"eventName":"ObjectRestore:Completed","s3":{"bucket":{"name":"my-bucket"},"object":{"key":"site/archive.zip"}}},
+        {"eventName":"ObjectTagging:Put","s3":{"bucket":{"name":"my-bucket"},"object":{"key":"site/private.html"}}},
+        {"eventName":"ObjectCreated:Copy","s3":{"bucket":{"name":"other-bucket"},"object":{"key":"site/index.html"}}}
+    ]}"#;
+
+    #[test]
+    fn extracts_changed_keys() {
+        assert_eq!(changed_keys(EVENT, "my-bucket").unwrap(), ["site/docs/read meé.html", "site/old.js", "site/private.html"]);
+        assert_eq!(changed_keys(EVENT, "arn:aws:s3:us-east-1:123456789012:accesspoint/static").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn unwraps_sns_notifications() {
+        let sns = serde_json::json!({ "Type": "Notification", "Message": EVENT }).to_string();
+        assert_eq!(changed_keys(&sns, "my-bucket").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn ignores_test_events() {
+        let test = r#"{"Service":"Amazon S3","Event":"s3:TestEvent","Bucket":"my-bucket"}"#;
+        assert!(changed_keys(test, "my-bucket").unwrap().is_empty());
+        assert!(changed_keys("{}", "my-bucket").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! A/B variants selected by a request header or cookie.
+//!
+//! An [`Experiment`] maps the value of a header (e.g. `X-Experiment: b`, set by an edge
+//! function or experimentation platform) or a cookie to an alternate bucket prefix holding that
+//! variant of the site.  Requests without a known value are served as usual.  Every response
+//! carries `Vary` for the header (or `Cookie`) so that caches keep the variants apart.
+//!
+//! [`Experiment::assign`] deterministically picks a variant for a user or session id, for
+//! setting the header or cookie in the first place.  A variant in another bucket needs its own
+//! [`S3Origin`](crate::S3Origin), routed by the application.
+use axum::http::{header, HeaderMap, HeaderName};
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Selector {
+    Header(HeaderName),
+    Cookie(String),
+}
+
+
+/// Variants of the site keyed by a header or cookie value, see [`experiment`](crate::experiment).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Experiment {
+    selector: Selector,
+    /// Values and their prefixes, in the order they were added.
+    variants: Vec<(String, String)>,
+}
+
+impl Experiment {
+    /// Select the variant by the value of the request header `name`.
+    pub fn header(name: HeaderName) -> Self {
+        Self { selector: Selector::Header(name), variants: Vec::new() }
+    }
+
+    /// Select the variant by the value of the cookie `name`.
+    pub fn cookie(name: impl Into<String>) -> Self {
+        Self { selector: Selector::Cookie(name.into()), variants: Vec::new() }
+    }
+
+    /// Serve requests carrying `value` from `prefix` (a full bucket prefix, like
+    /// [`prefix`](crate::S3OriginBuilder::prefix)).
+    pub fn variant(mut self, value: impl Into<String>, prefix: impl Into<String>) -> Self {
+        self.variants.push((value.into(), prefix.into()));
+        self
+    }
+
+    /// Deterministically assign `id` (e.g. a user or session id) to one of the variant values.
+    ///
+    /// The same id always gets the same variant as long as the variants are unchanged, across
+    /// processes and releases of this crate.  Returns `None` without variants.
+    pub fn assign(&self, id: &str) -> Option<&str> {
+        // FNV-1a: stable, unlike the std hashers
+        let hash = id.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+        let index = hash.checked_rem(self.variants.len() as u64)?;
+        self.variants.get(index as usize).map(|(value, _)| value.as_str())
+    }
+
+    /// The prefix of the variant selected by the request.
+    pub(crate) fn prefix(&self, headers: &HeaderMap) -> Option<&str> {
+        let value = match &self.selector {
+            Selector::Header(name) => headers.get(name)?.to_str().ok()?.trim(),
+            Selector::Cookie(name) => headers.get_all(header::COOKIE).iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find(|(key, _)| key == name)?
+                .1,
+        };
+        self.variants.iter()
+            .find(|(variant, _)| variant == value)
+            .map(|(_, prefix)| prefix.as_str())
+    }
+
+    /// The `Vary` value for responses.
+    pub(crate) fn vary(&self) -> &str {
+        match &self.selector {
+            Selector::Header(name) => name.as_str(),
+            Selector::Cookie(_) => "cookie",
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn selects_variants() {
+        let experiment = Experiment::header(HeaderName::from_static("x-experiment"))
+            .variant("a", "variants/a/")
+            .variant("b", "variants/b/");
+        let headers = HeaderMap::from_iter([(HeaderName::from_static("x-experiment"), HeaderValue::from_static("b"))]);
+        assert_eq!(experiment.prefix(&headers), Some("variants/b/"));
+        assert_eq!(experiment.prefix(&HeaderMap::new()), None);
+        assert_eq!(experiment.vary(), "x-experiment");
+
+        let experiment = Experiment::cookie("exp").variant("a", "variants/a/");
+        let headers = HeaderMap::from_iter([(header::COOKIE, HeaderValue::from_static("sid=1; exp=a"))]);
+        assert_eq!(experiment.prefix(&headers), Some("variants/a/"));
+        assert_eq!(experiment.vary(), "cookie");
+    }
+
+    #[test]
+    fn assigns_deterministically() {
+        let experiment = Experiment::cookie("exp").variant("a", "a/").variant("b", "b/");
+        assert_eq!(experiment.assign("user-1"), experiment.assign("user-1"));
+        let assigned = (0..100).filter(|i| experiment.assign(&format!("user-{i}")) == Some("a")).count();
+        assert!((30..70).contains(&assigned));
+        assert_eq!(Experiment::cookie("exp").assign("user-1"), None);
+    }
+}
@@ -0,0 +1,111 @@
+//! Serving from S3 when the app has no route.
+//!
+//! [`S3FallbackLayer`] wraps an existing service (e.g. a whole `Router`) and retries `GET` and
+//! `HEAD` requests it answers with `404 Not Found` against an [`S3Origin`], so app routes come
+//! first and static files second:
+//!
+//! ```rust
+//! # fn example(origin: axum_static_s3::S3Origin) {
+//! use axum::{routing::get, Router};
+//! use axum_static_s3::fallback::S3FallbackLayer;
+//!
+//! let app: Router = Router::new()
+//!     .route("/api/health", get(|| async { "ok" }))
+//!     .layer(S3FallbackLayer::new(origin));
+//! # }
+//! ```
+//!
+//! The retried request carries the method, URI, headers and extensions of the original one
+//! (e.g. `ConnectInfo`, for [`ip_filter`](crate::S3OriginBuilder::ip_filter)).  If S3 answers
+//! `404 Not Found` too, the app's response is returned, keeping its error page.
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{Method, Request, StatusCode},
+    response::Response,
+    BoxError,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::S3Origin;
+
+
+/// Wraps services in an [`S3Fallback`].
+#[derive(Clone, Debug)]
+pub struct S3FallbackLayer {
+    origin: S3Origin,
+}
+
+impl S3FallbackLayer {
+    /// Retry requests the wrapped service cannot find against `origin`.
+    pub fn new(origin: S3Origin) -> Self {
+        Self { origin }
+    }
+}
+
+impl<S> Layer<S> for S3FallbackLayer {
+    type Service = S3Fallback<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        S3Fallback { inner, origin: self.origin.clone() }
+    }
+}
+
+
+/// A service retrying `404 Not Found` responses of `S` against an S3 origin, see
+/// [`fallback`](crate::fallback).
+#[derive(Clone, Debug)]
+pub struct S3Fallback<S> {
+    inner: S,
+    origin: S3Origin,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for S3Fallback<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // Only bodyless reads can be retried; keep their head for the S3 request
+        let retry = matches!(*req.method(), Method::GET | Method::HEAD).then(|| {
+            let mut retry = Request::new(());
+            *retry.method_mut() = req.method().clone();
+            *retry.uri_mut() = req.uri().clone();
+            *retry.version_mut() = req.version();
+            *retry.headers_mut() = req.headers().clone();
+            *retry.extensions_mut() = req.extensions().clone();
+            retry
+        });
+
+        let response = self.inner.call(req);
+        let mut origin = self.origin.clone();
+        Box::pin(async move {
+            let response = response.await?.map(Body::new);
+            let retry = match retry {
+                Some(retry) if response.status() == StatusCode::NOT_FOUND => retry,
+                _ => return Ok(response),
+            };
+            let fallback = origin.call(retry).await.unwrap_or_else(|never| match never {});
+            match fallback.status() {
+                StatusCode::NOT_FOUND => Ok(response),
+                _ => Ok(fallback),
+            }
+        })
+    }
+}
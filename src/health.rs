@@ -0,0 +1,185 @@
+//! Health checks for load balancers.
+//!
+//! [`S3Origin::health_router`] serves a readiness endpoint that sends a HeadObject for a sentinel
+//! key (by default `index.html` under the bucket prefix) and answers
+//!
+//! - `200 OK` with `{"status":"ok","latency_ms":12,"cached":false}` when the object exists;
+//! - `503 Service Unavailable` with `{"status":"unavailable","error":"NotFound","cached":false}`
+//!   when S3 cannot be reached, the request times out or the sentinel is missing.
+//!
+//! The result is reused for a few seconds, so frequent probes from several load balancers cost
+//! one S3 request, and concurrent probes wait for the same check.  Configure the sentinel key,
+//! cache duration and timeout with [`health_check`](crate::S3OriginBuilder::health_check).
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+use crate::{probe, S3Error, S3Origin, S3OriginInner};
+
+
+/// The health check configuration.
+#[derive(Clone, Debug)]
+pub struct HealthCheck {
+    key: String,
+    cache_for: Duration,
+    timeout: Duration,
+}
+
+impl HealthCheck {
+    /// Check the object at `key`, relative to the bucket prefix.
+    pub fn new(key: impl Into<String>) -> Self {
+        Self { key: key.into(), ..Self::default() }
+    }
+
+    /// Reuse a result for this long; defaults to 5 seconds.
+    pub fn cache_for(mut self, cache_for: Duration) -> Self {
+        self.cache_for = cache_for;
+        self
+    }
+
+    /// Report S3 as unavailable if the HeadObject takes longer than this; defaults to 2 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self { key: "index.html".into(), cache_for: Duration::from_secs(5), timeout: Duration::from_secs(2) }
+    }
+}
+
+
+/// The outcome of a check: the HeadObject latency, or the name of the failure.
+type Outcome = Result<Duration, &'static str>;
+
+
+/// A health check with its last outcome.
+#[derive(Debug)]
+pub(crate) struct HealthState {
+    check: HealthCheck,
+    /// Held while checking, so concurrent probes share one request.
+    last: tokio::sync::Mutex<Option<(Instant, Outcome)>>,
+}
+
+impl HealthState {
+    pub(crate) fn new(check: HealthCheck) -> Self {
+        Self { check, last: tokio::sync::Mutex::new(None) }
+    }
+
+    /// The current outcome, and whether it was cached.
+    async fn outcome(&self, origin: &S3OriginInner) -> (Outcome, bool) {
+        let mut last = self.last.lock().await;
+        if let Some((checked, outcome)) = *last {
+            if checked.elapsed() < self.check.cache_for {
+                return (outcome, true);
+            }
+        }
+        let key = format!("{}{}", origin.bucket_prefix(), self.check.key.trim_start_matches('/'));
+        let started = Instant::now();
+        let outcome = match tokio::time::timeout(self.check.timeout, probe::head(origin, &key)).await {
+            Ok(Ok(_)) => Ok(started.elapsed()),
+            Ok(Err(error)) => Err(error_name(error)),
+            Err(_) => Err("Timeout"),
+        };
+        *last = Some((Instant::now(), outcome));
+        (outcome, false)
+    }
+}
+
+
+fn error_name(error: S3Error) -> &'static str {
+    match error {
+        S3Error::NotFound => "NotFound",
+        S3Error::Throttled => "Throttled",
+        S3Error::CredentialsUnavailable => "CredentialsUnavailable",
+        S3Error::BadGateway => "BadGateway",
+        _ => "InternalServerError",
+    }
+}
+
+
+impl S3Origin {
+    /// A router answering health checks at `path`, e.g. `/healthz`, see [`health`](crate::health).
+    ///
+    /// Merge it into the application, e.g. `Router::new().merge(origin.health_router("/healthz"))`.
+    ///
+    pub fn health_router(&self, path: &str) -> Router {
+        Router::new()
+            .route(path, get(health))
+            .with_state(self.clone())
+    }
+}
+
+
+async fn health(State(origin): State<S3Origin>) -> Response {
+    let (outcome, cached) = origin.inner.health.outcome(&origin.inner).await;
+    let (status, body) = match outcome {
+        Ok(latency) => (
+            StatusCode::OK,
+            format!("{{\"status\":\"ok\",\"latency_ms\":{},\"cached\":{}}}", latency.as_millis(), cached),
+        ),
+        Err(error) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("{{\"status\":\"unavailable\",\"error\":\"{}\",\"cached\":{}}}", error, cached),
+        ),
+    };
+    let headers = [
+        (header::CONTENT_TYPE, HeaderValue::from_static("application/json")),
+        (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+    ];
+    (status, headers, body).into_response()
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use tower_service::Service;
+
+    use crate::testing::{MockObject, MockS3};
+
+    async fn call(router: &mut Router) -> (StatusCode, String) {
+        let response = router.call(axum::http::Request::get("/healthz").body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn reports_sentinel_availability() {
+        let s3 = MockS3::new("my-bucket");
+        let origin = s3.origin()
+            .prefix("site/")
+            .health_check(HealthCheck::new("/ready.txt").cache_for(Duration::from_secs(60)))
+            .build()
+            .unwrap();
+        let mut router = origin.health_router("/healthz");
+
+        let (status, body) = call(&mut router).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, "{\"status\":\"unavailable\",\"error\":\"NotFound\",\"cached\":false}");
+
+        // Cached until the check expires
+        s3.put("site/ready.txt", MockObject::new("ok"));
+        let (status, body) = call(&mut router).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.ends_with("\"cached\":true}"));
+        assert_eq!(s3.requested_keys(), ["site/ready.txt"]);
+        assert_eq!(s3.requests()[0].method, "HEAD");
+
+        let origin = s3.origin().prefix("site/").health_check(HealthCheck::new("ready.txt")).build().unwrap();
+        let (status, body) = call(&mut origin.health_router("/healthz")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.starts_with("{\"status\":\"ok\",\"latency_ms\":"));
+    }
+}
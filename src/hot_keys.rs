@@ -0,0 +1,205 @@
+//! Caching only the keys that are requested often.
+//!
+//! Picking a cache capacity and TTL is easy; picking which objects deserve the memory is not.
+//! With [`hot_keys`](crate::S3OriginBuilder::hot_keys) the origin counts requests per key over
+//! a sliding window and only admits a key into the in-memory cache once it is requested at
+//! least `threshold` times per window.  Without an explicit
+//! [`cache`](crate::S3OriginBuilder::cache), a cache of [`HotKeys::capacity`] is created for
+//! the promoted keys.
+//!
+//! [`S3Origin::hot_keys`] reports the promotions.
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::S3Origin;
+
+
+/// Keys tracked at most; further keys are only tracked once idle ones are dropped.
+const MAX_TRACKED: usize = 10_000;
+
+
+/// Hot-key detection settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HotKeys {
+    threshold: u32,
+    window: Duration,
+    capacity: usize,
+}
+
+impl Default for HotKeys {
+    /// Promote keys requested 10 times within 10 seconds, into a 16 MiB cache.
+    fn default() -> Self {
+        Self { threshold: 10, window: Duration::from_secs(10), capacity: 16 * 1024 * 1024 }
+    }
+}
+
+impl HotKeys {
+    /// Promote keys requested at least `threshold` times within `window`.
+    pub fn new(threshold: u32, window: Duration) -> Self {
+        Self { threshold: threshold.max(1), window, ..Self::default() }
+    }
+
+    /// Set the capacity of the cache created when no [`cache`](crate::S3OriginBuilder::cache)
+    /// is configured.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub(crate) fn cache_capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+
+/// Statistics of hot-key detection, see [`S3Origin::hot_keys`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HotKeyStats {
+    /// Keys admitted into the cache because they were hot, counting re-admissions.
+    pub promotions: u64,
+    /// The keys currently tracked.
+    pub tracked: usize,
+    /// The tracked keys that were promoted, with their current request rate per window.
+    pub promoted: Vec<(String, f64)>,
+}
+
+
+struct Counter {
+    window_start: Instant,
+    current: u32,
+    previous: u32,
+    promoted: bool,
+}
+
+impl Counter {
+    /// Move the window forward to `now`.
+    fn advance(&mut self, now: Instant, window: Duration) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= window * 2 {
+            (self.previous, self.current, self.window_start) = (0, 0, now);
+        } else if elapsed >= window {
+            (self.previous, self.current, self.window_start) = (self.current, 0, self.window_start + window);
+        }
+    }
+
+    /// Requests over the last window, weighting the previous window by its remaining overlap.
+    fn rate(&self, now: Instant, window: Duration) -> f64 {
+        let overlap = 1.0 - now.duration_since(self.window_start).as_secs_f64() / window.as_secs_f64().max(f64::EPSILON);
+        self.current as f64 + self.previous as f64 * overlap.clamp(0.0, 1.0)
+    }
+}
+
+
+/// Request counters per key.
+pub(crate) struct HotKeyTracker {
+    config: HotKeys,
+    counters: Mutex<HashMap<String, Counter>>,
+    promotions: AtomicU64,
+}
+
+impl HotKeyTracker {
+    pub(crate) fn new(config: HotKeys) -> Self {
+        Self { config, counters: Mutex::default(), promotions: AtomicU64::new(0) }
+    }
+
+    /// Count a request for `key` that missed the cache; returns whether it should be cached.
+    pub(crate) fn record(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let window = self.config.window;
+        let mut counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+        if !counters.contains_key(key) && counters.len() >= MAX_TRACKED {
+            counters.retain(|_, counter| now.duration_since(counter.window_start) < window * 2);
+            if counters.len() >= MAX_TRACKED {
+                return false;
+            }
+        }
+        let counter = counters.entry(key.to_owned())
+            .or_insert(Counter { window_start: now, current: 0, previous: 0, promoted: false });
+        counter.advance(now, window);
+        counter.current = counter.current.saturating_add(1);
+        let hot = counter.rate(now, window) >= self.config.threshold as f64;
+        if hot {
+            counter.promoted = true;
+            self.promotions.fetch_add(1, Ordering::Relaxed);
+        }
+        hot
+    }
+
+    pub(crate) fn stats(&self) -> HotKeyStats {
+        let now = Instant::now();
+        let window = self.config.window;
+        let mut counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut promoted: Vec<_> = counters.iter_mut()
+            .filter(|(_, counter)| counter.promoted)
+            .map(|(key, counter)| {
+                counter.advance(now, window);
+                (key.clone(), counter.rate(now, window))
+            })
+            .collect();
+        promoted.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        HotKeyStats {
+            promotions: self.promotions.load(Ordering::Relaxed),
+            tracked: counters.len(),
+            promoted,
+        }
+    }
+}
+
+impl std::fmt::Debug for HotKeyTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HotKeyTracker")
+            .field("config", &self.config)
+            .field("promotions", &self.promotions)
+            .finish()
+    }
+}
+
+
+impl S3Origin {
+    /// Statistics of hot-key detection, if [`hot_keys`](crate::S3OriginBuilder::hot_keys) is
+    /// enabled.
+    pub fn hot_keys(&self) -> Option<HotKeyStats> {
+        self.inner.hot_keys.as_ref().map(HotKeyTracker::stats)
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn promotes_frequent_keys() {
+        let tracker = HotKeyTracker::new(HotKeys::new(3, Duration::from_secs(60)));
+        assert!(!tracker.record("index.html"));
+        assert!(!tracker.record("index.html"));
+        assert!(!tracker.record("rare.html"));
+        assert!(tracker.record("index.html"));
+
+        let stats = tracker.stats();
+        assert_eq!((stats.promotions, stats.tracked), (1, 2));
+        assert_eq!(stats.promoted, [("index.html".to_owned(), 3.0)]);
+    }
+
+    #[test]
+    fn slides_the_window() {
+        let window = Duration::from_secs(10);
+        let start = Instant::now();
+        let mut counter = Counter { window_start: start, current: 8, previous: 0, promoted: false };
+
+        // Halfway through the next window, half of the previous one still counts
+        counter.advance(start + window + window / 2, window);
+        assert_eq!((counter.previous, counter.current), (8, 0));
+        assert_eq!(counter.rate(start + window + window / 2, window), 4.0);
+
+        counter.advance(start + window * 4, window);
+        assert_eq!((counter.previous, counter.current), (0, 0));
+    }
+}
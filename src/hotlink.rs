@@ -0,0 +1,167 @@
+//! Hotlink protection.
+//!
+//! With [`hotlink_protection`](crate::S3OriginBuilder::hotlink_protection), objects of the
+//! protected content types (images and video by default) are only served to pages on the
+//! allowed hosts, judged by the request's `Origin` or `Referer` header.  Other sites get
+//! `403 Forbidden` or a placeholder object instead.
+//!
+//! Requests without either header (direct navigation, or browsers hiding the referrer) are
+//! allowed unless [`allow_empty`](HotlinkProtection::allow_empty) is turned off.  Protected
+//! responses carry `Vary: Origin, Referer`, so shared caches keep the answers apart.
+//!
+//! The content type is only known from the S3 response, so a hotlinked request still costs
+//! the GetObject; the body is dropped unread.
+use axum::{
+    http::{header, HeaderMap},
+    response::Response,
+};
+
+
+/// Hotlink protection configuration.
+#[derive(Clone, Debug)]
+pub struct HotlinkProtection {
+    allowed_hosts: Vec<String>,
+    content_types: Vec<String>,
+    allow_empty: bool,
+    placeholder: Option<String>,
+}
+
+impl HotlinkProtection {
+    /// Allow embedding from these hosts, e.g. `example.com` or `*.example.com` for any
+    /// subdomain.  Ports are ignored.
+    pub fn new<I, S>(allowed_hosts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            allowed_hosts: allowed_hosts.into_iter().map(|host| host.into().to_ascii_lowercase()).collect(),
+            content_types: vec!["image/".into(), "video/".into()],
+            allow_empty: true,
+            placeholder: None,
+        }
+    }
+
+    /// Protect these content types; a value ending in `/` matches the whole type.
+    ///
+    /// Defaults to `image/` and `video/`.
+    pub fn content_types<I, S>(mut self, content_types: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.content_types = content_types.into_iter().map(|content_type| content_type.into().to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Whether requests without `Origin` and `Referer` headers are allowed; defaults to `true`.
+    pub fn allow_empty(mut self, allow_empty: bool) -> Self {
+        self.allow_empty = allow_empty;
+        self
+    }
+
+    /// Serve the object at this path (e.g. `/hotlink.png`) to other sites instead of
+    /// `403 Forbidden`.
+    pub fn placeholder(mut self, path: impl Into<String>) -> Self {
+        self.placeholder = Some(path.into());
+        self
+    }
+
+    /// The placeholder path, if any.
+    pub(crate) fn placeholder_path(&self) -> Option<&str> {
+        self.placeholder.as_deref()
+    }
+
+    /// Whether the request comes from an allowed page.
+    pub(crate) fn allows(&self, headers: &HeaderMap) -> bool {
+        let source = headers.get(header::ORIGIN).or_else(|| headers.get(header::REFERER));
+        let Some(source) = source.and_then(|source| source.to_str().ok()) else {
+            return self.allow_empty;
+        };
+        // `Origin: null` is sent from sandboxed and privacy-sensitive contexts
+        if source == "null" {
+            return self.allow_empty;
+        }
+        host(source).is_some_and(|host| self.allowed_hosts.iter().any(|allowed| matches_host(allowed, &host)))
+    }
+
+    /// Whether the response is of a protected content type.
+    pub(crate) fn protects(&self, response: &Response) -> bool {
+        let Some(content_type) = response.headers().get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()) else {
+            return false;
+        };
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        self.content_types.iter().any(|protected| match protected.ends_with('/') {
+            true => mime.starts_with(protected.as_str()),
+            false => mime == *protected,
+        })
+    }
+
+    /// Mark a protected response as depending on the requesting page.
+    pub(crate) fn vary(response: &mut Response) {
+        crate::vary::add(response.headers_mut(), "Origin, Referer");
+    }
+}
+
+
+/// The lowercase host of an `Origin` or `Referer` URL, without the port.
+fn host(url: &str) -> Option<String> {
+    let (_scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority.rsplit_once('@').map_or(authority, |(_userinfo, host)| host);
+    let host = match authority.strip_prefix('[') {
+        // IPv6 literal
+        Some(literal) => literal.split_once(']')?.0,
+        None => authority.split(':').next()?,
+    };
+    Some(host.to_ascii_lowercase())
+}
+
+
+fn matches_host(allowed: &str, host: &str) -> bool {
+    match allowed.strip_prefix("*.") {
+        Some(domain) => host.strip_suffix(domain).is_some_and(|subdomain| subdomain.ends_with('.')),
+        None => allowed == host,
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use axum::{http::HeaderValue, response::IntoResponse};
+
+    fn headers(name: header::HeaderName, value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(name, HeaderValue::from_static(value))])
+    }
+
+    #[test]
+    fn allows_listed_hosts() {
+        let protection = HotlinkProtection::new(["example.com", "*.example.org"]);
+        assert!(protection.allows(&headers(header::REFERER, "https://example.com/blog/post")));
+        assert!(protection.allows(&headers(header::REFERER, "https://EXAMPLE.com:8443/")));
+        assert!(protection.allows(&headers(header::ORIGIN, "https://cdn.example.org")));
+        assert!(!protection.allows(&headers(header::ORIGIN, "https://example.org")));
+        assert!(!protection.allows(&headers(header::REFERER, "https://example.com.evil.net/")));
+        assert!(!protection.allows(&headers(header::REFERER, "https://notexample.com/")));
+        assert!(!protection.allows(&headers(header::REFERER, "https://example.com@evil.net/")));
+
+        assert!(protection.allows(&HeaderMap::new()));
+        assert!(protection.allows(&headers(header::ORIGIN, "null")));
+        assert!(!protection.clone().allow_empty(false).allows(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn protects_content_types() {
+        let response = |content_type: &'static str| ([(header::CONTENT_TYPE, content_type)], "").into_response();
+        let protection = HotlinkProtection::new(["example.com"]);
+        assert!(protection.protects(&response("image/png")));
+        assert!(protection.protects(&response("Video/MP4; codecs=avc1")));
+        assert!(!protection.protects(&response("text/html")));
+
+        let protection = protection.content_types(["application/pdf"]);
+        assert!(protection.protects(&response("application/pdf")));
+        assert!(!protection.protects(&response("image/png")));
+    }
+}
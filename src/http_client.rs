@@ -0,0 +1,105 @@
+//! Timeouts and connection pooling of the S3 client.
+//!
+//! The client built from `config` uses the SDK defaults: a 3.1 second connect timeout, no read or
+//! operation timeout, and an unbounded connection pool.  The [`S3OriginBuilder`] settings
+//! [`connect_timeout`](crate::S3OriginBuilder::connect_timeout),
+//! [`read_timeout`](crate::S3OriginBuilder::read_timeout),
+//! [`operation_timeout`](crate::S3OriginBuilder::operation_timeout),
+//! [`max_connections`](crate::S3OriginBuilder::max_connections) and
+//! [`pool_idle_timeout`](crate::S3OriginBuilder::pool_idle_timeout) tune them without building a
+//! client by hand.
+//!
+//! HTTP/2 is negotiated with ALPN where the endpoint offers it; S3 itself speaks HTTP/1.1, so
+//! every concurrent request needs its own connection there.
+//!
+//! [`S3OriginBuilder`]: crate::S3OriginBuilder
+use std::time::Duration;
+
+use aws_sdk_s3::{config::timeout::TimeoutConfig, Client as S3Client};
+use aws_smithy_http_client::{
+    pool::{Client, ConnectionPool},
+    tls::{rustls_provider::CryptoMode, Provider},
+};
+
+
+/// Client settings applied on [`build`](crate::S3OriginBuilder::build).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct HttpSettings {
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) operation_timeout: Option<Duration>,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) pool_idle_timeout: Option<Duration>,
+}
+
+impl HttpSettings {
+    /// `client` with the timeouts set, keeping those not set here, and a new connection pool if
+    /// it is configured.
+    pub(crate) fn apply(&self, client: S3Client) -> Result<S3Client, &'static str> {
+        let pooled = self.max_connections.is_some() || self.pool_idle_timeout.is_some();
+        let timed = self.connect_timeout.is_some() || self.read_timeout.is_some() || self.operation_timeout.is_some();
+        if !pooled && !timed {
+            return Ok(client);
+        }
+        let mut config = client.config().to_builder();
+        if timed {
+            let mut timeouts = TimeoutConfig::builder();
+            // Setting `None` would disable the timeout rather than keep the configured one
+            if let Some(timeout) = self.connect_timeout {
+                timeouts = timeouts.connect_timeout(timeout);
+            }
+            if let Some(timeout) = self.read_timeout {
+                timeouts = timeouts.read_timeout(timeout);
+            }
+            if let Some(timeout) = self.operation_timeout {
+                timeouts = timeouts.operation_timeout(timeout);
+            }
+            let configured = client.config().timeout_config().map(TimeoutConfig::to_builder).unwrap_or_default();
+            config = config.timeout_config(timeouts.take_unset_from(configured).build());
+        }
+        if pooled {
+            let mut pool = ConnectionPool::builder();
+            if let Some(max_connections) = self.max_connections {
+                if max_connections == 0 {
+                    return Err("max_connections must be positive");
+                }
+                pool = pool.max_connections_per_host(max_connections);
+            }
+            if let Some(idle_timeout) = self.pool_idle_timeout {
+                pool = pool.idle_timeout(idle_timeout);
+            }
+            let pool = pool.tls_provider(Provider::Rustls(CryptoMode::AwsLc))
+                .build_https()
+                .map_err(|_| "invalid S3 client connection pool")?;
+            let http_client = Client::new(&pool).map_err(|_| "invalid S3 client connection pool")?;
+            config = config.http_client(http_client);
+        }
+        Ok(S3Client::from_conf(config.build()))
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::config::BehaviorVersion;
+
+    #[test]
+    fn merges_timeouts() {
+        let timeouts = TimeoutConfig::builder()
+            .connect_timeout(Duration::from_secs(5))
+            .operation_timeout(Duration::from_secs(60))
+            .build();
+        let config = aws_sdk_s3::Config::builder().behavior_version(BehaviorVersion::latest()).timeout_config(timeouts).build();
+        let settings = HttpSettings { connect_timeout: Some(Duration::from_secs(1)), read_timeout: Some(Duration::from_secs(2)), ..HttpSettings::default() };
+        let client = settings.apply(S3Client::from_conf(config)).unwrap();
+        let timeouts = client.config().timeout_config().unwrap();
+        assert_eq!(timeouts.connect_timeout(), Some(Duration::from_secs(1)));
+        assert_eq!(timeouts.read_timeout(), Some(Duration::from_secs(2)));
+        assert_eq!(timeouts.operation_timeout(), Some(Duration::from_secs(60)));
+
+        let settings = HttpSettings { max_connections: Some(0), ..HttpSettings::default() };
+        assert_eq!(settings.apply(client).unwrap_err(), "max_connections must be positive");
+    }
+}
@@ -0,0 +1,307 @@
+//! Patching runtime values into HTML documents.
+//!
+//! Static builds often need one or two values that are only known at deploy time, such as
+//! an API URL.  An [`HtmlInjection`] rewrites `text/html` responses while streaming them:
+//!
+//! - [`placeholder`](HtmlInjection::placeholder) replaces every occurrence of a marker such as
+//!   `%%API_URL%%` with a value;
+//! - [`base_href`](HtmlInjection::base_href) inserts `<base href="…">` right after the
+//!   opening `<head>` tag;
+//! - [`csp_nonce`](HtmlInjection::csp_nonce) replaces a marker with a fresh nonce per
+//!   response and sends a `Content-Security-Policy` header carrying it.
+//!
+//! Only complete (`200 OK`), uncompressed documents are rewritten.  Their length changes, so
+//! `Content-Length` is dropped; the `ETag` is weakened, or dropped with a nonce, which makes
+//! every response unique.
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderValue, StatusCode},
+    response::Response,
+};
+use futures_core::Stream;
+use pin_project::pin_project;
+
+use crate::telemetry::{self, Feature};
+
+
+/// How far into a document the opening `<head>` tag is looked for.
+const HEAD_SEARCH_LIMIT: usize = 4096;
+
+
+/// Values to patch into HTML responses, see [`inject`](crate::inject).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HtmlInjection {
+    placeholders: Vec<(String, String)>,
+    base_href: Option<String>,
+    /// The nonce marker and the policy, with `{nonce}` where the nonce goes.
+    csp_nonce: Option<(String, String)>,
+}
+
+impl HtmlInjection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace every occurrence of `placeholder` with `value`.
+    ///
+    /// `value` is inserted verbatim; escape it for the context it appears in.
+    pub fn placeholder(mut self, placeholder: impl Into<String>, value: impl Into<String>) -> Self {
+        let placeholder = placeholder.into();
+        if !placeholder.is_empty() {
+            self.placeholders.push((placeholder, value.into()));
+        }
+        self
+    }
+
+    /// Insert `<base href="{href}">` after the opening `<head>` tag.
+    ///
+    /// The tag must appear within the first 4 KiB of the document.
+    pub fn base_href(mut self, href: impl Into<String>) -> Self {
+        self.base_href = Some(href.into());
+        self
+    }
+
+    /// Replace `placeholder` with a fresh nonce per response, and send `policy` with `{nonce}`
+    /// replaced by the nonce as `Content-Security-Policy`.
+    ///
+    /// E.g. `csp_nonce("%%CSP_NONCE%%", "script-src 'nonce-{nonce}' 'strict-dynamic'")` with
+    /// `<script nonce="%%CSP_NONCE%%">` in the documents.
+    pub fn csp_nonce(mut self, placeholder: impl Into<String>, policy: impl Into<String>) -> Self {
+        self.csp_nonce = Some((placeholder.into(), policy.into()));
+        self
+    }
+
+    /// Rewrite an HTML response; other responses are returned unchanged.
+    ///
+    /// Responses to HEAD requests get the same headers; their body is dropped unread.
+    pub(crate) fn apply(&self, mut response: Response) -> Response {
+        let headers = response.headers();
+        let is_html = headers.get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| crate::negotiation::essence(value) == "text/html");
+        if response.status() != StatusCode::OK || !is_html || headers.contains_key(header::CONTENT_ENCODING) {
+            return response;
+        }
+
+        let mut placeholders = self.placeholders.clone();
+        if let Some((placeholder, policy)) = &self.csp_nonce {
+            let nonce = nonce();
+            if let Ok(policy) = HeaderValue::try_from(policy.replace("{nonce}", &nonce)) {
+                response.headers_mut().insert(header::CONTENT_SECURITY_POLICY, policy);
+            }
+            placeholders.push((placeholder.clone(), nonce));
+        }
+
+        let headers = response.headers_mut();
+        headers.remove(header::CONTENT_LENGTH);
+        match (self.csp_nonce.is_some(), headers.remove(header::ETAG)) {
+            (false, Some(etag)) if !etag.as_bytes().starts_with(b"W/") => {
+                let mut weak = b"W/".to_vec();
+                weak.extend_from_slice(etag.as_bytes());
+                if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+                    headers.insert(header::ETAG, weak);
+                }
+            }
+            (false, Some(etag)) => {
+                headers.insert(header::ETAG, etag);
+            }
+            _ => {}
+        }
+
+        let rewriter = Rewriter {
+            placeholders,
+            base: self.base_href.as_ref().map(|href| format!("<base href=\"{}\">", href.replace('"', "&quot;"))),
+            pending: Vec::new(),
+        };
+        let mut response = response.map(|body| Body::from_stream(Rewrite {
+            body: body.into_data_stream(),
+            rewriter: Some(rewriter),
+        }));
+        telemetry::record(&mut response, Feature::HtmlInjection);
+        response
+    }
+}
+
+
+/// A random nonce: 128 bits, base64url.
+fn nonce() -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    (0..22).map(|_| char::from(ALPHABET[fastrand::usize(..64)])).collect()
+}
+
+
+/// Streaming placeholder replacement and `<base>` insertion.
+struct Rewriter {
+    placeholders: Vec<(String, String)>,
+    /// The `<base>` element while it has not been inserted.
+    base: Option<String>,
+    /// Bytes held back until they can be rewritten.
+    pending: Vec<u8>,
+}
+
+impl Rewriter {
+    fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(chunk);
+        if self.base.is_some() && !self.insert_base() && self.pending.len() < HEAD_SEARCH_LIMIT {
+            return Vec::new();
+        }
+        self.base = None;
+
+        // A placeholder starting before `safe` fits completely into the pending bytes
+        let longest = self.placeholders.iter().map(|(placeholder, _)| placeholder.len()).max().unwrap_or(1);
+        let safe = self.pending.len().saturating_sub(longest - 1);
+        self.replace(safe)
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        self.base = None;
+        self.replace(self.pending.len())
+    }
+
+    /// Insert the `<base>` element after `<head …>` if the tag is complete in `pending`.
+    fn insert_base(&mut self) -> bool {
+        let Some(base) = &self.base else {
+            return false;
+        };
+        let lowercase = self.pending.to_ascii_lowercase();
+        let head = lowercase.windows(5)
+            .enumerate()
+            .find(|(start, window)| {
+                *window == b"<head" && matches!(lowercase.get(start + 5), Some(b'>' | b' ' | b'\t' | b'\r' | b'\n'))
+            })
+            .map(|(start, _)| start);
+        let Some(end) = head.and_then(|start| lowercase[start..].iter().position(|byte| *byte == b'>').map(|end| start + end + 1)) else {
+            return false;
+        };
+        self.pending.splice(end..end, base.bytes());
+        self.base = None;
+        true
+    }
+
+    /// Rewrite and return the pending bytes before `safe`.
+    fn replace(&mut self, safe: usize) -> Vec<u8> {
+        let mut output = Vec::with_capacity(self.pending.len());
+        let mut i = 0;
+        'scan: while i < safe {
+            for (placeholder, value) in &self.placeholders {
+                if self.pending[i..].starts_with(placeholder.as_bytes()) {
+                    output.extend_from_slice(value.as_bytes());
+                    i += placeholder.len();
+                    continue 'scan;
+                }
+            }
+            output.push(self.pending[i]);
+            i += 1;
+        }
+        self.pending.drain(..i.min(self.pending.len()));
+        output
+    }
+}
+
+
+#[pin_project]
+struct Rewrite<S> {
+    #[pin]
+    body: S,
+    /// Taken once the body ends.
+    rewriter: Option<Rewriter>,
+}
+
+impl<S: Stream<Item = Result<Bytes, axum::Error>>> Stream for Rewrite<S> {
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            let Some(rewriter) = this.rewriter.as_mut() else {
+                return Poll::Ready(None);
+            };
+            match this.body.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    let output = rewriter.push(&chunk);
+                    if !output.is_empty() {
+                        return Poll::Ready(Some(Ok(output.into())));
+                    }
+                }
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Some(Err(error))),
+                Poll::Ready(None) => {
+                    let output = rewriter.finish();
+                    *this.rewriter = None;
+                    if !output.is_empty() {
+                        return Poll::Ready(Some(Ok(output.into())));
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    fn rewrite(injection: &HtmlInjection, chunks: &[&str]) -> String {
+        let mut rewriter = Rewriter {
+            placeholders: injection.placeholders.clone(),
+            base: injection.base_href.as_ref().map(|href| format!("<base href=\"{}\">", href)),
+            pending: Vec::new(),
+        };
+        let mut output = Vec::new();
+        for chunk in chunks {
+            output.extend(rewriter.push(chunk.as_bytes()));
+        }
+        output.extend(rewriter.finish());
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn replaces_placeholders_across_chunks() {
+        let injection = HtmlInjection::new().placeholder("%%API_URL%%", "https://api.example.com");
+        assert_eq!(
+            rewrite(&injection, &["<script>const api = \"%%API", "_URL%%\";</script>%%"]),
+            "<script>const api = \"https://api.example.com\";</script>%%",
+        );
+    }
+
+    #[test]
+    fn inserts_base_href() {
+        let injection = HtmlInjection::new().base_href("/app/");
+        assert_eq!(
+            rewrite(&injection, &["<html><HEAD lang=", "\"en\"><title>x</title></head>"]),
+            "<html><HEAD lang=\"en\"><base href=\"/app/\"><title>x</title></head>",
+        );
+        assert_eq!(rewrite(&injection, &["<header>no head</header>"]), "<header>no head</header>");
+    }
+
+    #[tokio::test]
+    async fn rewrites_html_responses() {
+        let injection = HtmlInjection::new().csp_nonce("%%NONCE%%", "script-src 'nonce-{nonce}'");
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(header::CONTENT_LENGTH, "35")
+            .header(header::ETAG, "\"abc\"")
+            .body(Body::from("<script nonce=\"%%NONCE%%\"></script>"))
+            .unwrap();
+
+        let response = injection.apply(response);
+        let policy = response.headers()[header::CONTENT_SECURITY_POLICY].to_str().unwrap().to_owned();
+        assert!(!response.headers().contains_key(header::CONTENT_LENGTH));
+        assert!(!response.headers().contains_key(header::ETAG));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let nonce = policy.strip_prefix("script-src 'nonce-").unwrap().strip_suffix('\'').unwrap();
+        assert_eq!(nonce.len(), 22);
+        assert_eq!(body, format!("<script nonce=\"{}\"></script>", nonce));
+
+        let css = Response::builder().header(header::CONTENT_TYPE, "text/css").body(Body::from("%%NONCE%%")).unwrap();
+        let css = injection.apply(css);
+        assert!(!css.headers().contains_key(header::CONTENT_SECURITY_POLICY));
+    }
+}
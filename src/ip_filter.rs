@@ -0,0 +1,149 @@
+//! IP allow and deny lists.
+//!
+//! With [`ip_filter`](crate::S3OriginBuilder::ip_filter), requests are checked against CIDR
+//! lists before any S3 call: a request from a denied network, or from outside the allowed
+//! networks when there are any, is answered with `403 Forbidden`.
+//!
+//! The client address is found as set by the origin's
+//! [`client_identity`](crate::S3OriginBuilder::client_identity): by default the peer address
+//! from axum's `ConnectInfo`, so the router must be served with
+//! `into_make_service_with_connect_info::<SocketAddr>()`.  Behind proxies or load balancers, it
+//! is read from the one forwarding header the proxies append to, `X-Forwarded-For` unless
+//! [`ClientIdentity::header`] names `Forwarded`; the other header is ignored, since clients can
+//! send it themselves.  Requests whose address is unknown are denied.
+use std::{net::IpAddr, str::FromStr};
+
+use axum::http::Request;
+use ipnet::IpNet;
+
+use crate::client_identity::ClientIdentity;
+
+
+/// IP filter configuration.
+#[derive(Clone, Debug, Default)]
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    trusted_proxies: Option<usize>,
+    /// The first entry that is not a network or address, reported by `build()`.
+    invalid: Option<String>,
+}
+
+impl IpFilter {
+    /// An empty filter, allowing every known address.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only allow requests from this network (e.g. `10.0.0.0/8`) or address, and any other
+    /// allowed ones.
+    pub fn allow(mut self, cidr: &str) -> Self {
+        match parse_net(cidr) {
+            Some(net) => self.allow.push(net),
+            None => { self.invalid.get_or_insert_with(|| cidr.to_owned()); }
+        }
+        self
+    }
+
+    /// Deny requests from this network or address, even if it is allowed.
+    pub fn deny(mut self, cidr: &str) -> Self {
+        match parse_net(cidr) {
+            Some(net) => self.deny.push(net),
+            None => { self.invalid.get_or_insert_with(|| cidr.to_owned()); }
+        }
+        self
+    }
+
+    /// Take the client address from the forwarding header set by this many proxies, overriding
+    /// [`ClientIdentity::trusted_proxies`] for the filter.
+    ///
+    /// The header is the origin's [`ClientIdentity::header`], `X-Forwarded-For` by default.
+    ///
+    /// Defaults to the origin's [`client_identity`](crate::S3OriginBuilder::client_identity).
+    pub fn trusted_proxies(mut self, hops: usize) -> Self {
+        self.trusted_proxies = Some(hops);
+        self
+    }
+
+    /// Fails for entries that are not a network or address.
+    pub(crate) fn validate(&self) -> Result<(), &'static str> {
+        match self.invalid {
+            Some(_) => Err("invalid network in ip_filter"),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the request comes from an allowed address, as found by `identity`.
+    pub(crate) fn allows<B>(&self, req: &Request<B>, identity: ClientIdentity) -> bool {
+        match identity.with_trusted_proxies(self.trusted_proxies).client_ip(req) {
+            Some(ip) => self.allows_ip(ip),
+            None => false,
+        }
+    }
+
+    fn allows_ip(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        !self.deny.iter().any(|net| net.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+    }
+}
+
+
+/// A network, or a single address.
+fn parse_net(cidr: &str) -> Option<IpNet> {
+    let cidr = cidr.trim();
+    IpNet::from_str(cidr).ok()
+        .or_else(|| IpAddr::from_str(cidr).ok().map(IpNet::from))
+        .map(|net| net.trunc())
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn checks_lists() {
+        let filter = IpFilter::new().allow("10.0.0.0/8").allow("2001:db8::/32").deny("10.1.0.0/16");
+        assert!(filter.validate().is_ok());
+        assert!(filter.allows_ip(ip("10.2.3.4")));
+        assert!(filter.allows_ip(ip("::ffff:10.2.3.4")));
+        assert!(filter.allows_ip(ip("2001:db8::1")));
+        assert!(!filter.allows_ip(ip("10.1.2.3")));
+        assert!(!filter.allows_ip(ip("192.168.1.1")));
+
+        let filter = IpFilter::new().deny("192.168.1.1");
+        assert!(!filter.allows_ip(ip("192.168.1.1")));
+        assert!(filter.allows_ip(ip("192.168.1.2")));
+
+        assert!(IpFilter::new().allow("10.0.0.0/33").validate().is_err());
+        assert!(IpFilter::new().deny("intranet").validate().is_err());
+    }
+
+    #[test]
+    fn overrides_trusted_proxies() {
+        let request = Request::builder().header("x-forwarded-for", "6.6.6.6, 10.1.2.3").body(()).unwrap();
+        let filter = IpFilter::new().allow("10.0.0.0/8");
+        assert!(filter.allows(&request, ClientIdentity::new().trusted_proxies(1)));
+        assert!(!filter.allows(&request, ClientIdentity::new().trusted_proxies(2)));
+        assert!(filter.clone().trusted_proxies(1).allows(&request, ClientIdentity::new().trusted_proxies(2)));
+        // Without the peer address, the client is unknown
+        assert!(!filter.trusted_proxies(0).allows(&request, ClientIdentity::new().trusted_proxies(1)));
+    }
+
+    #[test]
+    fn ignores_spoofed_forwarded_headers() {
+        let request = Request::builder()
+            .header("forwarded", "for=10.1.2.3")
+            .header("x-forwarded-for", "6.6.6.6")
+            .body(())
+            .unwrap();
+        let filter = IpFilter::new().allow("10.0.0.0/8");
+        assert!(!filter.allows(&request, ClientIdentity::new().trusted_proxies(1)));
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt;
+
+use axum::extract::{NestedPath, OriginalUri};
+
+
+/// Maximum length of an S3 object key, in bytes.
+const MAX_KEY_LEN: usize = 1024;
+
+
+/// Where the request path used to build the S3 key comes from.
+///
+/// Under `Router::nest_service("/static", origin)` axum strips `/static` from the request URI
+/// before calling the origin, and records the full URI in the [`OriginalUri`] extension and
+/// the mount point in the [`NestedPath`] extension.  [`prune_path`](crate::S3OriginBuilder::prune_path)
+/// is applied after the path is selected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathSource {
+    /// The request URI as received by the origin (default).
+    ///
+    /// Under `nest_service` the mount prefix is already removed.
+    #[default]
+    Request,
+    /// The full URI as received by the router ([`OriginalUri`]), including any mount prefix.
+    ///
+    /// Use this when the bucket layout mirrors the public URL layout.
+    OriginalUri,
+    /// The [`OriginalUri`] with the [`NestedPath`] mount prefix removed.
+    ///
+    /// This is the path relative to the mount point even when layers between the router and the
+    /// origin rewrote the request URI.
+    Nested,
+}
+
+
+impl PathSource {
+    /// Select the request path, without its leading `/`.
+    ///
+    /// Falls back to the request URI when the extensions are missing (e.g. outside a `Router`).
+    pub(crate) fn path<'a, B>(&self, req: &'a axum::http::Request<B>) -> &'a str {
+        let request_path = req.uri().path();
+        let original_path = req.extensions()
+            .get::<OriginalUri>()
+            .map(|original| original.0.path());
+
+        let path = match (self, original_path) {
+            (PathSource::Request, _) | (_, None) => request_path,
+            (PathSource::OriginalUri, Some(original_path)) => original_path,
+            (PathSource::Nested, Some(original_path)) => {
+                match req.extensions().get::<NestedPath>() {
+                    Some(nested) => original_path
+                        .strip_prefix(nested.as_str().trim_end_matches('/'))
+                        .unwrap_or(original_path),
+                    None => original_path,
+                }
+            }
+        };
+        path.strip_prefix('/').unwrap_or(path)
+    }
+}
+
+
+/// The API Gateway stage (or other path prefix added in front of the app) of a request, as a
+/// request extension.
+///
+/// With [`stage_prefix`](crate::S3OriginBuilder::stage_prefix) the stage is removed from the
+/// start of the request path before [`prune_path`](crate::S3OriginBuilder::prune_path).  It
+/// is taken from this extension, e.g. inserted by a layer from the Lambda request context, or
+/// else from the `X-Forwarded-Prefix` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StagePrefix(pub String);
+
+
+/// Remove the stage of a request from the start of `path` (without leading `/`).
+///
+/// Paths that do not start with the stage are returned as is, so requests invoked both through
+/// the gateway and directly map to the same key.
+pub(crate) fn strip_stage<'a, B>(req: &axum::http::Request<B>, path: &'a str) -> &'a str {
+    let stage = match req.extensions().get::<StagePrefix>() {
+        Some(stage) => Some(stage.0.as_str()),
+        None => req.headers().get(X_FORWARDED_PREFIX).and_then(|prefix| prefix.to_str().ok()),
+    };
+    let Some(stage) = stage.map(|stage| stage.trim().trim_matches('/')).filter(|stage| !stage.is_empty()) else {
+        return path;
+    };
+    match path.strip_prefix(stage) {
+        Some("") => "",
+        Some(rest) => rest.strip_prefix('/').unwrap_or(path),
+        None => path,
+    }
+}
+
+
+const X_FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+
+
+/// Why a request path could not be mapped to an S3 key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyError {
+    /// The percent-decoded path is not valid UTF-8.
+    InvalidEncoding,
+    /// The key exceeds the S3 limit of 1024 bytes.
+    TooLong,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::InvalidEncoding => write!(f, "request path is not valid UTF-8 after percent-decoding"),
+            KeyError::TooLong => write!(f, "S3 key exceeds {} bytes", MAX_KEY_LEN),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+
+/// The S3 key a response was served from, inserted into the response extensions.
+///
+/// With [`clean_urls`](crate::S3OriginBuilder::clean_urls) this is the candidate that was
+/// served, or the last candidate tried for a `404`.  Responses produced without an S3 lookup
+/// (e.g. trailing-slash redirects, `405`) carry no key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolvedKey(pub String);
+
+
+/// Takes a request path (without leading `/`), trims the first `prune_path` components and
+/// creates a new S3 key
+///
+/// Components are pruned before percent-decoding, so an encoded `%2F` does not shift them.
+pub(crate) fn request_to_key(bucket_prefix: &str, path: &str, prune_path: usize) -> Result<String, KeyError> {
+    let request_path: String = match prune_path {
+        0 => path.to_string(),
+        _ => path.split('/').skip(prune_path).collect::<Vec<_>>().join("/"),
+    };
+
+    let request_path = percent_encoding::percent_decode_str(&request_path)
+        .decode_utf8()
+        .map_err(|_| KeyError::InvalidEncoding)?;
+
+    let key = format!("{}{}", bucket_prefix, request_path.trim_start_matches('/'));
+    match key.len() > MAX_KEY_LEN {
+        true => Err(KeyError::TooLong),
+        false => Ok(key),
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, routing::get};
+    use tower_service::Service;
+
+    #[test]
+    fn prunes_once() {
+        assert_eq!(request_to_key("deploy/", "stage/my-app/static/deployment/index.html", 3).unwrap(), "deploy/deployment/index.html");
+        assert_eq!(request_to_key("deploy/", "index.html", 0).unwrap(), "deploy/index.html");
+        assert_eq!(request_to_key("", "a/b", 5).unwrap(), "");
+    }
+
+    #[test]
+    fn strips_stages() {
+        let request = |prefix: Option<&str>| {
+            let mut request = axum::http::Request::builder();
+            if let Some(prefix) = prefix {
+                request = request.header("x-forwarded-prefix", prefix);
+            }
+            request.body(()).unwrap()
+        };
+        assert_eq!(strip_stage(&request(Some("/prod")), "prod/index.html"), "index.html");
+        assert_eq!(strip_stage(&request(Some("prod/")), "prod"), "");
+        assert_eq!(strip_stage(&request(Some("/prod")), "production/index.html"), "production/index.html");
+        assert_eq!(strip_stage(&request(Some("/prod")), "index.html"), "index.html");
+        assert_eq!(strip_stage(&request(Some("/")), "index.html"), "index.html");
+        assert_eq!(strip_stage(&request(None), "prod/index.html"), "prod/index.html");
+
+        // The extension takes precedence over the header
+        let mut request = request(Some("/prod"));
+        request.extensions_mut().insert(StagePrefix("v1/dev".into()));
+        assert_eq!(strip_stage(&request, "v1/dev/app.js"), "app.js");
+        assert_eq!(strip_stage(&request, "prod/app.js"), "prod/app.js");
+    }
+
+    #[test]
+    fn decodes_keys() {
+        assert_eq!(request_to_key("", "my%20file%C3%A9.txt", 0).unwrap(), "my file\u{e9}.txt");
+        assert_eq!(request_to_key("", "stage/a%2Fb", 1).unwrap(), "a/b");
+        assert_eq!(request_to_key("", "%FF", 0), Err(KeyError::InvalidEncoding));
+        assert_eq!(request_to_key("", &"a".repeat(1025), 0), Err(KeyError::TooLong));
+    }
+
+    /// Route a request through a nested router and report the path each source selects.
+    async fn nested_paths(uri: &str) -> String {
+        let handler = |req: axum::extract::Request| async move {
+            let (parts, _) = req.into_parts();
+            let req = axum::http::Request::from_parts(parts, ());
+            [PathSource::Request, PathSource::OriginalUri, PathSource::Nested]
+                .map(|source| source.path(&req).to_string())
+                .join(" | ")
+        };
+        let mut app = Router::new().nest("/static", Router::new().route("/{*path}", get(handler)));
+
+        let response = app.call(axum::http::Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn path_sources() {
+        assert_eq!(nested_paths("/static/css/site.css").await, "css/site.css | static/css/site.css | css/site.css");
+    }
+}
@@ -0,0 +1,161 @@
+//! Running in AWS Lambda.
+//!
+//! With the `lambda` feature, `run` and `run_with_streaming_response` serve Lambda HTTP
+//! events with an origin, see [the crate documentation](crate#aws-lambda).  The rest of this
+//! module is about objects too large for a buffered Lambda response.
+//!
+//! A Lambda function invoked through API Gateway, or a Function URL in `BUFFERED` mode, returns
+//! at most 6 MB, counting binary bodies base64-encoded.  Larger responses fail after the object
+//! was already fetched, and the client sees a `502`.  With
+//! [`lambda_response_limit`](crate::S3OriginBuilder::lambda_response_limit), objects that would
+//! not fit are answered with a `307 Temporary Redirect` to a presigned S3 URL instead, so the
+//! client downloads them from S3 directly:
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use axum_static_s3::lambda::LambdaLimit;
+//!
+//! let limit = LambdaLimit::new().expires_in(Duration::from_secs(60));
+//! ```
+//!
+//! The limit only applies when the origin runs in Lambda, detected by the
+//! `AWS_LAMBDA_FUNCTION_NAME` environment variable at [`build`](crate::S3OriginBuilder::build),
+//! so local development keeps streaming every object.  Functions streaming their responses with
+//! `run_with_streaming_response` do not need it.
+//!
+//! The presigned response carries the object's own metadata: `Cache-Control` rules and other
+//! header policies of the origin do not apply to it, and the client needs access to S3.  Objects
+//! of a custom [`backend`](crate::S3OriginBuilder::backend) cannot be presigned and still fail.
+use std::time::Duration;
+
+use aws_sdk_s3::presigning::PresigningConfig;
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{S3Error, S3OriginInner};
+
+#[cfg(feature = "lambda")]
+pub use crate::lambda_events::{run, run_with_streaming_response};
+
+
+/// The response payload limit of a buffered Lambda invocation.
+pub const PAYLOAD_LIMIT: u64 = 6 * 1024 * 1024;
+
+/// Room left in the payload for the status, headers and JSON envelope.
+const ENVELOPE: u64 = 64 * 1024;
+
+
+/// Lambda response limit configuration.
+#[derive(Clone, Debug)]
+pub struct LambdaLimit {
+    limit: u64,
+    expires_in: Duration,
+    detect_runtime: bool,
+}
+
+impl LambdaLimit {
+    /// The 6 MB limit, with presigned URLs valid for 5 minutes, applied in Lambda.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The payload limit in bytes; defaults to [`PAYLOAD_LIMIT`].
+    pub fn limit(mut self, bytes: u64) -> Self {
+        self.limit = bytes;
+        self
+    }
+
+    /// How long presigned URLs are valid; defaults to 5 minutes.
+    pub fn expires_in(mut self, expires_in: Duration) -> Self {
+        self.expires_in = expires_in;
+        self
+    }
+
+    /// Only apply the limit when running in Lambda; defaults to `true`.
+    pub fn detect_runtime(mut self, detect: bool) -> Self {
+        self.detect_runtime = detect;
+        self
+    }
+
+    /// The limit, if it applies in this environment.
+    pub(crate) fn resolve(self) -> Result<Option<Self>, &'static str> {
+        PresigningConfig::expires_in(self.expires_in).map_err(|_| "lambda_response_limit expiry must be at most 7 days")?;
+        match !self.detect_runtime || std::env::var_os("AWS_LAMBDA_FUNCTION_NAME").is_some() {
+            true => Ok(Some(self)),
+            false => Ok(None),
+        }
+    }
+
+    /// Whether a body of `length` bytes exceeds the limit; binary bodies are base64-encoded.
+    pub(crate) fn exceeds(&self, length: Option<i64>, content_type: Option<&str>) -> bool {
+        let Some(length) = length.and_then(|length| u64::try_from(length).ok()) else {
+            return false;
+        };
+        let encoded = match content_type.is_some_and(is_text) {
+            true => length,
+            false => length.div_ceil(3) * 4,
+        };
+        encoded + ENVELOPE > self.limit
+    }
+
+    /// A redirect to a presigned URL of `key`.
+    pub(crate) async fn redirect(&self, origin: &S3OriginInner, key: &str) -> Response {
+        let presigned = match PresigningConfig::expires_in(self.expires_in) {
+            Ok(config) => origin.s3_client.get_object().bucket(&origin.bucket).key(key).presigned(config).await,
+            Err(_) => return S3Error::MaxSizeExceeded.into_response(),
+        };
+        let location = match presigned.map(|request| HeaderValue::try_from(request.uri())) {
+            Ok(Ok(location)) => location,
+            _error => {
+                #[cfg(feature = "trace")]
+                tracing::warn!("S3Origin: presigning {} failed: {:?}", key, _error);
+                return S3Error::MaxSizeExceeded.into_response();
+            }
+        };
+        let headers = [
+            (header::LOCATION, location),
+            (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+        ];
+        (StatusCode::TEMPORARY_REDIRECT, headers).into_response()
+    }
+}
+
+impl Default for LambdaLimit {
+    fn default() -> Self {
+        Self { limit: PAYLOAD_LIMIT, expires_in: Duration::from_secs(300), detect_runtime: true }
+    }
+}
+
+
+/// Content types `lambda_http` sends as text rather than base64.
+fn is_text(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(essence.as_str(), "application/json" | "application/javascript" | "application/xml")
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_encoded_lengths() {
+        let limit = LambdaLimit::new().limit(1024 * 1024 + ENVELOPE);
+        assert!(!limit.exceeds(Some(1024 * 1024), Some("text/html; charset=utf-8")));
+        assert!(limit.exceeds(Some(1024 * 1024 + 1), Some("text/html")));
+        assert!(!limit.exceeds(Some(3 * 256 * 1024), Some("image/png")));
+        assert!(limit.exceeds(Some(3 * 256 * 1024 + 1), None));
+        assert!(!limit.exceeds(None, None));
+        assert!(is_text("application/ld+json"));
+        assert!(!is_text("application/octet-stream"));
+
+        assert!(LambdaLimit::new().detect_runtime(false).resolve().unwrap().is_some());
+        assert!(LambdaLimit::new().expires_in(Duration::from_secs(8 * 86_400)).resolve().is_err());
+    }
+}
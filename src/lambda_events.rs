@@ -0,0 +1,482 @@
+//! Running the origin as a Lambda function, see [`lambda`](crate::lambda).
+//!
+//! Events of API Gateway REST APIs (payload format 1.0), HTTP APIs and Function URLs (payload
+//! format 2.0) and Application Load Balancers are converted to requests, and responses back to
+//! the payload format of the event.
+use std::{collections::HashMap, net::{IpAddr, SocketAddr}};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, Response},
+};
+use base64::Engine as _;
+use lambda_runtime::{service_fn, Context, Error, LambdaEvent, MetadataPrelude, StreamResponse};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tower_service::Service;
+
+use crate::{S3Origin, StagePrefix};
+
+
+/// Characters escaped in paths and query strings that API Gateway delivers decoded.
+const ESCAPED: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'<').add(b'>').add(b'`').add(b'{').add(b'}');
+
+/// Query string components are additionally split at these.
+const ESCAPED_QUERY: &AsciiSet = &ESCAPED.add(b'&').add(b'=').add(b'+').add(b'?');
+
+
+/// An HTTP event in any of the supported payload formats.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HttpEvent {
+    version: Option<String>,
+    // Payload format 2.0
+    raw_path: Option<String>,
+    raw_query_string: Option<String>,
+    cookies: Option<Vec<String>>,
+    // Payload format 1.0 and load balancers
+    http_method: Option<String>,
+    path: Option<String>,
+    query_string_parameters: Option<HashMap<String, String>>,
+    multi_value_query_string_parameters: Option<HashMap<String, Vec<String>>>,
+    multi_value_headers: Option<HashMap<String, Vec<String>>>,
+    // Both
+    headers: Option<HashMap<String, String>>,
+    #[serde(default)]
+    request_context: RequestContext,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RequestContext {
+    stage: Option<String>,
+    http: Option<HttpContext>,
+    identity: Option<Identity>,
+    elb: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HttpContext {
+    method: String,
+    source_ip: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Identity {
+    source_ip: Option<String>,
+}
+
+
+/// The payload format an event arrived in, which its response must use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PayloadFormat {
+    /// API Gateway REST APIs and HTTP APIs with payload format 1.0.
+    V1,
+    /// HTTP APIs with payload format 2.0 and Function URLs.
+    V2,
+    /// Application Load Balancers, with multi-value headers if enabled on the target group.
+    Alb { multi_value: bool },
+}
+
+
+impl HttpEvent {
+    fn format(&self) -> PayloadFormat {
+        if self.version.as_deref() == Some("2.0") {
+            PayloadFormat::V2
+        } else if self.request_context.elb.is_some() {
+            PayloadFormat::Alb { multi_value: self.multi_value_headers.is_some() }
+        } else {
+            PayloadFormat::V1
+        }
+    }
+
+    /// The request the event describes, and its payload format.
+    ///
+    /// The body is not converted, since the origin never reads it.  The stage of the request
+    /// context is inserted as a [`StagePrefix`] and the source address as [`ConnectInfo`].
+    pub(crate) fn into_request(self) -> Result<(Request<()>, PayloadFormat), Error> {
+        let format = self.format();
+        let (method, uri, source_ip) = match format {
+            PayloadFormat::V2 => {
+                let http = self.request_context.http.as_ref().ok_or("event has no requestContext.http")?;
+                let query = self.raw_query_string.as_deref().filter(|query| !query.is_empty());
+                let uri = uri(self.raw_path.as_deref().unwrap_or("/"), query.map(str::to_owned));
+                (http.method.as_str(), uri, http.source_ip.as_deref())
+            }
+            PayloadFormat::V1 | PayloadFormat::Alb { .. } => {
+                let method = self.http_method.as_deref().ok_or("event has no httpMethod")?;
+                let path = utf8_percent_encode(self.path.as_deref().unwrap_or("/"), ESCAPED).to_string();
+                let uri = uri(&path, self.query_string(format));
+                let source_ip = self.request_context.identity.as_ref().and_then(|identity| identity.source_ip.as_deref());
+                (method, uri, source_ip)
+            }
+        };
+
+        let mut request = Request::builder()
+            .method(Method::from_bytes(method.as_bytes())?)
+            .uri(uri)
+            .body(())?;
+        let headers = request.headers_mut();
+        for (name, values) in self.header_values() {
+            let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+                continue;
+            };
+            for value in values {
+                if let Ok(value) = HeaderValue::from_str(value) {
+                    headers.append(name.clone(), value);
+                }
+            }
+        }
+        // Payload format 2.0 moves cookies out of the headers
+        let cookies = self.cookies.as_deref().unwrap_or_default().join("; ");
+        if let Some(cookies) = Some(cookies).filter(|cookies| !cookies.is_empty()).and_then(|cookies| HeaderValue::from_str(&cookies).ok()) {
+            headers.insert(header::COOKIE, cookies);
+        }
+
+        let stage = self.request_context.stage.as_deref().filter(|stage| !stage.is_empty() && *stage != "$default");
+        if let Some(stage) = stage {
+            request.extensions_mut().insert(StagePrefix(stage.to_owned()));
+        }
+        if let Some(ip) = source_ip.and_then(|ip| ip.parse::<IpAddr>().ok()) {
+            request.extensions_mut().insert(ConnectInfo(SocketAddr::new(ip, 0)));
+        }
+        Ok((request, format))
+    }
+
+    /// The header values, from the multi-value headers if there are any.
+    fn header_values(&self) -> Vec<(&str, Vec<&str>)> {
+        match (&self.multi_value_headers, &self.headers) {
+            (Some(headers), _) => headers.iter()
+                .map(|(name, values)| (name.as_str(), values.iter().map(String::as_str).collect()))
+                .collect(),
+            (None, Some(headers)) => headers.iter()
+                .map(|(name, value)| (name.as_str(), vec![value.as_str()]))
+                .collect(),
+            (None, None) => Vec::new(),
+        }
+    }
+
+    /// The query string of a 1.0 payload.  Load balancers pass parameters as received, API
+    /// Gateway decodes them.
+    fn query_string(&self, format: PayloadFormat) -> Option<String> {
+        let parameters = match (&self.multi_value_query_string_parameters, &self.query_string_parameters) {
+            (Some(parameters), _) => parameters.iter()
+                .flat_map(|(name, values)| values.iter().map(move |value| (name, value)))
+                .collect::<Vec<_>>(),
+            (None, Some(parameters)) => parameters.iter().collect(),
+            (None, None) => Vec::new(),
+        };
+        let encode = |component: &str| match format {
+            PayloadFormat::Alb { .. } => component.to_owned(),
+            _ => utf8_percent_encode(component, ESCAPED_QUERY).to_string(),
+        };
+        let mut pairs = parameters.into_iter()
+            .map(|(name, value)| format!("{}={}", encode(name), encode(value)))
+            .collect::<Vec<_>>();
+        // Maps are unordered; sort so equal requests have equal URIs
+        pairs.sort();
+        Some(pairs.join("&")).filter(|query| !query.is_empty())
+    }
+}
+
+
+fn uri(path: &str, query: Option<String>) -> String {
+    match query {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_owned(),
+    }
+}
+
+
+/// The buffered response payload for an event of `format`.
+///
+/// Text bodies are sent as they are, others base64-encoded.
+pub(crate) async fn buffered_response(response: Response<axum::body::Body>, format: PayloadFormat) -> Result<Value, Error> {
+    let (parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX).await?;
+    let text = is_text(&parts.headers).then(|| std::str::from_utf8(&body).ok()).flatten();
+    let (body, is_base64_encoded) = match text {
+        Some(text) => (text.to_owned(), false),
+        None => (base64::engine::general_purpose::STANDARD.encode(&body), true),
+    };
+
+    let mut payload = Map::new();
+    payload.insert("statusCode".into(), Value::from(parts.status.as_u16()));
+    let (cookies, headers) = split_cookies(&parts.headers);
+    match format {
+        PayloadFormat::V2 => {
+            payload.insert("headers".into(), Value::Object(joined_headers(&headers)));
+            payload.insert("cookies".into(), Value::from(cookies));
+        }
+        PayloadFormat::V1 | PayloadFormat::Alb { multi_value: true } => {
+            payload.insert("multiValueHeaders".into(), Value::Object(multi_value_headers(&parts.headers)));
+        }
+        PayloadFormat::Alb { multi_value: false } => {
+            payload.insert("headers".into(), Value::Object(joined_headers(&parts.headers)));
+        }
+    }
+    if let PayloadFormat::Alb { .. } = format {
+        let reason = parts.status.canonical_reason().unwrap_or_default();
+        payload.insert("statusDescription".into(), Value::from(format!("{} {}", parts.status.as_u16(), reason)));
+    }
+    payload.insert("body".into(), Value::from(body));
+    payload.insert("isBase64Encoded".into(), Value::Bool(is_base64_encoded));
+    Ok(Value::Object(payload))
+}
+
+
+/// The streaming response for a Function URL in `RESPONSE_STREAM` mode.
+pub(crate) fn streaming_response(response: Response<axum::body::Body>) -> StreamResponse<axum::body::BodyDataStream> {
+    let (parts, body) = response.into_parts();
+    let (cookies, headers) = split_cookies(&parts.headers);
+    StreamResponse {
+        metadata_prelude: MetadataPrelude { status_code: parts.status, headers, cookies },
+        stream: body.into_data_stream(),
+    }
+}
+
+
+/// The `Set-Cookie` values, and the other headers.
+fn split_cookies(headers: &HeaderMap) -> (Vec<String>, HeaderMap) {
+    let cookies = headers.get_all(header::SET_COOKIE).iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .collect();
+    let mut headers = headers.clone();
+    headers.remove(header::SET_COOKIE);
+    (cookies, headers)
+}
+
+/// Headers with several values joined by commas; values that are not text are dropped.
+fn joined_headers(headers: &HeaderMap) -> Map<String, Value> {
+    let mut joined = HashMap::<&str, String>::new();
+    for (name, value) in headers {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        joined.entry(name.as_str())
+            .and_modify(|joined| {
+                joined.push_str(", ");
+                joined.push_str(value);
+            })
+            .or_insert_with(|| value.to_owned());
+    }
+    joined.into_iter().map(|(name, value)| (name.to_owned(), Value::from(value))).collect()
+}
+
+fn multi_value_headers(headers: &HeaderMap) -> Map<String, Value> {
+    let mut values = HashMap::<&str, Vec<&str>>::new();
+    for (name, value) in headers {
+        if let Ok(value) = value.to_str() {
+            values.entry(name.as_str()).or_default().push(value);
+        }
+    }
+    values.into_iter().map(|(name, values)| (name.to_owned(), Value::from(values))).collect()
+}
+
+
+/// Whether a body with these headers can be sent as text.
+fn is_text(headers: &HeaderMap) -> bool {
+    let encoded = headers.get(header::CONTENT_ENCODING).is_some_and(|encoding| encoding != "identity");
+    let content_type = headers.get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    let text = content_type.starts_with("text/")
+        || content_type.ends_with("+json")
+        || content_type.ends_with("+xml")
+        || matches!(content_type.as_str(), "application/json" | "application/javascript" | "application/xml");
+    text && !encoded
+}
+
+
+/// Serve one invocation with `origin`.
+async fn serve(mut origin: S3Origin, event: HttpEvent, context: Context) -> Result<(Response<axum::body::Body>, PayloadFormat), Error> {
+    let (mut request, format) = event.into_request()?;
+    request.extensions_mut().insert(context);
+    std::future::poll_fn(|cx| Service::<Request<()>>::poll_ready(&mut origin, cx)).await?;
+    let response = origin.call(request).await?;
+    Ok((response, format))
+}
+
+
+/// Run `origin` as the handler of a Lambda function with buffered responses.
+///
+/// Use this behind API Gateway, a load balancer or a Function URL in `BUFFERED` mode; see
+/// [`lambda_response_limit`](crate::S3OriginBuilder::lambda_response_limit) for objects over
+/// the 6 MB response limit.
+pub async fn run(origin: S3Origin) -> Result<(), Error> {
+    lambda_runtime::run(service_fn(move |event: LambdaEvent<HttpEvent>| {
+        let origin = origin.clone();
+        async move {
+            let (payload, context) = event.into_parts();
+            let (response, format) = serve(origin, payload, context).await?;
+            buffered_response(response, format).await
+        }
+    }))
+    .await
+}
+
+
+/// Run `origin` as the handler of a Lambda function streaming its responses.
+///
+/// Use this for Function URLs in `RESPONSE_STREAM` mode: bodies are streamed as they arrive
+/// from S3, and are not limited to 6 MB.
+pub async fn run_with_streaming_response(origin: S3Origin) -> Result<(), Error> {
+    lambda_runtime::run(service_fn(move |event: LambdaEvent<HttpEvent>| {
+        let origin = origin.clone();
+        async move {
+            let (payload, context) = event.into_parts();
+            let (response, _) = serve(origin, payload, context).await?;
+            Ok::<_, Error>(streaming_response(response))
+        }
+    }))
+    .await
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use aws_config::BehaviorVersion;
+    use futures_core::Stream as _;
+    use serde_json::json;
+
+    use crate::S3OriginBuilder;
+
+    fn event(json: Value) -> HttpEvent {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn origin() -> S3Origin {
+        let config = aws_sdk_s3::Config::builder().behavior_version(BehaviorVersion::latest()).build();
+        S3OriginBuilder::new()
+            .bucket("my-bucket")
+            .prefix("site/")
+            .client(aws_sdk_s3::Client::from_conf(config))
+            .synthetic_response("/robots.txt", "text/plain", "User-agent: *")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn converts_rest_api_events() {
+        let (request, format) = event(json!({
+            "httpMethod": "GET",
+            "path": "/docs/a b.html",
+            "multiValueQueryStringParameters": {"v": ["1"], "q": ["a&b"]},
+            "queryStringParameters": {"v": "1", "q": "a&b"},
+            "multiValueHeaders": {"accept": ["text/html", "*/*"]},
+            "requestContext": {"stage": "prod", "identity": {"sourceIp": "192.0.2.1"}},
+            "body": null,
+            "isBase64Encoded": false
+        })).into_request().unwrap();
+        assert_eq!(format, PayloadFormat::V1);
+        assert_eq!(request.uri(), "/docs/a%20b.html?q=a%26b&v=1");
+        assert_eq!(request.headers().get_all(header::ACCEPT).iter().count(), 2);
+        assert_eq!(request.extensions().get::<StagePrefix>(), Some(&StagePrefix("prod".into())));
+        assert_eq!(request.extensions().get::<ConnectInfo<SocketAddr>>().unwrap().0.ip().to_string(), "192.0.2.1");
+        assert_eq!(origin().resolve_key(&request).unwrap(), "site/docs/a b.html");
+    }
+
+    #[test]
+    fn converts_http_api_events() {
+        let (request, format) = event(json!({
+            "version": "2.0",
+            "rawPath": "/prod/index.html",
+            "rawQueryString": "a=1&b=%20",
+            "cookies": ["session=1", "theme=dark"],
+            "headers": {"accept-encoding": "gzip, br"},
+            "requestContext": {"stage": "prod", "http": {"method": "HEAD", "sourceIp": "2001:db8::1"}},
+            "isBase64Encoded": false
+        })).into_request().unwrap();
+        assert_eq!(format, PayloadFormat::V2);
+        assert_eq!(request.method(), Method::HEAD);
+        assert_eq!(request.uri(), "/prod/index.html?a=1&b=%20");
+        assert_eq!(request.headers()[header::COOKIE], "session=1; theme=dark");
+        // The stage is removed from the path without pruning
+        assert_eq!(origin().resolve_key(&request).unwrap(), "site/index.html");
+
+        // Function URLs and default stages have no stage in the path
+        let (request, _) = event(json!({
+            "version": "2.0",
+            "rawPath": "/$default/index.html",
+            "requestContext": {"stage": "$default", "http": {"method": "GET"}}
+        })).into_request().unwrap();
+        assert!(request.extensions().get::<StagePrefix>().is_none());
+
+        assert!(event(json!({"version": "2.0", "rawPath": "/"})).into_request().is_err());
+        assert!(event(json!({"source": "aws.events"})).into_request().is_err());
+    }
+
+    #[test]
+    fn converts_load_balancer_events() {
+        let (request, format) = event(json!({
+            "httpMethod": "GET",
+            "path": "/index.html",
+            "queryStringParameters": {"q": "a%26b"},
+            "headers": {"host": "example.com"},
+            "requestContext": {"elb": {"targetGroupArn": "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/site/1"}}
+        })).into_request().unwrap();
+        assert_eq!(format, PayloadFormat::Alb { multi_value: false });
+        assert_eq!(request.uri(), "/index.html?q=a%26b");
+        assert_eq!(request.headers()[header::HOST], "example.com");
+    }
+
+    #[tokio::test]
+    async fn buffers_responses_in_the_event_format() {
+        let response = || Response::builder()
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(header::VARY, "Accept")
+            .header(header::VARY, "Accept-Encoding")
+            .header(header::SET_COOKIE, "a=1")
+            .body(axum::body::Body::from("<h1>hi</h1>"))
+            .unwrap();
+
+        let payload = buffered_response(response(), PayloadFormat::V2).await.unwrap();
+        assert_eq!(payload["statusCode"], 200);
+        assert_eq!(payload["headers"]["vary"], "Accept, Accept-Encoding");
+        assert_eq!(payload["cookies"], json!(["a=1"]));
+        assert_eq!(payload["body"], "<h1>hi</h1>");
+        assert_eq!(payload["isBase64Encoded"], false);
+
+        let payload = buffered_response(response(), PayloadFormat::V1).await.unwrap();
+        assert_eq!(payload["multiValueHeaders"]["vary"], json!(["Accept", "Accept-Encoding"]));
+        assert_eq!(payload["multiValueHeaders"]["set-cookie"], json!(["a=1"]));
+
+        let payload = buffered_response(response(), PayloadFormat::Alb { multi_value: false }).await.unwrap();
+        assert_eq!(payload["statusDescription"], "200 OK");
+        assert_eq!(payload["headers"]["content-type"], "text/html; charset=utf-8");
+
+        let binary = Response::builder()
+            .header(header::CONTENT_TYPE, "text/css")
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(axum::body::Body::from(vec![0x1f, 0x8b]))
+            .unwrap();
+        let payload = buffered_response(binary, PayloadFormat::V1).await.unwrap();
+        assert_eq!(payload["body"], "H4s=");
+        assert_eq!(payload["isBase64Encoded"], true);
+    }
+
+    #[tokio::test]
+    async fn streams_responses() {
+        let payload = event(json!({
+            "version": "2.0",
+            "rawPath": "/robots.txt",
+            "requestContext": {"http": {"method": "GET"}}
+        }));
+        let (mut response, _) = serve(origin(), payload, Context::default()).await.unwrap();
+        response.headers_mut().insert(header::SET_COOKIE, HeaderValue::from_static("a=1"));
+        let mut response = streaming_response(response);
+        assert_eq!(response.metadata_prelude.status_code, 200);
+        assert_eq!(response.metadata_prelude.cookies, ["a=1"]);
+        assert!(!response.metadata_prelude.headers.contains_key(header::SET_COOKIE));
+        let chunk = std::future::poll_fn(|cx| std::pin::Pin::new(&mut response.stream).poll_next(cx)).await;
+        assert_eq!(chunk.unwrap().unwrap(), "User-agent: *");
+    }
+}
@@ -0,0 +1,73 @@
+//! Routing to locale prefixes by `Accept-Language`.
+//!
+//! A localized site keeps each language below its own prefix (`en/`, `de/`, `ja/`) inside the
+//! bucket prefix.  With [`Locales`] configured, a request for `/about.html` is served from the
+//! best locale for the client's `Accept-Language`, or the default locale, with
+//! `Content-Language` and `Vary: Accept-Language`.  Requests that already start with a locale
+//! prefix (`/de/about.html`) are served from it as-is.
+use axum::http::{header, HeaderMap, HeaderValue};
+
+use crate::negotiation::{language, preferences};
+
+
+/// Configured locales and their prefixes, see [`locale`](crate::locale).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Locales {
+    /// Lowercase language tags; the first one is the default.
+    tags: Vec<String>,
+    /// Prefixes relative to the bucket prefix, by tag.
+    prefixes: Vec<String>,
+}
+
+impl Locales {
+    /// Serve the default locale `tag` (e.g. `en`) from `prefix` (e.g. `en/`, relative to the
+    /// bucket prefix) when no other locale matches.
+    pub fn new(tag: impl Into<String>, prefix: impl Into<String>) -> Self {
+        Self { tags: Vec::new(), prefixes: Vec::new() }.locale(tag, prefix)
+    }
+
+    /// Add the locale `tag` served from `prefix`.
+    pub fn locale(mut self, tag: impl Into<String>, prefix: impl Into<String>) -> Self {
+        self.tags.push(tag.into().to_ascii_lowercase());
+        self.prefixes.push(prefix.into().trim_start_matches('/').to_owned());
+        self
+    }
+
+    /// The locale for a path relative to the bucket prefix: its index and whether it was
+    /// negotiated (rather than given by the path).
+    pub(crate) fn select(&self, path: &str, headers: &HeaderMap) -> (usize, bool) {
+        if let Some(index) = self.prefixes.iter().position(|prefix| path.starts_with(prefix.as_str())) {
+            return (index, false);
+        }
+        let tags: Vec<&str> = self.tags.iter().map(String::as_str).collect();
+        let preferences = preferences(headers, &header::ACCEPT_LANGUAGE);
+        (language(preferences.as_deref(), &tags).unwrap_or(0), true)
+    }
+
+    pub(crate) fn prefix(&self, index: usize) -> &str {
+        &self.prefixes[index]
+    }
+
+    pub(crate) fn content_language(&self, index: usize) -> Option<HeaderValue> {
+        HeaderValue::from_str(&self.tags[index]).ok()
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_locales() {
+        let locales = Locales::new("en", "en/").locale("de", "/de/").locale("ja", "ja/");
+        let headers = HeaderMap::from_iter([(header::ACCEPT_LANGUAGE, HeaderValue::from_static("de-DE, en;q=0.5"))]);
+
+        assert_eq!(locales.select("about.html", &headers), (1, true));
+        assert_eq!(locales.select("about.html", &HeaderMap::new()), (0, true));
+        assert_eq!(locales.select("ja/about.html", &headers), (2, false));
+        assert_eq!(locales.prefix(1), "de/");
+        assert_eq!(locales.content_language(2).unwrap(), "ja");
+    }
+}
@@ -0,0 +1,146 @@
+//! Maintenance mode.
+//!
+//! While a [`Maintenance`] page is set, with [`S3Origin::set_maintenance`] or
+//! [`maintenance`](crate::S3OriginBuilder::maintenance), every request is answered with
+//! `503 Service Unavailable`, `Retry-After` and the page, without calling S3: e.g. during a
+//! bucket migration, or while the deployed build is known to be broken.
+//!
+//! The page is an inline body, or an object loaded once with [`S3Origin::load_maintenance`]:
+//!
+//! ```rust
+//! # async fn example(origin: axum_static_s3::S3Origin) -> Result<(), axum_static_s3::S3Error> {
+//! let page = origin.load_maintenance("maintenance.html").await?;
+//! origin.set_maintenance(Some(page.retry_after(600)));
+//! // ...
+//! origin.set_maintenance(None);
+//! # Ok(())
+//! # }
+//! ```
+use std::{fmt, sync::PoisonError};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{S3Error, S3Origin};
+
+
+/// A maintenance page.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Maintenance {
+    body: Bytes,
+    content_type: HeaderValue,
+    retry_after: u64,
+}
+
+impl Maintenance {
+    /// A page with this HTML body.
+    pub fn new(body: impl Into<Bytes>) -> Self {
+        Self {
+            body: body.into(),
+            content_type: HeaderValue::from_static("text/html; charset=utf-8"),
+            retry_after: 300,
+        }
+    }
+
+    /// Set the `Content-Type` of the page; defaults to `text/html; charset=utf-8`.
+    pub fn content_type(mut self, content_type: HeaderValue) -> Self {
+        self.content_type = content_type;
+        self
+    }
+
+    /// Set the `Retry-After` header, in seconds; defaults to 300.
+    pub fn retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = seconds;
+        self
+    }
+
+    /// The `503` response to a request with this method.
+    pub(crate) fn response(&self, method: &Method) -> Response {
+        let body = match method == Method::HEAD {
+            true => Body::empty(),
+            false => Body::from(self.body.clone()),
+        };
+        let mut response = (StatusCode::SERVICE_UNAVAILABLE, body).into_response();
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_TYPE, self.content_type.clone());
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(self.body.len()));
+        headers.insert(header::RETRY_AFTER, HeaderValue::from(self.retry_after));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        response
+    }
+}
+
+/// Prints the body length, not the body.
+impl fmt::Debug for Maintenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Maintenance")
+            .field("body", &format_args!("{} bytes", self.body.len()))
+            .field("content_type", &self.content_type)
+            .field("retry_after", &self.retry_after)
+            .finish()
+    }
+}
+
+
+impl S3Origin {
+    /// The current maintenance page, if maintenance mode is on.
+    ///
+    pub fn maintenance(&self) -> Option<Maintenance> {
+        self.inner.maintenance.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Turn maintenance mode on with a page, or off with `None`; applies to new requests.
+    ///
+    pub fn set_maintenance(&self, maintenance: Option<Maintenance>) {
+        #[cfg(feature = "trace")]
+        tracing::info!("S3Origin: maintenance mode {}", if maintenance.is_some() { "on" } else { "off" });
+        *self.inner.maintenance.write().unwrap_or_else(PoisonError::into_inner) = maintenance;
+    }
+
+    /// Load a maintenance page from an object, relative to the bucket prefix.
+    ///
+    /// The page keeps the object's `Content-Type`.  Load it ahead of time: while S3 is
+    /// unavailable, so is the page.
+    ///
+    pub async fn load_maintenance(&self, key: &str) -> Result<Maintenance, S3Error> {
+        let inner = &self.inner;
+        let output = inner.s3_client.get_object()
+            .bucket(&inner.bucket)
+            .key(format!("{}{}", inner.bucket_prefix(), key.trim_start_matches('/')))
+            .send()
+            .await?;
+        let content_type = output.content_type().and_then(|content_type| HeaderValue::from_str(content_type).ok());
+        let body = output.body.collect().await.map_err(|_| S3Error::InternalServerError)?.into_bytes();
+        let maintenance = Maintenance::new(body);
+        Ok(match content_type {
+            Some(content_type) => maintenance.content_type(content_type),
+            None => maintenance,
+        })
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn builds_responses() {
+        let maintenance = Maintenance::new("<h1>Back soon</h1>").retry_after(60);
+        assert_eq!(format!("{:?}", maintenance), r#"Maintenance { body: 18 bytes, content_type: "text/html; charset=utf-8", retry_after: 60 }"#);
+
+        let response = maintenance.response(&Method::GET);
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        assert_eq!(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap(), "<h1>Back soon</h1>");
+
+        let response = maintenance.response(&Method::HEAD);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "18");
+        assert!(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+//! Deployment manifests mapping request paths to object keys.
+//!
+//! A manifest is a JSON document, stored in the bucket or on local disk, that maps paths
+//! (relative to the bucket prefix) to object keys (also relative to the bucket prefix) with
+//! optional per-file response headers:
+//!
+//! ```json
+//! {
+//!   "paths": {
+//!     "index.html": "releases/42/index.html",
+//!     "app.js": {
+//!       "key": "releases/42/app.3f9ab2.js",
+//!       "headers": { "cache-control": "public, max-age=31536000, immutable" }
+//!     },
+//!     "": {
+//!       "key": "releases/42/index.html",
+//!       "preload": ["</app.js>; rel=preload; as=script"]
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! `preload` entries are sent as `Link` headers, see [`preload`](crate::preload).
+//!
+//! Uploading the files of a release under a new key prefix and then replacing the manifest
+//! switches every mapped path at once.  Paths missing from the manifest are served as usual.
+//!
+//! The manifest is loaded by the first request (or [`S3Origin::reload_manifest`]) and, with
+//! [`Manifest::refresh_interval`], reloaded in the background once it is older than the
+//! interval.  A manifest that fails to reload is kept.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::http::{HeaderName, HeaderValue};
+use serde::Deserialize;
+
+use crate::{S3Error, S3Origin, S3OriginInner};
+
+
+/// Where the manifest is loaded from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestSource {
+    /// An object in the origin's bucket; the key is not relative to the bucket prefix.
+    S3(String),
+    /// A local file.
+    File(PathBuf),
+}
+
+
+/// Manifest configuration, see [`S3OriginBuilder::manifest`](crate::S3OriginBuilder::manifest).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    source: ManifestSource,
+    refresh_interval: Option<Duration>,
+}
+
+impl Manifest {
+    /// Load the manifest from `key` in the origin's bucket.
+    pub fn s3(key: impl Into<String>) -> Self {
+        Self { source: ManifestSource::S3(key.into()), refresh_interval: None }
+    }
+
+    /// Load the manifest from a local file.
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Self { source: ManifestSource::File(path.into()), refresh_interval: None }
+    }
+
+    /// Reload the manifest once it is older than `interval`.
+    ///
+    /// This is optional; by default the manifest is only reloaded by
+    /// [`S3Origin::reload_manifest`].
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = Some(interval);
+        self
+    }
+}
+
+
+/// Why a manifest could not be loaded.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ManifestError {
+    /// The manifest object could not be fetched.
+    Fetch(S3Error),
+    /// The manifest file could not be read.
+    Read(std::io::Error),
+    /// The manifest is not valid JSON of the expected shape.
+    Parse(serde_json::Error),
+    /// A per-file header has an invalid name or value.
+    InvalidHeader(String),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Fetch(error) => write!(f, "failed to fetch manifest: {:?}", error),
+            ManifestError::Read(error) => write!(f, "failed to read manifest: {}", error),
+            ManifestError::Parse(error) => write!(f, "invalid manifest: {}", error),
+            ManifestError::InvalidHeader(name) => write!(f, "invalid header {:?} in manifest", name),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+
+#[derive(Deserialize)]
+struct ManifestFile {
+    paths: HashMap<String, EntryFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EntryFile {
+    Key(String),
+    Entry {
+        key: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        #[serde(default)]
+        preload: Vec<String>,
+    },
+}
+
+
+/// A mapped path.
+#[derive(Debug)]
+pub(crate) struct Entry {
+    /// Relative to the bucket prefix.
+    pub(crate) key: String,
+    pub(crate) headers: Vec<(HeaderName, HeaderValue)>,
+    /// `Link` values.
+    pub(crate) preload: Vec<HeaderValue>,
+}
+
+
+fn parse(json: &[u8]) -> Result<Paths, ManifestError> {
+    let file: ManifestFile = serde_json::from_slice(json).map_err(ManifestError::Parse)?;
+    file.paths.into_iter()
+        .map(|(path, entry)| {
+            let (key, headers, preload) = match entry {
+                EntryFile::Key(key) => (key, BTreeMap::new(), Vec::new()),
+                EntryFile::Entry { key, headers, preload } => (key, headers, preload),
+            };
+            let headers = headers.into_iter()
+                .map(|(name, value)| {
+                    let header = HeaderName::try_from(name.as_str()).ok()
+                        .zip(HeaderValue::try_from(value).ok());
+                    header.ok_or(ManifestError::InvalidHeader(name))
+                })
+                .collect::<Result<_, _>>()?;
+            let preload = preload.into_iter()
+                .map(|link| HeaderValue::try_from(link).map_err(|_| ManifestError::InvalidHeader("link".into())))
+                .collect::<Result<_, _>>()?;
+            let key = key.trim_start_matches('/').to_owned();
+            Ok((path.trim_start_matches('/').to_owned(), Arc::new(Entry { key, headers, preload })))
+        })
+        .collect()
+}
+
+
+/// Entries by path, relative to the bucket prefix and without a leading slash.
+pub(crate) type Paths = HashMap<String, Arc<Entry>>;
+
+
+/// The loaded manifest of an origin.
+pub(crate) struct ManifestState {
+    config: Manifest,
+    current: RwLock<Option<(Arc<Paths>, Instant)>>,
+    reloading: AtomicBool,
+}
+
+impl ManifestState {
+    pub(crate) fn new(config: Manifest) -> Self {
+        Self { config, current: RwLock::new(None), reloading: AtomicBool::new(false) }
+    }
+
+    async fn load(&self, origin: &S3OriginInner) -> Result<Arc<Paths>, ManifestError> {
+        let json = match &self.config.source {
+            ManifestSource::S3(key) => {
+                let output = origin.s3_client.get_object()
+                    .bucket(&origin.bucket)
+                    .key(key)
+                    .send()
+                    .await
+                    .map_err(|error| ManifestError::Fetch(error.into()))?;
+                output.body.collect().await
+                    .map_err(|_| ManifestError::Fetch(S3Error::InternalServerError))?
+                    .into_bytes()
+                    .to_vec()
+            }
+            ManifestSource::File(path) => tokio::fs::read(path).await.map_err(ManifestError::Read)?,
+        };
+        let paths = Arc::new(parse(&json)?);
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = Some((paths.clone(), Instant::now()));
+        Ok(paths)
+    }
+
+    /// The current manifest, loading it first if necessary.
+    pub(crate) async fn paths(&self, origin: &Arc<S3OriginInner>) -> Result<Arc<Paths>, S3Error> {
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner).clone();
+        match current {
+            Some((paths, loaded)) => {
+                let stale = self.config.refresh_interval.is_some_and(|interval| loaded.elapsed() >= interval);
+                if stale && !self.reloading.swap(true, Ordering::Relaxed) {
+                    let origin = origin.clone();
+                    tokio::spawn(async move {
+                        if let Some(manifest) = &origin.manifest {
+                            let _result = manifest.load(&origin).await;
+                            #[cfg(feature = "trace")]
+                            if let Err(error) = _result {
+                                tracing::warn!("S3Origin: keeping manifest, reload failed: {}", error);
+                            }
+                            manifest.reloading.store(false, Ordering::Relaxed);
+                        }
+                    });
+                }
+                Ok(paths)
+            }
+            None => self.load(origin).await.map_err(|error| {
+                #[cfg(feature = "trace")]
+                tracing::error!("S3Origin: {}", error);
+                match error {
+                    ManifestError::Fetch(S3Error::NotFound) => S3Error::BadGateway,
+                    ManifestError::Fetch(error) => error,
+                    _ => S3Error::BadGateway,
+                }
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for ManifestState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.config.fmt(f)
+    }
+}
+
+
+impl S3Origin {
+    /// Load the manifest now, e.g. at startup or after a deploy.
+    ///
+    /// Does nothing unless a [`manifest`](crate::S3OriginBuilder::manifest) is configured.
+    /// On error the previous manifest is kept.
+    ///
+    pub async fn reload_manifest(&self) -> Result<(), ManifestError> {
+        match &self.inner.manifest {
+            Some(manifest) => manifest.load(&self.inner).await.map(|_| ()),
+            None => Ok(()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_manifests() {
+        let paths = parse(br#"{"paths": {
+            "/index.html": "releases/42/index.html",
+            "app.js": {"key": "releases/42/app.3f9ab2.js", "headers": {"Cache-Control": "immutable"}},
+            "": {"key": "releases/42/index.html", "preload": ["</app.js>; rel=preload; as=script"]}
+        }}"#).unwrap();
+
+        assert_eq!(paths["index.html"].key, "releases/42/index.html");
+        assert!(paths["index.html"].headers.is_empty());
+        assert_eq!(paths["app.js"].key, "releases/42/app.3f9ab2.js");
+        assert_eq!(paths["app.js"].headers, [(axum::http::header::CACHE_CONTROL, HeaderValue::from_static("immutable"))]);
+        assert_eq!(paths[""].preload, ["</app.js>; rel=preload; as=script"]);
+    }
+
+    #[test]
+    fn rejects_invalid_manifests() {
+        assert!(matches!(parse(br#"{"files": {}}"#), Err(ManifestError::Parse(_))));
+        assert!(matches!(
+            parse(br#"{"paths": {"a": {"key": "b", "headers": {"bad name": "x"}}}}"#),
+            Err(ManifestError::InvalidHeader(_))
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+//! Markdown to HTML rendering (feature `markdown`).
+//!
+//! When enabled with [`S3OriginBuilder::markdown`](crate::S3OriginBuilder::markdown), `.md` and
+//! `.markdown` objects are rendered to HTML for clients that prefer `text/html` (e.g. a browser),
+//! see [`render`](crate::render) for the negotiation rules.  Other clients receive the raw Markdown.
+//!
+//! The Markdown source is buffered up to [`MarkdownRenderer::max_source_size`] before rendering,
+//! since reference links may point anywhere in the document; larger objects are served raw.
+//! Raw HTML inside the Markdown is passed through, so only render trusted content.
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+use crate::render::{escape_html, Render, DEFAULT_MAX_SOURCE_SIZE};
+
+
+/// Default HTML page template.
+pub const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{title}}</title>
+{{stylesheet}}
+</head>
+<body>
+{{content}}
+</body>
+</html>
+"#;
+
+
+/// Renders Markdown objects into an HTML page.
+///
+/// The page template may contain the placeholders `{{title}}` (the text of the first heading),
+/// `{{stylesheet}}` (a `<link rel="stylesheet">` element, if a stylesheet is set) and
+/// `{{content}}` (the rendered Markdown).
+#[derive(Clone, Debug)]
+pub struct MarkdownRenderer {
+    template: String,
+    stylesheet: Option<String>,
+    max_source_size: usize,
+}
+
+
+impl MarkdownRenderer {
+    pub fn new() -> Self {
+        Self {
+            template: DEFAULT_TEMPLATE.to_string(),
+            stylesheet: None,
+            max_source_size: DEFAULT_MAX_SOURCE_SIZE,
+        }
+    }
+
+    /// Set the HTML page template.
+    ///
+    /// This is optional, and defaults to [`DEFAULT_TEMPLATE`].
+    ///
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    /// Set the URL of a stylesheet to link from the page.
+    ///
+    /// This is optional, and defaults to no stylesheet.
+    ///
+    pub fn stylesheet(mut self, href: impl Into<String>) -> Self {
+        self.stylesheet = Some(href.into());
+        self
+    }
+
+    /// Set the maximum size of a Markdown object that is rendered.
+    ///
+    /// This is optional, and defaults to [`DEFAULT_MAX_SOURCE_SIZE`](crate::render::DEFAULT_MAX_SOURCE_SIZE).
+    /// Larger objects are served as raw Markdown.
+    ///
+    pub fn max_source_size(mut self, max_source_size: usize) -> Self {
+        self.max_source_size = max_source_size;
+        self
+    }
+
+    /// Render a Markdown document into an HTML page.
+    pub fn render_html(&self, source: &str) -> String {
+        let options = Options::ENABLE_TABLES
+            | Options::ENABLE_STRIKETHROUGH
+            | Options::ENABLE_TASKLISTS
+            | Options::ENABLE_FOOTNOTES;
+
+        let mut content = String::with_capacity(source.len() * 3 / 2);
+        pulldown_cmark::html::push_html(&mut content, Parser::new_ext(source, options));
+
+        let stylesheet = self.stylesheet.as_deref()
+            .map(|href| format!(r#"<link rel="stylesheet" href="{}">"#, escape_html(href)))
+            .unwrap_or_default();
+
+        self.template
+            .replace("{{title}}", &escape_html(&title(source)))
+            .replace("{{stylesheet}}", &stylesheet)
+            .replace("{{content}}", &content)
+    }
+}
+
+impl Default for MarkdownRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl Render for MarkdownRenderer {
+    fn max_source_size(&self) -> usize {
+        self.max_source_size
+    }
+
+    fn render(&self, source: &[u8]) -> Vec<u8> {
+        self.render_html(&String::from_utf8_lossy(source)).into_bytes()
+    }
+}
+
+
+/// The text of the first heading, or an empty string.
+fn title(source: &str) -> String {
+    let mut title = String::new();
+    let mut in_heading = false;
+    for event in Parser::new(source) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => in_heading = true,
+            Event::End(TagEnd::Heading(_)) => break,
+            Event::Text(text) | Event::Code(text) if in_heading => title.push_str(&text),
+            _ => {}
+        }
+    }
+    title
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_into_template() {
+        let renderer = MarkdownRenderer::new()
+            .template("<title>{{title}}</title>{{stylesheet}}<main>{{content}}</main>")
+            .stylesheet("/docs.css");
+
+        let html = renderer.render_html("# Hello `<world>`\n\nSome *text*.\n");
+        assert_eq!(html, concat!(
+            "<title>Hello &lt;world&gt;</title>",
+            r#"<link rel="stylesheet" href="/docs.css">"#,
+            "<main><h1>Hello <code>&lt;world&gt;</code></h1>\n<p>Some <em>text</em>.</p>\n</main>",
+        ));
+    }
+}
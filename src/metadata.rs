@@ -0,0 +1,170 @@
+use std::time::{Duration, SystemTime};
+
+use aws_sdk_s3::{
+    config::http::HttpResponse,
+    error::SdkError,
+    operation::{
+        get_object::GetObjectOutput,
+        head_object::HeadObjectOutput,
+        RequestId, RequestIdExt,
+    },
+    primitives::DateTime,
+};
+
+use crate::conditional;
+
+
+/// Object metadata shared by GetObject and HeadObject responses.
+///
+/// Responses to GET and HEAD requests are both assembled from this, so both methods always
+/// produce the same status and headers.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ObjectMetadata {
+    pub(crate) content_type: Option<String>,
+    pub(crate) content_length: Option<i64>,
+    pub(crate) cache_control: Option<String>,
+    /// The raw `Expires` value; only used to derive cache TTLs.
+    pub(crate) expires: Option<String>,
+    pub(crate) content_disposition: Option<String>,
+    pub(crate) content_encoding: Option<String>,
+    /// The object's ETag, or a weak one synthesized when the backend returned none.
+    pub(crate) e_tag: Option<String>,
+    pub(crate) last_modified: Option<DateTime>,
+    pub(crate) website_redirect_location: Option<String>,
+    /// `x-amz-*` headers of the S3 response (lowercase names), including user metadata as
+    /// `x-amz-meta-*`.  Whether these are forwarded is decided by the origin's header policy.
+    pub(crate) amz_headers: Vec<(String, String)>,
+}
+
+
+/// GetObjectOutput and HeadObjectOutput expose the same accessors, but share no trait.
+macro_rules! impl_from_output {
+    ($output:ty) => {
+        impl From<&$output> for ObjectMetadata {
+            fn from(output: &$output) -> Self {
+                let mut amz_headers = Vec::new();
+                let mut push = |name: &str, value: Option<String>| {
+                    if let Some(value) = value {
+                        amz_headers.push((name.to_owned(), value));
+                    }
+                };
+
+                push("x-amz-request-id", output.request_id().map(str::to_owned));
+                push("x-amz-id-2", output.extended_request_id().map(str::to_owned));
+                push("x-amz-version-id", output.version_id().map(str::to_owned));
+                push("x-amz-expiration", output.expiration().map(str::to_owned));
+                push("x-amz-restore", output.restore().map(str::to_owned));
+                push("x-amz-website-redirect-location", output.website_redirect_location().map(str::to_owned));
+                push("x-amz-server-side-encryption", output.server_side_encryption().map(|v| v.as_str().to_owned()));
+                push("x-amz-server-side-encryption-aws-kms-key-id", output.ssekms_key_id().map(str::to_owned));
+                push("x-amz-storage-class", output.storage_class().map(|v| v.as_str().to_owned()));
+                push("x-amz-replication-status", output.replication_status().map(|v| v.as_str().to_owned()));
+                push("x-amz-mp-parts-count", output.parts_count().map(|v| v.to_string()));
+                push("x-amz-missing-meta", output.missing_meta().map(|v| v.to_string()));
+                push("x-amz-object-lock-mode", output.object_lock_mode().map(|v| v.as_str().to_owned()));
+
+                if let Some(metadata) = output.metadata() {
+                    let mut metadata: Vec<_> = metadata.iter().collect();
+                    metadata.sort();
+                    for (key, value) in metadata {
+                        push(&format!("x-amz-meta-{}", key.to_ascii_lowercase()), Some(value.clone()));
+                    }
+                }
+
+                // The size of the whole object, also for ranged responses
+                let size = output.content_range()
+                    .and_then(|range| range.rsplit_once('/')?.1.parse().ok())
+                    .or(output.content_length());
+                let e_tag = output.e_tag().map(str::to_owned)
+                    .or_else(|| Some(conditional::weak_etag(size?, output.last_modified()?)));
+
+                Self {
+                    content_type: output.content_type().map(str::to_owned),
+                    content_length: output.content_length(),
+                    cache_control: output.cache_control().map(str::to_owned),
+                    expires: output.expires_string().map(str::to_owned),
+                    content_disposition: output.content_disposition().map(str::to_owned),
+                    content_encoding: output.content_encoding().map(str::to_owned),
+                    e_tag,
+                    last_modified: output.last_modified().copied(),
+                    website_redirect_location: output.website_redirect_location().map(str::to_owned),
+                    amz_headers,
+                }
+            }
+        }
+    };
+}
+
+impl_from_output!(GetObjectOutput);
+impl_from_output!(HeadObjectOutput);
+
+
+impl ObjectMetadata {
+    /// Synthesize a weak ETag from the whole body of an object with neither ETag nor
+    /// modification time.
+    pub(crate) fn hash_e_tag(&mut self, body: &[u8]) {
+        if self.e_tag.is_none() {
+            self.e_tag = Some(conditional::hash_etag(body));
+        }
+    }
+}
+
+
+/// The object a response was served from, inserted into the response extensions.
+///
+/// Lets middleware (logging, billing, analytics) see exactly what was served without parsing
+/// headers.  Responses served from the cache carry the metadata of the cached object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct S3ObjectMeta {
+    pub bucket: String,
+    /// The full S3 key, including the bucket prefix.
+    pub key: String,
+    /// The object's ETag, or the weak one synthesized for backends without ETags.
+    pub etag: Option<String>,
+    pub version_id: Option<String>,
+    /// The length of the object, or of the range served.
+    pub content_length: Option<u64>,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl S3ObjectMeta {
+    pub(crate) fn new(bucket: &str, key: &str, metadata: &ObjectMetadata) -> Self {
+        Self {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            etag: metadata.e_tag.clone(),
+            version_id: metadata.amz_headers.iter()
+                .find(|(name, _)| name == "x-amz-version-id")
+                .map(|(_, version_id)| version_id.clone()),
+            content_length: metadata.content_length.and_then(|length| u64::try_from(length).ok()),
+            last_modified: metadata.last_modified.and_then(|date| SystemTime::try_from(date).ok()),
+        }
+    }
+}
+
+
+/// The S3 request IDs of the request a response was built from, in the response extensions.
+///
+/// AWS support needs both `x-amz-request-id` and `x-amz-id-2` to trace a request.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct S3RequestId {
+    pub(crate) request_id: String,
+    pub(crate) extended_request_id: Option<String>,
+}
+
+impl S3RequestId {
+    /// The IDs of a failed request, if S3 responded at all.
+    pub(crate) fn from_error<O, E>(result: &Result<O, SdkError<E, HttpResponse>>) -> Option<Self> {
+        let error = result.as_ref().err()?;
+        Some(Self {
+            request_id: error.request_id()?.to_owned(),
+            extended_request_id: error.extended_request_id().map(str::to_owned),
+        })
+    }
+}
+
+
+/// Time spent waiting for S3 responses while serving a request, in the response extensions.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct S3Latency(pub(crate) Duration);
@@ -0,0 +1,155 @@
+//! Which request methods are served, and how the others are rejected.
+//!
+//! By default `GET` and `HEAD` (see [`head_policy`](crate::S3OriginBuilder::head_policy)) are
+//! served, `OPTIONS` is answered with `204 No Content` and the allowed methods, and every other
+//! method is rejected with `405 Method Not Allowed`.  A [`MethodPolicy`] changes this:
+//!
+//! ```rust
+//! use axum::http::{Method, StatusCode};
+//! use axum_static_s3::MethodPolicy;
+//!
+//! let policy = MethodPolicy::new()
+//!     .reject(Method::TRACE, StatusCode::NOT_IMPLEMENTED)
+//!     .answer_options(false)
+//!     .otherwise(StatusCode::NOT_FOUND);
+//! ```
+use axum::{
+    http::{header, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{HeadPolicy, S3Error};
+
+
+/// Method handling configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MethodPolicy {
+    served: Vec<Method>,
+    rejected: Vec<(Method, StatusCode)>,
+    answer_options: bool,
+    otherwise: StatusCode,
+}
+
+impl Default for MethodPolicy {
+    fn default() -> Self {
+        Self {
+            served: vec![Method::GET, Method::HEAD],
+            rejected: Vec::new(),
+            answer_options: true,
+            otherwise: StatusCode::METHOD_NOT_ALLOWED,
+        }
+    }
+}
+
+impl MethodPolicy {
+    /// The default policy: serve `GET` and `HEAD`, answer `OPTIONS`, reject others with `405`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve this method like `GET`, e.g. `POST` for forms submitted to static pages.
+    pub fn serve(mut self, method: Method) -> Self {
+        self.rejected.retain(|(rejected, _)| *rejected != method);
+        if !self.served.contains(&method) {
+            self.served.push(method);
+        }
+        self
+    }
+
+    /// Reject this method with `status`, e.g. `TRACE` with `501 Not Implemented`, or `GET` and
+    /// `HEAD` to stop serving them.
+    pub fn reject(mut self, method: Method, status: StatusCode) -> Self {
+        self.served.retain(|served| *served != method);
+        self.rejected.retain(|(rejected, _)| *rejected != method);
+        self.rejected.push((method, status));
+        self
+    }
+
+    /// Set whether `OPTIONS` is answered with the allowed methods; defaults to `true`.
+    ///
+    /// When disabled, `OPTIONS` is rejected like any other method that is not served.
+    pub fn answer_options(mut self, enabled: bool) -> Self {
+        self.answer_options = enabled;
+        self
+    }
+
+    /// Set the status for methods neither served nor explicitly rejected; defaults to
+    /// `405 Method Not Allowed`.
+    ///
+    /// `404 Not Found` hides that the path exists for other methods.
+    pub fn otherwise(mut self, status: StatusCode) -> Self {
+        self.otherwise = status;
+        self
+    }
+
+    /// Whether the method is served.
+    pub(crate) fn serves(&self, method: &Method, head_policy: HeadPolicy) -> bool {
+        self.served.contains(method) && (method != Method::HEAD || head_policy != HeadPolicy::Disallow)
+    }
+
+    /// Whether `OPTIONS` requests are answered locally.
+    pub(crate) fn answers_options(&self, method: &Method) -> bool {
+        method == Method::OPTIONS && self.answer_options && !self.served.contains(method)
+            && !self.rejected.iter().any(|(rejected, _)| rejected == method)
+    }
+
+    /// The methods served, for `Allow` headers.
+    pub(crate) fn allow_header(&self, head_policy: HeadPolicy) -> HeaderValue {
+        let mut allowed = self.served.iter()
+            .filter(|method| self.serves(method, head_policy))
+            .map(Method::as_str)
+            .collect::<Vec<_>>();
+        if self.answer_options && !allowed.contains(&"OPTIONS") {
+            allowed.push("OPTIONS");
+        }
+        HeaderValue::from_str(&allowed.join(", ")).unwrap_or(HeaderValue::from_static("GET"))
+    }
+
+    /// The response to a method that is not served.
+    pub(crate) fn rejection(&self, method: &Method, head_policy: HeadPolicy) -> Response {
+        let status = self.rejected.iter()
+            .find(|(rejected, _)| rejected == method)
+            .map_or(self.otherwise, |(_, status)| *status);
+        match status {
+            StatusCode::NOT_FOUND => S3Error::NotFound.into_response(),
+            StatusCode::METHOD_NOT_ALLOWED => {
+                let mut response = S3Error::MethodNotAllowed.into_response();
+                response.headers_mut().insert(header::ALLOW, self.allow_header(head_policy));
+                response
+            }
+            status => status.into_response(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_methods() {
+        let policy = MethodPolicy::new();
+        assert!(policy.serves(&Method::GET, HeadPolicy::HeadObject));
+        assert!(!policy.serves(&Method::HEAD, HeadPolicy::Disallow));
+        assert!(policy.answers_options(&Method::OPTIONS));
+        assert_eq!(policy.allow_header(HeadPolicy::GetObject), "GET, HEAD, OPTIONS");
+        assert_eq!(policy.rejection(&Method::POST, HeadPolicy::GetObject).status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let policy = MethodPolicy::new()
+            .serve(Method::POST)
+            .reject(Method::HEAD, StatusCode::METHOD_NOT_ALLOWED)
+            .reject(Method::TRACE, StatusCode::NOT_IMPLEMENTED)
+            .answer_options(false)
+            .otherwise(StatusCode::NOT_FOUND);
+        assert!(policy.serves(&Method::POST, HeadPolicy::GetObject));
+        assert!(!policy.serves(&Method::HEAD, HeadPolicy::GetObject));
+        assert!(!policy.answers_options(&Method::OPTIONS));
+        assert_eq!(policy.allow_header(HeadPolicy::GetObject), "GET, POST");
+        assert_eq!(policy.rejection(&Method::TRACE, HeadPolicy::GetObject).status(), StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(policy.rejection(&Method::OPTIONS, HeadPolicy::GetObject).status(), StatusCode::NOT_FOUND);
+        let response = policy.rejection(&Method::HEAD, HeadPolicy::GetObject);
+        assert_eq!(response.headers()[header::ALLOW], "GET, POST");
+    }
+}
@@ -0,0 +1,204 @@
+//! Prometheus metrics.
+//!
+//! Every origin counts its requests by status class and records how long each took until the
+//! response head was ready.  [`S3Origin::metrics_router`] exposes these in the Prometheus text
+//! format, together with the other statistics the origin keeps:
+//!
+//! - `s3_origin_requests_total{status="2xx"}` and the `s3_origin_request_duration_seconds`
+//!   histogram;
+//! - `s3_origin_in_flight_requests`, and `s3_origin_concurrency_limit` with
+//!   [`adaptive_concurrency`](crate::S3OriginBuilder::adaptive_concurrency);
+//! - the S3 [cost](crate::cost) drivers `s3_origin_s3_requests_total{operation="GetObject"}`,
+//!   `s3_origin_s3_egress_bytes_total` and `s3_origin_cache_avoided_requests_total`, labelled
+//!   with the `bucket` and `prefix` of the origin;
+//! - `s3_origin_transfers_total{outcome="completed"|"aborted"}`, `s3_origin_aborted_bytes_total`
+//!   and `s3_origin_checksum_failures_total`;
+//! - `s3_origin_cache_hits_total`, `s3_origin_cache_misses_total`, `s3_origin_cache_size_bytes`,
+//!   `s3_origin_cache_capacity_bytes` and `s3_origin_cache_entries`, with
+//!   [`cache`](crate::S3OriginBuilder::cache);
+//! - `s3_origin_feature_requests_total{feature="cache"}`, with
+//!   [`feature_telemetry`](crate::S3OriginBuilder::feature_telemetry).
+//!
+//! Deployments without a metrics agent can scrape the origin directly.  The endpoint reveals no
+//! paths or keys, but like the [`admin`](crate::admin) router it is best mounted on an internal
+//! listener.
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+use crate::S3Origin;
+
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Status classes, `1xx` to `5xx`.
+const CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+
+/// Request counts and latencies of an origin.
+#[derive(Debug, Default)]
+pub(crate) struct RequestMetrics {
+    /// Requests by status class.
+    statuses: [AtomicU64; 5],
+    /// Requests by latency bucket, the last for those slower than every bound.
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    /// Total latency, in microseconds.
+    latency_micros: AtomicU64,
+}
+
+impl RequestMetrics {
+    pub(crate) fn record(&self, status: StatusCode, latency: Duration) {
+        let class = (status.as_u16() / 100).clamp(1, 5) as usize - 1;
+        self.statuses[class].fetch_add(1, Ordering::Relaxed);
+        let seconds = latency.as_secs_f64();
+        let bucket = BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_micros.fetch_add(latency.as_micros().try_into().unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    fn write(&self, out: &mut String) {
+        header(out, "s3_origin_requests_total", "counter", "Requests served, by status class.");
+        for (class, count) in CLASSES.iter().zip(&self.statuses) {
+            let _ = writeln!(out, "s3_origin_requests_total{{status=\"{}\"}} {}", class, count.load(Ordering::Relaxed));
+        }
+
+        header(out, "s3_origin_request_duration_seconds", "histogram", "Time until the response head was ready.");
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "s3_origin_request_duration_seconds_bucket{{le=\"{}\"}} {}", bound, cumulative);
+        }
+        cumulative += self.buckets[BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(out, "s3_origin_request_duration_seconds_bucket{{le=\"+Inf\"}} {}", cumulative);
+        let sum = self.latency_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "s3_origin_request_duration_seconds_sum {}", sum);
+        let _ = writeln!(out, "s3_origin_request_duration_seconds_count {}", cumulative);
+    }
+}
+
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// A label value, escaped.
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+
+impl S3Origin {
+    /// A router serving Prometheus metrics at `path`, e.g. `/metrics`, see [`metrics`](crate::metrics).
+    ///
+    /// Merge it into the application, e.g. `Router::new().merge(origin.metrics_router("/metrics"))`.
+    ///
+    pub fn metrics_router(&self, path: &str) -> Router {
+        Router::new()
+            .route(path, get(metrics))
+            .with_state(self.clone())
+    }
+
+    /// The metrics in the Prometheus text format.
+    fn prometheus_text(&self) -> String {
+        let mut out = String::new();
+        self.inner.requests.write(&mut out);
+        metric(&mut out, "s3_origin_in_flight_requests", "gauge", "Requests being served.", self.in_flight());
+        if let Some(limit) = self.concurrency_limit() {
+            metric(&mut out, "s3_origin_concurrency_limit", "gauge", "Current adaptive concurrency limit.", limit);
+        }
+
+        let transfers = self.transfer_stats();
+        header(&mut out, "s3_origin_transfers_total", "counter", "Object bodies streamed to clients, by outcome.");
+        let _ = writeln!(out, "s3_origin_transfers_total{{outcome=\"completed\"}} {}", transfers.completed);
+        let _ = writeln!(out, "s3_origin_transfers_total{{outcome=\"aborted\"}} {}", transfers.aborted);
+        metric(&mut out, "s3_origin_aborted_bytes_total", "counter", "Bytes not sent of aborted transfers.", transfers.aborted_bytes);
+        metric(&mut out, "s3_origin_checksum_failures_total", "counter", "Bodies failing checksum verification.", transfers.checksum_failures);
+
+        let cost = self.cost_stats();
+        let origin = format!("bucket=\"{}\",prefix=\"{}\"", label(&self.inner.bucket), label(&self.inner.bucket_prefix()));
+        header(&mut out, "s3_origin_s3_requests_total", "counter", "Requests sent to S3, by operation.");
+        for (operation, count) in [
+            ("GetObject", cost.get_requests),
+            ("HeadObject", cost.head_requests),
+            ("ListObjectsV2", cost.list_requests),
+            ("other", cost.other_requests),
+        ] {
+            let _ = writeln!(out, "s3_origin_s3_requests_total{{{},operation=\"{}\"}} {}", origin, operation, count);
+        }
+        header(&mut out, "s3_origin_s3_egress_bytes_total", "counter", "Bytes of object bodies S3 returned.");
+        let _ = writeln!(out, "s3_origin_s3_egress_bytes_total{{{}}} {}", origin, cost.bytes_egressed);
+        header(&mut out, "s3_origin_cache_avoided_requests_total", "counter", "Requests answered from the cache instead of S3.");
+        let _ = writeln!(out, "s3_origin_cache_avoided_requests_total{{{}}} {}", origin, cost.cache_avoided);
+
+        if let Some(stats) = self.cache_stats() {
+            metric(&mut out, "s3_origin_cache_hits_total", "counter", "Lookups answered from the cache.", stats.hits);
+            metric(&mut out, "s3_origin_cache_misses_total", "counter", "Lookups of keys that were not cached.", stats.misses);
+            metric(&mut out, "s3_origin_cache_size_bytes", "gauge", "Bytes currently cached.", stats.size);
+            metric(&mut out, "s3_origin_cache_capacity_bytes", "gauge", "Configured cache capacity.", stats.capacity);
+            metric(&mut out, "s3_origin_cache_entries", "gauge", "Cached objects.", stats.entries);
+        }
+
+        if let Some(counters) = self.feature_counters() {
+            header(&mut out, "s3_origin_feature_requests_total", "counter", "Requests an optional feature fired for.");
+            for (feature, count) in counters.snapshot() {
+                let _ = writeln!(out, "s3_origin_feature_requests_total{{feature=\"{}\"}} {}", feature, count);
+            }
+        }
+        out
+    }
+}
+
+
+async fn metrics(State(origin): State<S3Origin>) -> Response {
+    let headers = [
+        (header::CONTENT_TYPE, HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8")),
+        (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+    ];
+    (headers, origin.prometheus_text()).into_response()
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_requests() {
+        let metrics = RequestMetrics::default();
+        metrics.record(StatusCode::OK, Duration::from_millis(3));
+        metrics.record(StatusCode::NOT_MODIFIED, Duration::from_millis(30));
+        metrics.record(StatusCode::SERVICE_UNAVAILABLE, Duration::from_secs(20));
+        let mut out = String::new();
+        metrics.write(&mut out);
+
+        assert!(out.contains("# TYPE s3_origin_requests_total counter\n"));
+        assert!(out.contains("s3_origin_requests_total{status=\"2xx\"} 1\n"));
+        assert!(out.contains("s3_origin_requests_total{status=\"3xx\"} 1\n"));
+        assert!(out.contains("s3_origin_requests_total{status=\"4xx\"} 0\n"));
+        assert!(out.contains("s3_origin_requests_total{status=\"5xx\"} 1\n"));
+        assert!(out.contains("s3_origin_request_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(out.contains("s3_origin_request_duration_seconds_bucket{le=\"0.05\"} 2\n"));
+        assert!(out.contains("s3_origin_request_duration_seconds_bucket{le=\"10\"} 2\n"));
+        assert!(out.contains("s3_origin_request_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("s3_origin_request_duration_seconds_sum 20.033\n"));
+        assert!(out.contains("s3_origin_request_duration_seconds_count 3\n"));
+    }
+}
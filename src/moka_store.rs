@@ -0,0 +1,102 @@
+//! [`moka`] as the store of the in-memory cache.
+//!
+//! With the `moka` feature and [`moka_cache`](crate::S3OriginBuilder::moka_cache), cached
+//! objects live in a [`moka::sync::Cache`] instead of the built-in LRU: eviction is by weight
+//! (key and body length) with moka's TinyLFU admission, and entries expire once their TTL and
+//! staleness window have passed.  Bodies of objects with the same ETag are not shared.
+//!
+//! The store is synchronous because the cache is also used from synchronous code, such as
+//! [`S3Origin::handle_s3_event`](crate::S3Origin::handle_s3_event).
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::body::Bytes;
+use moka::{sync::Cache, Expiry};
+
+use crate::{cache::CachedObject, S3Origin};
+
+
+/// A cached object as stored in the moka cache, see [`S3Origin::moka_cache`].
+#[derive(Clone)]
+pub struct MokaEntry {
+    pub(crate) object: Arc<CachedObject>,
+    pub(crate) expires: Instant,
+    pub(crate) revalidating: Arc<AtomicBool>,
+}
+
+impl MokaEntry {
+    pub(crate) fn new(object: Arc<CachedObject>, expires: Instant) -> Self {
+        Self { object, expires, revalidating: Arc::default() }
+    }
+
+    pub fn body(&self) -> &Bytes {
+        &self.object.body
+    }
+
+    pub fn etag(&self) -> Option<&str> {
+        self.object.etag.as_deref()
+    }
+
+    /// When the entry stops being fresh; it is still served while stale.
+    pub fn expires(&self) -> Instant {
+        self.expires
+    }
+
+    /// Claim the revalidation of an expired entry; only the first caller gets `true`.
+    pub(crate) fn claim_revalidation(&self) -> bool {
+        !self.revalidating.swap(true, Ordering::Relaxed)
+    }
+
+    pub(crate) fn revalidation_failed(&self) {
+        self.revalidating.store(false, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for MokaEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MokaEntry")
+            .field("etag", &self.object.etag)
+            .field("len", &self.object.body.len())
+            .field("expires", &self.expires)
+            .finish()
+    }
+}
+
+
+/// Entries leave the cache once their staleness window has passed.
+struct StaleExpiry(Duration);
+
+impl Expiry<String, MokaEntry> for StaleExpiry {
+    fn expire_after_create(&self, _key: &String, value: &MokaEntry, created_at: Instant) -> Option<Duration> {
+        Some((value.expires + self.0).saturating_duration_since(created_at))
+    }
+
+    fn expire_after_update(&self, _key: &String, value: &MokaEntry, updated_at: Instant, _remaining: Option<Duration>) -> Option<Duration> {
+        Some((value.expires + self.0).saturating_duration_since(updated_at))
+    }
+}
+
+
+/// Build the moka cache for `capacity` bytes and a staleness window of `stale`.
+pub(crate) fn build(capacity: usize, stale: Duration) -> Cache<String, MokaEntry> {
+    Cache::builder()
+        .max_capacity(capacity as u64)
+        .weigher(|key: &String, entry: &MokaEntry| u32::try_from(key.len() + entry.object.body.len()).unwrap_or(u32::MAX))
+        .expire_after(StaleExpiry(stale))
+        .build()
+}
+
+
+impl S3Origin {
+    /// The underlying moka cache, if [`moka_cache`](crate::S3OriginBuilder::moka_cache) is
+    /// enabled, e.g. for inspecting its entries or weighted size.
+    pub fn moka_cache(&self) -> Option<&Cache<String, MokaEntry>> {
+        self.inner.cache.as_ref()?.moka()
+    }
+}
@@ -0,0 +1,184 @@
+//! Parsing of `Accept`-style request headers.
+use axum::http::{HeaderMap, HeaderName};
+
+
+/// A single entry of an `Accept`-style header: a value and its quality.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Preference {
+    pub(crate) value: String,
+    pub(crate) q: f32,
+}
+
+
+/// Parse all values of an `Accept`-style header (`Accept`, `Accept-Encoding`, `Accept-Language`).
+///
+/// Values are lowercased; entries with an unparsable quality are treated as `q=1`.  Returns
+/// `None` when the header is absent, which by convention means everything is acceptable.
+pub(crate) fn preferences(headers: &HeaderMap, name: &HeaderName) -> Option<Vec<Preference>> {
+    let mut values = headers.get_all(name).iter().peekable();
+    values.peek()?;
+
+    let preferences = values
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|entry| {
+            let mut params = entry.split(';');
+            let value = params.next()?.trim().to_ascii_lowercase();
+            if value.is_empty() {
+                return None;
+            }
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+            Some(Preference { value, q })
+        })
+        .collect();
+
+    Some(preferences)
+}
+
+
+/// The quality the client assigns to a media type under `Accept` preferences.
+///
+/// The most specific matching media range wins (`text/html` over `text/*` over `*/*`).
+/// Without preferences every media type has quality 1.
+pub(crate) fn media_quality(preferences: Option<&[Preference]>, media_type: &str) -> f32 {
+    let Some(preferences) = preferences else {
+        return 1.0;
+    };
+
+    let media_type = essence(media_type);
+    let main_type = media_type.split('/').next().unwrap_or_default();
+
+    let mut best: Option<(u8, f32)> = None;
+    for preference in preferences {
+        let specificity = if preference.value == media_type {
+            3
+        } else if preference.value.strip_suffix("/*") == Some(main_type) {
+            2
+        } else if preference.value == "*/*" {
+            1
+        } else {
+            continue;
+        };
+
+        if best.is_none_or(|(best, _)| specificity > best) {
+            best = Some((specificity, preference.q));
+        }
+    }
+
+    best.map(|(_, q)| q).unwrap_or(0.0)
+}
+
+
+/// The best of `available` language tags (lowercase) under `Accept-Language` preferences.
+///
+/// Ranges are tried in order of quality; a range matches a tag equal to it, a tag it is a more
+/// specific form of (`de-ch` → `de`) or a more specific tag (`de` → `de-ch`), in that order.
+/// Returns `None` without preferences, when nothing matches or for `*`.
+pub(crate) fn language(preferences: Option<&[Preference]>, available: &[&str]) -> Option<usize> {
+    let mut preferences: Vec<&Preference> = preferences?.iter().filter(|preference| preference.q > 0.0).collect();
+    preferences.sort_by(|a, b| b.q.total_cmp(&a.q));
+
+    let is_subtag = |tag: &str, of: &str| tag.strip_prefix(of).is_some_and(|rest| rest.starts_with('-'));
+    preferences.into_iter().find_map(|preference| {
+        let range = preference.value.as_str();
+        available.iter().position(|tag| *tag == range)
+            .or_else(|| available.iter().position(|tag| is_subtag(range, tag)))
+            .or_else(|| available.iter().position(|tag| is_subtag(tag, range)))
+    })
+}
+
+
+/// Extensions of the modern image formats the client explicitly accepts, best first.
+///
+/// Only explicit `image/avif` and `image/webp` entries count: `*/*` does not mean a client can
+/// decode them.  AVIF wins ties.
+pub(crate) fn image_formats(headers: &HeaderMap) -> Vec<&'static str> {
+    let Some(preferences) = preferences(headers, &axum::http::header::ACCEPT) else {
+        return Vec::new();
+    };
+    let quality = |media_type: &str| preferences.iter()
+        .filter(|preference| preference.value == media_type)
+        .map(|preference| preference.q)
+        .fold(0.0, f32::max);
+
+    let mut formats: Vec<(&'static str, f32)> = [("avif", quality("image/avif")), ("webp", quality("image/webp"))]
+        .into_iter()
+        .filter(|(_, q)| *q > 0.0)
+        .collect();
+    formats.sort_by(|a, b| b.1.total_cmp(&a.1));
+    formats.into_iter().map(|(extension, _)| extension).collect()
+}
+
+
+/// The media type without parameters, lowercased (`text/html; charset=utf-8` → `text/html`).
+pub(crate) fn essence(media_type: &str) -> String {
+    media_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase()
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use axum::http::{header, HeaderValue};
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn parses_preferences() {
+        let preferences = preferences(&accept("text/html, text/*;q=0.5 ,*/*; q=0.1"), &header::ACCEPT).unwrap();
+        assert_eq!(preferences, vec![
+            Preference { value: "text/html".into(), q: 1.0 },
+            Preference { value: "text/*".into(), q: 0.5 },
+            Preference { value: "*/*".into(), q: 0.1 },
+        ]);
+
+        assert!(super::preferences(&HeaderMap::new(), &header::ACCEPT).is_none());
+    }
+
+    #[test]
+    fn most_specific_range_wins() {
+        let preferences = preferences(&accept("text/html;q=0, text/*;q=0.5, */*;q=0.1"), &header::ACCEPT);
+        let preferences = preferences.as_deref();
+
+        assert_eq!(media_quality(preferences, "text/html; charset=utf-8"), 0.0);
+        assert_eq!(media_quality(preferences, "text/csv"), 0.5);
+        assert_eq!(media_quality(preferences, "image/png"), 0.1);
+        assert_eq!(media_quality(None, "image/png"), 1.0);
+
+        let preferences = super::preferences(&accept("text/html"), &header::ACCEPT);
+        assert_eq!(media_quality(preferences.as_deref(), "text/csv"), 0.0);
+    }
+
+    #[test]
+    fn accepts_image_formats() {
+        assert_eq!(image_formats(&accept("image/avif,image/webp,image/apng,*/*;q=0.8")), ["avif", "webp"]);
+        assert_eq!(image_formats(&accept("image/avif;q=0.5,image/webp")), ["webp", "avif"]);
+        assert!(image_formats(&accept("*/*")).is_empty());
+        assert!(image_formats(&HeaderMap::new()).is_empty());
+    }
+
+    #[test]
+    fn matches_languages() {
+        let language = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static(value));
+            super::language(preferences(&headers, &header::ACCEPT_LANGUAGE).as_deref(), &["en", "de", "pt-br"])
+        };
+
+        assert_eq!(language("fr, de;q=0.8, en;q=0.9"), Some(0));
+        assert_eq!(language("de-CH"), Some(1));
+        assert_eq!(language("pt"), Some(2));
+        assert_eq!(language("en;q=0, fr"), None);
+        assert_eq!(language("*"), None);
+        assert_eq!(super::language(None, &["en"]), None);
+    }
+}
@@ -0,0 +1,256 @@
+//! An [`ObjectBackend`] over the `object_store` crate.
+//!
+//! The crate is built without cloud stores; enable the `gcp`, `azure` or `aws` feature of
+//! `object_store` in the application to use them.
+use std::{
+    collections::HashMap,
+    fmt,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+};
+
+use ::object_store::{path::Path, Attribute, Attributes, GetOptions, GetRange, GetResult, ObjectMeta, ObjectStore};
+use aws_sdk_s3::{
+    operation::{get_object::GetObjectOutput, head_object::HeadObjectOutput},
+    primitives::{ByteStream, DateTime},
+};
+use axum::body::Bytes;
+use futures_core::stream::BoxStream;
+use http_body::{Body as HttpBody, Frame};
+
+use crate::{
+    backend::{BackendFuture, ObjectBackend},
+    range::ByteRange,
+    S3Error,
+};
+
+
+/// Objects in any [`ObjectStore`]: Google Cloud Storage, Azure Blob Storage, S3-compatible stores
+/// such as MinIO, local directories or memory.
+///
+/// Object keys are store paths; keys that are not valid paths (e.g. with empty segments) are
+/// not found.  Invalid or multiple byte ranges are ignored and the whole object is served, as
+/// S3 does.  Object attributes become the response metadata, with `Attribute::Metadata` as user
+/// metadata.
+pub struct ObjectStoreBackend {
+    store: Arc<dyn ObjectStore>,
+}
+
+impl ObjectStoreBackend {
+    /// The objects in `store`.
+    pub fn new(store: impl ObjectStore) -> Self {
+        Self { store: Arc::new(store) }
+    }
+
+    /// The objects in a store shared with the application.
+    pub fn from_arc(store: Arc<dyn ObjectStore>) -> Self {
+        Self { store }
+    }
+
+    async fn get_opts(&self, key: &str, options: GetOptions) -> Result<GetResult, S3Error> {
+        let path = Path::parse(key).map_err(|_| S3Error::NotFound)?;
+        self.store.get_opts(&path, options).await.map_err(error)
+    }
+}
+
+impl ObjectBackend for ObjectStoreBackend {
+    fn get<'a>(&'a self, key: &'a str, range: Option<&'a str>) -> BackendFuture<'a, GetObjectOutput> {
+        Box::pin(async move {
+            let range = range.and_then(parse_range);
+            let ranged = range.is_some();
+            let result = self.get_opts(key, GetOptions { range, ..GetOptions::default() }).await?;
+            let (start, end) = (result.range.start, result.range.end);
+            let size = result.meta.size;
+            let metadata = Metadata::new(&result.meta, &result.attributes);
+            let body = StreamBody(Mutex::new(result.into_stream()));
+            Ok(GetObjectOutput::builder()
+                .body(ByteStream::from_body_1_x(body))
+                .content_length((end - start) as i64)
+                .set_content_range(ranged.then(|| format!("bytes {}-{}/{}", start, end.saturating_sub(1), size)))
+                .accept_ranges("bytes")
+                .set_e_tag(metadata.e_tag)
+                .set_last_modified(metadata.last_modified)
+                .set_content_type(metadata.content_type)
+                .set_cache_control(metadata.cache_control)
+                .set_content_disposition(metadata.content_disposition)
+                .set_content_encoding(metadata.content_encoding)
+                .set_content_language(metadata.content_language)
+                .set_metadata(metadata.user)
+                .build())
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> BackendFuture<'a, HeadObjectOutput> {
+        Box::pin(async move {
+            // A HEAD through `get_opts`, since `ObjectStore::head` returns no attributes
+            let result = self.get_opts(key, GetOptions { head: true, ..GetOptions::default() }).await?;
+            let metadata = Metadata::new(&result.meta, &result.attributes);
+            Ok(HeadObjectOutput::builder()
+                .content_length(result.meta.size as i64)
+                .accept_ranges("bytes")
+                .set_e_tag(metadata.e_tag)
+                .set_last_modified(metadata.last_modified)
+                .set_content_type(metadata.content_type)
+                .set_cache_control(metadata.cache_control)
+                .set_content_disposition(metadata.content_disposition)
+                .set_content_encoding(metadata.content_encoding)
+                .set_content_language(metadata.content_language)
+                .set_metadata(metadata.user)
+                .build())
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BackendFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            // Stores list by path segments, so list the directory and match the rest
+            let directory = prefix.rsplit_once('/').map(|(directory, _)| Path::from(directory));
+            let mut objects = self.store.list(directory.as_ref());
+            let mut keys = Vec::new();
+            while let Some(object) = std::future::poll_fn(|cx| objects.as_mut().poll_next(cx)).await {
+                let object = object.map_err(error)?;
+                if object.location.as_ref().starts_with(prefix) {
+                    keys.push(object.location.to_string());
+                }
+            }
+            keys.sort_unstable();
+            Ok(keys)
+        })
+    }
+}
+
+impl fmt::Debug for ObjectStoreBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ObjectStoreBackend").field(&format_args!("{}", self.store)).finish()
+    }
+}
+
+
+/// The S3 metadata of an object.
+struct Metadata {
+    e_tag: Option<String>,
+    last_modified: Option<DateTime>,
+    content_type: Option<String>,
+    cache_control: Option<String>,
+    content_disposition: Option<String>,
+    content_encoding: Option<String>,
+    content_language: Option<String>,
+    user: Option<HashMap<String, String>>,
+}
+
+impl Metadata {
+    fn new(meta: &ObjectMeta, attributes: &Attributes) -> Self {
+        let attribute = |attribute: Attribute| attributes.get(&attribute).map(|value| value.to_string());
+        let user: HashMap<_, _> = attributes.iter()
+            .filter_map(|(attribute, value)| match attribute {
+                Attribute::Metadata(name) => Some((name.to_string(), value.to_string())),
+                _ => None,
+            })
+            .collect();
+        Self {
+            // S3 ETags are quoted, other stores' may not be
+            e_tag: meta.e_tag.as_ref().map(|e_tag| match e_tag.starts_with('"') || e_tag.starts_with("W/") {
+                true => e_tag.clone(),
+                false => format!("\"{}\"", e_tag),
+            }),
+            last_modified: Some(DateTime::from_millis(meta.last_modified.timestamp_millis())),
+            content_type: attribute(Attribute::ContentType),
+            cache_control: attribute(Attribute::CacheControl),
+            content_disposition: attribute(Attribute::ContentDisposition),
+            content_encoding: attribute(Attribute::ContentEncoding),
+            content_language: attribute(Attribute::ContentLanguage),
+            user: (!user.is_empty()).then_some(user),
+        }
+    }
+}
+
+
+/// A single `bytes=` range; `None` for anything else.
+fn parse_range(range: &str) -> Option<GetRange> {
+    match ByteRange::parse(range)? {
+        ByteRange::From(start, Some(end)) => Some(GetRange::Bounded(start..end.saturating_add(1))),
+        ByteRange::From(start, None) => Some(GetRange::Offset(start)),
+        ByteRange::Suffix(length) => Some(GetRange::Suffix(length)),
+    }
+}
+
+
+fn error(error: ::object_store::Error) -> S3Error {
+    match error {
+        ::object_store::Error::NotFound { .. } => S3Error::NotFound,
+        #[cfg(feature = "trace")]
+        error => {
+            tracing::warn!("S3Origin: object store request failed: {}", error);
+            S3Error::BadGateway
+        }
+        #[cfg(not(feature = "trace"))]
+        _ => S3Error::BadGateway,
+    }
+}
+
+
+/// An object body; the mutex makes the stream `Sync`, as `ByteStream` requires.
+struct StreamBody(Mutex<BoxStream<'static, ::object_store::Result<Bytes>>>);
+
+impl HttpBody for StreamBody {
+    type Data = Bytes;
+    type Error = ::object_store::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let stream = self.get_mut().0.get_mut().unwrap_or_else(PoisonError::into_inner);
+        stream.as_mut().poll_next(cx).map(|chunk| chunk.map(|chunk| chunk.map(Frame::data)))
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use ::object_store::{memory::InMemory, PutOptions, PutPayload};
+
+    #[test]
+    fn parses_ranges() {
+        assert!(matches!(parse_range("bytes=0-99"), Some(GetRange::Bounded(range)) if range == (0..100)));
+        assert!(matches!(parse_range("bytes=100-"), Some(GetRange::Offset(100))));
+        assert!(matches!(parse_range("bytes=-5"), Some(GetRange::Suffix(5))));
+        assert!(parse_range("bytes=5-1").is_none());
+        assert!(parse_range("bytes=0-1,5-6").is_none());
+        assert!(parse_range("items=0-1").is_none());
+    }
+
+    #[tokio::test]
+    async fn serves_objects() {
+        let store = InMemory::new();
+        let attributes = Attributes::from_iter([
+            (Attribute::ContentType, "text/plain"),
+            (Attribute::Metadata("owner".into()), "web"),
+        ]);
+        let options = PutOptions { attributes, ..PutOptions::default() };
+        store.put_opts(&Path::from("site/hello.txt"), PutPayload::from_static(b"hello world"), options).await.unwrap();
+        store.put(&Path::from("site/docs/a.txt"), PutPayload::from_static(b"a")).await.unwrap();
+        store.put(&Path::from("other.txt"), PutPayload::from_static(b"b")).await.unwrap();
+        let backend = ObjectStoreBackend::new(store);
+
+        let output = backend.get("site/hello.txt", None).await.unwrap();
+        assert_eq!(output.content_type(), Some("text/plain"));
+        assert_eq!(output.content_length(), Some(11));
+        assert!(output.e_tag().unwrap().starts_with('"'));
+        assert_eq!(output.metadata().unwrap()["owner"], "web");
+        assert_eq!(output.body.collect().await.unwrap().into_bytes(), "hello world");
+
+        let output = backend.get("site/hello.txt", Some("bytes=6-")).await.unwrap();
+        assert_eq!(output.content_range(), Some("bytes 6-10/11"));
+        assert_eq!(output.body.collect().await.unwrap().into_bytes(), "world");
+
+        let output = backend.head("site/hello.txt").await.unwrap();
+        assert_eq!(output.content_length(), Some(11));
+        assert_eq!(output.content_type(), Some("text/plain"));
+
+        assert_eq!(backend.get("missing.txt", None).await.unwrap_err(), S3Error::NotFound);
+        assert_eq!(backend.get("a//b", None).await.unwrap_err(), S3Error::NotFound);
+        assert_eq!(backend.list("site/").await.unwrap(), ["site/docs/a.txt", "site/hello.txt"]);
+        assert_eq!(backend.list("site/h").await.unwrap(), ["site/hello.txt"]);
+        assert_eq!(backend.list("").await.unwrap().len(), 3);
+    }
+}
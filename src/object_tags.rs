@@ -0,0 +1,117 @@
+//! Serving only objects with required tags.
+//!
+//! With [`required_tags`](crate::S3OriginBuilder::required_tags), the origin looks up the tags
+//! of every object it serves (GetObjectTagging) and answers `404 Not Found` unless all the
+//! required tags are set, e.g. `public=true`.  One bucket can then hold public and private
+//! objects without relying on key prefixes.  The origin's role needs `s3:GetObjectTagging`.
+//!
+//! Lookups are cached per key for [`cache_ttl`](RequiredTags::cache_ttl), and evicted by
+//! [`handle_s3_event`](crate::S3Origin::handle_s3_event) for `ObjectTagging` events.
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use aws_sdk_s3::Client as S3Client;
+
+use crate::S3Error;
+
+
+/// Keys whose tags are cached at most.
+const MAX_CACHED: usize = 10_000;
+
+
+/// Required object tag configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequiredTags {
+    tags: Vec<(String, String)>,
+    cache_ttl: Duration,
+}
+
+impl RequiredTags {
+    /// Only serve objects carrying all of these tags.
+    pub fn new<I, K, V>(tags: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        Self {
+            tags: tags.into_iter().map(|(key, value)| (key.into(), value.into())).collect(),
+            cache_ttl: Duration::from_secs(60),
+        }
+    }
+
+    /// Set how long an object's tags are cached; defaults to 60 seconds.
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+}
+
+
+/// Checks required tags, caching the outcome per key.
+#[derive(Debug)]
+pub(crate) struct TagCheck {
+    required: RequiredTags,
+    cache: Mutex<HashMap<String, (bool, Instant)>>,
+}
+
+impl TagCheck {
+    pub(crate) fn new(required: RequiredTags) -> Self {
+        Self { required, cache: Mutex::new(HashMap::new()) }
+    }
+
+    /// Whether the object may be served; fails like a GetObject request would.
+    pub(crate) async fn allows(&self, client: &S3Client, bucket: &str, key: &str) -> Result<bool, S3Error> {
+        if let Some(&(allowed, expires)) = self.cache.lock().unwrap_or_else(PoisonError::into_inner).get(key) {
+            if expires > Instant::now() {
+                return Ok(allowed);
+            }
+        }
+
+        let allowed = match client.get_object_tagging().bucket(bucket).key(key).send().await {
+            Ok(output) => self.matches(output.tag_set().iter().map(|tag| (tag.key(), tag.value()))),
+            Err(error) if error.as_service_error().and_then(|error| error.meta().code()) == Some("NoSuchKey") => false,
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        if cache.len() >= MAX_CACHED {
+            let now = Instant::now();
+            cache.retain(|_, (_, expires)| *expires > now);
+            if cache.len() >= MAX_CACHED {
+                cache.clear();
+            }
+        }
+        cache.insert(key.to_owned(), (allowed, Instant::now() + self.required.cache_ttl));
+        Ok(allowed)
+    }
+
+    /// Forget the cached tags of a key.
+    #[cfg(feature = "s3-events")]
+    pub(crate) fn invalidate(&self, key: &str) {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner).remove(key);
+    }
+
+    fn matches<'a>(&self, tags: impl Iterator<Item = (&'a str, &'a str)> + Clone) -> bool {
+        self.required.tags.iter().all(|(key, value)| tags.clone().any(|tag| tag == (key.as_str(), value.as_str())))
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_all_tags() {
+        let check = TagCheck::new(RequiredTags::new([("public", "true"), ("reviewed", "yes")]));
+        assert!(check.matches([("reviewed", "yes"), ("team", "web"), ("public", "true")].into_iter()));
+        assert!(!check.matches([("public", "true")].into_iter()));
+        assert!(!check.matches([("public", "TRUE"), ("reviewed", "yes")].into_iter()));
+        assert!(TagCheck::new(RequiredTags::new(Vec::<(String, String)>::new())).matches(std::iter::empty()));
+    }
+}
@@ -0,0 +1,213 @@
+//! Parallel ranged fetching of large objects.
+//!
+//! A single GetObject stream is limited by the throughput of one connection.  With
+//! [`parallel_fetch`](crate::S3OriginBuilder::parallel_fetch), whole-object GETs of objects
+//! larger than the threshold are split into parts: the first part is streamed from the original
+//! response while the following parts are fetched with concurrent ranged GETs (conditional on
+//! the ETag) and delivered in order.
+//!
+//! Parts after the first are buffered in memory until it is their turn, so at most
+//! `memory_budget / part_size` of them (and never more than `concurrency`) are in flight.
+//! Parallel bodies are not [resumed](crate::S3OriginBuilder::resume_retries).
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use aws_sdk_s3::{primitives::ByteStream, Client as S3Client};
+use axum::{body::Bytes, BoxError};
+use futures_core::Stream;
+use tokio::task::JoinHandle;
+
+use crate::cost::{self, CostCounters};
+
+
+/// Parallel fetch configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParallelFetch {
+    threshold: u64,
+    part_size: u64,
+    concurrency: usize,
+    memory_budget: u64,
+}
+
+impl ParallelFetch {
+    /// Fetch objects larger than `threshold` bytes in parts.
+    ///
+    /// Defaults to 8 MiB parts, 4 concurrent part requests and a 64 MiB memory budget.
+    pub fn new(threshold: u64) -> Self {
+        Self { threshold, part_size: 8 * 1024 * 1024, concurrency: 4, memory_budget: 64 * 1024 * 1024 }
+    }
+
+    /// Set the part size in bytes.
+    pub fn part_size(mut self, part_size: u64) -> Self {
+        self.part_size = part_size.max(1);
+        self
+    }
+
+    /// Set the maximum number of part requests in flight per response (at least 1).
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set the memory a single response may use for buffered parts.
+    ///
+    /// At least one part is always fetched ahead.
+    pub fn memory_budget(mut self, bytes: u64) -> Self {
+        self.memory_budget = bytes;
+        self
+    }
+
+    /// Whether an object of `size` bytes is fetched in parts.
+    pub(crate) fn applies(&self, size: Option<i64>) -> bool {
+        size.is_some_and(|size| size > 0 && size as u64 > self.threshold && size as u64 > self.part_size)
+    }
+
+    /// Part requests kept in flight.
+    fn window(&self) -> usize {
+        let budget = usize::try_from(self.memory_budget / self.part_size).unwrap_or(usize::MAX);
+        budget.clamp(1, self.concurrency)
+    }
+}
+
+
+/// An object body assembled from the original response and ranged part requests.
+pub(crate) struct ParallelBody {
+    /// The original response, until the first part has been delivered.
+    first: Option<ByteStream>,
+    first_remaining: u64,
+    parts: VecDeque<JoinHandle<Result<Bytes, BoxError>>>,
+    /// The offset of the next part to request.
+    next_offset: u64,
+    size: u64,
+    config: ParallelFetch,
+    client: Arc<S3Client>,
+    /// Parts are requested while the body streams, outside of the origin's scope.
+    cost: Option<Arc<CostCounters>>,
+    bucket: Arc<str>,
+    key: Arc<str>,
+    etag: Arc<str>,
+}
+
+impl ParallelBody {
+    /// Start fetching the parts of an object of `size` bytes; `body` is the whole-object stream.
+    pub(crate) fn new(config: ParallelFetch, client: Arc<S3Client>, bucket: &str, key: &str, etag: &str, size: u64, body: ByteStream) -> Self {
+        let mut body = Self {
+            first: Some(body),
+            first_remaining: config.part_size,
+            parts: VecDeque::new(),
+            next_offset: config.part_size,
+            size,
+            config,
+            client,
+            cost: cost::current(),
+            bucket: bucket.into(),
+            key: key.into(),
+            etag: etag.into(),
+        };
+        body.fill();
+        body
+    }
+
+    /// Request parts until the window is full.
+    fn fill(&mut self) {
+        while self.parts.len() < self.config.window() && self.next_offset < self.size {
+            let last = (self.next_offset + self.config.part_size).min(self.size) - 1;
+            let request = self.client.get_object()
+                .bucket(&*self.bucket)
+                .key(&*self.key)
+                .range(format!("bytes={}-{}", self.next_offset, last))
+                .if_match(&*self.etag);
+            self.parts.push_back(tokio::spawn(cost::scope(self.cost.clone(), async move {
+                let output = request.send().await?;
+                Ok(output.body.collect().await?.into_bytes())
+            })));
+            self.next_offset = last + 1;
+        }
+    }
+
+    /// Stop fetching after an error.
+    fn abort(&mut self) {
+        self.first = None;
+        for part in self.parts.drain(..) {
+            part.abort();
+        }
+    }
+}
+
+impl Stream for ParallelBody {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if let Some(first) = &mut this.first {
+            return match Pin::new(first).poll_next(cx) {
+                Poll::Ready(Some(Ok(mut chunk))) => {
+                    if chunk.len() as u64 >= this.first_remaining {
+                        chunk.truncate(this.first_remaining as usize);
+                        this.first = None;
+                    }
+                    this.first_remaining -= chunk.len() as u64;
+                    Poll::Ready(Some(Ok(chunk)))
+                }
+                Poll::Ready(Some(Err(error))) => {
+                    this.abort();
+                    Poll::Ready(Some(Err(error.into())))
+                }
+                Poll::Ready(None) => {
+                    this.abort();
+                    Poll::Ready(Some(Err("object body ended early".into())))
+                }
+                Poll::Pending => Poll::Pending,
+            };
+        }
+
+        let Some(part) = this.parts.front_mut() else {
+            return Poll::Ready(None);
+        };
+        match Pin::new(part).poll(cx) {
+            Poll::Ready(result) => {
+                this.parts.pop_front();
+                let result = result.map_err(BoxError::from).and_then(|part| part);
+                match result {
+                    Ok(_) => this.fill(),
+                    Err(_) => this.abort(),
+                }
+                Poll::Ready(Some(result))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for ParallelBody {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_window_by_budget() {
+        let mib = 1024 * 1024;
+        assert_eq!(ParallelFetch::new(0).window(), 4);
+        assert_eq!(ParallelFetch::new(0).memory_budget(16 * mib).window(), 2);
+        assert_eq!(ParallelFetch::new(0).memory_budget(0).window(), 1);
+        assert_eq!(ParallelFetch::new(0).part_size(mib).concurrency(0).window(), 1);
+
+        let config = ParallelFetch::new(100 * mib);
+        assert!(!config.applies(Some(100 * mib as i64)));
+        assert!(config.applies(Some(100 * mib as i64 + 1)));
+        assert!(!config.applies(None));
+    }
+}
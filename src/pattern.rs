@@ -0,0 +1,36 @@
+use globset::{Glob, GlobBuilder, GlobMatcher};
+
+
+/// Compile a glob pattern for matching request paths.
+///
+/// Paths are relative to the bucket prefix and have no leading `/`.  A pattern without a `/`
+/// matches against the file name in any directory; a pattern with a `/` matches the whole path.
+pub(crate) fn compile_glob(pattern: &str) -> Result<GlobMatcher, globset::Error> {
+    let glob: Glob = GlobBuilder::new(&normalize_glob(pattern))
+        .literal_separator(true)
+        .build()?;
+    Ok(glob.compile_matcher())
+}
+
+
+/// The pattern actually matched by [`compile_glob`].
+pub(crate) fn normalize_glob(pattern: &str) -> String {
+    let pattern = pattern.trim_start_matches('/');
+    if pattern.contains('/') {
+        pattern.to_string()
+    } else {
+        format!("**/{}", pattern)
+    }
+}
+
+
+/// Whether a pattern contains no glob syntax, i.e. matches exactly one path.
+pub(crate) fn is_literal(pattern: &str) -> bool {
+    !pattern.contains(['*', '?', '[', '{'])
+}
+
+
+/// The last component of a path.
+pub(crate) fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
@@ -0,0 +1,316 @@
+//! Serving precompressed variants of objects.
+//!
+//! Build pipelines often upload `app.js.br` and `app.js.gz` next to `app.js`.  With
+//! [`precompressed`](crate::S3OriginBuilder::precompressed), a request for `app.js` from a client
+//! accepting Brotli or gzip is answered with the matching variant, in the client's order of
+//! preference, falling back to the object itself:
+//!
+//! ```rust
+//! use axum_static_s3::precompressed::{Encoding, Precompressed};
+//!
+//! // Only gzip variants are uploaded; HEAD them once and remember which exist
+//! let precompressed = Precompressed::new().encodings([Encoding::Gzip]).prefetch(true);
+//! ```
+//!
+//! Variants must be uploaded with the `Content-Type` of the object; their `Content-Encoding` is
+//! set if they have none.  Responses for keys with a [precompressed
+//! extension](Precompressed::extensions) vary by `Accept-Encoding`.
+//!
+//! Trying `app.js.br`, then `app.js.gz`, then `app.js` costs a request to S3 for every variant
+//! that was never uploaded.  With [`prefetch`](Precompressed::prefetch), the first request for a
+//! key HEADs all its variants at once, and which of them exist is remembered for the
+//! [`existence_ttl`](Precompressed::existence_ttl); later requests go to the right key directly.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+
+use crate::{clean_urls::Candidate, cost, negotiation, probe, telemetry::Feature, S3OriginInner};
+
+
+/// Variants whose existence is remembered before the cache is cleared.
+const MAX_KNOWN: usize = 10_000;
+
+
+/// A content encoding of precompressed variants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// Brotli, `{key}.br`.
+    Brotli,
+    /// gzip, `{key}.gz`.
+    Gzip,
+}
+
+impl Encoding {
+    /// The `Content-Encoding` token.
+    fn token(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// The extension appended to the key.
+    fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+}
+
+
+/// Precompressed variant configuration.
+#[derive(Clone, Debug)]
+pub struct Precompressed {
+    encodings: Vec<Encoding>,
+    extensions: Vec<String>,
+    prefetch: bool,
+    existence_ttl: Duration,
+}
+
+impl Precompressed {
+    /// Brotli and gzip variants of text, script and style files, tried one after another.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The encodings variants are uploaded in; clients preferring them equally get the first.
+    pub fn encodings<I: IntoIterator<Item = Encoding>>(mut self, encodings: I) -> Self {
+        self.encodings = encodings.into_iter().collect();
+        self
+    }
+
+    /// Only look for variants of keys with these file extensions.
+    pub fn extensions<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extensions = extensions.into_iter().map(|extension| extension.into().to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// HEAD all variants of a key on its first request, and remember which exist.
+    pub fn prefetch(mut self, prefetch: bool) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    /// How long the existence of a variant is remembered with [`prefetch`](Self::prefetch);
+    /// defaults to 5 minutes.
+    pub fn existence_ttl(mut self, ttl: Duration) -> Self {
+        self.existence_ttl = ttl;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), &'static str> {
+        match self.encodings.is_empty() {
+            true => Err("precompressed needs at least one encoding"),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether variants of `key` are looked for.
+    fn applies(&self, key: &str) -> bool {
+        let extension = key.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+        extension.is_some_and(|extension| self.extensions.contains(&extension))
+    }
+
+    /// The encodings to try for `key`, in the client's order of preference.
+    fn accepted(&self, key: &str, headers: &HeaderMap) -> Vec<Encoding> {
+        let Some(preferences) = negotiation::preferences(headers, &header::ACCEPT_ENCODING).filter(|_| self.applies(key)) else {
+            return Vec::new();
+        };
+        let quality = |token: &str| preferences.iter()
+            .find(|preference| preference.value == token)
+            .or_else(|| preferences.iter().find(|preference| preference.value == "*"))
+            .map_or(0.0, |preference| preference.q);
+
+        let mut encodings = self.encodings.iter()
+            .map(|encoding| (*encoding, quality(encoding.token())))
+            .filter(|(_, q)| *q > 0.0)
+            .collect::<Vec<_>>();
+        encodings.sort_by(|a, b| b.1.total_cmp(&a.1));
+        encodings.into_iter().map(|(encoding, _)| encoding).collect()
+    }
+}
+
+impl Default for Precompressed {
+    fn default() -> Self {
+        let extensions = ["html", "htm", "css", "js", "mjs", "json", "svg", "xml", "txt", "wasm"];
+        Self {
+            encodings: vec![Encoding::Brotli, Encoding::Gzip],
+            extensions: extensions.map(str::to_owned).to_vec(),
+            prefetch: false,
+            existence_ttl: Duration::from_secs(300),
+        }
+    }
+}
+
+
+/// Serves precompressed variants, remembering which exist.
+#[derive(Debug)]
+pub(crate) struct PrecompressedVariants {
+    config: Precompressed,
+    /// Whether a variant exists by key and encoding, and since when that is known.
+    known: Mutex<HashMap<(String, Encoding), (bool, Instant)>>,
+}
+
+impl PrecompressedVariants {
+    pub(crate) fn new(config: Precompressed) -> Self {
+        Self { config, known: Mutex::new(HashMap::new()) }
+    }
+
+    /// Whether responses for `key` vary by `Accept-Encoding`.
+    pub(crate) fn applies(&self, key: &str) -> bool {
+        self.config.applies(key)
+    }
+
+    /// `candidates`, each preceded by its variants the client accepts.
+    pub(crate) fn candidates(&self, candidates: Vec<Candidate>, headers: &HeaderMap) -> Vec<Candidate> {
+        candidates.into_iter()
+            .flat_map(|candidate| {
+                let variants = self.config.accepted(&candidate.key, headers).into_iter().map(|encoding| Candidate {
+                    key: format!("{}.{}", candidate.key, encoding.extension()),
+                    directory_index: candidate.directory_index,
+                    feature: Some(Feature::Precompressed),
+                });
+                variants.collect::<Vec<_>>().into_iter().chain([candidate])
+            })
+            .collect()
+    }
+
+    /// Drop the variants known not to exist, HEADing the variants of keys not seen yet.
+    pub(crate) async fn resolve(&self, origin: &Arc<S3OriginInner>, candidates: Vec<Candidate>) -> Vec<Candidate> {
+        if !self.config.prefetch {
+            return candidates;
+        }
+        let now = Instant::now();
+        let mut keys = candidates.iter().filter_map(|candidate| Some(self.variant(candidate)?.0)).collect::<Vec<_>>();
+        keys.dedup();
+        let mut heads = tokio::task::JoinSet::new();
+        for key in keys {
+            for encoding in &self.config.encodings {
+                if self.known(key, *encoding, now).is_none() {
+                    let (origin, key, encoding) = (origin.clone(), key.to_owned(), *encoding);
+                    let variant = format!("{}.{}", key, encoding.extension());
+                    heads.spawn(cost::scope(Some(origin.cost.clone()), async move {
+                        (key, encoding, probe::ObjectStatus::from_head(probe::head(&origin, &variant).await))
+                    }));
+                }
+            }
+        }
+        while let Some(Ok((key, encoding, status))) = heads.join_next().await {
+            match status {
+                probe::ObjectStatus::Found { .. } => self.record(&key, encoding, true),
+                probe::ObjectStatus::Missing => self.record(&key, encoding, false),
+                probe::ObjectStatus::Failed(_) => {}
+            }
+        }
+
+        candidates.into_iter()
+            .filter(|candidate| match self.variant(candidate) {
+                Some((key, encoding)) => self.known(key, encoding, now) != Some(false),
+                None => true,
+            })
+            .collect()
+    }
+
+    /// Remember whether a variant exists from the status it was served with.
+    pub(crate) fn observe(&self, candidate: &Candidate, status: StatusCode) {
+        let Some((key, encoding)) = self.variant(candidate).filter(|_| self.config.prefetch) else {
+            return;
+        };
+        match status {
+            StatusCode::NOT_FOUND => self.record(key, encoding, false),
+            status if status.is_success() => self.record(key, encoding, true),
+            _ => {}
+        }
+    }
+
+    /// Mark a served variant with its encoding.
+    pub(crate) fn encode(&self, candidate: &Candidate, mut response: Response) -> Response {
+        if let Some((_, encoding)) = self.variant(candidate).filter(|_| response.status().is_success()) {
+            response.headers_mut().entry(header::CONTENT_ENCODING)
+                .or_insert(HeaderValue::from_static(encoding.token()));
+        }
+        response
+    }
+
+    /// The key and encoding of a variant candidate.
+    fn variant<'a>(&self, candidate: &'a Candidate) -> Option<(&'a str, Encoding)> {
+        if candidate.feature != Some(Feature::Precompressed) {
+            return None;
+        }
+        let (key, extension) = candidate.key.rsplit_once('.')?;
+        let encoding = self.config.encodings.iter().find(|encoding| encoding.extension() == extension)?;
+        Some((key, *encoding))
+    }
+
+    fn known(&self, key: &str, encoding: Encoding, now: Instant) -> Option<bool> {
+        let known = self.known.lock().unwrap_or_else(PoisonError::into_inner);
+        let (exists, since) = known.get(&(key.to_owned(), encoding))?;
+        (now.saturating_duration_since(*since) < self.config.existence_ttl).then_some(*exists)
+    }
+
+    fn record(&self, key: &str, encoding: Encoding, exists: bool) {
+        let mut known = self.known.lock().unwrap_or_else(PoisonError::into_inner);
+        if known.len() >= MAX_KNOWN {
+            known.clear();
+        }
+        known.insert((key.to_owned(), encoding), (exists, Instant::now()));
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    fn accept_encoding(value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(header::ACCEPT_ENCODING, HeaderValue::from_static(value))])
+    }
+
+    #[test]
+    fn negotiates_encodings() {
+        let config = Precompressed::new();
+        assert_eq!(config.accepted("app.js", &accept_encoding("gzip, deflate, br")), [Encoding::Brotli, Encoding::Gzip]);
+        assert_eq!(config.accepted("app.js", &accept_encoding("br;q=0.5, gzip")), [Encoding::Gzip, Encoding::Brotli]);
+        assert_eq!(config.accepted("app.js", &accept_encoding("*, br;q=0")), [Encoding::Gzip]);
+        assert_eq!(config.accepted("app.js", &accept_encoding("identity")), []);
+        assert_eq!(config.accepted("app.js", &HeaderMap::new()), []);
+        assert_eq!(config.accepted("photo.jpg", &accept_encoding("br")), []);
+        assert_eq!(Precompressed::new().encodings([Encoding::Gzip]).accepted("INDEX.HTML", &accept_encoding("br, gzip")), [Encoding::Gzip]);
+        assert!(Precompressed::new().encodings([]).validate().is_err());
+    }
+
+    #[test]
+    fn expands_and_remembers_variants() {
+        let variants = PrecompressedVariants::new(Precompressed::new().prefetch(true));
+        let candidate = Candidate { key: "app.js".into(), directory_index: false, feature: None };
+        let candidates = variants.candidates(vec![candidate], &accept_encoding("br, gzip"));
+        let keys = candidates.iter().map(|candidate| candidate.key.as_str()).collect::<Vec<_>>();
+        assert_eq!(keys, ["app.js.br", "app.js.gz", "app.js"]);
+        assert_eq!(variants.variant(&candidates[1]), Some(("app.js", Encoding::Gzip)));
+        assert_eq!(variants.variant(&candidates[2]), None);
+
+        let now = Instant::now();
+        variants.observe(&candidates[0], StatusCode::NOT_FOUND);
+        variants.observe(&candidates[1], StatusCode::OK);
+        variants.observe(&candidates[2], StatusCode::NOT_FOUND);
+        assert_eq!(variants.known("app.js", Encoding::Brotli, now), Some(false));
+        assert_eq!(variants.known("app.js", Encoding::Gzip, now), Some(true));
+        assert_eq!(variants.known("app.js", Encoding::Gzip, now + Duration::from_secs(301)), None);
+
+        let response = variants.encode(&candidates[1], Response::new(axum::body::Body::empty()));
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    }
+}
@@ -0,0 +1,152 @@
+//! Switching the bucket prefix at runtime.
+//!
+//! Blue/green static deployments upload each release under its own prefix (`releases/41/`,
+//! `releases/42/`) and switch traffic by changing the prefix the origin serves from.
+//! [`S3Origin::set_prefix`] switches immediately; [`S3Origin::watch_prefix`] polls a
+//! [`PrefixPointer`], a small S3 object or SSM parameter holding the active prefix, so that a
+//! deploy only has to overwrite the pointer:
+//!
+//! ```rust,ignore
+//! let watcher = origin.watch_prefix(PrefixPointer::s3("deploy/active"), Duration::from_secs(30));
+//! ```
+//!
+//! Requests in flight keep using the prefix they started with.  Cached objects are stored by
+//! full key, so entries of the previous prefix simply stop being used.
+use std::{fmt, sync::PoisonError, time::Duration};
+
+use crate::{S3Error, S3Origin};
+
+
+/// Where [`S3Origin::watch_prefix`] reads the active prefix from.
+///
+/// The value is used as-is apart from surrounding whitespace, so include the trailing `/`.
+pub struct PrefixPointer(Source);
+
+enum Source {
+    S3(String),
+    #[cfg(feature = "aws-parameterstore")]
+    Parameter(aws_sdk_ssm::Client, String),
+}
+
+impl PrefixPointer {
+    /// An object in the origin's bucket whose body is the prefix; the key is not relative to
+    /// the bucket prefix.
+    pub fn s3(key: impl Into<String>) -> Self {
+        Self(Source::S3(key.into()))
+    }
+
+    /// An SSM parameter whose value is the prefix.
+    #[cfg(feature = "aws-parameterstore")]
+    pub fn parameter(client: aws_sdk_ssm::Client, name: impl Into<String>) -> Self {
+        Self(Source::Parameter(client, name.into()))
+    }
+}
+
+/// Prints the source; the SSM client is not printed.
+impl fmt::Debug for PrefixPointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Source::S3(key) => f.debug_tuple("S3").field(key).finish(),
+            #[cfg(feature = "aws-parameterstore")]
+            Source::Parameter(_, name) => f.debug_tuple("Parameter").field(name).finish(),
+        }
+    }
+}
+
+
+/// Why the prefix pointer could not be read.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum PrefixError {
+    /// The pointer object could not be fetched.
+    Fetch(S3Error),
+    /// The SSM parameter could not be read.
+    #[cfg(feature = "aws-parameterstore")]
+    Parameter(String),
+    /// The pointer is not valid UTF-8.
+    InvalidPrefix,
+}
+
+impl fmt::Display for PrefixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrefixError::Fetch(error) => write!(f, "failed to fetch prefix pointer: {:?}", error),
+            #[cfg(feature = "aws-parameterstore")]
+            PrefixError::Parameter(error) => write!(f, "failed to read prefix parameter: {}", error),
+            PrefixError::InvalidPrefix => f.write_str("prefix pointer is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for PrefixError {}
+
+
+impl S3Origin {
+    /// The bucket prefix new requests are served from.
+    ///
+    pub fn prefix(&self) -> String {
+        self.inner.bucket_prefix().to_string()
+    }
+
+    /// Serve new requests from `prefix`, see [`prefix`](crate::prefix).
+    ///
+    pub fn set_prefix(&self, prefix: impl Into<String>) {
+        let prefix = prefix.into();
+        #[cfg(feature = "trace")]
+        tracing::info!("S3Origin: switching bucket prefix to {:?}", prefix);
+        *self.inner.bucket_prefix.write().unwrap_or_else(PoisonError::into_inner) = prefix.into();
+    }
+
+    /// Read `pointer` and switch to the prefix it holds, returning the prefix.
+    ///
+    pub async fn load_prefix(&self, pointer: &PrefixPointer) -> Result<String, PrefixError> {
+        let prefix = match &pointer.0 {
+            Source::S3(key) => {
+                let inner = &self.inner;
+                let output = inner.s3_client.get_object()
+                    .bucket(&inner.bucket)
+                    .key(key)
+                    .send()
+                    .await
+                    .map_err(|error| PrefixError::Fetch(error.into()))?;
+                let body = output.body.collect().await
+                    .map_err(|_| PrefixError::Fetch(S3Error::InternalServerError))?
+                    .into_bytes();
+                String::from_utf8(body.to_vec()).map_err(|_| PrefixError::InvalidPrefix)?
+            }
+            #[cfg(feature = "aws-parameterstore")]
+            Source::Parameter(client, name) => {
+                let output = client.get_parameter()
+                    .name(name)
+                    .send()
+                    .await
+                    .map_err(|error| PrefixError::Parameter(aws_sdk_ssm::error::DisplayErrorContext(error).to_string()))?;
+                output.parameter.and_then(|parameter| parameter.value).unwrap_or_default()
+            }
+        };
+        let prefix = prefix.trim().to_owned();
+        if *self.inner.bucket_prefix() != *prefix {
+            self.set_prefix(prefix.clone());
+        }
+        Ok(prefix)
+    }
+
+    /// Poll `pointer` every `interval` and switch to the prefix it holds.
+    ///
+    /// The first poll happens immediately.  Failed polls keep the current prefix.  The watcher
+    /// runs until the returned task is aborted.  Requires a Tokio runtime.
+    ///
+    pub fn watch_prefix(&self, pointer: PrefixPointer, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let origin = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let _result = origin.load_prefix(&pointer).await;
+                #[cfg(feature = "trace")]
+                if let Err(error) = _result {
+                    tracing::warn!("S3Origin: keeping bucket prefix, {}", error);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+}
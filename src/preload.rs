@@ -0,0 +1,74 @@
+//! `Link: rel=preload` headers for Early Hints.
+//!
+//! [`preload`](crate::S3OriginBuilder::preload) rules (and `preload` lists in a deployment
+//! [`manifest`](crate::manifest)) attach `Link` headers to successful responses, typically HTML
+//! entry points, naming the assets the browser will need:
+//!
+//! ```text
+//! Link: </assets/app.3f9ab2.js>; rel=preload; as=script
+//! ```
+//!
+//! A `103 Early Hints` response cannot be sent from a tower `Service`: hyper's server API only
+//! sends final responses.  CDNs that support Early Hints (e.g. Cloudflare) remember the `Link`
+//! headers of a response and send them as `103` ahead of later responses for the same URL, and
+//! browsers start fetching the assets as soon as the headers arrive.
+use axum::http::HeaderValue;
+use globset::GlobMatcher;
+
+use crate::pattern::compile_glob;
+
+
+/// A preload rule: a glob pattern and a `Link` value.
+#[derive(Clone, Debug)]
+struct PreloadRule {
+    matcher: GlobMatcher,
+    link: HeaderValue,
+}
+
+
+/// The preload rules of an origin.  Unlike other path rules, every matching rule applies.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Preloads(Vec<PreloadRule>);
+
+impl Preloads {
+    /// Compile `(pattern, link)` rules; patterns follow the
+    /// [`cache_control`](crate::S3OriginBuilder::cache_control) syntax.
+    pub(crate) fn new(rules: &[(String, String)]) -> Result<Self, &'static str> {
+        rules.iter()
+            .map(|(pattern, link)| {
+                let matcher = compile_glob(pattern).map_err(|_| "invalid preload pattern")?;
+                let link = HeaderValue::from_str(link).map_err(|_| "invalid preload link")?;
+                Ok(PreloadRule { matcher, link })
+            })
+            .collect::<Result<_, _>>()
+            .map(Preloads)
+    }
+
+    /// The `Link` values for a path relative to the bucket prefix.
+    pub(crate) fn links(&self, path: &str) -> Vec<HeaderValue> {
+        self.0.iter()
+            .filter(|rule| rule.matcher.is_match(path))
+            .map(|rule| rule.link.clone())
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_all_matching_links() {
+        let preloads = Preloads::new(&[
+            ("*.html".into(), "</app.js>; rel=preload; as=script".into()),
+            ("index.html".into(), "</hero.avif>; rel=preload; as=image".into()),
+        ]).unwrap();
+
+        assert_eq!(preloads.links("index.html").len(), 2);
+        assert_eq!(preloads.links("docs/about.html"), ["</app.js>; rel=preload; as=script"]);
+        assert!(preloads.links("app.js").is_empty());
+        assert!(Preloads::new(&[("*".into(), "bad\nlink".into())]).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+//! Truncated previews of large CSV and NDJSON objects.
+//!
+//! With [`S3OriginBuilder::preview`](crate::S3OriginBuilder::preview), a `GET` for a `.csv`,
+//! `.tsv`, `.ndjson` or `.jsonl` object with the query parameter `?preview=N` streams only the
+//! first `N` rows (plus the header row for CSV/TSV).  The object is read with consecutive ranged
+//! GetObject requests of [`Preview::chunk_size`] bytes, so only the bytes needed for the preview
+//! are transferred from S3, regardless of the object size.
+//!
+//! Previews are subject to the same checks as full responses: [required
+//! tags](crate::S3OriginBuilder::required_tags), the [`backend`](crate::S3OriginBuilder::backend),
+//! [`customize_request`](crate::S3OriginBuilder::customize_request) and
+//! [`max_size`](crate::S3OriginBuilder::max_size) of the whole object.
+use std::{
+    future::Future,
+    io::Error,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use aws_sdk_s3::{
+    config::http::HttpResponse,
+    error::SdkError,
+    operation::get_object::GetObjectError,
+};
+use axum::{
+    body::Bytes,
+    http::{header, HeaderValue, Request, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use futures_core::Stream;
+
+use crate::{exceeds_max_size, S3Error, S3OriginInner};
+
+
+/// Preview configuration.
+#[derive(Clone, Debug)]
+pub struct Preview {
+    max_rows: usize,
+    chunk_size: u64,
+    max_bytes: u64,
+}
+
+
+impl Preview {
+    pub fn new() -> Self {
+        Self {
+            max_rows: 1000,
+            chunk_size: 64 * 1024,
+            max_bytes: 16 * 1024 * 1024,
+        }
+    }
+
+    /// Set the maximum number of rows a client may request.
+    ///
+    /// This is optional, and defaults to 1000.  Larger `?preview=` values are clamped.
+    ///
+    pub fn max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = max_rows.max(1);
+        self
+    }
+
+    /// Set the size of each ranged GetObject request.
+    ///
+    /// This is optional, and defaults to 64 KiB.
+    ///
+    pub fn chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Set the maximum number of bytes read for a single preview.
+    ///
+    /// This is optional, and defaults to 16 MiB.  The preview ends early when the limit is
+    /// reached, which bounds the cost of objects with very long rows.
+    ///
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// The number of rows requested by `?preview=N`, clamped to `max_rows`.
+    ///
+    /// Returns `Some(Err(()))` if the parameter is present but not a positive number.
+    pub(crate) fn requested_rows(&self, uri: &Uri) -> Option<Result<usize, ()>> {
+        let value = uri.query()?
+            .split('&')
+            .find_map(|param| param.strip_prefix("preview="))?;
+
+        Some(match value.parse::<usize>() {
+            Ok(rows) if rows > 0 => Ok(rows.min(self.max_rows)),
+            _ => Err(()),
+        })
+    }
+}
+
+impl Default for Preview {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// The row format of a previewable object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PreviewFormat {
+    /// Comma or tab separated values with a header row; quoted fields may contain newlines.
+    Delimited,
+    /// One JSON document per line.
+    Lines,
+}
+
+impl PreviewFormat {
+    pub(crate) fn for_key(key: &str) -> Option<Self> {
+        let (_, extension) = crate::pattern::file_name(key).rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "csv" | "tsv" => Some(Self::Delimited),
+            "ndjson" | "jsonl" => Some(Self::Lines),
+            _ => None,
+        }
+    }
+}
+
+
+/// Counts rows across chunk boundaries.
+#[derive(Debug)]
+struct RowCounter {
+    format: PreviewFormat,
+    quoted: bool,
+    /// Rows still to be emitted, including the header row of delimited formats.
+    remaining: usize,
+}
+
+impl RowCounter {
+    fn new(format: PreviewFormat, rows: usize) -> Self {
+        let header = match format {
+            PreviewFormat::Delimited => 1,
+            PreviewFormat::Lines => 0,
+        };
+        Self { format, quoted: false, remaining: rows + header }
+    }
+
+    /// Scan `data`, returning how many bytes belong to the preview.
+    fn scan(&mut self, data: &[u8]) -> usize {
+        for (i, byte) in data.iter().enumerate() {
+            match byte {
+                b'"' if self.format == PreviewFormat::Delimited => self.quoted = !self.quoted,
+                b'\n' if !self.quoted => {
+                    self.remaining -= 1;
+                    if self.remaining == 0 {
+                        return i + 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        data.len()
+    }
+
+    fn done(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+
+/// A chunk of the object read with a ranged GetObject.
+struct Chunk {
+    data: Bytes,
+    /// Total object size, from `Content-Range`.
+    total: Option<u64>,
+    content_type: Option<String>,
+}
+
+
+type ChunkFuture = Pin<Box<dyn Future<Output = Result<Chunk, S3Error>> + Send>>;
+
+
+/// Read a chunk from the backend or S3, customized like the GetObject of a full response.
+async fn fetch_chunk(origin: Arc<S3OriginInner>, req: Arc<Request<()>>, key: String, start: u64, len: u64) -> Result<Chunk, S3Error> {
+    let range = format!("bytes={}-{}", start, start + len - 1);
+    let output = match &origin.backend {
+        Some(backend) => backend.get(&key, Some(&range)).await?,
+        None => {
+            let builder = origin.s3_client.get_object()
+                .bucket(&origin.bucket)
+                .key(&key)
+                .range(range);
+            let builder = match &origin.customize_request {
+                Some(customize) => customize(&req, builder),
+                None => builder,
+            };
+            match builder.send().await {
+                Ok(output) => output,
+                // The range starts at or past the end of the object (e.g. an empty object)
+                Err(error) if is_invalid_range(&error) => {
+                    return Ok(Chunk { data: Bytes::new(), total: Some(start), content_type: None });
+                }
+                Err(error) => return Err(S3Error::from(error)),
+            }
+        }
+    };
+
+    let total = output.content_range()
+        .and_then(|range| range.rsplit_once('/'))
+        .and_then(|(_, total)| total.parse().ok());
+    let content_type = output.content_type().map(str::to_owned);
+    let data = output.body.collect().await
+        .map_err(|_| S3Error::BadGateway)?
+        .into_bytes();
+
+    Ok(Chunk { data, total, content_type })
+}
+
+
+fn is_invalid_range(error: &SdkError<GetObjectError, HttpResponse>) -> bool {
+    matches!(error, SdkError::ServiceError(error) if error.raw().status().as_u16() == 416)
+}
+
+
+/// Streams the preview, fetching further chunks on demand.
+struct PreviewStream {
+    origin: Arc<S3OriginInner>,
+    req: Arc<Request<()>>,
+    key: String,
+    chunk_size: u64,
+    max_bytes: u64,
+    counter: RowCounter,
+    offset: u64,
+    total: Option<u64>,
+    pending: Option<Bytes>,
+    fetch: Option<ChunkFuture>,
+    done: bool,
+}
+
+impl PreviewStream {
+    /// Emit the preview part of a chunk and decide whether more chunks are needed.
+    fn emit(&mut self, data: Bytes) -> Bytes {
+        let len = self.counter.scan(&data);
+        self.offset += data.len() as u64;
+
+        let eof = data.is_empty() || self.total.is_some_and(|total| self.offset >= total);
+        if self.counter.done() || eof || self.offset >= self.max_bytes {
+            self.done = true;
+        }
+        data.slice(..len)
+    }
+
+    fn next_chunk_len(&self) -> u64 {
+        self.chunk_size.min(self.max_bytes.saturating_sub(self.offset)).max(1)
+    }
+}
+
+impl Stream for PreviewStream {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(data) = self.pending.take() {
+            return Poll::Ready(Some(Ok(self.emit(data))));
+        }
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        if self.fetch.is_none() {
+            let fetch = fetch_chunk(self.origin.clone(), self.req.clone(), self.key.clone(), self.offset, self.next_chunk_len());
+            self.fetch = Some(Box::pin(fetch));
+        }
+
+        let poll = match self.fetch.as_mut() {
+            Some(fetch) => fetch.as_mut().poll(cx),
+            None => return Poll::Ready(None),
+        };
+        match poll {
+            Poll::Ready(Ok(chunk)) => {
+                self.fetch = None;
+                self.total = chunk.total.or(self.total);
+                Poll::Ready(Some(Ok(self.emit(chunk.data))))
+            }
+            Poll::Ready(Err(_)) => {
+                self.fetch = None;
+                self.done = true;
+                Poll::Ready(Some(Err(Error::other("S3 ranged read failed"))))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+
+/// Serve a preview of `rows` rows of `key`.
+///
+/// The tags are checked and the first chunk is fetched before responding, so a missing or hidden
+/// object yields the usual error status.  The rest of the preview is streamed.
+pub(crate) async fn preview_response(origin: Arc<S3OriginInner>, req: Arc<Request<()>>, key: String, format: PreviewFormat, rows: usize) -> Response {
+    let Some(preview) = origin.preview.clone() else {
+        return S3Error::NotFound.into_response();
+    };
+    if let Some(required_tags) = &origin.required_tags {
+        match required_tags.allows(&origin.s3_client, &origin.bucket, &key).await {
+            Ok(true) => {}
+            Ok(false) => return S3Error::NotFound.into_response(),
+            Err(error) => return error.into_response(),
+        }
+    }
+
+    let first = match fetch_chunk(origin.clone(), req.clone(), key.clone(), 0, preview.chunk_size.min(preview.max_bytes).max(1)).await {
+        Ok(chunk) => chunk,
+        Err(error) => return error.into_response(),
+    };
+    let total = first.total.and_then(|total| i64::try_from(total).ok());
+    if exceeds_max_size(origin.max_size, total) {
+        return S3Error::MaxSizeExceeded.into_response();
+    }
+
+    let content_type = first.content_type
+        .and_then(|content_type| HeaderValue::from_str(&content_type).ok())
+        .unwrap_or(HeaderValue::from_static(match format {
+            PreviewFormat::Delimited => "text/csv",
+            PreviewFormat::Lines => "application/x-ndjson",
+        }));
+
+    let stream = PreviewStream {
+        origin,
+        req,
+        key,
+        chunk_size: preview.chunk_size,
+        max_bytes: preview.max_bytes,
+        counter: RowCounter::new(format, rows),
+        offset: 0,
+        total: first.total,
+        pending: Some(first.data),
+        fetch: None,
+        done: false,
+    };
+
+    let mut response = Response::new(axum::body::Body::from_stream(stream));
+    *response.status_mut() = StatusCode::OK;
+    response.headers_mut().insert(header::CONTENT_TYPE, content_type);
+    response
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requested_rows() {
+        let preview = Preview::new().max_rows(50);
+        let uri = |uri: &'static str| uri.parse::<Uri>().unwrap();
+
+        assert_eq!(preview.requested_rows(&uri("/data.csv?preview=10")), Some(Ok(10)));
+        assert_eq!(preview.requested_rows(&uri("/data.csv?a=1&preview=100")), Some(Ok(50)));
+        assert_eq!(preview.requested_rows(&uri("/data.csv?preview=0")), Some(Err(())));
+        assert_eq!(preview.requested_rows(&uri("/data.csv?preview=x")), Some(Err(())));
+        assert_eq!(preview.requested_rows(&uri("/data.csv")), None);
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(PreviewFormat::for_key("data/set.CSV"), Some(PreviewFormat::Delimited));
+        assert_eq!(PreviewFormat::for_key("events.jsonl"), Some(PreviewFormat::Lines));
+        assert_eq!(PreviewFormat::for_key("index.html"), None);
+    }
+
+    #[test]
+    fn counts_rows_across_chunks() {
+        let mut counter = RowCounter::new(PreviewFormat::Delimited, 2);
+        assert_eq!(counter.scan(b"a,b\n1,\"x\n"), 9);
+        assert!(!counter.done());
+        // The quoted newline does not end a row
+        assert_eq!(counter.scan(b"y\"\n2,z\n3,w\n"), 7);
+        assert!(counter.done());
+
+        let mut counter = RowCounter::new(PreviewFormat::Lines, 1);
+        assert_eq!(counter.scan(b"{\"a\":\"\\\"\"}\n{}\n"), 11);
+    }
+}
@@ -0,0 +1,216 @@
+//! Checking which objects exist.
+//!
+//! [`S3Origin::probe`] sends a HeadObject for each of a set of keys, a few at a time, e.g. to
+//! check at startup that every asset of a deployment was uploaded:
+//!
+//! ```rust,no_run
+//! # async fn example(origin: axum_static_s3::S3Origin) {
+//! use axum_static_s3::probe::ObjectStatus;
+//!
+//! let statuses = origin.probe(&["index.html", "assets/main.js", "assets/main.css"]).await;
+//! for (key, status) in &statuses {
+//!     if status == &ObjectStatus::Missing {
+//!         eprintln!("{} was not deployed", key);
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! Keys with content hashes are not known in advance; [`S3Origin::exists_matching`] lists the
+//! keys under the literal start of a glob instead, e.g. for a health check asking whether there
+//! is any `assets/index-*.js`.
+use std::collections::HashMap;
+
+use aws_sdk_s3::operation::head_object::HeadObjectOutput;
+
+use crate::{cost, pattern, S3Error, S3Origin, S3OriginInner};
+
+
+/// HeadObject requests in flight at once.
+const PROBE_CONCURRENCY: usize = 16;
+
+
+/// Whether an object exists.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ObjectStatus {
+    /// The object exists.
+    Found {
+        /// The size in bytes.
+        size: Option<i64>,
+        etag: Option<String>,
+    },
+    /// There is no object at the key.
+    Missing,
+    /// S3 could not be asked, e.g. because the request failed or was throttled.
+    Failed(S3Error),
+}
+
+impl ObjectStatus {
+    pub(crate) fn from_head(head: Result<HeadObjectOutput, S3Error>) -> Self {
+        match head {
+            Ok(head) => ObjectStatus::Found { size: head.content_length(), etag: head.e_tag().map(str::to_owned) },
+            Err(S3Error::NotFound) => ObjectStatus::Missing,
+            Err(error) => ObjectStatus::Failed(error),
+        }
+    }
+}
+
+
+/// HeadObject `key` (a full key) from the backend or S3.
+pub(crate) async fn head(origin: &S3OriginInner, key: &str) -> Result<HeadObjectOutput, S3Error> {
+    match &origin.backend {
+        Some(backend) => backend.head(key).await,
+        None => Ok(origin.s3_client.head_object().bucket(&origin.bucket).key(key).send().await?),
+    }
+}
+
+
+impl S3Origin {
+    /// Check which of `keys` exist, see [`probe`](crate::probe).
+    ///
+    /// Keys are relative to the bucket prefix, like request paths (`index.html`,
+    /// `assets/main.js`), and the statuses are keyed by them.  Up to 16 HeadObject requests are
+    /// sent at once.
+    ///
+    pub async fn probe(&self, keys: &[&str]) -> HashMap<String, ObjectStatus> {
+        let prefix = self.inner.bucket_prefix();
+        let mut statuses = HashMap::with_capacity(keys.len());
+        let mut heads = tokio::task::JoinSet::new();
+        for key in keys {
+            if heads.len() >= PROBE_CONCURRENCY {
+                if let Some(Ok((key, status))) = heads.join_next().await {
+                    statuses.insert(key, status);
+                }
+            }
+            let (origin, key) = (self.inner.clone(), key.to_string());
+            let full_key = format!("{}{}", prefix, key.trim_start_matches('/'));
+            heads.spawn(cost::scope(Some(origin.cost.clone()), async move {
+                (key, ObjectStatus::from_head(head(&origin, &full_key).await))
+            }));
+        }
+        while let Some(joined) = heads.join_next().await {
+            if let Ok((key, status)) = joined {
+                statuses.insert(key, status);
+            }
+        }
+        statuses
+    }
+
+    /// Whether any key matches the glob `pattern`, see [`probe`](crate::probe).
+    ///
+    /// Patterns are relative to the bucket prefix and match like
+    /// [`cache_control`](crate::S3OriginBuilder::cache_control) patterns: without a `/`, the
+    /// file name in any directory.  Only the keys under the part of the pattern before its first
+    /// wildcard are listed, and listing stops at the first match.
+    ///
+    /// Fails with [`S3Error::BadRequest`] if `pattern` is not a valid glob.
+    ///
+    pub async fn exists_matching(&self, pattern: &str) -> Result<bool, S3Error> {
+        let matcher = pattern::compile_glob(pattern).map_err(|_| S3Error::BadRequest)?;
+        let bucket_prefix = self.inner.bucket_prefix();
+        let prefix = format!("{}{}", bucket_prefix, literal_prefix(&pattern::normalize_glob(pattern)));
+        let matches = |key: &str| key.strip_prefix(&*bucket_prefix).is_some_and(|path| matcher.is_match(path));
+
+        let inner = &self.inner;
+        if let Some(backend) = &inner.backend {
+            return Ok(backend.list(&prefix).await?.iter().any(|key| matches(key)));
+        }
+        let mut pages = inner.s3_client.list_objects_v2()
+            .bucket(&inner.bucket)
+            .prefix(&prefix)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            if page?.contents().iter().filter_map(|object| object.key()).any(matches) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+
+/// The part of a glob before its first wildcard, which every match starts with.
+fn literal_prefix(pattern: &str) -> &str {
+    let end = pattern.find(['*', '?', '[', '{', '\\']).unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+    use aws_sdk_s3::operation::get_object::GetObjectOutput;
+
+    use crate::{backend::{BackendFuture, ObjectBackend}, S3OriginBuilder};
+
+    /// Objects with even numbers, recording the most HEAD requests in flight.
+    #[derive(Default)]
+    struct Numbered {
+        in_flight: AtomicUsize,
+        most: AtomicUsize,
+    }
+
+    impl ObjectBackend for Numbered {
+        fn get<'a>(&'a self, _key: &'a str, _range: Option<&'a str>) -> BackendFuture<'a, GetObjectOutput> {
+            Box::pin(async { Err(S3Error::NotFound) })
+        }
+
+        fn head<'a>(&'a self, key: &'a str) -> BackendFuture<'a, HeadObjectOutput> {
+            Box::pin(async move {
+                let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.most.fetch_max(in_flight, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                let number: usize = key.strip_prefix("site/").and_then(|key| key.parse().ok()).ok_or(S3Error::BadGateway)?;
+                match number % 2 {
+                    0 => Ok(HeadObjectOutput::builder().content_length(number as i64).build()),
+                    _ => Err(S3Error::NotFound),
+                }
+            })
+        }
+
+        fn list<'a>(&'a self, _prefix: &'a str) -> BackendFuture<'a, Vec<String>> {
+            Box::pin(async { Ok(Vec::new()) })
+        }
+    }
+
+    #[test]
+    fn reports_statuses() {
+        let head = HeadObjectOutput::builder().content_length(5).e_tag("\"1\"").build();
+        assert_eq!(ObjectStatus::from_head(Ok(head)), ObjectStatus::Found { size: Some(5), etag: Some("\"1\"".into()) });
+        assert_eq!(ObjectStatus::from_head(Err(S3Error::NotFound)), ObjectStatus::Missing);
+        assert_eq!(ObjectStatus::from_head(Err(S3Error::BadGateway)), ObjectStatus::Failed(S3Error::BadGateway));
+    }
+
+    #[test]
+    fn finds_literal_prefixes() {
+        assert_eq!(literal_prefix("assets/index-*.js"), "assets/index-");
+        assert_eq!(literal_prefix("docs/{a,b}/*.html"), "docs/");
+        assert_eq!(literal_prefix(&pattern::normalize_glob("index-*.js")), "");
+        assert_eq!(literal_prefix("robots.txt"), "robots.txt");
+    }
+
+    #[tokio::test]
+    async fn probes_keys_concurrently() {
+        let objects = Arc::new(Numbered::default());
+        let origin = S3OriginBuilder::new()
+            .bucket("my-bucket")
+            .prefix("site/")
+            .backend(objects.clone())
+            .build()
+            .unwrap();
+        let keys = (0..40).map(|number| number.to_string()).collect::<Vec<_>>();
+        let keys = keys.iter().map(String::as_str).chain(["/2", "x"]).collect::<Vec<_>>();
+
+        let statuses = origin.probe(&keys).await;
+        assert_eq!(statuses.len(), 42);
+        assert_eq!(statuses["4"], ObjectStatus::Found { size: Some(4), etag: None });
+        assert_eq!(statuses["/2"], ObjectStatus::Found { size: Some(2), etag: None });
+        assert_eq!(statuses["5"], ObjectStatus::Missing);
+        assert_eq!(statuses["x"], ObjectStatus::Failed(S3Error::BadGateway));
+        assert_eq!(objects.most.load(Ordering::SeqCst), PROBE_CONCURRENCY);
+    }
+}
@@ -0,0 +1,112 @@
+//! How query strings are treated.
+//!
+//! Query strings do not select objects, so by default they are ignored: `/app.js?v=123` is
+//! served and cached as `app.js`.  With [`QueryPolicy::cache_key`], the listed parameters
+//! become part of the cache key, so a new `?v=` cache-busting value fetches the object from S3
+//! again instead of serving the cached copy.  With [`QueryPolicy::reject_unexpected`],
+//! requests with any other parameter are answered with `400 Bad Request`.
+//!
+//! Parameters used by other features, such as [signed URLs](crate::signed_url) or
+//! [`version_query`](crate::S3OriginBuilder::version_query), must be listed to be accepted by
+//! `reject_unexpected`.
+use std::borrow::Cow;
+
+
+/// Query string configuration.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum QueryPolicy {
+    /// Ignore query strings (default).
+    #[default]
+    Ignore,
+    /// Include these parameters in the cache key.
+    CacheKey(Vec<String>),
+    /// Answer requests with any other parameter with `400 Bad Request`.
+    RejectUnexpected(Vec<String>),
+}
+
+impl QueryPolicy {
+    /// Include these parameters, e.g. `["v"]`, in the cache key.
+    pub fn cache_key<I, S>(params: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::CacheKey(params.into_iter().map(Into::into).collect())
+    }
+
+    /// Only accept these parameters; requests with others are rejected.
+    pub fn reject_unexpected<I, S>(allowed: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::RejectUnexpected(allowed.into_iter().map(Into::into).collect())
+    }
+
+    /// Whether a request with this query string is served.
+    pub(crate) fn allows(&self, query: Option<&str>) -> bool {
+        match self {
+            Self::RejectUnexpected(allowed) => params(query).all(|(name, _)| allowed.iter().any(|param| param == name)),
+            _ => true,
+        }
+    }
+
+    /// The cache key of an object requested with this query string.
+    ///
+    /// Listed parameters are appended in the order they are listed, e.g. `app.js?v=123`.
+    pub(crate) fn cache_key_for<'a>(&self, key: &'a str, query: Option<&str>) -> Cow<'a, str> {
+        let Self::CacheKey(listed) = self else {
+            return Cow::Borrowed(key);
+        };
+        let selected = listed.iter()
+            .filter_map(|param| params(query).find(|(name, _)| name == param))
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>();
+        match selected.is_empty() {
+            true => Cow::Borrowed(key),
+            false => Cow::Owned(format!("{}?{}", key, selected.join("&"))),
+        }
+    }
+
+    /// Whether cache keys may carry a query string after the object key.
+    pub(crate) fn varies_cache_key(&self) -> bool {
+        matches!(self, Self::CacheKey(listed) if !listed.is_empty())
+    }
+}
+
+
+/// The `name=value` pairs of a query string; `value` is empty for bare names.
+fn params(query: Option<&str>) -> impl Iterator<Item = (&str, &str)> {
+    query.unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_cache_keys() {
+        let policy = QueryPolicy::cache_key(["v", "lang"]);
+        assert_eq!(policy.cache_key_for("app.js", Some("lang=de&utm=x&v=123")), "app.js?v=123&lang=de");
+        assert_eq!(policy.cache_key_for("app.js", Some("utm=x")), "app.js");
+        assert_eq!(policy.cache_key_for("app.js", None), "app.js");
+        assert_eq!(QueryPolicy::Ignore.cache_key_for("app.js", Some("v=123")), "app.js");
+        assert!(policy.allows(Some("anything")));
+    }
+
+    #[test]
+    fn rejects_unexpected_params() {
+        let policy = QueryPolicy::reject_unexpected(["v"]);
+        assert!(policy.allows(None));
+        assert!(policy.allows(Some("")));
+        assert!(policy.allows(Some("v=123")));
+        assert!(!policy.allows(Some("v=123&debug")));
+        assert!(!policy.allows(Some("x=1")));
+        assert!(QueryPolicy::Ignore.allows(Some("x=1")));
+    }
+}
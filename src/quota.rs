@@ -0,0 +1,312 @@
+//! Byte quotas per path prefix or tenant.
+//!
+//! With [`byte_quota`](crate::S3OriginBuilder::byte_quota), the bytes served under a path prefix,
+//! or to a tenant named by a request header, are counted per day or per month (UTC).  Once a
+//! quota is used up, further requests are answered with `429 Too Many Requests` and a
+//! `Retry-After` until the next period, or with `403 Forbidden`, before any S3 call:
+//!
+//! ```rust
+//! use axum::http::HeaderName;
+//! use axum_static_s3::quota::{ByteQuota, QuotaPeriod};
+//!
+//! const GB: u64 = 1024 * 1024 * 1024;
+//! let quota = ByteQuota::new(QuotaPeriod::Monthly)
+//!     .prefix("free/", 100 * GB)
+//!     .per_tenant(HeaderName::from_static("x-tenant-id"), 10 * GB);
+//! ```
+//!
+//! A response is charged its `Content-Length` when its head is ready, whether or not the client
+//! reads the whole body, so concurrent downloads cannot overrun a quota by much.  Requests
+//! without the tenant header share one quota.
+//!
+//! The counts are kept in a [`QuotaStore`]; the default [`MemoryQuotaStore`] is local to the
+//! process, while a store backed by Redis or DynamoDB enforces the quotas across a fleet.  Store
+//! errors are logged under the `trace` feature and the request is served.
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use axum::{
+    http::{header, HeaderName, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError,
+};
+
+
+/// The future returned by [`QuotaStore`] methods.
+pub type QuotaFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send + 'a>>;
+
+
+/// A store of byte counts by quota key and period.
+///
+/// Keys are `prefix:<path prefix>` or `tenant:<header value>`; periods are `2024-05` for monthly
+/// and `2024-05-17` for daily quotas.  Counts of past periods are never read again.
+pub trait QuotaStore: Send + Sync + 'static {
+    /// The bytes counted for `key` in `period`.
+    fn used<'a>(&'a self, key: &'a str, period: &'a str) -> QuotaFuture<'a, u64>;
+
+    /// Add `bytes` to the count of `key` in `period`.
+    fn add<'a>(&'a self, key: &'a str, period: &'a str, bytes: u64) -> QuotaFuture<'a, ()>;
+}
+
+
+/// A store shared by several origins.
+impl<T: QuotaStore> QuotaStore for Arc<T> {
+    fn used<'a>(&'a self, key: &'a str, period: &'a str) -> QuotaFuture<'a, u64> {
+        (**self).used(key, period)
+    }
+
+    fn add<'a>(&'a self, key: &'a str, period: &'a str, bytes: u64) -> QuotaFuture<'a, ()> {
+        (**self).add(key, period, bytes)
+    }
+}
+
+
+/// An in-memory [`QuotaStore`], forgetting the counts of past periods.
+#[derive(Debug, Default)]
+pub struct MemoryQuotaStore {
+    counts: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl MemoryQuotaStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl QuotaStore for MemoryQuotaStore {
+    fn used<'a>(&'a self, key: &'a str, period: &'a str) -> QuotaFuture<'a, u64> {
+        let counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        let used = counts.get(&(key.to_owned(), period.to_owned())).copied().unwrap_or(0);
+        Box::pin(async move { Ok(used) })
+    }
+
+    fn add<'a>(&'a self, key: &'a str, period: &'a str, bytes: u64) -> QuotaFuture<'a, ()> {
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        // Periods of the same length are days or months; an older one is over
+        counts.retain(|(_, counted), _| counted.len() != period.len() || counted == period);
+        let count = counts.entry((key.to_owned(), period.to_owned())).or_default();
+        *count = count.saturating_add(bytes);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+
+/// How long a quota lasts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaPeriod {
+    /// A calendar day, UTC.
+    Daily,
+    /// A calendar month, UTC.
+    Monthly,
+}
+
+impl QuotaPeriod {
+    /// The period containing `now`, and the seconds until it ends.
+    fn at(self, now: SystemTime) -> (String, u64) {
+        let now = DateTime::from(now);
+        let date = now.fmt(DateTimeFormat::DateTime).unwrap_or_default();
+        let (year, month) = (date.get(..4).unwrap_or_default(), date.get(5..7).unwrap_or_default());
+        let (period, end) = match self {
+            QuotaPeriod::Daily => {
+                let end = (now.secs().div_euclid(86_400) + 1) * 86_400;
+                (date.get(..10).unwrap_or_default().to_owned(), end)
+            }
+            QuotaPeriod::Monthly => {
+                let next = match (year.parse::<i32>(), month.parse::<u32>()) {
+                    (Ok(year), Ok(12)) => format!("{:04}-01-01T00:00:00Z", year + 1),
+                    (Ok(year), Ok(month)) => format!("{:04}-{:02}-01T00:00:00Z", year, month + 1),
+                    _ => String::new(),
+                };
+                let end = DateTime::from_str(&next, DateTimeFormat::DateTime).map_or(now.secs(), |next| next.secs());
+                (date.get(..7).unwrap_or_default().to_owned(), end)
+            }
+        };
+        (period, end.saturating_sub(now.secs()).max(0) as u64)
+    }
+}
+
+
+/// Byte quota configuration.
+#[derive(Clone)]
+pub struct ByteQuota {
+    period: QuotaPeriod,
+    prefixes: Vec<(String, u64)>,
+    tenant: Option<(HeaderName, u64)>,
+    store: Arc<dyn QuotaStore>,
+    status: StatusCode,
+}
+
+impl ByteQuota {
+    /// Quotas renewed every `period`, kept in a [`MemoryQuotaStore`].
+    pub fn new(period: QuotaPeriod) -> Self {
+        Self {
+            period,
+            prefixes: Vec::new(),
+            tenant: None,
+            store: Arc::new(MemoryQuotaStore::new()),
+            status: StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// Serve at most `bytes` per period under the path `prefix`, e.g. `free/`, relative to the
+    /// bucket prefix.  Paths under several prefixes count towards the longest.
+    pub fn prefix(mut self, prefix: &str, bytes: u64) -> Self {
+        self.prefixes.push((prefix.trim_start_matches('/').to_owned(), bytes));
+        self
+    }
+
+    /// Serve at most `bytes` per period to every tenant, named by the `name` request header.
+    ///
+    /// Only use a header that clients cannot choose, e.g. one set by an authenticating gateway.
+    pub fn per_tenant(mut self, name: HeaderName, bytes: u64) -> Self {
+        self.tenant = Some((name, bytes));
+        self
+    }
+
+    /// Keep the counts in `store`.
+    pub fn store(mut self, store: impl QuotaStore) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    /// Answer requests over quota with this status; defaults to `429 Too Many Requests`, the
+    /// other choice being `403 Forbidden`.
+    pub fn exhausted_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), &'static str> {
+        match self.status {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::FORBIDDEN => Ok(()),
+            _ => Err("byte_quota status must be 429 or 403"),
+        }
+    }
+
+    /// The quota keys a request counts towards, with their limits.
+    pub(crate) fn keys<B>(&self, path: &str, req: &Request<B>) -> Vec<(String, u64)> {
+        let path = path.trim_start_matches('/');
+        let prefix = self.prefixes.iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, limit)| (format!("prefix:{}", prefix), *limit));
+        let tenant = self.tenant.as_ref().map(|(name, limit)| {
+            let tenant = req.headers().get(name).map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+            (format!("tenant:{}", tenant.unwrap_or_default()), *limit)
+        });
+        prefix.into_iter().chain(tenant).collect()
+    }
+
+    /// The response for a request over any of its quotas.
+    pub(crate) async fn check(&self, keys: &[(String, u64)]) -> Option<Response> {
+        let (period, remaining) = self.period.at(SystemTime::now());
+        for (key, limit) in keys {
+            match self.store.used(key, &period).await {
+                Ok(used) if used >= *limit => {
+                    let mut response = (self.status, "Quota exceeded").into_response();
+                    if self.status == StatusCode::TOO_MANY_REQUESTS {
+                        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(remaining.max(1)));
+                    }
+                    return Some(response);
+                }
+                Ok(_) => {}
+                Err(_error) => {
+                    #[cfg(feature = "trace")]
+                    tracing::warn!("S3Origin: reading quota {} failed: {}", key, _error);
+                }
+            }
+        }
+        None
+    }
+
+    /// The bytes `response` counts towards the quotas: the length of successful bodies.
+    pub(crate) fn charged(response: &Response) -> Option<u64> {
+        if !response.status().is_success() {
+            return None;
+        }
+        response.headers().get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse::<u64>().ok())
+            .filter(|bytes| *bytes > 0)
+    }
+
+    /// Count `bytes` towards the quotas.
+    pub(crate) async fn charge(&self, keys: &[(String, u64)], bytes: u64) {
+        let (period, _) = self.period.at(SystemTime::now());
+        for (key, _) in keys {
+            if let Err(_error) = self.store.add(key, &period, bytes).await {
+                #[cfg(feature = "trace")]
+                tracing::warn!("S3Origin: counting quota {} failed: {}", key, _error);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ByteQuota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ByteQuota")
+            .field("period", &self.period)
+            .field("prefixes", &self.prefixes)
+            .field("tenant", &self.tenant)
+            .field("store", &crate::redact::Opaque("store"))
+            .field("status", &self.status)
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn finds_periods() {
+        // 2024-02-29T23:00:00Z
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_247_600);
+        assert_eq!(QuotaPeriod::Daily.at(now), ("2024-02-29".into(), 3600));
+        assert_eq!(QuotaPeriod::Monthly.at(now), ("2024-02".into(), 3600));
+        // 2023-12-15T00:00:00Z
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_702_598_400);
+        assert_eq!(QuotaPeriod::Monthly.at(now), ("2023-12".into(), 17 * 86_400));
+    }
+
+    #[tokio::test]
+    async fn enforces_quotas() {
+        let quota = ByteQuota::new(QuotaPeriod::Daily)
+            .prefix("free/", 100)
+            .prefix("free/large/", 1000)
+            .per_tenant(HeaderName::from_static("x-tenant"), 150);
+        let request = Request::get("/free/a.bin").header("x-tenant", "acme").body(()).unwrap();
+        let keys = quota.keys("/free/a.bin", &request);
+        assert_eq!(keys, [("prefix:free/".to_owned(), 100), ("tenant:acme".to_owned(), 150)]);
+        assert_eq!(quota.keys("free/large/b.bin", &Request::new(())), [("prefix:free/large/".to_owned(), 1000), ("tenant:".to_owned(), 150)]);
+
+        let mut response = Response::new(axum::body::Body::empty());
+        response.headers_mut().insert(header::CONTENT_LENGTH, HeaderValue::from(60));
+        assert!(quota.check(&keys).await.is_none());
+        assert_eq!(ByteQuota::charged(&response), Some(60));
+        quota.charge(&keys, 60).await;
+        assert!(quota.check(&keys).await.is_none());
+        quota.charge(&keys, 60).await;
+
+        let response = quota.check(&keys).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+        // The tenant has used 120 of 150 bytes elsewhere
+        assert!(quota.check(&keys[1..]).await.is_none());
+
+        let quota = quota.exhausted_status(StatusCode::FORBIDDEN);
+        let response = quota.check(&keys).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!response.headers().contains_key(header::RETRY_AFTER));
+        assert!(quota.exhausted_status(StatusCode::NOT_FOUND).validate().is_err());
+    }
+}
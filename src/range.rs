@@ -0,0 +1,213 @@
+//! Range request policy.
+//!
+//! `Range` headers are forwarded to S3, which serves `206 Partial Content`.  Before that, the
+//! header is validated: anything but a single well-formed `bytes=` range (non-ASCII values,
+//! other units, several ranges) is ignored and the whole object is served, as HTTP allows.
+//!
+//! The builder can [disable](crate::S3OriginBuilder::ranges) ranges entirely, [clamp their
+//! size](crate::S3OriginBuilder::max_range_size) (clients read the served range from
+//! `Content-Range`), and serve [whole objects](crate::S3OriginBuilder::no_ranges_for) of some
+//! content types.  The content type is only known from the S3 response, so a ranged request for
+//! such an object costs a second GetObject.
+use axum::{
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    response::Response,
+};
+
+
+/// A single byte range, as parsed from a `Range` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ByteRange {
+    /// `bytes=start-end` (inclusive), or `bytes=start-` to the end of the object.
+    From(u64, Option<u64>),
+    /// `bytes=-length`, the last `length` bytes.
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Parse a `Range` header value; `None` for anything but a single valid `bytes=` range.
+    pub(crate) fn parse(range: &str) -> Option<Self> {
+        let (start, end) = range.trim().strip_prefix("bytes=")?.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        match (start.is_empty(), end.is_empty()) {
+            (true, false) => Some(ByteRange::Suffix(end.parse().ok()?)),
+            (false, true) => Some(ByteRange::From(start.parse().ok()?, None)),
+            (false, false) => {
+                let (start, end) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+                (start <= end).then_some(ByteRange::From(start, Some(end)))
+            }
+            (true, true) => None,
+        }
+    }
+
+    /// The range limited to at most `max_size` bytes.
+    fn clamp(self, max_size: u64) -> Self {
+        let max_size = max_size.max(1);
+        match self {
+            ByteRange::From(start, end) => {
+                let last = start.saturating_add(max_size - 1);
+                ByteRange::From(start, Some(end.map_or(last, |end| end.min(last))))
+            }
+            ByteRange::Suffix(length) => ByteRange::Suffix(length.min(max_size)),
+        }
+    }
+
+    /// The `Range` header requesting this range.
+    fn header_value(self) -> Option<HeaderValue> {
+        let value = match self {
+            ByteRange::From(start, Some(end)) => format!("bytes={}-{}", start, end),
+            ByteRange::From(start, None) => format!("bytes={}-", start),
+            ByteRange::Suffix(length) => format!("bytes=-{}", length),
+        };
+        HeaderValue::try_from(value).ok()
+    }
+}
+
+
+/// How `Range` headers are served.
+#[derive(Clone, Debug)]
+pub(crate) struct RangePolicy {
+    pub(crate) enabled: bool,
+    pub(crate) max_size: Option<u64>,
+    /// Lowercase content types served whole; a value ending in `/` matches the whole type.
+    pub(crate) whole_content_types: Vec<String>,
+}
+
+impl Default for RangePolicy {
+    fn default() -> Self {
+        Self { enabled: true, max_size: None, whole_content_types: Vec::new() }
+    }
+}
+
+impl RangePolicy {
+    /// Remove or rewrite the request's `Range` header; returns whether it was changed.
+    pub(crate) fn apply(&self, headers: &mut HeaderMap) -> bool {
+        let Some(range) = headers.get(header::RANGE) else {
+            return false;
+        };
+        let range = match self.enabled {
+            true => range.to_str().ok().and_then(ByteRange::parse),
+            false => None,
+        };
+        let Some(range) = range else {
+            headers.remove(header::RANGE);
+            return true;
+        };
+        match self.max_size.map(|max_size| range.clamp(max_size)) {
+            Some(clamped) if clamped != range => {
+                match clamped.header_value() {
+                    Some(value) => headers.insert(header::RANGE, value),
+                    None => headers.remove(header::RANGE),
+                };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The request without its `Range` header, if a ranged response might have to be replaced
+    /// by the whole object.
+    pub(crate) fn whole_request(&self, req: &Request<()>) -> Option<Request<()>> {
+        if self.whole_content_types.is_empty() || !req.headers().contains_key(header::RANGE) {
+            return None;
+        }
+        let mut whole = Request::new(());
+        *whole.method_mut() = req.method().clone();
+        *whole.uri_mut() = req.uri().clone();
+        *whole.headers_mut() = req.headers().clone();
+        *whole.extensions_mut() = req.extensions().clone();
+        whole.headers_mut().remove(header::RANGE);
+        Some(whole)
+    }
+
+    /// Whether a partial response is of a content type that is served whole.
+    pub(crate) fn serves_whole(&self, response: &Response) -> bool {
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return false;
+        }
+        let Some(content_type) = response.headers().get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()) else {
+            return false;
+        };
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        self.whole_content_types.iter().any(|whole| match whole.ends_with('/') {
+            true => mime.starts_with(whole.as_str()),
+            false => mime == *whole,
+        })
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    fn range(value: &[u8]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_bytes(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(ByteRange::parse("bytes=0-99"), Some(ByteRange::From(0, Some(99))));
+        assert_eq!(ByteRange::parse("bytes=100-"), Some(ByteRange::From(100, None)));
+        assert_eq!(ByteRange::parse("bytes=-5"), Some(ByteRange::Suffix(5)));
+        assert_eq!(ByteRange::parse("bytes=5-1"), None);
+        assert_eq!(ByteRange::parse("bytes=0-1,5-6"), None);
+        assert_eq!(ByteRange::parse("items=0-1"), None);
+        assert_eq!(ByteRange::parse("bytes=-"), None);
+    }
+
+    #[test]
+    fn validates_and_clamps_ranges() {
+        let policy = RangePolicy::default();
+        let mut headers = range(b"bytes=0-99");
+        assert!(!policy.apply(&mut headers));
+        assert_eq!(headers[header::RANGE], "bytes=0-99");
+
+        // Non-ASCII and malformed ranges are ignored
+        let mut headers = range("bytes=0-\u{e9}".as_bytes());
+        assert!(policy.apply(&mut headers));
+        assert!(!headers.contains_key(header::RANGE));
+        let mut headers = range(b"bytes=0-1,5-6");
+        assert!(policy.apply(&mut headers));
+        assert!(!headers.contains_key(header::RANGE));
+        assert!(!policy.apply(&mut HeaderMap::new()));
+
+        let policy = RangePolicy { max_size: Some(10), ..RangePolicy::default() };
+        for (requested, served) in [("bytes=0-99", "bytes=0-9"), ("bytes=5-", "bytes=5-14"), ("bytes=-50", "bytes=-10")] {
+            let mut headers = range(requested.as_bytes());
+            assert!(policy.apply(&mut headers));
+            assert_eq!(headers[header::RANGE], served);
+        }
+        let mut headers = range(b"bytes=0-9");
+        assert!(!policy.apply(&mut headers));
+
+        let policy = RangePolicy { enabled: false, ..RangePolicy::default() };
+        let mut headers = range(b"bytes=0-9");
+        assert!(policy.apply(&mut headers));
+        assert!(!headers.contains_key(header::RANGE));
+    }
+
+    #[test]
+    fn serves_content_types_whole() {
+        let policy = RangePolicy { whole_content_types: vec!["text/".into(), "application/json".into()], ..RangePolicy::default() };
+        let partial = |content_type: &'static str| {
+            let mut response = Response::new(axum::body::Body::empty());
+            *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+            response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+            response
+        };
+        assert!(policy.serves_whole(&partial("text/html; charset=utf-8")));
+        assert!(policy.serves_whole(&partial("application/json")));
+        assert!(!policy.serves_whole(&partial("video/mp4")));
+
+        let request = Request::get("/a.txt").header(header::RANGE, "bytes=0-1").body(()).unwrap();
+        let whole = policy.whole_request(&request).unwrap();
+        assert_eq!(whole.uri(), "/a.txt");
+        assert!(!whole.headers().contains_key(header::RANGE));
+        assert!(policy.whole_request(&Request::get("/a.txt").body(()).unwrap()).is_none());
+        assert!(RangePolicy::default().whole_request(&request).is_none());
+    }
+}
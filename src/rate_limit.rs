@@ -0,0 +1,267 @@
+//! Rate limiting per client.
+//!
+//! With [`rate_limit`](crate::S3OriginBuilder::rate_limit), every client gets a token bucket:
+//! each request takes a token, tokens refill at the sustained rate, and up to `burst` tokens
+//! accumulate while the client is idle.  A request finding the bucket empty is answered with
+//! `429 Too Many Requests` and a `Retry-After` for the next token, before any S3 call.
+//!
+//! Clients are told apart by address, as found by the origin's
+//! [`client_identity`](crate::S3OriginBuilder::client_identity).  IPv6 clients are limited per `/64`, since a
+//! single host usually controls a whole one.  Alternatively [`key_header`](RateLimit::key_header)
+//! keys the buckets by a header, e.g. an API key set by an upstream gateway.  Requests without
+//! a key share one bucket, so leaving the key out does not avoid the limit.  At most 10,000
+//! clients get a bucket of their own; while that many are still refilling, new clients share
+//! one, so rotating keys or addresses does not grow memory or avoid the limit either.
+//!
+//! ```rust
+//! use axum_static_s3::rate_limit::RateLimit;
+//!
+//! // 10 requests per second, bursts of up to 50, behind one load balancer
+//! let rate_limit = RateLimit::per_second(10.0).burst(50).trusted_proxies(1);
+//! ```
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::{header, HeaderName, HeaderValue, Request},
+    response::{IntoResponse, Response},
+};
+
+use crate::{client_identity::ClientIdentity, S3Error};
+
+
+/// Buckets kept before idle ones are dropped.
+const MAX_CLIENTS: usize = 10_000;
+
+/// How often a full map of buckets is searched for refilled ones.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+
+/// Rate limit configuration.
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    rate: f64,
+    burst: u32,
+    trusted_proxies: Option<usize>,
+    key_header: Option<HeaderName>,
+}
+
+impl RateLimit {
+    /// Allow `rate` requests per second per client, sustained; bursts default to one second's worth.
+    pub fn per_second(rate: f64) -> Self {
+        Self { rate, burst: rate.ceil().max(1.0) as u32, trusted_proxies: None, key_header: None }
+    }
+
+    /// Allow up to `burst` requests at once after an idle period.
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    /// Take the client address from the forwarding headers set by this many proxies, overriding
+    /// [`ClientIdentity::trusted_proxies`] for the limit.
+    ///
+    /// Defaults to the origin's [`client_identity`](crate::S3OriginBuilder::client_identity).
+    pub fn trusted_proxies(mut self, hops: usize) -> Self {
+        self.trusted_proxies = Some(hops);
+        self
+    }
+
+    /// Key the buckets by this header instead of the client address.
+    pub fn key_header(mut self, name: HeaderName) -> Self {
+        self.key_header = Some(name);
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), &'static str> {
+        match self.rate.is_finite() && self.rate > 0.0 && self.burst > 0 {
+            true => Ok(()),
+            false => Err("rate_limit needs a positive rate and burst"),
+        }
+    }
+}
+
+
+/// What the buckets are keyed by.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum ClientKey {
+    Address(IpAddr),
+    Header(Vec<u8>),
+    Unknown,
+    /// New clients while every bucket is taken.
+    Overflow,
+}
+
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<ClientKey, Bucket>,
+    pruned: Option<Instant>,
+}
+
+
+/// The token buckets of an origin.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    config: RateLimit,
+    identity: ClientIdentity,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: RateLimit, identity: ClientIdentity) -> Self {
+        let identity = identity.with_trusted_proxies(config.trusted_proxies);
+        Self { config, identity, buckets: Mutex::default() }
+    }
+
+    /// `429 Too Many Requests` if the client has no token left.
+    pub(crate) fn check<B>(&self, req: &Request<B>) -> Option<Response> {
+        let wait = self.take(self.key(req), Instant::now())?;
+        let mut response = S3Error::RateLimited.into_response();
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        Some(response)
+    }
+
+    fn key<B>(&self, req: &Request<B>) -> ClientKey {
+        if let Some(name) = &self.config.key_header {
+            return match req.headers().get(name) {
+                Some(value) => ClientKey::Header(value.as_bytes().to_vec()),
+                None => ClientKey::Unknown,
+            };
+        }
+        match self.identity.client_ip(req) {
+            Some(IpAddr::V6(ip)) => ClientKey::Address(IpAddr::V6((u128::from(ip) & (!0u128 << 64)).into())),
+            Some(ip) => ClientKey::Address(ip),
+            None => ClientKey::Unknown,
+        }
+    }
+
+    /// Take a token; if there is none, the time until the next one.
+    fn take(&self, mut key: ClientKey, now: Instant) -> Option<Duration> {
+        let RateLimit { rate, burst, .. } = self.config;
+        let burst = f64::from(burst);
+        let mut state = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let Buckets { buckets, pruned } = &mut *state;
+        if buckets.len() >= MAX_CLIENTS && !buckets.contains_key(&key) {
+            // Buckets that have refilled are the same as new ones; searching for them is O(n)
+            if pruned.is_none_or(|pruned| now.saturating_duration_since(pruned) >= PRUNE_INTERVAL) {
+                buckets.retain(|_, bucket| bucket.tokens + now.saturating_duration_since(bucket.updated).as_secs_f64() * rate < burst);
+                *pruned = Some(now);
+            }
+            if buckets.len() >= MAX_CLIENTS {
+                key = ClientKey::Overflow;
+            }
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket { tokens: burst, updated: now });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return None;
+        }
+        Some(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use axum::{extract::ConnectInfo, http::StatusCode};
+
+    fn request(peer: &str) -> Request<()> {
+        let mut request = Request::get("/a.txt").body(()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 4711)));
+        request
+    }
+
+    #[test]
+    fn limits_bursts_and_refills() {
+        let limiter = RateLimiter::new(RateLimit::per_second(2.0).burst(3), ClientIdentity::new());
+        let key = ClientKey::Address("10.0.0.1".parse().unwrap());
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.take(key.clone(), start), None);
+        }
+        assert_eq!(limiter.take(key.clone(), start), Some(Duration::from_millis(500)));
+        assert_eq!(limiter.take(ClientKey::Unknown, start), None);
+
+        // Two tokens per second
+        assert_eq!(limiter.take(key.clone(), start + Duration::from_millis(500)), None);
+        assert!(limiter.take(key.clone(), start + Duration::from_millis(600)).is_some());
+        for _ in 0..3 {
+            assert_eq!(limiter.take(key.clone(), start + Duration::from_secs(60)), None);
+        }
+        assert!(limiter.take(key, start + Duration::from_secs(60)).is_some());
+    }
+
+    #[test]
+    fn caps_clients() {
+        let limiter = RateLimiter::new(RateLimit::per_second(1.0), ClientIdentity::new());
+        let start = Instant::now();
+        let client = |number: u32| ClientKey::Header(number.to_be_bytes().to_vec());
+        for number in 0..MAX_CLIENTS as u32 {
+            assert_eq!(limiter.take(client(number), start), None);
+        }
+
+        // Every bucket is still refilling, so new clients share one
+        assert_eq!(limiter.take(client(u32::MAX), start), None);
+        assert!(limiter.take(client(u32::MAX - 1), start).is_some());
+        assert!(limiter.take(client(u32::MAX - 2), start + Duration::from_millis(100)).is_some());
+        assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), MAX_CLIENTS + 1);
+        // Known clients keep their own bucket
+        assert!(limiter.take(client(0), start).is_some());
+
+        // Once the buckets have refilled there is room again
+        assert_eq!(limiter.take(client(u32::MAX - 3), start + Duration::from_secs(2)), None);
+        assert_eq!(limiter.take(client(u32::MAX - 4), start + Duration::from_secs(2)), None);
+        assert!(limiter.buckets.lock().unwrap().buckets.len() <= 3);
+    }
+
+    #[test]
+    fn keys_clients() {
+        let limiter = RateLimiter::new(RateLimit::per_second(1.0), ClientIdentity::new());
+        assert_eq!(limiter.key(&request("10.0.0.1")), ClientKey::Address("10.0.0.1".parse().unwrap()));
+        assert_eq!(limiter.key(&request("::ffff:10.0.0.1")), ClientKey::Address("10.0.0.1".parse().unwrap()));
+        assert_eq!(limiter.key(&request("2001:db8::1")), limiter.key(&request("2001:db8::2:3")));
+        assert_ne!(limiter.key(&request("2001:db8::1")), limiter.key(&request("2001:db8:0:1::1")));
+        assert_eq!(limiter.key(&Request::get("/").body(()).unwrap()), ClientKey::Unknown);
+
+        // The limit's own proxy count wins over the origin's
+        let mut forwarded = request("172.16.0.1");
+        forwarded.headers_mut().insert("x-forwarded-for", HeaderValue::from_static("10.0.0.1, 10.0.0.2"));
+        let limiter = RateLimiter::new(RateLimit::per_second(1.0), ClientIdentity::new().trusted_proxies(1));
+        assert_eq!(limiter.key(&forwarded), ClientKey::Address("10.0.0.2".parse().unwrap()));
+        let limiter = RateLimiter::new(RateLimit::per_second(1.0).trusted_proxies(2), ClientIdentity::new().trusted_proxies(1));
+        assert_eq!(limiter.key(&forwarded), ClientKey::Address("10.0.0.1".parse().unwrap()));
+
+        let limiter = RateLimiter::new(RateLimit::per_second(1.0).key_header(HeaderName::from_static("x-api-key")), ClientIdentity::new());
+        let mut request = request("10.0.0.1");
+        request.headers_mut().insert("x-api-key", HeaderValue::from_static("ci"));
+        assert_eq!(limiter.key(&request), ClientKey::Header(b"ci".to_vec()));
+
+        let response = limiter.check(&request);
+        assert!(response.is_none());
+        let response = limiter.check(&request).unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+        assert!(RateLimit::per_second(0.0).validate().is_err());
+        assert!(RateLimit::per_second(1.0).burst(0).validate().is_err());
+    }
+}
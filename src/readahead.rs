@@ -0,0 +1,122 @@
+//! Bounded readahead between the S3 body and the HTTP body.
+//!
+//! Without readahead the S3 body is read in lockstep with the client: a slow client stalls the
+//! S3 connection (which may time out), and a slow S3 read stalls a fast client.  With
+//! [`readahead`](crate::S3OriginBuilder::readahead) a task reads the object body ahead of the
+//! client until the buffered chunks reach the budget.
+use std::{
+    future::poll_fn,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::body::{Body, Bytes};
+use futures_core::Stream;
+use tokio::{
+    sync::{mpsc, Semaphore},
+    task::JoinHandle,
+};
+
+
+type Item = Result<Bytes, axum::Error>;
+
+
+/// Read `body` ahead by up to `budget` bytes.
+pub(crate) fn readahead(body: Body, budget: usize) -> Body {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let permits = Arc::new(Semaphore::new(budget));
+    let task = tokio::spawn(fill(body, sender, permits.clone(), budget));
+    Body::from_stream(Readahead { receiver, permits, task })
+}
+
+
+/// Move chunks from the body into the channel while the budget allows.
+async fn fill(body: Body, sender: mpsc::UnboundedSender<(Item, u32)>, permits: Arc<Semaphore>, budget: usize) {
+    let mut stream = body.into_data_stream();
+    while let Some(item) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+        // A chunk larger than the budget takes all of it
+        let size = match &item {
+            Ok(chunk) => u32::try_from(chunk.len().min(budget)).unwrap_or(u32::MAX),
+            Err(_) => 0,
+        };
+        let Ok(permit) = permits.acquire_many(size).await else {
+            return;
+        };
+        permit.forget();
+        if sender.send((item, size)).is_err() {
+            return;
+        }
+    }
+}
+
+
+struct Readahead {
+    receiver: mpsc::UnboundedReceiver<(Item, u32)>,
+    permits: Arc<Semaphore>,
+    task: JoinHandle<()>,
+}
+
+impl Stream for Readahead {
+    type Item = Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match this.receiver.poll_recv(cx) {
+            Poll::Ready(Some((item, size))) => {
+                this.permits.add_permits(size as usize);
+                Poll::Ready(Some(item))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for Readahead {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use std::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+
+    use super::*;
+
+    struct Chunks {
+        remaining: usize,
+        read: Arc<AtomicUsize>,
+    }
+
+    impl Stream for Chunks {
+        type Item = Result<Bytes, std::io::Error>;
+
+        fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let this = self.get_mut();
+            if this.remaining == 0 {
+                return Poll::Ready(None);
+            }
+            this.remaining -= 1;
+            this.read.fetch_add(1, Ordering::SeqCst);
+            Poll::Ready(Some(Ok(Bytes::from_static(b"0123456789"))))
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_ahead_within_budget() {
+        let read = Arc::new(AtomicUsize::new(0));
+        let body = readahead(Body::from_stream(Chunks { remaining: 4, read: read.clone() }), 20);
+
+        // Two chunks fit the budget; the third waits for room
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(read.load(Ordering::SeqCst), 3);
+
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        assert_eq!(body.len(), 40);
+        assert_eq!(read.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,22 @@
+//! Redaction of configuration in `Debug` output.
+//!
+//! `Debug` implementations of the origin and its configuration types never print credentials,
+//! signing secrets or tokens, so `{:?}` logging of a configured origin is safe.  Values that may
+//! hold secrets, such as clients, SDK configs and callbacks, are printed as [`Opaque`].
+use std::fmt;
+
+
+/// Printed instead of a value that is set but has no useful, safe `Debug` output.
+pub(crate) struct Opaque(pub(crate) &'static str);
+
+impl fmt::Debug for Opaque {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}>", self.0)
+    }
+}
+
+
+/// `Some(<name>)` or `None`, without the value.
+pub(crate) fn opaque<T>(value: &Option<T>, name: &'static str) -> Option<Opaque> {
+    value.as_ref().map(|_| Opaque(name))
+}
@@ -0,0 +1,72 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::Response,
+};
+
+
+/// How objects carrying `x-amz-website-redirect-location` metadata are served.
+///
+/// S3 static website hosting answers requests for such objects with a redirect instead of the
+/// object body; buckets migrated from website hosting rely on this.  The location may be an
+/// absolute URL or a path starting with `/`, which is sent unchanged (i.e. relative to the
+/// server root, not the mount point of the origin).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WebsiteRedirect {
+    /// Redirect with `301 Moved Permanently`, like S3 website hosting (default).
+    #[default]
+    Permanent,
+    /// Redirect with `302 Found`.
+    Temporary,
+    /// Ignore the metadata and serve the object body.
+    Ignore,
+}
+
+
+impl WebsiteRedirect {
+    /// Build the redirect response for a location, if redirects are enabled and the location is valid.
+    pub(crate) fn response(&self, location: &str) -> Option<Response> {
+        let status = match self {
+            WebsiteRedirect::Permanent => StatusCode::MOVED_PERMANENTLY,
+            WebsiteRedirect::Temporary => StatusCode::FOUND,
+            WebsiteRedirect::Ignore => return None,
+        };
+
+        let location = HeaderValue::from_str(location).ok().filter(|_| is_valid_location(location))?;
+        Some(redirect(status, location))
+    }
+}
+
+
+/// Whether `location` is an absolute URL or a path starting with `/`.
+pub(crate) fn is_valid_location(location: &str) -> bool {
+    location.starts_with('/') || location.starts_with("http://") || location.starts_with("https://")
+}
+
+
+/// A redirect to `location` with `status` and an empty body.
+pub(crate) fn redirect(status: StatusCode, location: HeaderValue) -> Response {
+    let mut response = Response::new(axum::body::Body::empty());
+    *response.status_mut() = status;
+    response.headers_mut().insert(header::LOCATION, location);
+    response
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects() {
+        let response = WebsiteRedirect::Permanent.response("/new/index.html").unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[header::LOCATION], "/new/index.html");
+
+        let response = WebsiteRedirect::Temporary.response("https://example.com/").unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+
+        assert!(WebsiteRedirect::Ignore.response("/new/index.html").is_none());
+        assert!(WebsiteRedirect::Permanent.response("javascript:alert(1)").is_none());
+    }
+}
@@ -0,0 +1,372 @@
+//! Serving rendered forms of objects through content negotiation.
+//!
+//! A [`Render`] implementation is registered per file extension with
+//! [`S3OriginBuilder::renderer`](crate::S3OriginBuilder::renderer).  For matching paths the
+//! origin compares the client's `Accept` preference for the object's native content type with
+//! its preference for the rendered type:
+//!
+//! - the rendered form is served when the client prefers it (e.g. a browser sending
+//!   `text/html,…,*/*;q=0.8` for a `text/markdown` object);
+//! - the raw object is served otherwise, including ties (no `Accept`, or `*/*`).
+//!
+//! Both forms carry `Vary: Accept`.  Range requests always receive the raw object, and so do
+//! objects stored with a `Content-Encoding`, since their bytes are not the source.  The rendered
+//! form gets a weak ETag of its own (`W/"<etag>-html"`) and none of the object's checksums.
+//!
+//! The crate ships [`CsvTable`], and `MarkdownRenderer` with the `markdown` feature.
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::negotiation::{media_quality, preferences};
+use crate::telemetry::{self, Feature};
+
+
+/// Default maximum size of an object that is rendered: 1 MiB.
+pub const DEFAULT_MAX_SOURCE_SIZE: usize = 1024 * 1024;
+
+
+/// Transforms an object into an alternative representation.
+pub trait Render: Send + Sync + 'static {
+    /// The content type of the rendered form.
+    fn content_type(&self) -> &str {
+        "text/html; charset=utf-8"
+    }
+
+    /// The maximum object size that is rendered; larger objects are served raw.
+    ///
+    /// The object is buffered in memory up to this size before rendering.
+    fn max_source_size(&self) -> usize {
+        DEFAULT_MAX_SOURCE_SIZE
+    }
+
+    /// Render the complete object.
+    fn render(&self, source: &[u8]) -> Vec<u8>;
+}
+
+
+/// Whether the client prefers the rendered form over the object's native content type.
+pub(crate) fn prefers_rendered(request_headers: &HeaderMap, native_type: &str, rendered_type: &str) -> bool {
+    let preferences = preferences(request_headers, &header::ACCEPT);
+    let native = media_quality(preferences.as_deref(), native_type);
+    let rendered = media_quality(preferences.as_deref(), rendered_type);
+    rendered > native
+}
+
+
+/// Render a successful response if the client prefers the rendered form.
+///
+/// Responses that are not `200 OK`, are content-encoded, or whose `Content-Length` is unknown or
+/// exceeds the renderer's `max_source_size`, are served raw.  `Vary: Accept` is added in all
+/// cases.
+pub(crate) async fn render_response(renderer: &dyn Render, request_headers: &HeaderMap, mut response: Response) -> Response {
+    crate::vary::add(response.headers_mut(), "accept");
+    if !renders(renderer, request_headers, &response) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let source = match axum::body::to_bytes(body, renderer.max_source_size()).await {
+        Ok(source) => source,
+        Err(_) => return (StatusCode::BAD_GATEWAY, "Bad gateway").into_response(),
+    };
+
+    let rendered = renderer.render(&source);
+    set_rendered_headers(renderer, &mut parts.headers);
+    parts.headers.insert(header::CONTENT_LENGTH, HeaderValue::from(rendered.len()));
+    let mut response = Response::from_parts(parts, axum::body::Body::from(rendered));
+    telemetry::record(&mut response, Feature::Render);
+    response
+}
+
+
+/// Adjust the headers of a response to a HEAD request the same way [`render_response`] would.
+///
+/// The rendered length is unknown without fetching and rendering the object, so
+/// `Content-Length` is omitted.
+pub(crate) fn render_head(renderer: &dyn Render, request_headers: &HeaderMap, mut response: Response) -> Response {
+    crate::vary::add(response.headers_mut(), "accept");
+    if renders(renderer, request_headers, &response) {
+        set_rendered_headers(renderer, response.headers_mut());
+        response.headers_mut().remove(header::CONTENT_LENGTH);
+        telemetry::record(&mut response, Feature::Render);
+    }
+    response
+}
+
+
+fn renders(renderer: &dyn Render, request_headers: &HeaderMap, response: &Response) -> bool {
+    let headers = response.headers();
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    let native_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream");
+
+    let encoded = headers.get(header::CONTENT_ENCODING).is_some_and(|encoding| encoding != "identity");
+
+    response.status() == StatusCode::OK
+        && !encoded
+        && content_length.is_some_and(|length| length <= renderer.max_source_size())
+        && prefers_rendered(request_headers, native_type, renderer.content_type())
+}
+
+
+/// Describe the rendered form: its content type, a weak ETag of its own, and no checksums of the
+/// object's bytes.
+fn set_rendered_headers(renderer: &dyn Render, headers: &mut HeaderMap) {
+    let content_type = HeaderValue::from_str(renderer.content_type())
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+    headers.insert(header::CONTENT_TYPE, content_type);
+
+    let etag = headers.remove(header::ETAG);
+    let etag = etag.as_ref()
+        .and_then(|etag| etag.to_str().ok())
+        .and_then(|etag| rendered_etag(etag, renderer.content_type()));
+    if let Some(etag) = etag {
+        headers.insert(header::ETAG, etag);
+    }
+    let checksums = headers.keys()
+        .filter(|name| name.as_str().starts_with("x-amz-checksum-") || name.as_str() == "content-md5")
+        .cloned()
+        .collect::<Vec<_>>();
+    for name in checksums {
+        headers.remove(name);
+    }
+}
+
+
+/// `W/"<opaque>-<subtype>"` for the ETag of the object, e.g. `W/"abc-html"` for `"abc"` rendered
+/// as `text/html`.
+fn rendered_etag(etag: &str, content_type: &str) -> Option<HeaderValue> {
+    let opaque = etag.strip_prefix("W/").unwrap_or(etag).trim_matches('"');
+    let subtype = content_type.split(';').next()?.split('/').nth(1)?.trim();
+    HeaderValue::from_str(&format!("W/\"{}-{}\"", opaque, subtype)).ok()
+}
+
+
+/// Renders CSV objects as an HTML table preview.
+///
+/// The first row is rendered as the table header.  Only the first `max_rows` data rows are
+/// shown.
+#[derive(Clone, Debug)]
+pub struct CsvTable {
+    max_rows: usize,
+    max_source_size: usize,
+}
+
+
+impl CsvTable {
+    pub fn new() -> Self {
+        Self {
+            max_rows: 1000,
+            max_source_size: DEFAULT_MAX_SOURCE_SIZE,
+        }
+    }
+
+    /// Set the maximum number of data rows shown.
+    ///
+    /// This is optional, and defaults to 1000.
+    ///
+    pub fn max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = max_rows;
+        self
+    }
+
+    /// Set the maximum size of a CSV object that is rendered.
+    ///
+    /// This is optional, and defaults to [`DEFAULT_MAX_SOURCE_SIZE`].
+    ///
+    pub fn max_source_size(mut self, max_source_size: usize) -> Self {
+        self.max_source_size = max_source_size;
+        self
+    }
+}
+
+impl Default for CsvTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Render for CsvTable {
+    fn max_source_size(&self) -> usize {
+        self.max_source_size
+    }
+
+    fn render(&self, source: &[u8]) -> Vec<u8> {
+        let source = String::from_utf8_lossy(source);
+        let mut rows = parse_csv(&source).into_iter();
+
+        let mut html = String::from("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"></head>\n<body>\n<table>\n");
+        if let Some(header) = rows.next() {
+            html.push_str("<thead><tr>");
+            for cell in header {
+                html.push_str(&format!("<th>{}</th>", escape_html(&cell)));
+            }
+            html.push_str("</tr></thead>\n<tbody>\n");
+        }
+        let mut truncated = false;
+        for (i, row) in rows.enumerate() {
+            if i == self.max_rows {
+                truncated = true;
+                break;
+            }
+            html.push_str("<tr>");
+            for cell in row {
+                html.push_str(&format!("<td>{}</td>", escape_html(&cell)));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</tbody>\n</table>\n");
+        if truncated {
+            html.push_str(&format!("<p>Showing the first {} rows.</p>\n", self.max_rows));
+        }
+        html.push_str("</body>\n</html>\n");
+        html.into_bytes()
+    }
+}
+
+
+/// Parse CSV (RFC 4180): `,` separated, `"` quoted with `""` escapes, CRLF or LF line endings.
+pub(crate) fn parse_csv(source: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if cell.is_empty() => quoted = true,
+            (',', false) => row.push(std::mem::take(&mut cell)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            (c, _) => cell.push(c),
+        }
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    rows
+}
+
+
+pub(crate) fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    fn csv_response() -> Response {
+        Response::builder()
+            .header(header::CONTENT_TYPE, "text/csv")
+            .header(header::CONTENT_LENGTH, 8)
+            .body(axum::body::Body::from("a,b\n1,2\n"))
+            .unwrap()
+    }
+
+    #[test]
+    fn negotiates_between_native_and_rendered() {
+        let browser = accept("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8");
+        assert!(prefers_rendered(&browser, "text/csv", "text/html"));
+        assert!(!prefers_rendered(&accept("text/csv"), "text/csv", "text/html"));
+        assert!(!prefers_rendered(&accept("*/*"), "text/csv", "text/html"));
+        assert!(!prefers_rendered(&HeaderMap::new(), "text/csv", "text/html"));
+        assert!(!prefers_rendered(&accept("text/csv, text/html;q=0.5"), "text/csv", "text/html"));
+    }
+
+    #[test]
+    fn parses_csv() {
+        assert_eq!(parse_csv("a,\"b,\"\"c\"\"\"\r\n1,2"), vec![
+            vec!["a".to_string(), "b,\"c\"".to_string()],
+            vec!["1".to_string(), "2".to_string()],
+        ]);
+    }
+
+    #[test]
+    fn csv_table_truncates() {
+        let html = String::from_utf8(CsvTable::new().max_rows(1).render(b"h\n<1>\n2\n")).unwrap();
+        assert!(html.contains("<th>h</th>"));
+        assert!(html.contains("<td>&lt;1&gt;</td>"));
+        assert!(!html.contains("<td>2</td>"));
+        assert!(html.contains("Showing the first 1 rows."));
+    }
+
+    #[tokio::test]
+    async fn renders_preferred_form() {
+        let renderer = CsvTable::new();
+
+        let response = render_response(&renderer, &accept("text/html"), csv_response()).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+        assert_eq!(response.headers()[header::VARY], "accept");
+
+        let response = render_response(&renderer, &accept("text/csv"), csv_response()).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/csv");
+        assert_eq!(response.headers()[header::VARY], "accept");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"a,b\n1,2\n");
+    }
+
+    #[tokio::test]
+    async fn rendered_form_has_its_own_etag() {
+        let mut raw = csv_response();
+        raw.headers_mut().insert(header::ETAG, HeaderValue::from_static("\"abc\""));
+        raw.headers_mut().insert("x-amz-checksum-crc32", HeaderValue::from_static("DUoRhQ=="));
+        let response = render_response(&CsvTable::new(), &accept("text/html"), raw).await;
+        assert_eq!(response.headers()[header::ETAG], "W/\"abc-html\"");
+        assert!(!response.headers().contains_key("x-amz-checksum-crc32"));
+
+        assert_eq!(rendered_etag("W/\"abc\"", "text/html; charset=utf-8").unwrap(), "W/\"abc-html\"");
+    }
+
+    #[tokio::test]
+    async fn encoded_objects_are_served_raw() {
+        let mut raw = csv_response();
+        raw.headers_mut().insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        let response = render_response(&CsvTable::new(), &accept("text/html"), raw).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/csv");
+        assert_eq!(response.headers()[header::VARY], "accept");
+    }
+
+    #[test]
+    fn head_omits_rendered_length() {
+        let response = render_head(&CsvTable::new(), &accept("text/html"), csv_response());
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+        assert!(!response.headers().contains_key(header::CONTENT_LENGTH));
+    }
+}
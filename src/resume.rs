@@ -0,0 +1,156 @@
+//! Resuming object bodies that fail mid-stream.
+//!
+//! Once the response head is sent, an error reading the S3 body can only truncate the download.
+//! With [`resume_retries`](crate::S3OriginBuilder::resume_retries) the body instead reissues a
+//! ranged GetObject from the first byte not yet delivered, conditional on the object's ETag
+//! (`If-Match`), so the client receives the remaining bytes of the same object version.  If the
+//! object changed, or the retries are used up, the original error ends the body.
+//!
+//! Retries are sent without the [`customize_request`](crate::S3OriginBuilder::customize_request)
+//! hook, so they fail for objects that need per-request parameters such as SSE-C keys.
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use aws_sdk_s3::{
+    error::SdkError,
+    operation::get_object::{GetObjectError, GetObjectOutput},
+    primitives::{ByteStream, ByteStreamError},
+    Client as S3Client,
+};
+use axum::body::Bytes;
+use futures_core::Stream;
+
+use crate::cost::{self, CostCounters};
+
+
+type Reconnect = Pin<Box<dyn Future<Output = Result<GetObjectOutput, SdkError<GetObjectError>>> + Send>>;
+
+
+enum State {
+    Streaming(ByteStream),
+    /// A ranged GET is in flight; the error is returned if it fails.
+    Reconnecting(Reconnect, ByteStreamError),
+    Done,
+}
+
+
+/// An object body that resumes from the last delivered byte after a read error.
+pub(crate) struct ResumableBody {
+    state: State,
+    client: Arc<S3Client>,
+    /// Reconnects happen while the body streams, outside of the origin's scope.
+    cost: Option<Arc<CostCounters>>,
+    bucket: String,
+    key: String,
+    etag: String,
+    /// The absolute offset of the next byte.
+    offset: u64,
+    /// The absolute offset of the last byte, for ranged responses.
+    end: Option<u64>,
+    retries: u32,
+}
+
+impl ResumableBody {
+    /// Wrap the body of `output`; bodies without an ETag are returned unchanged.
+    pub(crate) fn new(client: Arc<S3Client>, bucket: &str, key: &str, output: &mut GetObjectOutput, retries: u32) -> Option<Self> {
+        let etag = output.e_tag.clone()?;
+        let (offset, end) = output.content_range()
+            .and_then(parse_content_range)
+            .map_or((0, None), |(start, end)| (start, Some(end)));
+        Some(Self {
+            state: State::Streaming(std::mem::take(&mut output.body)),
+            client,
+            cost: cost::current(),
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            etag,
+            offset,
+            end,
+            retries,
+        })
+    }
+
+    fn reconnect(&self) -> Reconnect {
+        let range = match self.end {
+            Some(end) => format!("bytes={}-{}", self.offset, end),
+            None => format!("bytes={}-", self.offset),
+        };
+        let request = self.client.get_object()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .range(range)
+            .if_match(&self.etag);
+        Box::pin(cost::scope(self.cost.clone(), request.send()))
+    }
+}
+
+impl Stream for ResumableBody {
+    type Item = Result<Bytes, ByteStreamError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match &mut this.state {
+                State::Streaming(body) => match Pin::new(body).poll_next(cx) {
+                    Poll::Ready(Some(Ok(chunk))) => {
+                        this.offset += chunk.len() as u64;
+                        return Poll::Ready(Some(Ok(chunk)));
+                    }
+                    Poll::Ready(Some(Err(error))) if this.retries > 0 => {
+                        #[cfg(feature = "trace")]
+                        tracing::warn!("S3Origin: body of {} failed at byte {}, resuming: {}", this.key, this.offset, error);
+                        this.retries -= 1;
+                        this.state = State::Reconnecting(this.reconnect(), error);
+                    }
+                    Poll::Ready(Some(Err(error))) => {
+                        this.state = State::Done;
+                        return Poll::Ready(Some(Err(error)));
+                    }
+                    Poll::Ready(None) => {
+                        this.state = State::Done;
+                        return Poll::Ready(None);
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+                State::Reconnecting(reconnect, _) => match reconnect.as_mut().poll(cx) {
+                    Poll::Ready(Ok(output)) => this.state = State::Streaming(output.body),
+                    Poll::Ready(Err(_error)) => {
+                        #[cfg(feature = "trace")]
+                        tracing::warn!("S3Origin: resuming {} failed: {}", this.key, aws_sdk_s3::error::DisplayErrorContext(&_error));
+                        if let State::Reconnecting(_, error) = std::mem::replace(&mut this.state, State::Done) {
+                            return Poll::Ready(Some(Err(error)));
+                        }
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+                State::Done => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+
+/// The first and last byte of a `Content-Range: bytes {first}-{last}/{length}` value.
+fn parse_content_range(value: &str) -> Option<(u64, u64)> {
+    let (range, _length) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    Some((first.trim().parse().ok()?, last.trim().parse().ok()?))
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_content_ranges() {
+        assert_eq!(parse_content_range("bytes 100-199/1000"), Some((100, 199)));
+        assert_eq!(parse_content_range("bytes */1000"), None);
+        assert_eq!(parse_content_range("items 1-2/3"), None);
+    }
+}
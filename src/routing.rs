@@ -0,0 +1,28 @@
+//! Adapters for mounting an origin in a router or serving it alone.
+use std::convert::Infallible;
+
+use axum::{
+    routing::{any_service, IntoMakeService, MethodRouter},
+    Router,
+};
+
+use crate::S3Origin;
+
+
+impl S3Origin {
+    /// The origin as a [`MethodRouter`] for any method, e.g. for `Router::route` or to add
+    /// method-specific layers.
+    ///
+    pub fn method_router<S>(&self) -> MethodRouter<S, Infallible>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        any_service(self.clone())
+    }
+
+    /// Serve the origin alone, for every path, e.g. with `axum::serve(listener, origin.into_make_service())`.
+    ///
+    pub fn into_make_service(self) -> IntoMakeService<Router> {
+        Router::new().fallback_service(self).into_make_service()
+    }
+}
@@ -0,0 +1,299 @@
+//! Precedence, validation and dry-run evaluation of path rules.
+//!
+//! Path rules are the glob-based builder options:
+//!
+//! - [`redirect`](crate::S3OriginBuilder::redirect) answers with a redirect,
+//! - [`public`](crate::S3OriginBuilder::public) exempts paths from authentication,
+//! - [`cache_control`](crate::S3OriginBuilder::cache_control) sets `Cache-Control`,
+//! - [`attachment`](crate::S3OriginBuilder::attachment) sets `Content-Disposition`,
+//! - [`content_type`](crate::S3OriginBuilder::content_type) overrides `Content-Type`.
+//!
+//! Precedence is:
+//!
+//! 1. Rules of different kinds are independent: a path can match one rule of each kind.
+//! 2. Within a kind, rules are evaluated in the order they were added and the first match wins.
+//! 3. Built-in behavior (immutable-asset detection, object metadata) only applies when no rule
+//!    of that kind matches.
+//! 4. Public rules are checked before anything else, since they decide whether the request is
+//!    authenticated at all.  IP filters and rate limits still apply to public paths.
+//! 5. Redirect rules are answered after authentication and before S3 is asked, so the header
+//!    rules never see a redirected path.
+//!
+//! [`S3OriginBuilder::validate`](crate::S3OriginBuilder::validate) reports rules that can never
+//! apply under these precedence rules, and [`build`](crate::S3OriginBuilder::build) refuses them.
+//! [`S3Origin::evaluate_rules`](crate::S3Origin::evaluate_rules) shows which rules apply to a path.
+use std::fmt;
+
+use axum::http::HeaderValue;
+use globset::GlobMatcher;
+
+use crate::{pattern::{compile_glob, is_literal, normalize_glob}, redirect};
+
+
+/// The kind of a path rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RuleKind {
+    CacheControl,
+    Attachment,
+    Redirect,
+    Public,
+    ContentType,
+}
+
+
+/// A path rule as configured on the builder.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub kind: RuleKind,
+    /// Position among the rules of the same kind, in the order they were added.
+    pub index: usize,
+    pub pattern: String,
+    /// The header value, for rules that set one.
+    pub value: Option<String>,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} rule #{} `{}`", self.kind, self.index, self.pattern)?;
+        if let Some(value) = &self.value {
+            write!(f, " => `{}`", value)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Why a rule can never apply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// An earlier rule has the same pattern and value.
+    Duplicate,
+    /// An earlier rule has the same pattern but a different value.
+    Conflicting,
+    /// An earlier rule matches every path this rule matches.
+    Shadowed,
+}
+
+
+/// A rule that can never apply, and the earlier rule responsible.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuleConflict {
+    pub kind: ConflictKind,
+    pub rule: Rule,
+    pub by: Rule,
+}
+
+impl fmt::Display for RuleConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.kind {
+            ConflictKind::Duplicate => "duplicates",
+            ConflictKind::Conflicting => "conflicts with",
+            ConflictKind::Shadowed => "is shadowed by",
+        };
+        write!(f, "{} {} {}", self.rule, reason, self.by)
+    }
+}
+
+
+/// The rules that apply to a path, as reported by [`S3Origin::evaluate_rules`](crate::S3Origin::evaluate_rules).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleEvaluation {
+    /// The `Cache-Control` rule that matches.
+    pub cache_control: Option<Rule>,
+    /// The `Cache-Control` value from immutable-asset detection, when no rule matches.
+    ///
+    /// HTML detection by content type is not visible to a dry run; only the file name is used.
+    /// If neither is set, the object's own `Cache-Control` metadata is used.
+    pub detected_cache_control: Option<&'static str>,
+    /// The attachment rule that matches.
+    pub attachment: Option<Rule>,
+    /// The file extension whose renderer applies, if any.
+    pub renderer: Option<String>,
+    /// The redirect rule that matches; the other rules never apply to a redirected path.
+    pub redirect: Option<Rule>,
+    /// The public rule that matches, exempting the path from authentication.
+    pub public: Option<Rule>,
+    /// The `Content-Type` rule that matches.
+    pub content_type: Option<Rule>,
+}
+
+
+/// The compiled rules of one kind, for kinds without a policy of their own.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PathRules {
+    rules: Vec<(GlobMatcher, Option<HeaderValue>)>,
+}
+
+impl PathRules {
+    /// Compile the rules of `kind`, in the order they were added.
+    pub(crate) fn new(rules: &[Rule], kind: RuleKind) -> Result<Self, &'static str> {
+        let (invalid_pattern, invalid_value) = match kind {
+            RuleKind::Redirect => ("invalid redirect pattern", "invalid redirect location"),
+            RuleKind::Public => ("invalid public pattern", ""),
+            RuleKind::ContentType => ("invalid content_type pattern", "invalid content_type value"),
+            RuleKind::CacheControl => ("invalid cache_control pattern", "invalid cache_control value"),
+            RuleKind::Attachment => ("invalid attachment pattern", ""),
+        };
+        let rules = rules.iter()
+            .filter(|rule| rule.kind == kind)
+            .map(|rule| {
+                let matcher = compile_glob(&rule.pattern).map_err(|_| invalid_pattern)?;
+                let value = match rule.value.as_deref() {
+                    Some(location) if kind == RuleKind::Redirect && !redirect::is_valid_location(location) => {
+                        return Err(invalid_value);
+                    }
+                    Some(value) => Some(HeaderValue::from_str(value).map_err(|_| invalid_value)?),
+                    None => None,
+                };
+                Ok((matcher, value))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+
+    /// The index of the first rule matching the path.
+    pub(crate) fn matching_rule(&self, path: &str) -> Option<usize> {
+        self.rules.iter().position(|(matcher, _)| matcher.is_match(path))
+    }
+
+    /// The value of the first rule matching the path.
+    pub(crate) fn value(&self, path: &str) -> Option<&HeaderValue> {
+        self.matching_rule(path).and_then(|index| self.rules[index].1.as_ref())
+    }
+}
+
+
+/// Where a rule-governed header value came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Source {
+    /// A configured rule.
+    Rule,
+    /// Built-in detection, e.g. immutable assets.
+    Detected,
+    /// The object's own metadata.
+    Object,
+}
+
+
+/// Find rules that can never apply.
+///
+/// Shadowing is detected when an earlier rule of the same kind matches every path (`**`, `*`),
+/// or when a later rule is a literal path the earlier rule matches.  Overlapping wildcard
+/// patterns are allowed, since the later rule may still match paths the earlier one does not.
+pub(crate) fn validate(rules: &[Rule]) -> Vec<RuleConflict> {
+    let mut conflicts = Vec::new();
+
+    for (i, rule) in rules.iter().enumerate() {
+        let earlier = rules[..i].iter().filter(|earlier| earlier.kind == rule.kind);
+        for earlier in earlier {
+            if let Some(kind) = conflict(earlier, rule) {
+                conflicts.push(RuleConflict { kind, rule: rule.clone(), by: earlier.clone() });
+                break;
+            }
+        }
+    }
+
+    conflicts
+}
+
+
+fn conflict(earlier: &Rule, rule: &Rule) -> Option<ConflictKind> {
+    let earlier_pattern = normalize_glob(&earlier.pattern);
+    let pattern = normalize_glob(&rule.pattern);
+
+    if earlier_pattern == pattern {
+        return Some(match earlier.value == rule.value {
+            true => ConflictKind::Duplicate,
+            false => ConflictKind::Conflicting,
+        });
+    }
+
+    if earlier_pattern == "**" || earlier_pattern == "**/*" || earlier_pattern == "**/**" {
+        return Some(ConflictKind::Shadowed);
+    }
+
+    // A file name pattern applies in every directory, so only another file name pattern can
+    // shadow it
+    if !is_literal(&rule.pattern) || (!pattern.contains('/') && earlier_pattern.contains('/')) {
+        return None;
+    }
+    let matcher = compile_glob(&earlier.pattern).ok()?;
+    match matcher.is_match(rule.pattern.trim_start_matches('/')) {
+        true => Some(ConflictKind::Shadowed),
+        false => None,
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    fn rule(kind: RuleKind, index: usize, pattern: &str, value: Option<&str>) -> Rule {
+        Rule { kind, index, pattern: pattern.to_string(), value: value.map(str::to_string) }
+    }
+
+    #[test]
+    fn detects_conflicts() {
+        let rules = [
+            rule(RuleKind::CacheControl, 0, "*.js", Some("max-age=60")),
+            rule(RuleKind::CacheControl, 1, "/**/*.js", Some("max-age=5")),
+            rule(RuleKind::CacheControl, 2, "*.js", Some("max-age=60")),
+            rule(RuleKind::CacheControl, 3, "/vendor/jquery.js", Some("max-age=1")),
+            rule(RuleKind::CacheControl, 4, "/vendor/*.css", Some("max-age=1")),
+            rule(RuleKind::CacheControl, 5, "jquery.js", Some("max-age=1")),
+            rule(RuleKind::Attachment, 0, "*.js", None),
+        ];
+
+        let conflicts = validate(&rules);
+        let kinds: Vec<_> = conflicts.iter().map(|c| (c.rule.index, c.kind)).collect();
+        assert_eq!(kinds, [
+            (1, ConflictKind::Conflicting),
+            (2, ConflictKind::Duplicate),
+            (3, ConflictKind::Shadowed),
+            (5, ConflictKind::Shadowed),
+        ]);
+        assert_eq!(
+            conflicts[2].to_string(),
+            "CacheControl rule #3 `/vendor/jquery.js` => `max-age=1` is shadowed by CacheControl rule #0 `*.js` => `max-age=60`"
+        );
+    }
+
+    #[test]
+    fn catch_all_shadows_later_rules() {
+        let rules = [
+            rule(RuleKind::Attachment, 0, "**", None),
+            rule(RuleKind::Attachment, 1, "/downloads/*.zip", None),
+        ];
+        assert_eq!(validate(&rules)[0].kind, ConflictKind::Shadowed);
+    }
+
+    #[test]
+    fn compiles_rules_of_a_kind() {
+        let rules = [
+            rule(RuleKind::Redirect, 0, "/old/**", Some("/new/")),
+            rule(RuleKind::ContentType, 0, "*.wasm", Some("application/wasm")),
+            rule(RuleKind::Redirect, 1, "blog.html", Some("https://blog.example.com/")),
+        ];
+        let redirects = PathRules::new(&rules, RuleKind::Redirect).unwrap();
+        assert_eq!(redirects.matching_rule("old/a/b.html"), Some(0));
+        assert_eq!(redirects.value("docs/blog.html").unwrap(), "https://blog.example.com/");
+        assert!(redirects.value("app.wasm").is_none());
+        assert_eq!(PathRules::new(&rules, RuleKind::ContentType).unwrap().value("app.wasm").unwrap(), "application/wasm");
+
+        let invalid = [rule(RuleKind::Redirect, 0, "/old/**", Some("new/"))];
+        assert_eq!(PathRules::new(&invalid, RuleKind::Redirect).unwrap_err(), "invalid redirect location");
+        let invalid = [rule(RuleKind::Public, 0, "[", None)];
+        assert_eq!(PathRules::new(&invalid, RuleKind::Public).unwrap_err(), "invalid public pattern");
+    }
+
+    #[test]
+    fn overlapping_wildcards_are_allowed() {
+        let rules = [
+            rule(RuleKind::CacheControl, 0, "/assets/**", Some("max-age=60")),
+            rule(RuleKind::CacheControl, 1, "*.js", Some("max-age=5")),
+        ];
+        assert!(validate(&rules).is_empty());
+    }
+}
@@ -0,0 +1,234 @@
+//! Caching segments of media objects.
+//!
+//! Ranged requests bypass the [`cache`](crate::S3OriginBuilder::cache), which only holds whole
+//! objects, and videos are usually too large for it anyway.  Players seeking through a video
+//! request the same few ranges over and over, though: the start, and the index at the end of
+//! the file.  With [`segment_cache`](crate::S3OriginBuilder::segment_cache) objects are split
+//! into fixed-size segments, and the first and last ones are kept in the cache:
+//!
+//! ```rust
+//! use axum_static_s3::segments::SegmentCache;
+//!
+//! // 2 MiB segments; the first three and the last one of each video are cached
+//! let segments = SegmentCache::new().segment_size(2 * 1024 * 1024).head(3).tail(1);
+//! ```
+//!
+//! A range starting in a cached segment is answered from it, and a range starting in a segment
+//! that should be cached fetches the whole segment.  Either way the response ends at the end of
+//! the segment, so clients asking for `bytes=0-` get the first segment and continue from its
+//! `Content-Range`, as for [`max_range_size`](crate::S3OriginBuilder::max_range_size).  Other
+//! ranges stream from S3 unchanged.  The last segments are only known once the size of the
+//! object is, i.e. after its first segment was cached.
+//!
+//! Segments count against the cache capacity, expire with its TTL and are evicted with their
+//! object by [`purge`](crate::admin).
+use axum::{
+    http::{header, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    cache::{CachedObject, MemoryCache},
+    metadata::ObjectMetadata,
+    range::ByteRange,
+    telemetry::{self, Feature},
+    S3OriginInner,
+};
+
+
+/// Segment cache configuration.
+#[derive(Clone, Debug)]
+pub struct SegmentCache {
+    segment_size: u64,
+    head: u64,
+    tail: u64,
+    extensions: Vec<String>,
+}
+
+impl SegmentCache {
+    /// 1 MiB segments, caching the first two and the last of audio and video files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The size of a segment in bytes.
+    pub fn segment_size(mut self, bytes: u64) -> Self {
+        self.segment_size = bytes;
+        self
+    }
+
+    /// Cache the first `segments` segments of an object.
+    pub fn head(mut self, segments: u64) -> Self {
+        self.head = segments;
+        self
+    }
+
+    /// Cache the last `segments` segments of an object.
+    pub fn tail(mut self, segments: u64) -> Self {
+        self.tail = segments;
+        self
+    }
+
+    /// Only segment keys with these file extensions; defaults to common audio and video formats.
+    pub fn extensions<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extensions = extensions.into_iter().map(|extension| extension.into().to_ascii_lowercase()).collect();
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), &'static str> {
+        match self.segment_size > 0 && self.head + self.tail > 0 {
+            true => Ok(()),
+            false => Err("segment_cache needs a positive segment size and segments to cache"),
+        }
+    }
+
+    /// Whether ranges of `key` are served from segments.
+    pub(crate) fn applies(&self, key: &str) -> bool {
+        let extension = key.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+        extension.is_some_and(|extension| self.extensions.contains(&extension))
+    }
+
+    /// Whether segment `index` of an object of `size` bytes is cached.
+    fn caches(&self, index: u64, size: Option<u64>) -> bool {
+        let last = size.map(|size| size.div_ceil(self.segment_size));
+        index < self.head || last.is_some_and(|last| index < last && index + self.tail >= last)
+    }
+
+    /// The response to a ranged GET of `key` from a cached segment, fetching the segment if it
+    /// should be cached; `None` to serve the range from S3 as usual.
+    pub(crate) async fn serve(
+        &self,
+        origin: &S3OriginInner,
+        cache: &MemoryCache,
+        req: &Request<()>,
+        cache_key: &str,
+        key: &str,
+        path: &str,
+    ) -> Option<Response> {
+        let range = ByteRange::parse(req.headers().get(header::RANGE)?.to_str().ok()?)?;
+        // Segments hold the size of the whole object as their length
+        let size = cache.peek(&segment_key(cache_key, 0))
+            .and_then(|object| u64::try_from(object.metadata.content_length?).ok());
+        let (start, end) = match range {
+            ByteRange::From(start, end) => (start, end),
+            ByteRange::Suffix(length) => (size?.checked_sub(length.max(1))?, None),
+        };
+        let index = start / self.segment_size;
+        if !self.caches(index, size) {
+            return None;
+        }
+
+        let segment_key = segment_key(cache_key, index);
+        let (object, hit) = match cache.get(&segment_key) {
+            Some(hit) => (hit.object, true),
+            None => {
+                // Errors are left to the regular GET, which reports them with request IDs
+                let object = self.fetch(origin, key, index).await?;
+                let size = object.metadata.content_length.and_then(|size| u64::try_from(size).ok());
+                let object = match self.caches(index, size) && cache.admits(Some(object.body.len() as i64)) {
+                    true => cache.insert(segment_key, object),
+                    false => std::sync::Arc::new(object),
+                };
+                (object, false)
+            }
+        };
+
+        let offset = index * self.segment_size;
+        let last = (offset + object.body.len() as u64).checked_sub(1)?;
+        if start > last {
+            return None;
+        }
+        let end = end.map_or(last, |end| end.min(last));
+        let size = object.metadata.content_length?;
+        let body = object.body.slice((start - offset) as usize..=(end - offset) as usize);
+
+        let mut metadata = object.metadata.clone();
+        metadata.content_length = Some(body.len() as i64);
+        let mut response = Response::new(axum::body::Body::from(body));
+        let applied = crate::check_metadata(&metadata, origin)
+            .and_then(|()| crate::apply_metadata(&mut response, &metadata, origin, key, path));
+        if let Err(e) = applied {
+            return Some(e.into_response());
+        }
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+        let content_range = HeaderValue::try_from(format!("bytes {}-{}/{}", start, end, size)).ok()?;
+        response.headers_mut().insert(header::CONTENT_RANGE, content_range);
+        if hit {
+            telemetry::record(&mut response, Feature::Cache);
+            origin.cost.cache_hit();
+        }
+        Some(response)
+    }
+
+    /// Segment `index` of `key`, with the size of the whole object as its length.
+    async fn fetch(&self, origin: &S3OriginInner, key: &str, index: u64) -> Option<CachedObject> {
+        let offset = index * self.segment_size;
+        let range = format!("bytes={}-{}", offset, offset + self.segment_size - 1);
+        let output = match &origin.backend {
+            Some(backend) => backend.get(key, Some(&range)).await.ok()?,
+            None => origin.s3_client.get_object().bucket(&origin.bucket).key(key).range(&range).send().await.ok()?,
+        };
+        let mut metadata = ObjectMetadata::from(&output);
+        if metadata.website_redirect_location.is_some() {
+            return None;
+        }
+        metadata.content_length = output.content_range()
+            .and_then(|range| range.rsplit_once('/')?.1.parse().ok());
+        metadata.content_length?;
+        let body = output.body.collect().await.ok()?.into_bytes();
+        // Segments share an ETag, but not their bodies
+        Some(CachedObject { metadata, etag: None, body })
+    }
+}
+
+impl Default for SegmentCache {
+    fn default() -> Self {
+        let extensions = ["mp4", "m4v", "m4a", "mov", "webm", "mkv", "ogv", "ogg", "oga", "mp3", "aac", "flac", "wav"];
+        Self {
+            segment_size: 1024 * 1024,
+            head: 2,
+            tail: 1,
+            extensions: extensions.map(str::to_owned).to_vec(),
+        }
+    }
+}
+
+
+/// The prefix of the segments' cache keys; whole objects with NUL in their key are not cached.
+pub(crate) fn segment_prefix(cache_key: &str) -> String {
+    format!("{}\0", cache_key)
+}
+
+
+fn segment_key(cache_key: &str, index: u64) -> String {
+    format!("{}{}", segment_prefix(cache_key), index)
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_segments() {
+        let segments = SegmentCache::new().segment_size(10).head(2).tail(1);
+        assert!(segments.caches(0, None));
+        assert!(segments.caches(1, None));
+        assert!(!segments.caches(2, None));
+        // 95 bytes are 10 segments; the last one is cached
+        assert!(!segments.caches(8, Some(95)));
+        assert!(segments.caches(9, Some(95)));
+        assert!(!segments.caches(10, Some(95)));
+
+        assert!(segments.applies("videos/intro.MP4"));
+        assert!(!segments.applies("index.html"));
+        assert!(!SegmentCache::new().extensions(["bin"]).applies("a.mp4"));
+        assert!(SegmentCache::new().head(0).tail(0).validate().is_err());
+        assert!(SegmentCache::new().segment_size(0).validate().is_err());
+    }
+}
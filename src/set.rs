@@ -0,0 +1,119 @@
+//! Several origins sharing one client and cache.
+//!
+//! An app often serves more than one part of a bucket, with different options per mount, e.g.
+//! immutable `/assets` and attachment `/downloads`.  Separately built origins each get their own
+//! S3 client and cache; an [`S3OriginSet`] builds them from one base origin instead, so they
+//! share its connection pool and cache capacity:
+//!
+//! ```rust
+//! # fn example(client: aws_sdk_s3::Client) -> Result<(), &'static str> {
+//! use axum::Router;
+//! use axum_static_s3::{S3OriginBuilder, S3OriginSet};
+//!
+//! let set = S3OriginSet::new(S3OriginBuilder::new()
+//!     .bucket("my-static-files-bucket")
+//!     .client(client)
+//!     .cache(32 * 1024 * 1024))?;
+//!
+//! let router: Router = Router::new()
+//!     .nest_service("/assets", set.origin(S3OriginBuilder::new().prefix("assets/").immutable_assets(true))?)
+//!     .nest_service("/downloads", set.origin(S3OriginBuilder::new().prefix("downloads/").attachment("*"))?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Cached objects are keyed by their full object key, so mounts with different prefixes never
+//! see each other's entries.
+use std::sync::Arc;
+
+use aws_sdk_s3::Client as S3Client;
+
+use crate::{cache::MemoryCache, S3Origin, S3OriginBuilder};
+
+
+/// The parts of the base origin that mounts share.
+#[derive(Clone)]
+pub(crate) struct Shared {
+    pub(crate) bucket: String,
+    pub(crate) client: Arc<S3Client>,
+    pub(crate) cache: Option<Arc<MemoryCache>>,
+}
+
+/// Prints the bucket; the S3 client (and its credentials) is not printed.
+impl std::fmt::Debug for Shared {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shared")
+            .field("bucket", &self.bucket)
+            .field("cache", &self.cache.as_ref().map(|cache| cache.stats()))
+            .finish()
+    }
+}
+
+
+/// Builds origins sharing the bucket, S3 client and cache of a base origin.
+#[derive(Clone, Debug)]
+pub struct S3OriginSet {
+    base: S3Origin,
+}
+
+impl S3OriginSet {
+    /// A set sharing the bucket, client and cache of the origin `base` builds.
+    ///
+    /// Client options ([`client`](S3OriginBuilder::client), [`config`](S3OriginBuilder::config),
+    /// [`assume_role`](S3OriginBuilder::assume_role), [`anonymous`](S3OriginBuilder::anonymous),
+    /// ...) and cache options ([`cache`](S3OriginBuilder::cache),
+    /// [`cache_ttl`](S3OriginBuilder::cache_ttl), ...) belong here.
+    pub fn new(base: S3OriginBuilder) -> Result<Self, &'static str> {
+        Ok(Self { base: base.build()? })
+    }
+
+    /// Build an origin with the set's bucket, client and cache, and its own prefix and policies.
+    ///
+    /// Bucket and client options of `builder` are ignored.  Cache options only apply if the base
+    /// origin has no cache.
+    pub fn origin(&self, builder: S3OriginBuilder) -> Result<S3Origin, &'static str> {
+        let inner = &self.base.inner;
+        let mut builder = builder;
+        builder.shared = Some(Shared {
+            bucket: inner.bucket.clone(),
+            client: inner.s3_client.clone(),
+            cache: inner.cache.clone(),
+        });
+        builder.build()
+    }
+
+    /// The base origin, e.g. to serve the whole bucket or to prefetch into the shared cache.
+    pub fn base(&self) -> &S3Origin {
+        &self.base
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::config::{BehaviorVersion, Region};
+
+    #[test]
+    fn shares_client_and_cache() {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .build();
+        let set = S3OriginSet::new(S3OriginBuilder::new()
+            .bucket("my-bucket")
+            .client(S3Client::from_conf(config))
+            .cache(1024 * 1024)).unwrap();
+
+        let assets = set.origin(S3OriginBuilder::new().prefix("assets/").immutable_assets(true)).unwrap();
+        let downloads = set.origin(S3OriginBuilder::new().bucket("ignored").prefix("downloads/")).unwrap();
+        for origin in [&assets, &downloads] {
+            assert!(Arc::ptr_eq(&origin.inner.s3_client, &set.base().inner.s3_client));
+            assert!(Arc::ptr_eq(origin.inner.cache.as_ref().unwrap(), set.base().inner.cache.as_ref().unwrap()));
+        }
+        let debug = format!("{:?}", downloads);
+        assert!(debug.contains("bucket: \"my-bucket\", bucket_prefix: \"downloads/\""));
+        assert!(format!("{:?}", assets).contains("immutable_assets: true"));
+    }
+}
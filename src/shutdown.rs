@@ -0,0 +1,203 @@
+//! Graceful shutdown.
+//!
+//! During a rollout (ECS, Kubernetes) the old task should finish the downloads it started, but
+//! take no new requests.  [`S3Origin::shutdown`] starts draining: new calls are answered with
+//! `503 Service Unavailable` and `Connection: close`, or kept waiting in `poll_ready`, see
+//! [`DrainMode`].  Requests already being served, including the bodies still streaming, run to
+//! completion; [`S3Origin::wait_idle`] resolves once they have:
+//!
+//! ```rust
+//! # async fn example(origin: axum_static_s3::S3Origin) {
+//! // On SIGTERM, after the load balancer stopped sending traffic
+//! origin.shutdown();
+//! tokio::time::timeout(std::time::Duration::from_secs(30), origin.wait_idle()).await.ok();
+//! # }
+//! ```
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use pin_project::pin_project;
+use tokio::sync::Notify;
+
+use crate::S3Origin;
+
+
+/// How new calls are handled while draining.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DrainMode {
+    /// Answer with `503 Service Unavailable`, `Retry-After` and `Connection: close` (default).
+    #[default]
+    Reject,
+    /// Never become ready again in `poll_ready`, for hosts that stop routing to services that
+    /// are not ready.
+    ///
+    /// `Router` does not check readiness before routing; there, requests wait until the server
+    /// shuts down.
+    Pending,
+}
+
+
+/// Tracks draining and the requests and bodies being served.
+#[derive(Debug, Default)]
+pub(crate) struct Drain {
+    mode: DrainMode,
+    draining: AtomicBool,
+    active: AtomicUsize,
+    idle: Notify,
+}
+
+impl Drain {
+    pub(crate) fn new(mode: DrainMode) -> Self {
+        Self { mode, ..Self::default() }
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+
+    /// Whether `poll_ready` should keep new calls waiting.
+    pub(crate) fn holds_calls(&self) -> bool {
+        self.mode == DrainMode::Pending && self.is_draining()
+    }
+
+    /// The answer to a new call while draining.
+    pub(crate) fn rejection(&self, retry_after: u64) -> Option<Response> {
+        if !self.is_draining() {
+            return None;
+        }
+        let mut response = (StatusCode::SERVICE_UNAVAILABLE, "Service unavailable").into_response();
+        let headers = response.headers_mut();
+        headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        headers.insert(header::CONNECTION, HeaderValue::from_static("close"));
+        Some(response)
+    }
+}
+
+
+/// Counts a request as active until dropped; moved into the response body.
+pub(crate) struct Active(Arc<Drain>);
+
+impl Active {
+    pub(crate) fn new(drain: Arc<Drain>) -> Self {
+        drain.active.fetch_add(1, Ordering::AcqRel);
+        Self(drain)
+    }
+
+    /// Keep the request active until `response` has been sent.
+    pub(crate) fn attach(self, response: Response) -> Response {
+        response.map(|body| Body::new(TrackedBody { body, _active: self }))
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        if self.0.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+
+#[pin_project]
+struct TrackedBody {
+    #[pin]
+    body: Body,
+    _active: Active,
+}
+
+impl HttpBody for TrackedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<http_body::Frame<Bytes>, axum::Error>>> {
+        self.project().body.poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+
+impl S3Origin {
+    /// Stop taking new requests, see [`shutdown`](crate::shutdown).
+    ///
+    /// Applies to all clones of the origin.  There is no way back; build a new origin instead.
+    ///
+    pub fn shutdown(&self) {
+        #[cfg(feature = "trace")]
+        tracing::info!("S3Origin: draining");
+        self.inner.drain.draining.store(true, Ordering::Release);
+    }
+
+    /// Whether [`shutdown`](Self::shutdown) was called.
+    ///
+    pub fn is_draining(&self) -> bool {
+        self.inner.drain.is_draining()
+    }
+
+    /// The number of requests being served, including response bodies still streaming.
+    ///
+    pub fn active(&self) -> usize {
+        self.inner.drain.active.load(Ordering::Acquire)
+    }
+
+    /// Resolves once no request is being served and no response body is streaming.
+    ///
+    /// Resolves immediately when idle, whether or not the origin is draining.
+    ///
+    pub async fn wait_idle(&self) {
+        let drain = &self.inner.drain;
+        loop {
+            let idle = drain.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+            if drain.active.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_bodies() {
+        let drain = Arc::new(Drain::new(DrainMode::Reject));
+        assert!(drain.rejection(1).is_none());
+
+        let response = Active::new(drain.clone()).attach(Response::new(Body::from("hello")));
+        assert_eq!(response.body().size_hint().exact(), Some(5));
+        assert_eq!(drain.active.load(Ordering::Acquire), 1);
+
+        drain.draining.store(true, Ordering::Release);
+        let rejection = drain.rejection(7).unwrap();
+        assert_eq!(rejection.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(rejection.headers()[header::CONNECTION], "close");
+        assert!(!drain.holds_calls());
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "hello");
+        assert_eq!(drain.active.load(Ordering::Acquire), 0);
+    }
+}
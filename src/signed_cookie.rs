@@ -0,0 +1,194 @@
+//! Validating signed cookies.
+//!
+//! Signing every URL is impractical for a page that loads many protected assets.  With
+//! [`signed_cookies`](crate::S3OriginBuilder::signed_cookies), like CloudFront signed cookies,
+//! the application grants a browser session access to every path under a prefix until an
+//! expiry time by setting three cookies:
+//!
+//! - `S3Origin-Policy`: the expiry and path prefix, base64url-encoded;
+//! - `S3Origin-Key-Id`: the id of the signing key, if there is one;
+//! - `S3Origin-Signature`: an HMAC-SHA256 of the policy and key id, base64url-encoded.
+//!
+//! Prefixes are matched against the path as the origin sees it (see
+//! [`path_source`](crate::S3OriginBuilder::path_source)), percent-encoded.  Requests without
+//! a valid, unexpired policy for their path are answered with `403 Forbidden`, unless they
+//! carry a valid [signed URL](crate::signed_url).
+//!
+//! ```rust
+//! use std::time::{Duration, SystemTime};
+//! use axum::http::{header, HeaderMap};
+//! use axum_static_s3::signed_cookie::SignedCookies;
+//!
+//! let signed_cookies = SignedCookies::new("secret");
+//! let mut headers = HeaderMap::new();
+//! for cookie in signed_cookies.sign("/docs/", SystemTime::now() + Duration::from_secs(3600)) {
+//!     headers.append(header::SET_COOKIE, cookie.parse().unwrap());
+//! }
+//! ```
+use std::{
+    fmt,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::http::{header, HeaderMap};
+use base64::Engine as _;
+use hmac::Mac as _;
+
+use crate::redact::Opaque;
+use crate::signed_url::{finalize_mac, hmac, SigningKeys, StaticKeys};
+
+
+const POLICY: &str = "S3Origin-Policy";
+const KEY_ID: &str = "S3Origin-Key-Id";
+const SIGNATURE: &str = "S3Origin-Signature";
+
+
+/// Signed cookie configuration.
+#[derive(Clone)]
+pub struct SignedCookies {
+    keys: Arc<dyn SigningKeys>,
+}
+
+impl SignedCookies {
+    /// Sign and check cookies with a single secret.
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self::with_keys(StaticKeys::new("", secret))
+    }
+
+    /// Sign and check cookies with rotating keys.
+    pub fn with_keys(keys: impl SigningKeys) -> Self {
+        Self { keys: Arc::new(keys) }
+    }
+
+    /// The `Set-Cookie` values granting access to paths starting with `prefix` until `expires`.
+    ///
+    /// The cookies are `HttpOnly`, `Secure` and `SameSite=Lax`, with `Path=/`.
+    pub fn sign(&self, prefix: &str, expires: SystemTime) -> Vec<String> {
+        let expires = expires.duration_since(UNIX_EPOCH).map_or(0, |expires| expires.as_secs());
+        let max_age = expires.saturating_sub(now_secs(SystemTime::now()));
+        let prefix = prefix.strip_prefix('/').unwrap_or(prefix);
+        let policy = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(format!("{expires}\n{prefix}"));
+        let (id, secret) = self.keys.current();
+        let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(finalize_mac(hmac(&secret, format!("{policy}\n{id}").as_bytes())));
+
+        let cookie = |name: &str, value: &str| {
+            format!("{name}={value}; Path=/; Max-Age={max_age}; HttpOnly; Secure; SameSite=Lax")
+        };
+        let mut cookies = vec![cookie(POLICY, &policy), cookie(SIGNATURE, &signature)];
+        if !id.is_empty() {
+            cookies.push(cookie(KEY_ID, &id));
+        }
+        cookies
+    }
+
+    /// Whether the request's cookies hold a valid, unexpired policy covering `path`.
+    pub(crate) fn verify(&self, path: &str, headers: &HeaderMap, now: SystemTime) -> bool {
+        let (mut policy, mut id, mut signature) = (None, "", None);
+        let cookies = headers.get_all(header::COOKIE).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='));
+        for (name, value) in cookies {
+            match name {
+                POLICY => policy = Some(value),
+                KEY_ID => id = value,
+                SIGNATURE => signature = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(value).ok(),
+                _ => {}
+            }
+        }
+        let (Some(policy), Some(signature)) = (policy, signature) else {
+            return false;
+        };
+        let Some(secret) = self.keys.get(id) else {
+            return false;
+        };
+        let mac = hmac(&secret, format!("{policy}\n{id}").as_bytes());
+        if mac.is_none_or(|mac| mac.verify_slice(&signature).is_err()) {
+            return false;
+        }
+
+        let Some(decoded) = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(policy).ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+        else {
+            return false;
+        };
+        let Some((expires, prefix)) = decoded.split_once('\n') else {
+            return false;
+        };
+        expires.parse::<u64>().is_ok_and(|expires| now_secs(now) <= expires)
+            && path.strip_prefix('/').unwrap_or(path).starts_with(prefix)
+    }
+}
+
+impl fmt::Debug for SignedCookies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignedCookies").field("keys", &Opaque("keys")).finish()
+    }
+}
+
+
+fn now_secs(now: SystemTime) -> u64 {
+    now.duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs())
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use axum::http::HeaderValue;
+
+    /// The `Cookie` header a browser would send for `Set-Cookie` values.
+    fn cookie_header(cookies: &[String]) -> HeaderMap {
+        let value = cookies.iter()
+            .map(|cookie| cookie.split(';').next().unwrap())
+            .collect::<Vec<_>>()
+            .join("; ");
+        HeaderMap::from_iter([(header::COOKIE, HeaderValue::try_from(format!("theme=dark; {value}")).unwrap())])
+    }
+
+    #[test]
+    fn verifies_policies() {
+        let now = SystemTime::now();
+        let signed_cookies = SignedCookies::new("secret");
+        let cookies = signed_cookies.sign("/docs/", now + Duration::from_secs(60));
+        assert_eq!(cookies.len(), 2);
+        assert!(cookies[0].ends_with("; Path=/; Max-Age=60; HttpOnly; Secure; SameSite=Lax")
+            || cookies[0].ends_with("; Path=/; Max-Age=59; HttpOnly; Secure; SameSite=Lax"));
+        let headers = cookie_header(&cookies);
+
+        assert!(signed_cookies.verify("docs/guide/index.html", &headers, now));
+        assert!(!signed_cookies.verify("admin/index.html", &headers, now));
+        assert!(!signed_cookies.verify("docs/index.html", &headers, now + Duration::from_secs(120)));
+        assert!(!signed_cookies.verify("docs/index.html", &HeaderMap::new(), now));
+        assert!(!SignedCookies::new("other secret").verify("docs/index.html", &headers, now));
+
+        // A policy for another prefix does not match the signature
+        let forged = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(format!("{}\n", now_secs(now) + 60));
+        let value = headers[header::COOKIE].to_str().unwrap()
+            .split("; ")
+            .map(|pair| match pair.starts_with(POLICY) {
+                true => format!("{POLICY}={forged}"),
+                false => pair.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        let headers = HeaderMap::from_iter([(header::COOKIE, HeaderValue::try_from(value).unwrap())]);
+        assert!(!signed_cookies.verify("admin/index.html", &headers, now));
+    }
+
+    #[test]
+    fn rotates_keys() {
+        let now = SystemTime::now();
+        let old = SignedCookies::with_keys(StaticKeys::new("1", "old secret"));
+        let new = SignedCookies::with_keys(StaticKeys::new("2", "new secret").accept("1", "old secret"));
+
+        let headers = cookie_header(&old.sign("", now + Duration::from_secs(60)));
+        assert!(new.verify("index.html", &headers, now));
+        let headers = cookie_header(&new.sign("", now + Duration::from_secs(60)));
+        assert!(!old.verify("index.html", &headers, now));
+    }
+}
@@ -0,0 +1,236 @@
+//! Validating signed URLs.
+//!
+//! With [`signed_urls`](crate::S3OriginBuilder::signed_urls), every request must carry a
+//! signature over its path and an expiry time, similar to CloudFront signed URLs but checked by
+//! the origin itself:
+//!
+//! ```text
+//! /reports/2024.pdf?expires=1735689600&key=2024-06&signature=3q2-7wbnP...
+//! ```
+//!
+//! The signature is an HMAC-SHA256 of the expiry, key id and path (as the origin sees it, see
+//! [`path_source`](crate::S3OriginBuilder::path_source)), base64url-encoded without padding.
+//! Other query parameters are not signed.  Requests with a missing, invalid or expired signature
+//! are answered with `403 Forbidden`, unless they carry valid
+//! [signed cookies](crate::signed_cookie).
+//!
+//! Application code creates the URLs with [`SignedUrls::sign`], using the same configuration:
+//!
+//! ```rust
+//! use std::time::{Duration, SystemTime};
+//! use axum_static_s3::signed_url::{SignedUrls, StaticKeys};
+//!
+//! let signed_urls = SignedUrls::with_keys(
+//!     StaticKeys::new("2024-06", "new secret").accept("2024-01", "previous secret"),
+//! );
+//! let url = signed_urls.sign("/reports/2024.pdf", SystemTime::now() + Duration::from_secs(300));
+//! ```
+use std::{
+    fmt,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::Engine as _;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::redact::Opaque;
+
+
+const EXPIRES: &str = "expires";
+const KEY: &str = "key";
+const SIGNATURE: &str = "signature";
+
+
+/// The secrets signatures are made and checked with.
+///
+/// Implement this to fetch keys from a secret manager and rotate them without rebuilding the
+/// origin; [`StaticKeys`] holds a fixed set.  Key ids appear in URLs and should be URL-safe.
+pub trait SigningKeys: Send + Sync + 'static {
+    /// The id and secret new URLs are signed with.
+    fn current(&self) -> (String, Vec<u8>);
+
+    /// The secret with this id, if URLs signed with it are still accepted.
+    fn get(&self, id: &str) -> Option<Vec<u8>>;
+}
+
+
+/// A fixed set of signing keys: one to sign with, and any number still accepted.
+#[derive(Clone)]
+pub struct StaticKeys {
+    current: (String, Vec<u8>),
+    accepted: Vec<(String, Vec<u8>)>,
+}
+
+impl StaticKeys {
+    /// Sign and accept URLs with this key.
+    pub fn new(id: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        Self { current: (id.into(), secret.into()), accepted: Vec::new() }
+    }
+
+    /// Also accept URLs signed with this key, e.g. the previous key during a rotation.
+    pub fn accept(mut self, id: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        self.accepted.push((id.into(), secret.into()));
+        self
+    }
+}
+
+impl SigningKeys for StaticKeys {
+    fn current(&self) -> (String, Vec<u8>) {
+        self.current.clone()
+    }
+
+    fn get(&self, id: &str) -> Option<Vec<u8>> {
+        std::iter::once(&self.current)
+            .chain(&self.accepted)
+            .find(|(key_id, _)| key_id == id)
+            .map(|(_, secret)| secret.clone())
+    }
+}
+
+impl fmt::Debug for StaticKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticKeys")
+            .field("current", &self.current.0)
+            .field("accepted", &self.accepted.iter().map(|(id, _)| id).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+
+/// Signed URL configuration.
+#[derive(Clone)]
+pub struct SignedUrls {
+    keys: Arc<dyn SigningKeys>,
+}
+
+impl SignedUrls {
+    /// Sign and check URLs with a single secret; the URLs have no `key` parameter.
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self::with_keys(StaticKeys::new("", secret))
+    }
+
+    /// Sign and check URLs with rotating keys.
+    pub fn with_keys(keys: impl SigningKeys) -> Self {
+        Self { keys: Arc::new(keys) }
+    }
+
+    /// Sign `path` (without a query, percent-encoded as in the URL) until `expires`.
+    ///
+    /// Returns the path with the signature parameters appended.
+    pub fn sign(&self, path: &str, expires: SystemTime) -> String {
+        let expires = expires.duration_since(UNIX_EPOCH).map_or(0, |expires| expires.as_secs());
+        let (id, secret) = self.keys.current();
+        let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(finalize_mac(mac(&secret, expires, &id, path)));
+        match id.is_empty() {
+            true => format!("{path}?{EXPIRES}={expires}&{SIGNATURE}={signature}"),
+            false => format!("{path}?{EXPIRES}={expires}&{KEY}={id}&{SIGNATURE}={signature}"),
+        }
+    }
+
+    /// Whether `query` holds a valid, unexpired signature for `path`.
+    pub(crate) fn verify(&self, path: &str, query: Option<&str>, now: SystemTime) -> bool {
+        let (mut expires, mut id, mut signature) = (None, "", None);
+        for (name, value) in query.unwrap_or_default().split('&').filter_map(|pair| pair.split_once('=')) {
+            match name {
+                EXPIRES => expires = value.parse::<u64>().ok(),
+                KEY => id = value,
+                SIGNATURE => signature = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(value).ok(),
+                _ => {}
+            }
+        }
+        let (Some(expires), Some(signature)) = (expires, signature) else {
+            return false;
+        };
+        if now.duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs()) > expires {
+            return false;
+        }
+        let Some(secret) = self.keys.get(id) else {
+            return false;
+        };
+        mac(&secret, expires, id, path).is_some_and(|mac| mac.verify_slice(&signature).is_ok())
+    }
+}
+
+impl fmt::Debug for SignedUrls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignedUrls").field("keys", &Opaque("keys")).finish()
+    }
+}
+
+
+/// The MAC over a URL's signed parts, before finalizing.
+fn mac(secret: &[u8], expires: u64, id: &str, path: &str) -> Option<Hmac<Sha256>> {
+    // The origin sees paths without their leading `/`
+    let path = path.strip_prefix('/').unwrap_or(path);
+    hmac(secret, format!("{expires}\n{id}\n{path}").as_bytes())
+}
+
+/// The HMAC-SHA256 of `message`, before finalizing; HMAC accepts secrets of any length.
+pub(crate) fn hmac(secret: &[u8], message: &[u8]) -> Option<Hmac<Sha256>> {
+    Some(Hmac::<Sha256>::new_from_slice(secret).ok()?.chain_update(message))
+}
+
+/// The bytes of a MAC; empty without one, so no signature matches them.
+pub(crate) fn finalize_mac(mac: Option<Hmac<Sha256>>) -> Vec<u8> {
+    mac.map(|mac| mac.finalize().into_bytes().to_vec()).unwrap_or_default()
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn split(url: &str) -> (&str, Option<&str>) {
+        match url.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (url, None),
+        }
+    }
+
+    #[test]
+    fn keys_any_length() {
+        for secret in [&b""[..], b"secret", &[7; 64], &[7; 100]] {
+            let expected = Hmac::<Sha256>::new_from_slice(secret).unwrap().chain_update(b"message").finalize();
+            assert_eq!(hmac(secret, b"message").unwrap().finalize().into_bytes(), expected.into_bytes());
+        }
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let now = SystemTime::now();
+        let signed_urls = SignedUrls::new("secret");
+        let url = signed_urls.sign("/a%20b.pdf", now + Duration::from_secs(60));
+        assert!(!url.contains("key="));
+        let (path, query) = split(&url);
+        assert!(signed_urls.verify(path, query, now));
+        assert!(!signed_urls.verify(path, query, now + Duration::from_secs(120)));
+        assert!(!signed_urls.verify("/other.pdf", query, now));
+        assert!(!signed_urls.verify(path, None, now));
+        assert!(!SignedUrls::new("other secret").verify(path, query, now));
+
+        let tampered = query.unwrap().replacen("expires=", "expires=9", 1);
+        assert!(!signed_urls.verify(path, Some(&tampered), now));
+    }
+
+    #[test]
+    fn rotates_keys() {
+        let now = SystemTime::now();
+        let old = SignedUrls::with_keys(StaticKeys::new("1", "old secret"));
+        let new = SignedUrls::with_keys(StaticKeys::new("2", "new secret").accept("1", "old secret"));
+
+        let url = old.sign("/index.html", now + Duration::from_secs(60));
+        let (path, query) = split(&url);
+        assert!(new.verify(path, query, now));
+
+        let url = new.sign("/index.html", now + Duration::from_secs(60));
+        assert!(url.contains("&key=2&"));
+        let (path, query) = split(&url);
+        assert!(!old.verify(path, query, now));
+        assert!(new.verify(path, query, now));
+    }
+}
@@ -0,0 +1,373 @@
+//! `sitemap.xml` generated from the bucket listing.
+//!
+//! [`S3Origin::sitemap_router`] serves a sitemap of the pages under a prefix, listed with
+//! ListObjectsV2 when it is requested, so a static site does not need to generate one at build
+//! time.  Configure it with [`sitemap`](crate::S3OriginBuilder::sitemap):
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use axum_static_s3::sitemap::Sitemap;
+//!
+//! let sitemap = Sitemap::new("https://www.example.com")
+//!     .include("*.html")
+//!     .exclude("drafts/**")
+//!     .cache_for(Duration::from_secs(600));
+//! ```
+//!
+//! Every matching key becomes a `<loc>` below the base URL, with its last modification time as
+//! `<lastmod>`.  `index.html` files are listed as their directory (`docs/index.html` as
+//! `/docs/`), and with [`clean_urls`](crate::S3OriginBuilder::clean_urls) other pages without
+//! their `.html` extension.  The sitemap is reused until it is older than `cache_for`, and holds
+//! at most 50,000 URLs, the limit of the sitemap protocol.
+//!
+//! With [`required_tags`](crate::S3OriginBuilder::required_tags), only pages the origin would
+//! serve are listed: the tags of every included page are checked, costing a GetObjectTagging
+//! request per page unless they are cached.
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use aws_sdk_s3::primitives::DateTimeFormat;
+use axum::{
+    extract::State,
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use globset::GlobMatcher;
+use percent_encoding::{AsciiSet, CONTROLS};
+
+use crate::{cost, pattern, render::escape_html, S3Error, S3Origin, S3OriginInner};
+
+
+/// URLs per sitemap, as allowed by the sitemap protocol.
+const MAX_URLS: usize = 50_000;
+
+/// GetObjectTagging requests in flight at once.
+const TAG_CHECK_CONCURRENCY: usize = 16;
+
+/// Characters percent-encoded in sitemap URL paths.
+const PATH: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>').add(b'?').add(b'`').add(b'{').add(b'}');
+
+
+/// Sitemap configuration.
+#[derive(Clone, Debug)]
+pub struct Sitemap {
+    base_url: String,
+    prefix: String,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    cache_for: Duration,
+}
+
+impl Sitemap {
+    /// List pages as URLs below `base_url`, e.g. `https://www.example.com`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            prefix: String::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            cache_for: Duration::from_secs(3600),
+        }
+    }
+
+    /// Only list keys under `prefix`, relative to the bucket prefix; defaults to every key.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// List paths matching this glob; defaults to `*.html` unless a pattern is added.
+    ///
+    /// Patterns match paths relative to the bucket prefix, see
+    /// [`cache_control`](crate::S3OriginBuilder::cache_control).
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    /// Leave out paths matching this glob.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Reuse a generated sitemap for this long; defaults to 1 hour.
+    pub fn cache_for(mut self, cache_for: Duration) -> Self {
+        self.cache_for = cache_for;
+        self
+    }
+}
+
+
+/// A sitemap configuration with its last generated sitemap.
+#[derive(Debug)]
+pub(crate) struct SitemapState {
+    config: Sitemap,
+    include: Vec<GlobMatcher>,
+    exclude: Vec<GlobMatcher>,
+    /// Held while listing, so concurrent requests share one listing.
+    last: tokio::sync::Mutex<Option<(Instant, String)>>,
+}
+
+impl SitemapState {
+    pub(crate) fn new(config: Sitemap) -> Result<Self, &'static str> {
+        if !config.base_url.starts_with("https://") && !config.base_url.starts_with("http://") {
+            return Err("sitemap base URL must start with http:// or https://");
+        }
+        let compile = |patterns: &[String]| {
+            patterns.iter()
+                .map(|pattern| pattern::compile_glob(pattern))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| "invalid sitemap glob")
+        };
+        let include = match config.include.is_empty() {
+            true => compile(&["*.html".to_owned()])?,
+            false => compile(&config.include)?,
+        };
+        let exclude = compile(&config.exclude)?;
+        Ok(Self { config, include, exclude, last: tokio::sync::Mutex::new(None) })
+    }
+
+    /// The sitemap, generated again once it is older than `cache_for`.
+    async fn sitemap(&self, origin: &Arc<S3OriginInner>) -> Result<String, S3Error> {
+        let mut last = self.last.lock().await;
+        if let Some((generated, sitemap)) = &*last {
+            if generated.elapsed() < self.config.cache_for {
+                return Ok(sitemap.clone());
+            }
+        }
+        let bucket_prefix = origin.bucket_prefix();
+        let prefix = format!("{}{}", bucket_prefix, self.config.prefix.trim_start_matches('/'));
+        let pages = list(origin, &prefix).await?.into_iter()
+            .filter(|(key, _)| key.strip_prefix(&*bucket_prefix).is_some_and(|path| self.includes(path)))
+            .collect();
+        let pages = visible(origin, pages).await?;
+        let entries = pages.into_iter().filter_map(|(key, modified)| {
+            Some((self.url(key.strip_prefix(&*bucket_prefix)?, origin.clean_urls), modified))
+        });
+        let sitemap = render(entries);
+        *last = Some((Instant::now(), sitemap.clone()));
+        Ok(sitemap)
+    }
+
+    /// Whether the page at `path` is listed.
+    fn includes(&self, path: &str) -> bool {
+        self.include.iter().any(|glob| glob.is_match(path)) && !self.exclude.iter().any(|glob| glob.is_match(path))
+    }
+
+    /// The URL of the page at `path`.
+    fn url(&self, path: &str, clean_urls: bool) -> String {
+        let path = match path.strip_suffix("index.html") {
+            Some(directory) if directory.is_empty() || directory.ends_with('/') => directory,
+            _ if clean_urls => path.strip_suffix(".html").unwrap_or(path),
+            _ => path,
+        };
+        let path = percent_encoding::utf8_percent_encode(path, PATH);
+        format!("{}/{}", self.config.base_url.trim_end_matches('/'), path)
+    }
+}
+
+
+/// The keys under `prefix`, with their last modification time if known.
+async fn list(origin: &S3OriginInner, prefix: &str) -> Result<Vec<(String, Option<String>)>, S3Error> {
+    if let Some(backend) = &origin.backend {
+        return Ok(backend.list(prefix).await?.into_iter().map(|key| (key, None)).collect());
+    }
+    let mut objects = Vec::new();
+    let mut pages = origin.s3_client.list_objects_v2()
+        .bucket(&origin.bucket)
+        .prefix(prefix)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        for object in page?.contents() {
+            let Some(key) = object.key() else { continue };
+            let modified = object.last_modified().and_then(|modified| modified.fmt(DateTimeFormat::DateTime).ok());
+            objects.push((key.to_owned(), modified));
+        }
+    }
+    Ok(objects)
+}
+
+
+/// The `pages` carrying the required tags, in order; all of them without required tags.
+async fn visible(origin: &Arc<S3OriginInner>, pages: Vec<(String, Option<String>)>) -> Result<Vec<(String, Option<String>)>, S3Error> {
+    if origin.required_tags.is_none() {
+        return Ok(pages);
+    }
+    let mut allowed = vec![false; pages.len()];
+    let mut record = |joined: Result<(usize, Result<bool, S3Error>), tokio::task::JoinError>| {
+        let (index, allows) = joined.map_err(|_| S3Error::InternalServerError)?;
+        allowed[index] = allows?;
+        Ok::<_, S3Error>(())
+    };
+    let mut checks = tokio::task::JoinSet::new();
+    for (index, (key, _)) in pages.iter().enumerate() {
+        if checks.len() >= TAG_CHECK_CONCURRENCY {
+            if let Some(joined) = checks.join_next().await {
+                record(joined)?;
+            }
+        }
+        let (origin, key) = (origin.clone(), key.clone());
+        checks.spawn(cost::scope(Some(origin.cost.clone()), async move {
+            let allows = match &origin.required_tags {
+                Some(required_tags) => required_tags.allows(&origin.s3_client, &origin.bucket, &key).await,
+                None => Ok(true),
+            };
+            (index, allows)
+        }));
+    }
+    while let Some(joined) = checks.join_next().await {
+        record(joined)?;
+    }
+    Ok(pages.into_iter().zip(allowed).filter_map(|(page, allowed)| allowed.then_some(page)).collect())
+}
+
+
+fn render(entries: impl Iterator<Item = (String, Option<String>)>) -> String {
+    let mut sitemap = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for (count, (url, modified)) in entries.enumerate() {
+        if count == MAX_URLS {
+            #[cfg(feature = "trace")]
+            tracing::warn!("S3Origin: sitemap truncated to {} URLs", MAX_URLS);
+            break;
+        }
+        sitemap.push_str("<url><loc>");
+        sitemap.push_str(&escape_html(&url));
+        sitemap.push_str("</loc>");
+        if let Some(modified) = modified {
+            sitemap.push_str("<lastmod>");
+            sitemap.push_str(&modified);
+            sitemap.push_str("</lastmod>");
+        }
+        sitemap.push_str("</url>\n");
+    }
+    sitemap.push_str("</urlset>\n");
+    sitemap
+}
+
+
+impl S3Origin {
+    /// A router serving the [`sitemap`](crate::sitemap) at `path`, e.g. `/sitemap.xml`.
+    ///
+    /// Merge it into the application, e.g. `Router::new().merge(origin.sitemap_router("/sitemap.xml"))`.
+    /// Without a [`sitemap`](crate::S3OriginBuilder::sitemap) configuration it answers `404`.
+    ///
+    pub fn sitemap_router(&self, path: &str) -> Router {
+        Router::new()
+            .route(path, get(sitemap))
+            .with_state(self.clone())
+    }
+}
+
+
+async fn sitemap(State(origin): State<S3Origin>) -> Response {
+    let Some(state) = &origin.inner.sitemap else {
+        return S3Error::NotFound.into_response();
+    };
+    match state.sitemap(&origin.inner).await {
+        Ok(sitemap) => ([(header::CONTENT_TYPE, HeaderValue::from_static("application/xml"))], sitemap).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use tower_service::Service;
+
+    use crate::{object_tags::RequiredTags, testing::{MockObject, MockS3}};
+
+    fn site() -> MockS3 {
+        let page = || MockObject::new("<h1>Page</h1>").content_type("text/html");
+        MockS3::new("my-bucket")
+            .object("site/about.html", page().tag("public", "true"))
+            .object("site/app.js", MockObject::new("app()").tag("public", "true"))
+            .object("site/docs/index.html", page())
+            .object("site/drafts/new.html", page().tag("public", "true"))
+            .object("site/index.html", page().tag("public", "true"))
+            .object("site/q&a page.html", page())
+    }
+
+    async fn urls(router: &mut Router) -> Vec<String> {
+        let response = router.call(axum::http::Request::get("/sitemap.xml").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/xml");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("<url><loc>")?.split_once("</loc>").map(|(url, _)| url.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn builds_urls() {
+        let state = SitemapState::new(Sitemap::new("https://example.com/")).unwrap();
+        assert_eq!(state.url("index.html", false), "https://example.com/");
+        assert_eq!(state.url("docs/index.html", true), "https://example.com/docs/");
+        assert_eq!(state.url("docs/about.html", true), "https://example.com/docs/about");
+        assert_eq!(state.url("docs/about.html", false), "https://example.com/docs/about.html");
+        assert_eq!(state.url("myindex.html", false), "https://example.com/myindex.html");
+        assert_eq!(state.url("a b#c.html", false), "https://example.com/a%20b%23c.html");
+
+        assert!(SitemapState::new(Sitemap::new("example.com")).is_err());
+        assert!(SitemapState::new(Sitemap::new("https://example.com").exclude("[")).is_err());
+
+        let sitemap = render([("https://example.com/".to_owned(), Some("2024-01-02T03:04:05Z".to_owned()))].into_iter());
+        assert!(sitemap.contains("<url><loc>https://example.com/</loc><lastmod>2024-01-02T03:04:05Z</lastmod></url>\n"));
+    }
+
+    #[tokio::test]
+    async fn serves_cached_sitemaps() {
+        let s3 = site();
+        let origin = s3.origin()
+            .prefix("site/")
+            .sitemap(Sitemap::new("https://example.com").exclude("drafts/**"))
+            .build()
+            .unwrap();
+        let mut router = origin.sitemap_router("/sitemap.xml");
+        for _ in 0..2 {
+            assert_eq!(urls(&mut router).await, [
+                "https://example.com/about.html",
+                "https://example.com/docs/",
+                "https://example.com/",
+                "https://example.com/q&amp;a%20page.html",
+            ]);
+        }
+        assert_eq!(s3.requests().len(), 1);
+        assert_eq!(s3.requests()[0].query.as_deref(), Some("list-type=2&prefix=site%2F"));
+
+        let origin = s3.origin().build().unwrap();
+        let response = origin.sitemap_router("/sitemap.xml")
+            .call(axum::http::Request::get("/sitemap.xml").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn lists_only_pages_with_required_tags() {
+        let s3 = site();
+        let origin = s3.origin()
+            .prefix("site/")
+            .required_tags(RequiredTags::new([("public", "true")]))
+            .sitemap(Sitemap::new("https://example.com").exclude("drafts/**"))
+            .build()
+            .unwrap();
+        let urls = urls(&mut origin.sitemap_router("/sitemap.xml")).await;
+        assert_eq!(urls, ["https://example.com/about.html", "https://example.com/"]);
+        // Only included pages are checked
+        let mut checked = s3.requests().into_iter()
+            .filter(|request| request.query.as_deref() == Some("tagging"))
+            .map(|request| request.key)
+            .collect::<Vec<_>>();
+        checked.sort();
+        assert_eq!(checked, ["site/about.html", "site/docs/index.html", "site/index.html", "site/q&a page.html"]);
+    }
+}
@@ -0,0 +1,308 @@
+//! Request spans, with the `trace` feature.
+//!
+//! Every request is served inside a span, by default named `s3_origin` with the target
+//! `axum_static_s3`, recording
+//!
+//! - `bucket`: the bucket name;
+//! - `key` and `s3_url`: the object key and its `s3://bucket/key` URL, once resolved;
+//! - `status`: the response status;
+//! - `latency_ms`: the time until the response head was ready.
+//!
+//! The S3 requests are child spans of it.  [`S3OriginBuilder::span`](crate::S3OriginBuilder::span)
+//! sets the name and target, e.g. to tell the origins of an application apart in filters, and
+//! which of the fields are recorded:
+//!
+//! ```rust
+//! use axum_static_s3::span::{SpanConfig, SpanField};
+//!
+//! let span = SpanConfig::new("static_assets")
+//!     .target("my_app::assets")
+//!     .fields([SpanField::Key, SpanField::Status]);
+//! ```
+//!
+//! Span names and targets are static in `tracing`, so the configured strings are leaked once for
+//! every origin built with a [`SpanConfig`].
+use std::{sync::OnceLock, time::Duration};
+
+use axum::http::StatusCode;
+use tracing::{
+    callsite::{Callsite, Identifier},
+    field::FieldSet,
+    metadata::Kind,
+    subscriber::Interest,
+    Level, Metadata, Span,
+};
+
+
+/// A field of the request span.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SpanField {
+    /// `bucket`, the bucket name.
+    Bucket,
+    /// `key`, the object key.
+    Key,
+    /// `status`, the response status code.
+    Status,
+    /// `latency_ms`, the time until the response head was ready.
+    Latency,
+}
+
+impl SpanField {
+    const ALL: [SpanField; 4] = [SpanField::Bucket, SpanField::Key, SpanField::Status, SpanField::Latency];
+
+    fn name(self) -> &'static str {
+        match self {
+            SpanField::Bucket => "bucket",
+            SpanField::Key => "key",
+            SpanField::Status => "status",
+            SpanField::Latency => "latency_ms",
+        }
+    }
+}
+
+
+/// The name, target and fields of the request span.
+#[derive(Clone, Debug)]
+pub struct SpanConfig {
+    name: String,
+    target: String,
+    fields: Vec<SpanField>,
+}
+
+impl SpanConfig {
+    /// A span named `name`, with the default target and all fields.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), ..Self::default() }
+    }
+
+    /// The target of the span; defaults to `axum_static_s3`.
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = target.into();
+        self
+    }
+
+    /// Record only these fields; defaults to all.  `s3_url` is always declared.
+    pub fn fields(mut self, fields: impl IntoIterator<Item = SpanField>) -> Self {
+        self.fields = fields.into_iter().collect();
+        self
+    }
+}
+
+impl Default for SpanConfig {
+    fn default() -> Self {
+        Self {
+            name: "s3_origin".into(),
+            target: "axum_static_s3".into(),
+            fields: SpanField::ALL.to_vec(),
+        }
+    }
+}
+
+
+/// The callsite of a configured span.
+struct SpanCallsite {
+    name: &'static str,
+    target: &'static str,
+    fields: &'static [&'static str],
+    metadata: OnceLock<Metadata<'static>>,
+}
+
+/// Identifies the metadata of a callsite asked for it before [`RequestSpan::new`] set it.
+static UNINITIALIZED: SpanCallsite = SpanCallsite {
+    name: "s3_origin",
+    target: "axum_static_s3",
+    fields: &[],
+    metadata: OnceLock::new(),
+};
+
+impl SpanCallsite {
+    /// The metadata, identified by `callsite` if this call creates it.
+    fn metadata_for(&self, callsite: &'static SpanCallsite) -> &Metadata<'static> {
+        self.metadata.get_or_init(|| Metadata::new(
+            self.name,
+            self.target,
+            Level::INFO,
+            Some(file!()),
+            Some(line!()),
+            Some(module_path!()),
+            FieldSet::new(self.fields, Identifier(callsite)),
+            Kind::SPAN,
+        ))
+    }
+}
+
+impl Callsite for SpanCallsite {
+    fn set_interest(&self, _interest: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        // Set by `RequestSpan::new`, identified by this callsite, before it is registered
+        self.metadata_for(&UNINITIALIZED)
+    }
+}
+
+
+/// Creates the request spans of an origin.
+#[derive(Clone, Copy)]
+pub(crate) struct RequestSpan {
+    metadata: &'static Metadata<'static>,
+}
+
+impl RequestSpan {
+    pub(crate) fn new(config: &SpanConfig) -> Self {
+        let name: &'static str = Box::leak(config.name.clone().into_boxed_str());
+        let target: &'static str = Box::leak(config.target.clone().into_boxed_str());
+        let fields: &'static [&'static str] = Box::leak(
+            SpanField::ALL.into_iter()
+                .filter(|field| config.fields.contains(field))
+                .map(SpanField::name)
+                .chain(["s3_url"])
+                .collect::<Vec<_>>()
+                .into_boxed_slice()
+        );
+        let callsite: &'static SpanCallsite = Box::leak(Box::new(SpanCallsite { name, target, fields, metadata: OnceLock::new() }));
+        let metadata = callsite.metadata_for(callsite);
+        tracing::callsite::register(callsite);
+        Self { metadata }
+    }
+
+    /// The span of origins without a [`SpanConfig`], created once.
+    pub(crate) fn default_span() -> Self {
+        static DEFAULT: OnceLock<RequestSpan> = OnceLock::new();
+        *DEFAULT.get_or_init(|| RequestSpan::new(&SpanConfig::default()))
+    }
+
+    /// A span for a request to `bucket`; fields not configured are ignored when recorded.
+    pub(crate) fn start(&self, bucket: &str) -> Span {
+        if !tracing::dispatcher::get_default(|dispatch| dispatch.enabled(self.metadata)) {
+            return Span::none();
+        }
+        let values: Vec<Option<&dyn tracing::Value>> = vec![None; self.metadata.fields().len()];
+        let span = Span::new(self.metadata, &self.metadata.fields().value_set_all(&values));
+        span.record("bucket", bucket);
+        span
+    }
+
+    pub(crate) fn finish(span: &Span, status: StatusCode, latency: Duration) {
+        span.record("status", status.as_u16());
+        span.record("latency_ms", latency.as_millis() as u64);
+    }
+}
+
+impl std::fmt::Debug for RequestSpan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestSpan")
+            .field("name", &self.metadata.name())
+            .field("target", &self.metadata.target())
+            .field("fields", &self.metadata.fields().iter().map(|field| field.name()).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn declares_configured_fields() {
+        let span = RequestSpan::new(&SpanConfig::new("assets").target("my_app").fields([SpanField::Key, SpanField::Status]));
+        assert_eq!(span.metadata.name(), "assets");
+        assert_eq!(span.metadata.target(), "my_app");
+        assert!(span.metadata.is_span());
+        let fields = span.metadata.fields().iter().map(|field| field.name()).collect::<Vec<_>>();
+        assert_eq!(fields, ["key", "status", "s3_url"]);
+
+        let span = RequestSpan::new(&SpanConfig::default());
+        let fields = span.metadata.fields().iter().map(|field| field.name()).collect::<Vec<_>>();
+        assert_eq!(fields, ["bucket", "key", "status", "latency_ms", "s3_url"]);
+    }
+
+    /// Records the fields of every span, and tracks the current one.
+    #[derive(Default)]
+    struct Capture {
+        records: std::sync::Mutex<Vec<String>>,
+        spans: std::sync::Mutex<Vec<&'static Metadata<'static>>>,
+        current: std::sync::Mutex<Vec<tracing::span::Id>>,
+    }
+
+    impl tracing::field::Visit for &Capture {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.records.lock().unwrap().push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl tracing::Subscriber for Capture {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span.metadata());
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            if self.spans.lock().unwrap()[span.into_u64() as usize - 1].name() == "assets" {
+                values.record(&mut &*self);
+            }
+        }
+
+        fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+        fn event(&self, _event: &tracing::Event<'_>) {}
+
+        fn enter(&self, span: &tracing::span::Id) {
+            self.current.lock().unwrap().push(span.clone());
+        }
+
+        fn exit(&self, _span: &tracing::span::Id) {
+            self.current.lock().unwrap().pop();
+        }
+
+        fn current_span(&self) -> tracing_core::span::Current {
+            match self.current.lock().unwrap().last() {
+                Some(id) => tracing_core::span::Current::new(id.clone(), self.spans.lock().unwrap()[id.into_u64() as usize - 1]),
+                None => tracing_core::span::Current::none(),
+            }
+        }
+    }
+
+    struct Missing;
+
+    impl crate::backend::ObjectBackend for Missing {
+        fn get<'a>(&'a self, _key: &'a str, _range: Option<&'a str>) -> crate::backend::BackendFuture<'a, aws_sdk_s3::operation::get_object::GetObjectOutput> {
+            Box::pin(async { Err(crate::S3Error::NotFound) })
+        }
+
+        fn head<'a>(&'a self, _key: &'a str) -> crate::backend::BackendFuture<'a, aws_sdk_s3::operation::head_object::HeadObjectOutput> {
+            Box::pin(async { Err(crate::S3Error::NotFound) })
+        }
+
+        fn list<'a>(&'a self, _prefix: &'a str) -> crate::backend::BackendFuture<'a, Vec<String>> {
+            Box::pin(async { Ok(Vec::new()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn records_into_the_request_span() {
+        use tower_service::Service;
+
+        let capture = std::sync::Arc::new(Capture::default());
+        let _default = tracing::subscriber::set_default(capture.clone());
+        let mut origin = crate::S3OriginBuilder::new()
+            .bucket("my-bucket")
+            .prefix("site/")
+            .backend(std::sync::Arc::new(Missing))
+            .span(SpanConfig::new("assets").fields([SpanField::Key, SpanField::Status]))
+            .build()
+            .unwrap();
+        let response = origin.call(axum::http::Request::get("/a.txt").body(()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let records = capture.records.lock().unwrap().clone();
+        assert_eq!(records, ["key=\"site/a.txt\"", "s3_url=\"s3://my-bucket/site/a.txt\"", "status=404"]);
+    }
+}
@@ -0,0 +1,307 @@
+//! The stages of serving a request, as composable services.
+//!
+//! [`S3Origin`] serves a request in three stages, each available as a [`Service`] sharing the
+//! origin's configuration:
+//!
+//! 1. [`KeyMapper`] maps a request to an [`ObjectRequest`] (see [`S3Origin::resolve_key`]);
+//! 2. [`ObjectFetcher`] fetches the object from S3 as a [`FetchedObject`];
+//! 3. [`Responder`] assembles the response from the object metadata (`Content-Type`,
+//!    `Cache-Control`, `Content-Disposition`, forwarded `x-amz-*` headers, ...).
+//!
+//! Composing them yourself lets you insert middleware between stages, e.g. a cache in front of
+//! the fetcher.  The composed stages serve a single key: clean-URL candidates, `?v=` mapping,
+//! previews and renderers are only applied by [`S3Origin`] itself.
+//!
+//! ```rust,no_run
+//! # use axum_static_s3::{S3Origin, S3Error, stages::ObjectRequest};
+//! use tower_service::Service;
+//!
+//! async fn serve(origin: &S3Origin, request: axum::extract::Request) -> axum::response::Response {
+//!     let (mut mapper, mut fetcher, mut responder) = (origin.key_mapper(), origin.fetcher(), origin.responder());
+//!
+//!     let object = async {
+//!         let object_request: ObjectRequest = mapper.call(request).await?;
+//!         // ... consult a cache here ...
+//!         fetcher.call(object_request).await
+//!     };
+//!     match object.await {
+//!         Ok(object) => responder.call(object).await.unwrap_or_else(|never| match never {}),
+//!         Err(error) => axum::response::IntoResponse::into_response(error),
+//!     }
+//! }
+//! ```
+use std::{
+    convert::Infallible,
+    future::{ready, Future, Ready},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use aws_sdk_s3::operation::{get_object::GetObjectOutput, head_object::HeadObjectOutput};
+use axum::{
+    http::{HeaderMap, Method, Request, Uri},
+    response::{IntoResponse, Response},
+};
+use tower_service::Service;
+
+use crate::{HeadPolicy, S3Error, S3Origin, S3OriginInner};
+
+
+/// A request for a single object, produced by [`KeyMapper`].
+#[derive(Clone, Debug)]
+pub struct ObjectRequest {
+    /// The full S3 key, including the bucket prefix.
+    pub key: String,
+    /// `GET` or `HEAD`.
+    pub method: Method,
+    pub uri: Uri,
+    /// The request headers; `Range` is forwarded to S3.
+    pub headers: HeaderMap,
+}
+
+impl ObjectRequest {
+    /// The request without a body, for [`customize_request`](crate::S3OriginBuilder::customize_request).
+    pub(crate) fn to_request(&self) -> Request<()> {
+        let mut request = Request::new(());
+        *request.method_mut() = self.method.clone();
+        *request.uri_mut() = self.uri.clone();
+        *request.headers_mut() = self.headers.clone();
+        request
+    }
+}
+
+
+/// The S3 output for an object.
+#[derive(Debug)]
+pub enum ObjectOutput {
+    Get(GetObjectOutput),
+    Head(HeadObjectOutput),
+}
+
+
+/// An object fetched by [`ObjectFetcher`].
+#[derive(Debug)]
+pub struct FetchedObject {
+    /// The full S3 key, including the bucket prefix.
+    pub key: String,
+    /// The request method; responses to `HEAD` have no body.
+    pub method: Method,
+    pub output: ObjectOutput,
+}
+
+
+/// Maps requests to [`ObjectRequest`]s.
+///
+/// Fails with [`S3Error::MethodNotAllowed`] or [`S3Error::BadRequest`].
+#[derive(Clone)]
+pub struct KeyMapper {
+    origin: Arc<S3OriginInner>,
+}
+
+impl<B> Service<Request<B>> for KeyMapper {
+    type Response = ObjectRequest;
+    type Error = S3Error;
+    type Future = Ready<Result<ObjectRequest, S3Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        if !self.origin.method_policy.serves(req.method(), self.origin.head_policy) {
+            return ready(Err(S3Error::MethodNotAllowed));
+        }
+        let key = match self.origin.resolve_key(&self.origin.bucket_prefix(), &req) {
+            Ok(key) => key,
+            Err(_) => return ready(Err(S3Error::BadRequest)),
+        };
+
+        let (mut parts, _body) = req.into_parts();
+        self.origin.ranges.apply(&mut parts.headers);
+        ready(Ok(ObjectRequest {
+            key,
+            method: parts.method,
+            uri: parts.uri,
+            headers: parts.headers,
+        }))
+    }
+}
+
+
+/// Fetches objects from S3.
+///
+/// `HEAD` requests use HeadObject unless the origin's [`HeadPolicy`] says otherwise.  Objects
+/// without the [required tags](crate::S3OriginBuilder::required_tags) fail with
+/// [`S3Error::NotFound`].
+/// [`max_size`](crate::S3OriginBuilder::max_size) is checked by the [`Responder`], except for
+/// the [`parallel_head`](crate::S3OriginBuilder::parallel_head) precheck.
+#[derive(Clone)]
+pub struct ObjectFetcher {
+    origin: Arc<S3OriginInner>,
+}
+
+impl Service<ObjectRequest> for ObjectFetcher {
+    type Response = FetchedObject;
+    type Error = S3Error;
+    type Future = Pin<Box<dyn Future<Output = Result<FetchedObject, S3Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: ObjectRequest) -> Self::Future {
+        let origin = self.origin.clone();
+        Box::pin(async move {
+            if let Some(required_tags) = &origin.required_tags {
+                if !required_tags.allows(&origin.s3_client, &origin.bucket, &req.key).await? {
+                    return Err(S3Error::NotFound);
+                }
+            }
+            let head = req.method == Method::HEAD && origin.head_policy == HeadPolicy::HeadObject;
+            if let Some(backend) = &origin.backend {
+                let output = match head {
+                    true => ObjectOutput::Head(backend.head(&req.key).await?),
+                    false => {
+                        let range = req.headers.get(axum::http::header::RANGE).and_then(|range| range.to_str().ok());
+                        ObjectOutput::Get(backend.get(&req.key, range).await?)
+                    }
+                };
+                return Ok(FetchedObject { key: req.key, method: req.method, output });
+            }
+            let output = if head {
+                let output = origin.s3_client.head_object()
+                    .bucket(&origin.bucket)
+                    .key(&req.key)
+                    .send()
+                    .await?;
+                ObjectOutput::Head(output)
+            } else {
+                let builder = origin.s3_client.get_object()
+                    .bucket(&origin.bucket)
+                    .key(&req.key);
+                let builder = crate::make_request_builder(&req.headers, builder);
+                let builder = match &origin.customize_request {
+                    Some(customize) => customize(&req.to_request(), builder),
+                    None => builder,
+                };
+                let precheck = origin.parallel_head
+                    && origin.max_size.is_some()
+                    && !req.headers.contains_key(axum::http::header::RANGE);
+                ObjectOutput::Get(crate::get_object(&origin, builder, &req.key, precheck).await??)
+            };
+
+            Ok(FetchedObject { key: req.key, method: req.method, output })
+        })
+    }
+}
+
+
+/// Assembles responses from [`FetchedObject`]s.
+///
+/// Objects that may not be served (e.g. exceeding `max_size`) produce the error response.
+#[derive(Clone)]
+pub struct Responder {
+    origin: Arc<S3OriginInner>,
+}
+
+impl Service<FetchedObject> for Responder {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Ready<Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, object: FetchedObject) -> Self::Future {
+        let origin = &self.origin;
+        let path = object.key.strip_prefix(&*origin.bucket_prefix()).unwrap_or(&object.key);
+
+        let response = match object.output {
+            ObjectOutput::Get(output) => {
+                crate::get_output_response(output, origin, &object.key, path)
+            }
+            ObjectOutput::Head(output) => {
+                crate::head_output_response(output, origin, &object.key, path)
+            }
+        };
+        let response = response.unwrap_or_else(|e| e.into_response());
+
+        ready(Ok(match object.method == Method::HEAD {
+            true => crate::strip_body(response),
+            false => response,
+        }))
+    }
+}
+
+
+impl S3Origin {
+    /// The key mapping stage of this origin, see [`stages`](crate::stages).
+    pub fn key_mapper(&self) -> KeyMapper {
+        KeyMapper { origin: self.inner.clone() }
+    }
+
+    /// The object fetch stage of this origin, see [`stages`](crate::stages).
+    pub fn fetcher(&self) -> ObjectFetcher {
+        ObjectFetcher { origin: self.inner.clone() }
+    }
+
+    /// The response assembly stage of this origin, see [`stages`](crate::stages).
+    pub fn responder(&self) -> Responder {
+        Responder { origin: self.inner.clone() }
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::{config::{BehaviorVersion, Region}, primitives::ByteStream};
+    use axum::http::{header, StatusCode};
+
+    fn origin() -> S3Origin {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .build();
+        crate::S3OriginBuilder::new()
+            .bucket("my-bucket")
+            .prefix("site/")
+            .client(aws_sdk_s3::Client::from_conf(config))
+            .cache_control("*.css", "max-age=60")
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn maps_requests() {
+        let mut mapper = origin().key_mapper();
+
+        let request = Request::head("/css/a%20b.css").header(header::RANGE, "bytes=0-1").body(()).unwrap();
+        let object_request = mapper.call(request).await.unwrap();
+        assert_eq!(object_request.key, "site/css/a b.css");
+        assert_eq!(object_request.method, Method::HEAD);
+        assert_eq!(object_request.headers[header::RANGE], "bytes=0-1");
+
+        let request = Request::post("/index.html").body(()).unwrap();
+        assert_eq!(mapper.call(request).await.unwrap_err(), S3Error::MethodNotAllowed);
+    }
+
+    #[tokio::test]
+    async fn assembles_responses() {
+        let output = GetObjectOutput::builder()
+            .content_type("text/css")
+            .content_length(4)
+            .body(ByteStream::from_static(b"a{} "))
+            .build();
+        let object = FetchedObject { key: "site/css/site.css".into(), method: Method::GET, output: ObjectOutput::Get(output) };
+
+        let response = origin().responder().call(object).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "max-age=60");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"a{} ");
+    }
+}
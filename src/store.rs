@@ -0,0 +1,282 @@
+//! Pluggable shared cache stores.
+//!
+//! The in-memory [`cache`](crate::S3OriginBuilder::cache) is local to one process.  With
+//! [`cache_store`](crate::S3OriginBuilder::cache_store) a [`CacheStore`] becomes a second tier
+//! behind it: objects missing from memory are looked up in the store before going to S3, and
+//! objects fetched from S3 are written to it in the background.  A store backed by Redis,
+//! ElastiCache or DynamoDB lets a fleet of instances share one cache.
+//!
+//! The crate ships a [`MemoryStore`], and a [`DiskStore`] with the `disk-cache` feature.
+//!
+//! Store entries expire by the TTL given to [`CacheStore::put`].  Purging a single key (the
+//! [`admin`](crate::admin) purge or [`S3Origin::handle_s3_event`](crate::S3Origin::handle_s3_event))
+//! also invalidates it in the store; purging a prefix only affects the in-memory cache.  Store
+//! errors are treated as misses.
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use axum::{body::Bytes, BoxError};
+
+use crate::{
+    cache::{CachedObject, MemoryCache, TtlPolicy},
+    conditional,
+    metadata::ObjectMetadata,
+};
+#[cfg(feature = "disk-cache")]
+pub use crate::disk_store::DiskStore;
+
+
+/// The future returned by [`CacheStore`] methods.
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send + 'a>>;
+
+
+/// A cached object as kept by a [`CacheStore`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoredObject {
+    pub body: Bytes,
+    pub etag: Option<String>,
+    /// Response metadata as lowercase header names and values: `content-type`,
+    /// `cache-control`, `expires`, `content-disposition`, `content-encoding`, `last-modified` and
+    /// `x-amz-*` headers.
+    pub headers: Vec<(String, String)>,
+}
+
+impl From<&CachedObject> for StoredObject {
+    fn from(object: &CachedObject) -> Self {
+        let metadata = &object.metadata;
+        let headers = [
+            ("content-type", &metadata.content_type),
+            ("cache-control", &metadata.cache_control),
+            ("expires", &metadata.expires),
+            ("content-disposition", &metadata.content_disposition),
+            ("content-encoding", &metadata.content_encoding),
+            ("last-modified", &metadata.last_modified.and_then(|date| date.fmt(DateTimeFormat::HttpDate).ok())),
+        ];
+        let headers = headers.into_iter()
+            .filter_map(|(name, value)| Some((name.to_owned(), value.clone()?)))
+            .chain(metadata.amz_headers.iter().cloned())
+            .collect();
+        Self { body: object.body.clone(), etag: object.etag.clone(), headers }
+    }
+}
+
+impl From<StoredObject> for CachedObject {
+    fn from(object: StoredObject) -> Self {
+        let mut metadata = ObjectMetadata { content_length: i64::try_from(object.body.len()).ok(), ..ObjectMetadata::default() };
+        for (name, value) in object.headers {
+            match name.as_str() {
+                "content-type" => metadata.content_type = Some(value),
+                "cache-control" => metadata.cache_control = Some(value),
+                "expires" => metadata.expires = Some(value),
+                "content-disposition" => metadata.content_disposition = Some(value),
+                "content-encoding" => metadata.content_encoding = Some(value),
+                "last-modified" => metadata.last_modified = DateTime::from_str(&value, DateTimeFormat::HttpDate).ok(),
+                name if name.starts_with("x-amz-") => {
+                    if name == "x-amz-website-redirect-location" {
+                        metadata.website_redirect_location = Some(value.clone());
+                    }
+                    metadata.amz_headers.push((name.to_owned(), value));
+                }
+                _ => {}
+            }
+        }
+        // Synthesized ETags are derived again, the same way as when the object was fetched
+        metadata.e_tag = object.etag.clone().or_else(|| {
+            Some(conditional::weak_etag(metadata.content_length?, metadata.last_modified.as_ref()?))
+        });
+        metadata.hash_e_tag(&object.body);
+        Self { metadata, etag: object.etag, body: object.body }
+    }
+}
+
+
+/// A store of cached objects by S3 key.
+///
+/// Implementations decide how entries are evicted, but must not return an entry after its TTL.
+pub trait CacheStore: Send + Sync + 'static {
+    /// The object cached for `key`, if any.
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StoredObject>>;
+
+    /// Cache `object` for `key` for `ttl`, replacing any previous entry.
+    fn put<'a>(&'a self, key: &'a str, object: StoredObject, ttl: Duration) -> StoreFuture<'a, ()>;
+
+    /// Remove the entry for `key`, if any.
+    fn invalidate<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()>;
+}
+
+
+/// A store shared by several origins.
+impl<T: CacheStore> CacheStore for Arc<T> {
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StoredObject>> {
+        (**self).get(key)
+    }
+
+    fn put<'a>(&'a self, key: &'a str, object: StoredObject, ttl: Duration) -> StoreFuture<'a, ()> {
+        (**self).put(key, object, ttl)
+    }
+
+    fn invalidate<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        (**self).invalidate(key)
+    }
+}
+
+
+/// A size-bounded in-memory [`CacheStore`], with least-recently-used eviction.
+pub struct MemoryStore(MemoryCache);
+
+impl MemoryStore {
+    /// A store of up to `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self(MemoryCache::new(capacity, Duration::ZERO, Duration::ZERO))
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StoredObject>> {
+        Box::pin(async move { Ok(self.0.get(key).map(|hit| StoredObject::from(&*hit.object))) })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, object: StoredObject, ttl: Duration) -> StoreFuture<'a, ()> {
+        self.0.insert_for(key.to_owned(), object.into(), ttl);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn invalidate<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        self.0.invalidate(key);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl fmt::Debug for MemoryStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MemoryStore").field(&self.0).finish()
+    }
+}
+
+
+/// The configured store of an origin.
+#[derive(Clone)]
+pub(crate) struct StoreTier {
+    store: Arc<dyn CacheStore>,
+    ttl: TtlPolicy,
+    max_size: usize,
+}
+
+impl StoreTier {
+    pub(crate) fn new(store: Arc<dyn CacheStore>, ttl: TtlPolicy, max_size: usize) -> Self {
+        Self { store, ttl, max_size }
+    }
+
+    pub(crate) fn admits(&self, content_length: Option<i64>) -> bool {
+        content_length.is_some_and(|length| usize::try_from(length).is_ok_and(|length| length <= self.max_size))
+    }
+
+    pub(crate) async fn get(&self, key: &str) -> Option<CachedObject> {
+        match self.store.get(key).await {
+            Ok(object) => object.map(CachedObject::from),
+            Err(_error) => {
+                #[cfg(feature = "trace")]
+                tracing::warn!("S3Origin: cache store lookup of {} failed: {}", key, _error);
+                None
+            }
+        }
+    }
+
+    /// Write `object` to the store without waiting for it.
+    pub(crate) fn put_in_background(&self, key: &str, object: &CachedObject) {
+        let (store, key, ttl) = (self.store.clone(), key.to_owned(), self.ttl.ttl(&object.metadata));
+        let object = StoredObject::from(object);
+        tokio::spawn(async move {
+            if let Err(_error) = store.put(&key, object, ttl).await {
+                #[cfg(feature = "trace")]
+                tracing::warn!("S3Origin: caching {} in the store failed: {}", key, _error);
+            }
+        });
+    }
+
+    pub(crate) async fn invalidate(&self, key: &str) {
+        if let Err(_error) = self.store.invalidate(key).await {
+            #[cfg(feature = "trace")]
+            tracing::warn!("S3Origin: invalidating {} in the store failed: {}", key, _error);
+        }
+    }
+
+    /// Invalidate `keys` without waiting, if called within a Tokio runtime.
+    #[cfg(feature = "s3-events")]
+    pub(crate) fn invalidate_in_background(&self, keys: Vec<String>) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let tier = self.clone();
+        runtime.spawn(async move {
+            for key in keys {
+                tier.invalidate(&key).await;
+            }
+        });
+    }
+}
+
+impl fmt::Debug for StoreTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoreTier")
+            .field("ttl", &self.ttl)
+            .field("max_size", &self.max_size)
+            .finish_non_exhaustive()
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_objects() {
+        let object = StoredObject {
+            body: Bytes::from_static(b"<p>hi</p>"),
+            etag: Some("\"abc\"".into()),
+            headers: vec![
+                ("content-type".into(), "text/html".into()),
+                ("content-encoding".into(), "gzip".into()),
+                ("last-modified".into(), "Wed, 21 Oct 2015 07:28:00 GMT".into()),
+                ("x-amz-website-redirect-location".into(), "/new".into()),
+                ("x-amz-meta-owner".into(), "web".into()),
+            ],
+        };
+        let cached = CachedObject::from(object.clone());
+        assert_eq!(cached.metadata.content_type.as_deref(), Some("text/html"));
+        assert_eq!(cached.metadata.content_length, Some(9));
+        assert_eq!(cached.metadata.content_encoding.as_deref(), Some("gzip"));
+        assert_eq!(cached.metadata.website_redirect_location.as_deref(), Some("/new"));
+        assert_eq!(cached.metadata.e_tag.as_deref(), Some("\"abc\""));
+        assert_eq!(StoredObject::from(&cached), object);
+
+        // Objects without an ETag get the same weak one every time they are loaded
+        let object = StoredObject { etag: None, ..object };
+        let cached = CachedObject::from(object.clone());
+        assert_eq!(cached.metadata.e_tag.as_deref(), Some("W/\"9-56273e80\""));
+        assert_eq!(StoredObject::from(&cached), object);
+        let object = StoredObject { headers: Vec::new(), ..object };
+        assert_eq!(CachedObject::from(object.clone()).metadata.e_tag, CachedObject::from(object).metadata.e_tag);
+    }
+
+    #[tokio::test]
+    async fn memory_store_expires_entries() {
+        let store = MemoryStore::new(1024);
+        let object = StoredObject { body: Bytes::from_static(b"1"), ..StoredObject::default() };
+        store.put("a", object.clone(), Duration::from_secs(60)).await.unwrap();
+        store.put("b", object.clone(), Duration::ZERO).await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), Some(object));
+        assert_eq!(store.get("b").await.unwrap(), None);
+
+        store.invalidate("a").await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,78 @@
+//! Fixed responses for well-known paths.
+//!
+//! Crawlers and browsers request `/robots.txt` and `/favicon.ico` from every site, whether it
+//! has them or not.  [`synthetic_response`](crate::S3OriginBuilder::synthetic_response) answers
+//! such paths with a body held in memory, without a request to S3.
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, HeaderValue},
+    response::Response,
+};
+
+use crate::conditional;
+
+
+/// A body served for a path.
+#[derive(Clone, Debug)]
+pub(crate) struct SyntheticResponse {
+    /// The request path, without leading `/`.
+    path: String,
+    content_type: HeaderValue,
+    etag: HeaderValue,
+    body: Bytes,
+}
+
+impl SyntheticResponse {
+    pub(crate) fn new(path: &str, content_type: &str, body: Bytes) -> Result<Self, &'static str> {
+        let content_type = HeaderValue::try_from(content_type).map_err(|_| "invalid synthetic response content type")?;
+        let etag = HeaderValue::try_from(conditional::hash_etag(&body)).map_err(|_| "invalid synthetic response")?;
+        Ok(Self { path: path.trim_start_matches('/').to_owned(), content_type, etag, body })
+    }
+
+    /// The response for `path`, if one is configured.
+    pub(crate) fn find<'a>(responses: &'a [Self], path: &str) -> Option<&'a Self> {
+        responses.iter().find(|response| response.path == path)
+    }
+
+    pub(crate) fn response(&self, request_headers: &HeaderMap, is_head: bool) -> Response {
+        let body = match is_head {
+            true => Body::empty(),
+            false => Body::from(self.body.clone()),
+        };
+        let mut response = Response::new(body);
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_TYPE, self.content_type.clone());
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(self.body.len()));
+        headers.insert(header::ETAG, self.etag.clone());
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("public, max-age=3600"));
+        conditional::apply(request_headers, response)
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+
+    #[test]
+    fn answers_paths() {
+        let responses = [
+            SyntheticResponse::new("/robots.txt", "text/plain", Bytes::from_static(b"User-agent: *\nAllow: /\n")).unwrap(),
+            SyntheticResponse::new("favicon.ico", "image/x-icon", Bytes::new()).unwrap(),
+        ];
+        assert!(SyntheticResponse::find(&responses, "robots.txt").is_some());
+        assert!(SyntheticResponse::find(&responses, "favicon.ico").is_some());
+        assert!(SyntheticResponse::find(&responses, "docs/robots.txt").is_none());
+
+        let response = responses[0].response(&HeaderMap::new(), true);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "23");
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, response.headers()[header::ETAG].clone());
+        assert_eq!(responses[0].response(&headers, false).status(), StatusCode::NOT_MODIFIED);
+
+        assert!(SyntheticResponse::new("robots.txt", "text/plain\n", Bytes::new()).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+//! Per-origin feature usage telemetry.
+//!
+//! With [`S3OriginBuilder::feature_telemetry`](crate::S3OriginBuilder::feature_telemetry) every
+//! response records which optional features actually fired while serving it.  The origin keeps
+//! aggregate [`FeatureCounters`] and calls the callback after each request, so operators can
+//! confirm that a configuration does what they expect, e.g. that a `cache_control` rule really
+//! matches the paths it was written for.
+//!
+//! The [`Features`] of a response are also available in its extensions, whether or not a
+//! callback is configured.
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use axum::response::Response;
+
+
+/// An optional feature that can fire while serving a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Feature {
+    /// A [`cache_control`](crate::S3OriginBuilder::cache_control) rule set `Cache-Control`.
+    CacheControlRule,
+    /// [`immutable_assets`](crate::S3OriginBuilder::immutable_assets) detection set `Cache-Control`.
+    ImmutableAssets,
+    /// An [`attachment`](crate::S3OriginBuilder::attachment) rule set `Content-Disposition`.
+    Attachment,
+    /// At least one `x-amz-*` header was forwarded.
+    AmzHeaders,
+    /// Website redirect metadata produced a redirect.
+    WebsiteRedirect,
+    /// The object was rejected for exceeding [`max_size`](crate::S3OriginBuilder::max_size).
+    MaxSize,
+    /// The [`parallel_head`](crate::S3OriginBuilder::parallel_head) precheck cancelled the GET.
+    ParallelHead,
+    /// A clean-URL candidate other than the literal key was served.
+    CleanUrls,
+    /// A trailing-slash redirect was sent.
+    TrailingSlash,
+    /// A rendered form was served instead of the raw object.
+    Render,
+    /// A `?preview=N` preview was served.
+    Preview,
+    /// A legacy `?v=` version was served from its versioned key.
+    VersionQuery,
+    /// The object was served from the in-memory [`cache`](crate::S3OriginBuilder::cache).
+    Cache,
+    /// The key was mapped by the deployment [`manifest`](crate::S3OriginBuilder::manifest).
+    Manifest,
+    /// The request was served from the [`canary`](crate::S3OriginBuilder::canary) prefix.
+    Canary,
+    /// An A/B variant selected by an [`experiment`](crate::S3OriginBuilder::experiment) was served.
+    Experiment,
+    /// The object was served from the locale negotiated by [`locales`](crate::S3OriginBuilder::locales).
+    Locale,
+    /// An AVIF or WebP copy was served by [`image_negotiation`](crate::S3OriginBuilder::image_negotiation).
+    ImageFormat,
+    /// An HTML document was rewritten by [`html_injection`](crate::S3OriginBuilder::html_injection).
+    HtmlInjection,
+    /// [`preload`](crate::S3OriginBuilder::preload) `Link` headers were added.
+    Preload,
+    /// The object was fetched in parts by [`parallel_fetch`](crate::S3OriginBuilder::parallel_fetch).
+    ParallelFetch,
+    /// A key detected as hot by [`hot_keys`](crate::S3OriginBuilder::hot_keys) was promoted into the cache.
+    HotKey,
+    /// A gzip-encoded object was decompressed by [`decompress`](crate::S3OriginBuilder::decompress).
+    Decompression,
+    /// A `Range` header was ignored, shortened by [`max_range_size`](crate::S3OriginBuilder::max_range_size)
+    /// or answered with the whole object by [`no_ranges_for`](crate::S3OriginBuilder::no_ranges_for).
+    RangePolicy,
+    /// An object too large for a Lambda response was redirected to a presigned URL by
+    /// [`lambda_response_limit`](crate::S3OriginBuilder::lambda_response_limit).
+    LambdaRedirect,
+    /// A [`synthetic_response`](crate::S3OriginBuilder::synthetic_response) was served.
+    Synthetic,
+    /// A `/.well-known/` path was served from the [`well_known`](crate::S3OriginBuilder::well_known) prefix.
+    WellKnown,
+    /// A [`precompressed`](crate::S3OriginBuilder::precompressed) variant was served.
+    Precompressed,
+    /// A [`redirect`](crate::S3OriginBuilder::redirect) rule answered the request.
+    RedirectRule,
+    /// A [`content_type`](crate::S3OriginBuilder::content_type) rule set `Content-Type`.
+    ContentTypeRule,
+}
+
+impl Feature {
+    /// All features, in declaration order.
+    pub const ALL: [Feature; 30] = [
+        Feature::CacheControlRule,
+        Feature::ImmutableAssets,
+        Feature::Attachment,
+        Feature::AmzHeaders,
+        Feature::WebsiteRedirect,
+        Feature::MaxSize,
+        Feature::ParallelHead,
+        Feature::CleanUrls,
+        Feature::TrailingSlash,
+        Feature::Render,
+        Feature::Preview,
+        Feature::VersionQuery,
+        Feature::Cache,
+        Feature::Manifest,
+        Feature::Canary,
+        Feature::Experiment,
+        Feature::Locale,
+        Feature::ImageFormat,
+        Feature::HtmlInjection,
+        Feature::Preload,
+        Feature::ParallelFetch,
+        Feature::HotKey,
+        Feature::Decompression,
+        Feature::RangePolicy,
+        Feature::LambdaRedirect,
+        Feature::Synthetic,
+        Feature::WellKnown,
+        Feature::Precompressed,
+        Feature::RedirectRule,
+        Feature::ContentTypeRule,
+    ];
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Feature::CacheControlRule => "cache_control_rule",
+            Feature::ImmutableAssets => "immutable_assets",
+            Feature::Attachment => "attachment",
+            Feature::AmzHeaders => "amz_headers",
+            Feature::WebsiteRedirect => "website_redirect",
+            Feature::MaxSize => "max_size",
+            Feature::ParallelHead => "parallel_head",
+            Feature::CleanUrls => "clean_urls",
+            Feature::TrailingSlash => "trailing_slash",
+            Feature::Render => "render",
+            Feature::Preview => "preview",
+            Feature::VersionQuery => "version_query",
+            Feature::Cache => "cache",
+            Feature::Manifest => "manifest",
+            Feature::Canary => "canary",
+            Feature::Experiment => "experiment",
+            Feature::Locale => "locale",
+            Feature::ImageFormat => "image_format",
+            Feature::HtmlInjection => "html_injection",
+            Feature::Preload => "preload",
+            Feature::ParallelFetch => "parallel_fetch",
+            Feature::HotKey => "hot_key",
+            Feature::Decompression => "decompression",
+            Feature::RangePolicy => "range_policy",
+            Feature::LambdaRedirect => "lambda_redirect",
+            Feature::Synthetic => "synthetic",
+            Feature::WellKnown => "well_known",
+            Feature::Precompressed => "precompressed",
+            Feature::RedirectRule => "redirect_rule",
+            Feature::ContentTypeRule => "content_type_rule",
+        };
+        f.write_str(name)
+    }
+}
+
+
+/// The set of features that fired for a single response.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Features(u32);
+
+impl Features {
+    pub fn contains(&self, feature: Feature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Feature> + '_ {
+        Feature::ALL.into_iter().filter(|feature| self.contains(*feature))
+    }
+
+    pub(crate) fn insert(&mut self, feature: Feature) {
+        self.0 |= feature.bit();
+    }
+}
+
+
+/// Record that `feature` fired for `response`.
+pub(crate) fn record(response: &mut Response, feature: Feature) {
+    let extensions = response.extensions_mut();
+    match extensions.get_mut::<Features>() {
+        Some(features) => features.insert(feature),
+        None => {
+            let mut features = Features::default();
+            features.insert(feature);
+            extensions.insert(features);
+        }
+    }
+}
+
+
+/// Aggregate feature counters of an origin.
+#[derive(Debug, Default)]
+pub struct FeatureCounters {
+    requests: AtomicU64,
+    features: [AtomicU64; Feature::ALL.len()],
+}
+
+impl FeatureCounters {
+    /// The number of requests served.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests `feature` fired for.
+    pub fn get(&self, feature: Feature) -> u64 {
+        self.features[feature as usize].load(Ordering::Relaxed)
+    }
+
+    /// The count of every feature, in declaration order.
+    pub fn snapshot(&self) -> Vec<(Feature, u64)> {
+        Feature::ALL.into_iter().map(|feature| (feature, self.get(feature))).collect()
+    }
+
+    fn add(&self, features: Features) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        for feature in features.iter() {
+            self.features[feature as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+
+pub(crate) type TelemetryCallback = Arc<dyn Fn(Features, &FeatureCounters) + Send + Sync>;
+
+
+/// Counters and callback of an origin with telemetry enabled.
+#[derive(Clone)]
+pub(crate) struct Telemetry {
+    pub(crate) counters: Arc<FeatureCounters>,
+    pub(crate) callback: TelemetryCallback,
+}
+
+impl Telemetry {
+    pub(crate) fn report(&self, response: &Response) {
+        let features = response.extensions().get::<Features>().copied().unwrap_or_default();
+        self.counters.add(features);
+        (self.callback)(features, &self.counters);
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_features() {
+        let mut response = Response::new(axum::body::Body::empty());
+        record(&mut response, Feature::Render);
+        record(&mut response, Feature::CacheControlRule);
+
+        let features = response.extensions().get::<Features>().copied().unwrap();
+        assert_eq!(features.iter().collect::<Vec<_>>(), [Feature::CacheControlRule, Feature::Render]);
+
+        let counters = FeatureCounters::default();
+        counters.add(features);
+        counters.add(Features::default());
+        assert_eq!(counters.requests(), 2);
+        assert_eq!(counters.get(Feature::Render), 1);
+        assert_eq!(counters.get(Feature::Preview), 0);
+    }
+}
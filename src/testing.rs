@@ -0,0 +1,446 @@
+//! An in-memory S3 for integration tests (`testing` feature).
+//!
+//! [`MockS3`] answers the S3 requests of an origin (GetObject, HeadObject, GetObjectTagging,
+//! ListObjectsV2) from an in-memory object map, through the SDK's HTTP client hook: the origin runs its usual
+//! request path, without network access or credentials.  Requests are recorded, so tests can
+//! assert which keys were fetched, e.g. that a cached object is not fetched twice:
+//!
+//! ```rust
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! use axum::{body::Body, http::Request, Router};
+//! use axum_static_s3::testing::{MockObject, MockS3};
+//! use tower_service::Service;
+//!
+//! let s3 = MockS3::new("my-bucket")
+//!     .object("site/index.html", MockObject::new("<h1>Hello</h1>").content_type("text/html"));
+//! let origin = s3.origin().prefix("site/").cache(1024 * 1024).build().unwrap();
+//! let mut app = Router::new().fallback_service(origin);
+//!
+//! for _ in 0..2 {
+//!     let response = app.call(Request::get("/index.html").body(Body::empty()).unwrap()).await.unwrap();
+//!     assert_eq!(response.status(), 200);
+//! }
+//! assert_eq!(s3.requested_keys(), ["site/index.html"]);
+//! # }
+//! ```
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use aws_sdk_s3::{
+    config::{
+        http::{HttpRequest, HttpResponse},
+        BehaviorVersion, Region,
+    },
+    primitives::SdkBody,
+    Client as S3Client,
+};
+use aws_smithy_runtime_api::client::http::{http_client_fn, HttpConnector, HttpConnectorFuture, SharedHttpConnector};
+use axum::{body::Bytes, http::StatusCode};
+
+use crate::S3OriginBuilder;
+
+
+/// The most keys in a ListObjectsV2 page, like S3.
+const MAX_KEYS: usize = 1000;
+
+
+/// An object stored in a [`MockS3`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MockObject {
+    body: Bytes,
+    content_type: Option<String>,
+    headers: Vec<(String, String)>,
+    metadata: Vec<(String, String)>,
+    tags: Vec<(String, String)>,
+}
+
+impl MockObject {
+    /// An object with this body.
+    pub fn new(body: impl Into<Bytes>) -> Self {
+        Self { body: body.into(), ..Self::default() }
+    }
+
+    /// Set the `Content-Type`; without one, S3 answers `binary/octet-stream`.
+    pub fn content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = Some(content_type.into());
+        self
+    }
+
+    /// Add a response header stored with the object, e.g. `Cache-Control` or
+    /// `x-amz-website-redirect-location`.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Add user metadata, served as `x-amz-meta-{name}`.
+    pub fn metadata(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.push((name.into(), value.into()));
+        self
+    }
+
+    /// Add an object tag.
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    fn etag(&self) -> String {
+        format!("\"{:08x}{:08x}\"", crc32fast::hash(&self.body), self.body.len())
+    }
+}
+
+
+/// A request received by a [`MockS3`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockRequest {
+    /// `GET` or `HEAD`.
+    pub method: String,
+    /// The object key; empty for ListObjectsV2.
+    pub key: String,
+    /// The query string, e.g. `tagging` for GetObjectTagging or `list-type=2&prefix=site%2F` for
+    /// ListObjectsV2.
+    pub query: Option<String>,
+    /// The `Range` header, if any.
+    pub range: Option<String>,
+}
+
+
+#[derive(Default)]
+struct State {
+    objects: BTreeMap<String, MockObject>,
+    requests: Vec<MockRequest>,
+}
+
+
+/// An in-memory bucket, see [`testing`](crate::testing).
+///
+/// Clones share the objects and the recorded requests.
+#[derive(Clone)]
+pub struct MockS3 {
+    bucket: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl fmt::Debug for MockS3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("MockS3")
+            .field("bucket", &self.bucket)
+            .field("objects", &state.objects.keys().collect::<Vec<_>>())
+            .field("requests", &state.requests.len())
+            .finish()
+    }
+}
+
+impl MockS3 {
+    /// An empty bucket named `bucket`.
+    pub fn new(bucket: impl Into<String>) -> Self {
+        Self { bucket: bucket.into(), state: Arc::default() }
+    }
+
+    /// Add an object.
+    pub fn object(self, key: impl Into<String>, object: MockObject) -> Self {
+        self.put(key, object);
+        self
+    }
+
+    /// Add or replace an object, e.g. to simulate a deployment during a test.
+    pub fn put(&self, key: impl Into<String>, object: MockObject) {
+        self.state().objects.insert(key.into(), object);
+    }
+
+    /// Remove an object.
+    pub fn delete(&self, key: &str) {
+        self.state().objects.remove(key);
+    }
+
+    /// An S3 client answered by this bucket.
+    pub fn client(&self) -> S3Client {
+        let connector = SharedHttpConnector::new(Connector(self.clone()));
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .endpoint_url("http://s3.mock")
+            .force_path_style(true)
+            .http_client(http_client_fn(move |_, _| connector.clone()))
+            .build();
+        S3Client::from_conf(config)
+    }
+
+    /// A builder with the bucket and client set.
+    pub fn origin(&self) -> S3OriginBuilder {
+        S3OriginBuilder::new().bucket(self.bucket.clone()).client(self.client())
+    }
+
+    /// The requests received so far, oldest first.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state().requests.clone()
+    }
+
+    /// The keys requested so far, oldest first, including requests for missing objects.
+    pub fn requested_keys(&self) -> Vec<String> {
+        self.state().requests.iter().map(|request| request.key.clone()).collect()
+    }
+
+    /// Forget the recorded requests.
+    pub fn clear_requests(&self) {
+        self.state().requests.clear();
+    }
+
+    /// Panics unless `key` was requested.
+    #[track_caller]
+    pub fn assert_requested(&self, key: &str) {
+        let keys = self.requested_keys();
+        assert!(keys.iter().any(|requested| requested == key), "{:?} was not requested; requested: {:?}", key, keys);
+    }
+
+    /// Panics if `key` was requested.
+    #[track_caller]
+    pub fn assert_not_requested(&self, key: &str) {
+        let keys = self.requested_keys();
+        assert!(!keys.iter().any(|requested| requested == key), "{:?} was requested", key);
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Answer a request like S3 would.
+    fn respond(&self, request: &HttpRequest) -> HttpResponse {
+        let Ok(uri) = request.uri().parse::<axum::http::Uri>() else {
+            return error(StatusCode::BAD_REQUEST, "InvalidURI");
+        };
+        let path = uri.path().trim_start_matches('/');
+        let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+        let key = percent_encoding::percent_decode_str(key).decode_utf8_lossy().into_owned();
+        let headers = request.headers();
+        let mut state = self.state();
+        state.requests.push(MockRequest {
+            method: request.method().to_owned(),
+            key: key.clone(),
+            query: uri.query().filter(|query| !query.starts_with("x-id=")).map(str::to_owned),
+            range: headers.get("range").map(str::to_owned),
+        });
+
+        if bucket != self.bucket {
+            return error(StatusCode::NOT_FOUND, "NoSuchBucket");
+        }
+        let query = uri.query().unwrap_or_default();
+        if key.is_empty() && query.split('&').any(|param| param == "list-type=2") {
+            return list(&self.bucket, &state.objects, query);
+        }
+        let Some(object) = state.objects.get(&key) else {
+            return error(StatusCode::NOT_FOUND, "NoSuchKey");
+        };
+
+        if query.split('&').any(|param| param == "tagging" || param.starts_with("tagging=")) {
+            let tags = object.tags.iter()
+                .map(|(key, value)| format!("<Tag><Key>{}</Key><Value>{}</Value></Tag>", key, value))
+                .collect::<String>();
+            return response(StatusCode::OK, format!("<Tagging><TagSet>{}</TagSet></Tagging>", tags));
+        }
+
+        let etag = object.etag();
+        if headers.get("if-none-match").is_some_and(|value| value == etag) {
+            return with_object_headers(HttpResponse::new(StatusCode::NOT_MODIFIED.into(), SdkBody::empty()), object, &etag);
+        }
+        if headers.get("if-match").is_some_and(|value| value != etag) {
+            return error(StatusCode::PRECONDITION_FAILED, "PreconditionFailed");
+        }
+
+        let length = object.body.len();
+        let range = headers.get("range").map(|range| parse_range(range, length));
+        let (status, body, content_range) = match range {
+            None => (StatusCode::OK, object.body.clone(), None),
+            Some(None) => return error(StatusCode::RANGE_NOT_SATISFIABLE, "InvalidRange"),
+            Some(Some((start, end))) => (StatusCode::PARTIAL_CONTENT, object.body.slice(start..=end), Some(format!("bytes {}-{}/{}", start, end, length))),
+        };
+        let content_length = body.len();
+        let body = match request.method() {
+            "HEAD" => SdkBody::empty(),
+            _ => SdkBody::from(body),
+        };
+        let mut response = with_object_headers(HttpResponse::new(status.into(), body), object, &etag);
+        response.headers_mut().insert("content-length", content_length.to_string());
+        if let Some(content_range) = content_range {
+            response.headers_mut().insert("content-range", content_range);
+        }
+        response
+    }
+}
+
+
+/// Answers requests from a [`MockS3`].
+#[derive(Debug)]
+struct Connector(MockS3);
+
+impl HttpConnector for Connector {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        let mut response = self.0.respond(&request);
+        // S3 answers HEAD requests without a body, errors included
+        if request.method() == "HEAD" {
+            *response.body_mut() = SdkBody::empty();
+        }
+        HttpConnectorFuture::ready(Ok(response))
+    }
+}
+
+
+fn response(status: StatusCode, body: String) -> HttpResponse {
+    let mut response = HttpResponse::new(status.into(), SdkBody::from(body));
+    response.headers_mut().insert("content-type", "application/xml");
+    response
+}
+
+
+fn error(status: StatusCode, code: &str) -> HttpResponse {
+    response(status, format!("<Error><Code>{}</Code><Message>{}</Message></Error>", code, code))
+}
+
+
+/// A ListObjectsV2 page of the keys under `prefix`, after the `continuation-token` key.
+fn list(bucket: &str, objects: &BTreeMap<String, MockObject>, query: &str) -> HttpResponse {
+    let params = query.split('&')
+        .filter_map(|param| param.split_once('='))
+        .map(|(name, value)| (name, percent_encoding::percent_decode_str(value).decode_utf8_lossy().into_owned()))
+        .collect::<BTreeMap<_, _>>();
+    let param = |name| params.get(name).map(String::as_str).unwrap_or_default();
+    let max_keys = param("max-keys").parse().unwrap_or(MAX_KEYS).min(MAX_KEYS);
+    let after = match param("continuation-token") {
+        "" => param("start-after"),
+        token => token,
+    };
+
+    let mut keys = objects.iter()
+        .filter(|(key, _)| key.starts_with(param("prefix")) && key.as_str() > after)
+        .peekable();
+    let page = keys.by_ref().take(max_keys).collect::<Vec<_>>();
+    let contents = page.iter()
+        .map(|(key, object)| format!(
+            "<Contents><Key>{}</Key><LastModified>2026-01-01T00:00:00.000Z</LastModified><Size>{}</Size><ETag>{}</ETag></Contents>",
+            escape(key), object.body.len(), escape(&object.etag()),
+        ))
+        .collect::<String>();
+    let truncated = match (keys.peek(), page.last()) {
+        (Some(_), Some((last, _))) => format!("<IsTruncated>true</IsTruncated><NextContinuationToken>{}</NextContinuationToken>", escape(last)),
+        _ => "<IsTruncated>false</IsTruncated>".to_owned(),
+    };
+    response(StatusCode::OK, format!(
+        "<ListBucketResult><Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys>{}{}</ListBucketResult>",
+        escape(bucket), escape(param("prefix")), page.len(), max_keys, truncated, contents,
+    ))
+}
+
+
+/// `value` escaped for XML text.
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+
+fn with_object_headers(mut response: HttpResponse, object: &MockObject, etag: &str) -> HttpResponse {
+    let headers = response.headers_mut();
+    headers.insert("etag", etag.to_owned());
+    headers.insert("accept-ranges", "bytes");
+    headers.insert("last-modified", "Thu, 01 Jan 2026 00:00:00 GMT");
+    headers.insert("content-type", object.content_type.clone().unwrap_or_else(|| "binary/octet-stream".into()));
+    for (name, value) in &object.headers {
+        headers.insert(name.to_ascii_lowercase(), value.clone());
+    }
+    for (name, value) in &object.metadata {
+        headers.insert(format!("x-amz-meta-{}", name.to_ascii_lowercase()), value.clone());
+    }
+    response
+}
+
+
+/// The inclusive byte range of a `Range` header, or `None` if it is not satisfiable.
+fn parse_range(range: &str, length: usize) -> Option<(usize, usize)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let last = length.checked_sub(1)?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => (length.saturating_sub(suffix.parse().ok()?), last),
+        (start, "") => (start.parse().ok()?, last),
+        (start, end) => (start.parse().ok()?, end.parse::<usize>().ok()?.min(last)),
+    };
+    (start <= end).then_some((start, end))
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some((0, 4)));
+        assert_eq!(parse_range("bytes=5-", 10), Some((5, 9)));
+        assert_eq!(parse_range("bytes=-3", 10), Some((7, 9)));
+        assert_eq!(parse_range("bytes=8-20", 10), Some((8, 9)));
+        assert_eq!(parse_range("bytes=10-", 10), None);
+        assert_eq!(parse_range("bytes=0-0", 0), None);
+    }
+
+    #[tokio::test]
+    async fn serves_objects() {
+        let s3 = MockS3::new("my-bucket")
+            .object("a b.txt", MockObject::new("hello world").content_type("text/plain").metadata("author", "me").tag("public", "true"));
+        let client = s3.client();
+
+        let output = client.get_object().bucket("my-bucket").key("a b.txt").send().await.unwrap();
+        assert_eq!(output.content_type(), Some("text/plain"));
+        assert_eq!(output.metadata().unwrap()["author"], "me");
+        let etag = output.e_tag().unwrap().to_owned();
+        assert_eq!(output.body.collect().await.unwrap().into_bytes(), "hello world");
+
+        let output = client.get_object().bucket("my-bucket").key("a b.txt").range("bytes=6-").send().await.unwrap();
+        assert_eq!(output.content_range(), Some("bytes 6-10/11"));
+        assert_eq!(output.body.collect().await.unwrap().into_bytes(), "world");
+
+        let output = client.head_object().bucket("my-bucket").key("a b.txt").send().await.unwrap();
+        assert_eq!(output.content_length(), Some(11));
+        let error = client.get_object().bucket("my-bucket").key("a b.txt").if_none_match(etag).send().await.unwrap_err();
+        assert_eq!(error.raw_response().unwrap().status().as_u16(), 304);
+
+        let output = client.get_object_tagging().bucket("my-bucket").key("a b.txt").send().await.unwrap();
+        assert_eq!(output.tag_set()[0].key(), "public");
+
+        let error = client.get_object().bucket("my-bucket").key("missing").send().await.unwrap_err();
+        assert!(error.into_service_error().is_no_such_key());
+        let error = client.head_object().bucket("my-bucket").key("missing").send().await.unwrap_err();
+        assert!(error.into_service_error().is_not_found());
+
+        assert_eq!(s3.requested_keys(), ["a b.txt", "a b.txt", "a b.txt", "a b.txt", "a b.txt", "missing", "missing"]);
+        assert_eq!(s3.requests()[1].range.as_deref(), Some("bytes=6-"));
+        assert_eq!(s3.requests()[4].query.as_deref(), Some("tagging"));
+        s3.assert_not_requested("b.txt");
+    }
+
+    #[tokio::test]
+    async fn lists_objects() {
+        let s3 = MockS3::new("my-bucket")
+            .object("site/a&b.html", MockObject::new("a"))
+            .object("site/b.html", MockObject::new("bb"))
+            .object("site/c.html", MockObject::new("ccc"))
+            .object("other.html", MockObject::new(""));
+        let client = s3.client();
+
+        let output = client.list_objects_v2().bucket("my-bucket").prefix("site/").max_keys(2).send().await.unwrap();
+        let keys = output.contents().iter().filter_map(|object| object.key()).collect::<Vec<_>>();
+        assert_eq!(keys, ["site/a&b.html", "site/b.html"]);
+        assert_eq!(output.contents()[1].size(), Some(2));
+        assert_eq!(output.is_truncated(), Some(true));
+
+        let pages = client.list_objects_v2().bucket("my-bucket").prefix("site/").max_keys(2).into_paginator().send();
+        let pages = pages.collect::<Result<Vec<_>, _>>().await.unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[1].contents()[0].key(), Some("site/c.html"));
+        assert_eq!(s3.requests()[0].key, "");
+    }
+}
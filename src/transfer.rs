@@ -0,0 +1,121 @@
+//! Counting completed and aborted object transfers.
+//!
+//! When a client disconnects, hyper drops the response body, and with it the S3 object body:
+//! the GetObject connection is closed right away and no further bytes are fetched.  Readahead
+//! and parallel part requests are aborted with it.  [`S3Origin::transfer_stats`] counts how
+//! often that happens.
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use axum::body::{Body, Bytes};
+use futures_core::Stream;
+
+use crate::S3Origin;
+
+
+/// Transfer statistics of an origin, see [`S3Origin::transfer_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    /// Object bodies sent completely.
+    pub completed: u64,
+    /// Object bodies dropped before their end, usually because the client disconnected.
+    pub aborted: u64,
+    /// Bytes sent of the aborted bodies.
+    pub aborted_bytes: u64,
+    /// Bodies that did not match their checksum, see
+    /// [`verify_checksums`](crate::S3OriginBuilder::verify_checksums).
+    pub checksum_failures: u64,
+}
+
+
+#[derive(Debug, Default)]
+pub(crate) struct TransferCounters {
+    completed: AtomicU64,
+    aborted: AtomicU64,
+    aborted_bytes: AtomicU64,
+    checksum_failures: AtomicU64,
+}
+
+impl TransferCounters {
+    /// Count the outcome of `body` once it ends or is dropped.
+    pub(crate) fn track(self: &Arc<Self>, body: Body) -> Body {
+        Body::from_stream(Tracked {
+            body: body.into_data_stream(),
+            counters: self.clone(),
+            sent: None,
+        })
+    }
+
+    pub(crate) fn checksum_failed(&self) {
+        self.checksum_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> TransferStats {
+        TransferStats {
+            completed: self.completed.load(Ordering::Relaxed),
+            aborted: self.aborted.load(Ordering::Relaxed),
+            aborted_bytes: self.aborted_bytes.load(Ordering::Relaxed),
+            checksum_failures: self.checksum_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+
+struct Tracked {
+    body: axum::body::BodyDataStream,
+    counters: Arc<TransferCounters>,
+    /// Bytes sent while the body is being read; `None` before the first read and after the end.
+    sent: Option<u64>,
+}
+
+impl Stream for Tracked {
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.body).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => *this.sent.get_or_insert(0) += chunk.len() as u64,
+            Poll::Ready(Some(Err(_))) => {
+                this.sent.get_or_insert(0);
+            }
+            Poll::Ready(None) => {
+                this.sent = None;
+                this.counters.completed.fetch_add(1, Ordering::Relaxed);
+            }
+            Poll::Pending => {
+                this.sent.get_or_insert(0);
+            }
+        }
+        poll
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        // Bodies dropped unread (e.g. for HEAD requests) are not transfers
+        if let Some(sent) = self.sent {
+            #[cfg(feature = "trace")]
+            tracing::debug!("S3Origin: transfer aborted after {} bytes", sent);
+            self.counters.aborted.fetch_add(1, Ordering::Relaxed);
+            self.counters.aborted_bytes.fetch_add(sent, Ordering::Relaxed);
+        }
+    }
+}
+
+
+impl S3Origin {
+    /// Counts of completed and aborted object transfers.
+    ///
+    /// Bodies that are never read, such as those of HEAD requests answered with a GET, and
+    /// responses served from the cache are not counted.
+    pub fn transfer_stats(&self) -> TransferStats {
+        self.inner.transfers.stats()
+    }
+}
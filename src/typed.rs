@@ -0,0 +1,180 @@
+//! A builder that checks required options at compile time.
+//!
+//! [`S3OriginBuilder::build`] fails at runtime when the bucket or the client/config is missing.
+//! [`S3Origin::builder`] returns a [`TypedBuilder`] that tracks both in its type, so `build` is
+//! only available once they are set:
+//!
+//! ```rust
+//! # fn example(client: aws_sdk_s3::Client) {
+//! use axum_static_s3::S3Origin;
+//!
+//! let origin = S3Origin::builder()
+//!     .bucket("my-static-files-bucket")
+//!     .client(client)
+//!     .prefix("static/")
+//!     .max_size(12 * 1024 * 1024)
+//!     .with(|builder| builder.cache(16 * 1024 * 1024))
+//!     .build()
+//!     .expect("Failed to build S3 origin");
+//! # }
+//! ```
+//!
+//! Forgetting the bucket is a compile error:
+//!
+//! ```rust,compile_fail
+//! # fn example(client: aws_sdk_s3::Client) {
+//! let origin = axum_static_s3::S3Origin::builder().client(client).build();
+//! # }
+//! ```
+//!
+//! Common options are available directly; [`with`](TypedBuilder::with) applies any other
+//! [`S3OriginBuilder`] option.  `build` can still fail for invalid values, such as conflicting
+//! rules.
+use std::{marker::PhantomData, time::Duration};
+
+use aws_config::SdkConfig as AwsSdkConfig;
+use aws_sdk_s3::Client as S3Client;
+
+use crate::{lambda::LambdaLimit, HeadPolicy, PathSource, S3Origin, S3OriginBuilder, TrailingSlash};
+
+
+/// A required option that is not set yet.
+#[derive(Debug)]
+pub struct Missing;
+
+/// A required option that is set.
+#[derive(Debug)]
+pub struct Set;
+
+
+/// An [`S3OriginBuilder`] tracking whether the bucket (`B`) and the client or config (`C`) are
+/// set.
+#[derive(Debug)]
+pub struct TypedBuilder<B, C> {
+    builder: S3OriginBuilder,
+    state: PhantomData<(B, C)>,
+}
+
+impl S3Origin {
+    /// A builder that requires the bucket and client or config at compile time, see
+    /// [`typed`](crate::typed).
+    ///
+    pub fn builder() -> TypedBuilder<Missing, Missing> {
+        TypedBuilder { builder: S3OriginBuilder::new(), state: PhantomData }
+    }
+}
+
+impl<B, C> TypedBuilder<B, C> {
+    fn transition<B2, C2>(self, apply: impl FnOnce(S3OriginBuilder) -> S3OriginBuilder) -> TypedBuilder<B2, C2> {
+        TypedBuilder { builder: apply(self.builder), state: PhantomData }
+    }
+
+    /// Apply any [`S3OriginBuilder`] option.
+    pub fn with(self, apply: impl FnOnce(S3OriginBuilder) -> S3OriginBuilder) -> Self {
+        self.transition(apply)
+    }
+
+    /// The runtime-checked builder, e.g. to pass it to code expecting one.
+    pub fn into_builder(self) -> S3OriginBuilder {
+        self.builder
+    }
+}
+
+impl<C> TypedBuilder<Missing, C> {
+    /// See [`S3OriginBuilder::bucket`].
+    pub fn bucket(self, bucket: impl Into<String>) -> TypedBuilder<Set, C> {
+        self.transition(|builder| builder.bucket(bucket))
+    }
+}
+
+impl<B> TypedBuilder<B, Missing> {
+    /// See [`S3OriginBuilder::client`].
+    pub fn client(self, client: S3Client) -> TypedBuilder<B, Set> {
+        self.transition(|builder| builder.client(client))
+    }
+
+    /// See [`S3OriginBuilder::config`].
+    pub fn config(self, config: AwsSdkConfig) -> TypedBuilder<B, Set> {
+        self.transition(|builder| builder.config(config))
+    }
+}
+
+impl TypedBuilder<Set, Set> {
+    /// Build the S3 origin.
+    ///
+    /// The required options are set, so this only fails for invalid option values, see
+    /// [`S3OriginBuilder::build`].
+    pub fn build(self) -> Result<S3Origin, &'static str> {
+        self.builder.build()
+    }
+}
+
+
+/// Forward options that keep the required-option state.
+macro_rules! forward {
+    ($($name:ident($($arg:ident: $ty:ty),*);)*) => {
+        impl<B, C> TypedBuilder<B, C> {
+            $(
+                #[doc = concat!("See [`S3OriginBuilder::", stringify!($name), "`].")]
+                pub fn $name(self, $($arg: $ty),*) -> Self {
+                    self.with(|builder| builder.$name($($arg),*))
+                }
+            )*
+        }
+    };
+}
+
+forward! {
+    prefix(prefix: impl Into<String>);
+    prune_path(prune_path: usize);
+    path_source(path_source: PathSource);
+    stage_prefix(enabled: bool);
+    anonymous(anonymous: bool);
+    assume_role(role_arn: impl Into<String>, session_name: impl Into<String>);
+    expected_bucket_owner(account_id: impl Into<String>);
+    endpoint_url(endpoint_url: impl Into<String>);
+    connect_timeout(timeout: Duration);
+    read_timeout(timeout: Duration);
+    operation_timeout(timeout: Duration);
+    max_connections(max: usize);
+    pool_idle_timeout(timeout: Duration);
+    max_size(max_size: i64);
+    lambda_response_limit(limit: LambdaLimit);
+    head_policy(policy: HeadPolicy);
+    clean_urls(enabled: bool);
+    trailing_slash(trailing_slash: TrailingSlash);
+    cache_control(pattern: impl Into<String>, value: impl Into<String>);
+    immutable_assets(enabled: bool);
+    attachment(pattern: impl Into<String>);
+    cache(capacity: usize);
+    cache_ttl(ttl: Duration);
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::config::{BehaviorVersion, Region};
+
+    #[test]
+    fn builds_with_required_options() {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .build();
+        // Options may be set in any order
+        let origin = S3Origin::builder()
+            .prefix("site/")
+            .client(S3Client::from_conf(config))
+            .clean_urls(true)
+            .bucket("my-bucket")
+            .with(|builder| builder.error_id_header(true))
+            .build()
+            .unwrap();
+        let debug = format!("{:?}", origin);
+        assert!(debug.contains("bucket: \"my-bucket\", bucket_prefix: \"site/\""));
+        assert!(debug.contains("clean_urls: true"));
+        assert!(debug.contains("error_id_header: true"));
+    }
+}
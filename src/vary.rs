@@ -0,0 +1,62 @@
+//! Merging of `Vary` headers.
+//!
+//! Negotiation features each add the request headers their response depends on.  They are
+//! merged into a single `Vary` header, with names compared case-insensitively, so shared caches
+//! see each name once whatever the order the features ran in.
+use axum::http::{header, HeaderMap, HeaderValue};
+
+
+/// Add the comma-separated header `names` to the `Vary` header of a response.
+///
+/// `Vary: *` already covers every name and is kept as is.
+pub(crate) fn add(headers: &mut HeaderMap, names: &str) {
+    let mut merged: Vec<String> = Vec::new();
+    let existing = headers.get_all(header::VARY).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    for name in existing.iter().map(String::as_str).chain(names.split(',')) {
+        let name = name.trim();
+        if name.is_empty() || merged.iter().any(|merged| merged.eq_ignore_ascii_case(name)) {
+            continue;
+        }
+        merged.push(name.to_owned());
+    }
+    if merged.iter().any(|name| name == "*") {
+        merged = vec!["*".to_owned()];
+    }
+    if let Ok(value) = HeaderValue::try_from(merged.join(", ")) {
+        headers.insert(header::VARY, value);
+    }
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_names() {
+        let mut headers = HeaderMap::new();
+        add(&mut headers, "accept");
+        assert_eq!(headers[header::VARY], "accept");
+
+        add(&mut headers, "Accept, accept-language");
+        add(&mut headers, "cookie");
+        assert_eq!(headers.get_all(header::VARY).iter().count(), 1);
+        assert_eq!(headers[header::VARY], "accept, accept-language, cookie");
+
+        let mut headers = HeaderMap::new();
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        headers.append(header::VARY, HeaderValue::from_static("Referer"));
+        add(&mut headers, "origin");
+        assert_eq!(headers[header::VARY], "Origin, Referer");
+
+        add(&mut headers, "*");
+        assert_eq!(headers[header::VARY], "*");
+        add(&mut headers, "accept");
+        assert_eq!(headers[header::VARY], "*");
+    }
+}
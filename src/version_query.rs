@@ -0,0 +1,130 @@
+//! Mapping legacy `?v=123` asset URLs onto versioned key layouts.
+//!
+//! Older asset pipelines bust caches with a query string (`/app.js?v=123`) while newer ones
+//! version the key itself (`v123/app.js`, `app.123.js`).  With
+//! [`S3OriginBuilder::version_query`](crate::S3OriginBuilder::version_query) such requests are
+//! served from the versioned key, so HTML cached by clients keeps working after a migration.
+use axum::http::Uri;
+
+
+/// Maximum length of a version value; longer values are ignored.
+const MAX_VERSION_LEN: usize = 64;
+
+
+/// Translation of a version query parameter into a versioned key.
+///
+/// The template is expanded relative to the bucket prefix, with the placeholders:
+///
+/// - `{version}`: the value of the query parameter;
+/// - `{path}`: the request path, e.g. `js/app.js`;
+/// - `{dir}`: the directory of the path including its trailing `/`, e.g. `js/`, or empty;
+/// - `{file}`: the file name, e.g. `app.js`;
+/// - `{stem}` and `{ext}`: the file name before and after its last `.`, e.g. `app` and `js`.
+///
+/// `VersionQuery::new("v{version}/{path}")` serves `/js/app.js?v=123` from `v123/js/app.js`,
+/// `VersionQuery::new("{dir}{stem}.{version}.{ext}")` from `js/app.123.js`.
+///
+/// Only versions made of ASCII letters, digits, `.`, `-` and `_` are mapped, so the query can't
+/// reach keys outside the template.
+#[derive(Clone, Debug)]
+pub struct VersionQuery {
+    param: String,
+    template: String,
+    fallback: bool,
+}
+
+
+impl VersionQuery {
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            param: "v".to_string(),
+            template: template.into(),
+            fallback: true,
+        }
+    }
+
+    /// Set the name of the query parameter.
+    ///
+    /// This is optional, and defaults to `v`.
+    ///
+    pub fn param(mut self, param: impl Into<String>) -> Self {
+        self.param = param.into();
+        self
+    }
+
+    /// Serve the unversioned key when the versioned key does not exist.
+    ///
+    /// This is optional, and defaults to enabled.  The fallback costs one more S3 request for
+    /// versions that were never migrated.
+    ///
+    pub fn fallback(mut self, enabled: bool) -> Self {
+        self.fallback = enabled;
+        self
+    }
+
+    pub(crate) fn falls_back(&self) -> bool {
+        self.fallback
+    }
+
+    /// The versioned path for a request path (relative to the bucket prefix), if the request
+    /// carries a valid version.
+    pub(crate) fn map(&self, path: &str, uri: &Uri) -> Option<String> {
+        let version = uri.query()?
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| *name == self.param)
+            .map(|(_, value)| value)?;
+        if !is_valid_version(version) || path.is_empty() || path.ends_with('/') {
+            return None;
+        }
+
+        let (dir, file) = match path.rsplit_once('/') {
+            Some((dir, file)) => (&path[..dir.len() + 1], file),
+            None => ("", path),
+        };
+        let (stem, ext) = file.rsplit_once('.').unwrap_or((file, ""));
+
+        let versioned = self.template
+            .replace("{version}", version)
+            .replace("{path}", path)
+            .replace("{dir}", dir)
+            .replace("{file}", file)
+            .replace("{stem}", stem)
+            .replace("{ext}", ext);
+        Some(versioned.trim_start_matches('/').to_string())
+    }
+}
+
+
+fn is_valid_version(version: &str) -> bool {
+    !version.is_empty()
+        && version.len() <= MAX_VERSION_LEN
+        && version != "."
+        && version != ".."
+        && version.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-' | b'_'))
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    fn map(query: &VersionQuery, path: &str, uri: &'static str) -> Option<String> {
+        query.map(path, &uri.parse::<Uri>().unwrap())
+    }
+
+    #[test]
+    fn maps_versions() {
+        let query = VersionQuery::new("v{version}/{path}");
+        assert_eq!(map(&query, "js/app.js", "/js/app.js?v=123").as_deref(), Some("v123/js/app.js"));
+        assert_eq!(map(&query, "js/app.js", "/js/app.js?x=1&v=123").as_deref(), Some("v123/js/app.js"));
+        assert_eq!(map(&query, "js/app.js", "/js/app.js"), None);
+        assert_eq!(map(&query, "js/app.js", "/js/app.js?v=../x"), None);
+        assert_eq!(map(&query, "js/app.js", "/js/app.js?v="), None);
+
+        let query = VersionQuery::new("{dir}{stem}.{version}.{ext}").param("rev");
+        assert_eq!(map(&query, "js/app.js", "/js/app.js?rev=2024-01").as_deref(), Some("js/app.2024-01.js"));
+        assert_eq!(map(&query, "app.js", "/app.js?rev=7").as_deref(), Some("app.7.js"));
+    }
+}
@@ -0,0 +1,95 @@
+//! Serving `/.well-known/` paths apart from the site.
+//!
+//! Files such as `security.txt`, `apple-app-site-association` or `assetlinks.json` are owned by
+//! other teams than the site, and must survive its deployments.  With
+//! [`well_known`](crate::S3OriginBuilder::well_known), `/.well-known/` is served from a prefix of
+//! its own, from files given inline, or both:
+//!
+//! ```rust
+//! use axum_static_s3::well_known::WellKnown;
+//!
+//! let well_known = WellKnown::new()
+//!     .prefix("shared/well-known/")
+//!     .file("security.txt", "text/plain", "Contact: mailto:security@example.com\n");
+//! ```
+//!
+//! `/.well-known/{path}` is served from the key `{prefix}{path}`.  The prefix is a key prefix in
+//! the bucket: the bucket prefix, canaries, experiments and locales do not apply to it, and path
+//! rules see `{path}`.  Inline files are answered from memory like a
+//! [`synthetic_response`](crate::S3OriginBuilder::synthetic_response), and take precedence over
+//! the prefix.  Without a prefix, other `/.well-known/` paths are served like any path.
+use axum::body::Bytes;
+
+use crate::{key::{request_to_key, KeyError}, synthetic::SyntheticResponse};
+
+
+/// The path well-known resources are served under, without leading `/`.
+const WELL_KNOWN: &str = ".well-known/";
+
+
+/// Well-known path configuration.
+#[derive(Clone, Debug, Default)]
+pub struct WellKnown {
+    prefix: Option<String>,
+    files: Vec<(String, String, Bytes)>,
+}
+
+impl WellKnown {
+    /// No well-known paths; add a prefix or files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `/.well-known/` from the keys under `prefix`, e.g. `well-known/`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Answer `/.well-known/{name}` with `body`.
+    pub fn file(mut self, name: impl Into<String>, content_type: impl Into<String>, body: impl Into<Bytes>) -> Self {
+        self.files.push((name.into(), content_type.into(), body.into()));
+        self
+    }
+
+    /// The inline files as synthetic responses, and the prefix.
+    pub(crate) fn resolve(self) -> Result<(Vec<SyntheticResponse>, Option<String>), &'static str> {
+        let files = self.files.into_iter()
+            .map(|(name, content_type, body)| {
+                let path = format!("{}{}", WELL_KNOWN, name.trim_start_matches('/'));
+                SyntheticResponse::new(&path, &content_type, body)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.prefix.as_ref().is_some_and(|prefix| prefix.is_empty()) {
+            return Err("well_known prefix must not be empty");
+        }
+        Ok((files, self.prefix))
+    }
+}
+
+
+/// The key serving `path` from the well-known `prefix`, if `path` is well-known.
+pub(crate) fn key(prefix: &str, path: &str) -> Option<Result<String, KeyError>> {
+    let path = path.strip_prefix(WELL_KNOWN)?;
+    Some(request_to_key(prefix, path, 0))
+}
+
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_well_known_paths() {
+        assert_eq!(key("shared/", ".well-known/security.txt"), Some(Ok("shared/security.txt".into())));
+        assert_eq!(key("shared/", ".well-known/a%20b"), Some(Ok("shared/a b".into())));
+        assert_eq!(key("shared/", "well-known/security.txt"), None);
+        assert_eq!(key("shared/", "docs/.well-known/security.txt"), None);
+
+        let (files, prefix) = WellKnown::new().file("/security.txt", "text/plain", "Contact: x").resolve().unwrap();
+        assert!(SyntheticResponse::find(&files, ".well-known/security.txt").is_some());
+        assert_eq!(prefix, None);
+        assert!(WellKnown::new().prefix("").resolve().is_err());
+    }
+}